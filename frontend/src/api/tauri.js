@@ -4,41 +4,117 @@ import { listen } from '@tauri-apps/api/event';
 // --- Auth & State ---
 
 export const CheckLogin = () => invoke('check_login');
+export const GetAccountInfo = () => invoke('get_account_info');
 export const StartQRLogin = () => invoke('start_qr_login');
 export const StopQRLogin = () => invoke('stop_qr_login');
+export const ClearSession = () => invoke('clear_session');
+export const GetSessions = () => invoke('get_sessions');
+export const PruneSessions = () => invoke('prune_sessions');
+export const SetKeepaliveInterval = (minutes) => invoke('set_keepalive_interval', { minutes });
+export const SetCustomProxies = (entries) => invoke('set_custom_proxies', { entries });
+export const GetProxyPoolStatus = () => invoke('get_proxy_pool_status');
+export const ClearProxyCache = () => invoke('clear_proxy_cache');
+export const ClearHttpCache = () => invoke('clear_http_cache');
+export const TestProxy = (protocol, country, customUrl = null, probeGate = true) =>
+  invoke('test_proxy', { protocol, country, customUrl, probeGate });
 export const GetUserState = () => invoke('get_user_state');
 export const SaveUserState = (state) => invoke('save_user_state_cmd', { state });
 export const GetMembers = () => invoke('get_members');
+export const AddMember = (name, idCard, phone, relation = '') => invoke('add_member', { name, idCard, phone, relation });
+export const GetAddresses = () => invoke('get_addresses');
+export const AddAddress = (regionId, detailText) => invoke('add_address', { regionId, detailText });
+export const ListProfiles = () => invoke('list_profiles');
+export const CreateProfile = (name) => invoke('create_profile', { name });
+export const DeleteProfile = (name) => invoke('delete_profile', { name });
+export const SwitchProfile = (name) => invoke('switch_profile', { name });
+export const GetConfigPaths = () => invoke('get_config_paths');
+export const OpenConfigDir = () => invoke('open_config_dir');
+export const RevealPath = (path) => invoke('reveal_path', { path });
+export const CreateDiagnosticsBundle = () => invoke('create_diagnostics_bundle');
+export const GetSubmitTraces = () => invoke('get_submit_traces');
+export const ExportSettings = (path, includeCookies = true) => invoke('export_settings', { path: path || null, includeCookies });
+export const ImportSettings = (path) => invoke('import_settings', { path: path || null });
 
 // --- Data Fetching ---
 
 export const GetCities = () => invoke('get_cities');
 
 export const GetHospitalsByCity = (cityId) => invoke('get_hospitals_by_city', { cityId: cityId });
+export const GetHospitalDetail = (unitId) => invoke('get_hospital_detail', { unitId });
+export const GetBookingRules = (unitId, depId, cityPinyin = '') =>
+  invoke('get_booking_rules', { unitId, depId, cityPinyin });
+export const ApplyBookingRules = (config, rules) => invoke('apply_booking_rules', { config, rules });
 
-export const GetDepsByUnit = (unitId, cityPinyin) => invoke('get_deps_by_unit', { unitId: unitId, cityPinyin: cityPinyin || '' });
+export const GetDepsByUnit = (unitId, cityPinyin, cityId) => invoke('get_deps_by_unit', {
+    unitId: unitId,
+    cityPinyin: cityPinyin || '',
+    cityId: cityId ? String(cityId) : null
+});
+export const GetDepsFlat = (unitId, cityPinyin, cityId) => invoke('get_deps_flat', {
+    unitId: unitId,
+    cityPinyin: cityPinyin || '',
+    cityId: cityId ? String(cityId) : null
+});
+export const SearchCatalog = (cityId, cityPinyin, keyword) => invoke('search_catalog', {
+    cityId: String(cityId || ''),
+    cityPinyin: cityPinyin || '',
+    keyword: keyword || ''
+});
+export const ParseBookingUrl = (url, cityId) => invoke('parse_booking_url', { url, cityId: cityId ? String(cityId) : null });
 
 export const GetSchedule = (unitId, depId, date) => invoke('get_schedule', {
     unitId: unitId,
     depId: depId,
     date: date
 });
+export const GetAvailabilityCalendar = (unitId, depId, days = 14) => invoke('get_availability_calendar', {
+    unitId: unitId,
+    depId: depId,
+    days: days
+});
+export const GetDepartmentDoctors = (unitId, depId, lookaheadDays = 7) =>
+  invoke('get_department_doctors', { unitId, depId, lookaheadDays });
 
-export const GetTicketDetail = (unitId, depId, scheduleId, memberId) => invoke('get_ticket_detail', {
+export const GetAddressOptions = (unitId, depId, scheduleId) => invoke('get_address_options', { unitId, depId, scheduleId });
+export const GetTicketDetail = (unitId, depId, scheduleId, memberId, cityPinyin) => invoke('get_ticket_detail', {
     unitId: unitId,
     depId: depId,
     scheduleId: scheduleId,
-    memberId: memberId
+    memberId: memberId,
+    cityPinyin: cityPinyin || null
 });
 
 // --- Grab Task ---
 
-export const StartGrab = (config) => invoke('start_grab', { config });
+export const StartGrab = (config, confirmed = false) => invoke('start_grab', { config, confirmed });
+export const ValidateGrabConfig = (config) => invoke('validate_grab_config', { config });
 export const StopGrab = () => invoke('stop_grab');
+export const ResumeGrabAfterCaptcha = () => invoke('resume_grab_after_captcha');
+export const StartGrabFromPreset = (name, confirmed = false) => invoke('start_grab_from_preset', { name, confirmed });
+export const RepeatLastGrab = (unitId, depId, newDates, confirmed = false) =>
+  invoke('repeat_last_grab', { unitId, depId, newDates, confirmed });
+export const ListRepeatTemplates = () => invoke('list_repeat_templates');
+export const ListGrabPresets = () => invoke('list_grab_presets');
+export const SaveGrabPreset = (name, config) => invoke('save_grab_preset', { name, config });
+export const DeleteGrabPreset = (name) => invoke('delete_grab_preset', { name });
+export const ResumePendingGrab = () => invoke('resume_pending_grab');
+export const ForceExit = () => invoke('force_exit');
+export const GetGrabHistory = (limit) => invoke('get_grab_history', { limit });
+export const ClearGrabHistory = () => invoke('clear_grab_history');
+export const OpenOrderUrl = (url) => invoke('open_order_url', { url });
+export const GetNetworkStats = () => invoke('get_network_stats');
+export const RunHealthCheck = () => invoke('run_health_check');
+export const GetTimeOffset = () => invoke('get_time_offset');
+export const ExportAppointmentIcs = (success, path = null) => invoke('export_appointment_ics', { success, path });
 
 // --- Logs ---
 
 export const ExportLogs = (logs) => invoke('export_logs', { logs });
+export const SetLogLevel = (level) => invoke('set_log_level', { level });
+export const GetAlertConfig = () => invoke('get_alert_config');
+export const SetAlertConfig = (config) => invoke('set_alert_config', { config });
+export const SetClientProfile = (preset, profile = null) => invoke('set_client_profile', { preset, profile });
+export const SetNetworkSettings = (network) => invoke('set_network_settings', { network });
 
 // --- Events ---
 
@@ -20,11 +20,15 @@ const loadingMembers = ref(false)
 // Login Flow State
 const qrStatus = ref('等待启动')
 const qrImageUrl = ref('')
+// Pixel size of the QR image, so the <img> keeps its box while it loads
+const qrImageSize = ref(null)
 const loginRunning = ref(false)
 const loginChecked = ref(false)
 const loginFailCount = ref(0)
 const loginAttemptActive = ref(false)
 const loginNotice = ref('')
+// Unix seconds when the session cookies expire, if the server said
+const sessionExpiresAt = ref(null)
 const loginFailLimit = 3
 
 export function useAuth() {
@@ -35,6 +39,13 @@ export function useAuth() {
         return loggedIn.value ? '已登录' : '未登录'
     })
 
+    // "登录将于 X 小时后过期" while the expiry is known
+    const expiryLabel = computed(() => {
+        if (!loggedIn.value || !sessionExpiresAt.value) return ''
+        const hours = Math.round((sessionExpiresAt.value * 1000 - Date.now()) / 3600000)
+        return hours > 0 ? `登录将于 ${hours} 小时后过期` : '登录即将过期'
+    })
+
     // Start QR Code Login Flow
     const startLogin = async () => {
         loginNotice.value = ''
@@ -111,8 +122,9 @@ export function useAuth() {
     // Initialize Listeners
     const initAuthListeners = () => {
         // Check initial login status
-        CheckLogin().then(ok => {
-            loggedIn.value = Boolean(ok)
+        CheckLogin().then(status => {
+            loggedIn.value = Boolean(status?.logged_in)
+            sessionExpiresAt.value = status?.session_expires_at || null
             loginChecked.value = true
             if (loggedIn.value) {
                 loadMembers()
@@ -125,8 +137,9 @@ export function useAuth() {
         // QR Image Update
         EventsOn('qr-image', (payload) => {
             const base64 = payload?.base64 || ''
-            const mime = base64.startsWith('/9j/') ? 'image/jpeg' : 'image/png'
+            const mime = payload?.mime || 'image/png'
             qrImageUrl.value = base64 ? `data:${mime};base64,${base64}` : ''
+            qrImageSize.value = payload?.width && payload?.height ? { width: payload.width, height: payload.height } : null
             if (payload?.uuid) {
                 pushLog('info', `二维码已刷新`)
             }
@@ -139,37 +152,53 @@ export function useAuth() {
             }
         })
 
-        // Login Status Update
+        EventsOn('session-expiry', (payload) => {
+            sessionExpiresAt.value = payload?.expiresAt || null
+        })
+
+        // Login Status Update: fires only when the session changes
         EventsOn('login-status', (payload) => {
             const isLoggedIn = Boolean(payload?.loggedIn)
             loggedIn.value = isLoggedIn
             loginChecked.value = true
-            loginRunning.value = false
 
             if (isLoggedIn) {
-                loginFailCount.value = 0
                 loginNotice.value = ''
-                loginAttemptActive.value = false
-                pushLog('success', '登录成功')
                 loadMembers()
             } else {
                 members.value = []
-                if (loginAttemptActive.value) {
-                    loginFailCount.value += 1
-                    loginAttemptActive.value = false
-
-                    if (loginFailCount.value >= loginFailLimit) {
-                        loginNotice.value = `登录连续失败（${loginFailCount.value}次）`
-                        pushLog('warn', loginNotice.value)
-                        StopQRLogin()
-                    } else {
-                        loginNotice.value = '登录失败，请重试'
-                    }
-                } else {
+                if (!loginAttemptActive.value) {
                     loginNotice.value = '登录已失效'
                 }
             }
         })
+
+        // End of a QR login attempt, also when the session was already live
+        EventsOn('qr-login-result', (payload) => {
+            loginRunning.value = false
+            const wasActive = loginAttemptActive.value
+            loginAttemptActive.value = false
+
+            if (payload?.success) {
+                loggedIn.value = true
+                loginChecked.value = true
+                loginFailCount.value = 0
+                loginNotice.value = ''
+                pushLog('success', '登录成功')
+                // The scanned account may differ from the previous one
+                loadMembers()
+                return
+            }
+            if (!wasActive) return
+            loginFailCount.value += 1
+            if (loginFailCount.value >= loginFailLimit) {
+                loginNotice.value = `登录连续失败（${loginFailCount.value}次）`
+                pushLog('warn', loginNotice.value)
+                StopQRLogin()
+            } else {
+                loginNotice.value = '登录失败，请重试'
+            }
+        })
     }
 
     return {
@@ -181,9 +210,11 @@ export function useAuth() {
         loadingMembers,
         qrStatus,
         qrImageUrl,
+        qrImageSize,
         loginRunning,
         loginNotice,
         statusLabel,
+        expiryLabel,
 
         startLogin,
         stopLogin,
@@ -1,5 +1,5 @@
 import { ref } from 'vue'
-import { StartGrab, StopGrab, EventsOn } from '../api/tauri'
+import { StartGrab, StopGrab, ResumeGrabAfterCaptcha, ResumePendingGrab, ForceExit, OpenOrderUrl, EventsOn } from '../api/tauri'
 import { useLogger } from './useLogger'
 
 // Task Configuration State
@@ -9,6 +9,28 @@ const grabResult = ref(null)
 const preferredHours = ref([])
 const timeTypes = ref([])
 const selectedScheduleId = ref('')
+// Pickup address chosen for the selected schedule ({ id, text }), null = let the page decide
+const selectedAddress = ref(null)
+// Latest grab-countdown payload while waiting for start_time
+const countdown = ref(null)
+
+// Short beep per play-alert kind: [frequency Hz, duration ms]
+const ALERT_TONES = { found: [880, 200], success: [1320, 600], failure: [330, 400] }
+
+const playAlert = (kind) => {
+    const [freq, ms] = ALERT_TONES[kind] || ALERT_TONES.found
+    try {
+        const ctx = new (window.AudioContext || window.webkitAudioContext)()
+        const osc = ctx.createOscillator()
+        osc.frequency.value = freq
+        osc.connect(ctx.destination)
+        osc.start()
+        osc.stop(ctx.currentTime + ms / 1000)
+        osc.onended = () => ctx.close()
+    } catch {
+        // Audio unavailable (e.g. no output device); the log entry still shows the event
+    }
+}
 
 export function useGrabTask() {
     const { pushLog, stringifyError } = useLogger()
@@ -64,11 +86,25 @@ export function useGrabTask() {
 
     const startGrab = async (configPayload) => {
         grabResult.value = null
+        countdown.value = null
         try {
             const validConfig = buildGrabConfig(configPayload)
             grabRunning.value = true
             // We pass the config to backend
-            await StartGrab(validConfig)
+            let start = await StartGrab(validConfig)
+            if (start?.status === 'needs_confirmation') {
+                if (!window.confirm(`将使用账号 ${start.identity?.label || '未知账号'} 抢号，确认继续？`)) {
+                    grabRunning.value = false
+                    pushLog('warn', '已取消抢号')
+                    return
+                }
+                start = await StartGrab(validConfig, true)
+                if (start?.status !== 'started') {
+                    grabRunning.value = false
+                    pushLog('warn', '确认已过期或账号已变更，请重新开始')
+                    return
+                }
+            }
             pushLog('info', '抢号任务已启动')
         } catch (err) {
             grabRunning.value = false
@@ -86,11 +122,61 @@ export function useGrabTask() {
         // Note: grabRunning usually set to false by event 'grab-finished' or manual toggle
         // But immediate feedback is good
         grabRunning.value = false
+        countdown.value = null
+    }
+
+    // Offer (or auto-restart) a grab interrupted by a crash or reboot
+    const resumePendingGrab = async () => {
+        try {
+            if (await ResumePendingGrab()) {
+                grabRunning.value = true
+                pushLog('info', '已自动恢复未完成的抢号任务')
+            }
+        } catch (err) {
+            pushLog('warn', `恢复抢号任务失败: ${stringifyError(err)}`)
+        }
     }
 
     const initGrabListeners = () => {
+        EventsOn('grab-resume-available', (payload) => {
+            if (!payload?.config || grabRunning.value) return
+            if (window.confirm('检测到上次未完成的抢号任务，是否恢复？')) {
+                startGrab(payload.config)
+            }
+        })
+        EventsOn('grab-countdown', (payload) => {
+            countdown.value = payload?.remaining_ms > 0 ? payload : null
+        })
+        EventsOn('grab-captcha-required', async (payload) => {
+            const minutes = Math.round((payload?.timeout_secs || 600) / 60)
+            pushLog('warn', `触发验证码，抢号已暂停，请在 ${minutes} 分钟内完成验证`)
+            if (payload?.url && window.confirm('触发验证码，是否在浏览器中打开挂号页完成验证？')) {
+                try {
+                    await OpenOrderUrl(payload.url)
+                } catch (err) {
+                    pushLog('error', `打开浏览器失败: ${stringifyError(err)}`)
+                }
+            }
+            if (!window.confirm('在浏览器中完成验证后点击确定继续抢号')) return
+            try {
+                await ResumeGrabAfterCaptcha()
+            } catch (err) {
+                pushLog('error', `继续抢号失败: ${stringifyError(err)}`)
+            }
+        })
+        EventsOn('play-alert', (payload) => playAlert(payload?.kind))
+        EventsOn('confirm-exit', async () => {
+            if (!window.confirm('抢号/扫码任务正在进行，确定退出吗？')) return
+            pushLog('warn', '正在停止任务并退出...')
+            try {
+                await ForceExit()
+            } catch (err) {
+                pushLog('error', `退出失败: ${stringifyError(err)}`)
+            }
+        })
         EventsOn('grab-finished', (payload) => {
             grabRunning.value = false
+            countdown.value = null
             grabResult.value = payload || null
             if (payload?.success) {
                 pushLog('success', payload?.message || '抢号完成')
@@ -107,6 +193,8 @@ export function useGrabTask() {
         preferredHours,
         timeTypes,
         selectedScheduleId,
+        selectedAddress,
+        countdown,
 
         addDateRange,
         addTargetDate,
@@ -114,6 +202,7 @@ export function useGrabTask() {
         clearTargetDates,
         startGrab,
         stopGrab,
+        resumePendingGrab,
         initGrabListeners
     }
 }
@@ -2,7 +2,7 @@ import { ref, computed } from 'vue'
 import {
     GetCities,
     GetHospitalsByCity,
-    GetDepsByUnit,
+    GetDepsFlat,
     GetSchedule
 } from '../api/tauri'
 import { useLogger } from './useLogger'
@@ -145,23 +145,14 @@ export function useHospitalData() {
             const cityPinyin = cityObj?.pinyin || ''
             pushLog('info', `正在根据城市拼音加载科室: ${cityPinyin || '默认(www)'} (医院ID: ${unitIdVal})`)
 
-            const data = await GetDepsByUnit(String(unitIdVal), cityPinyin)
-            const items = []
-            if (Array.isArray(data)) {
-                data.forEach((item) => {
-                    if (Array.isArray(item.childs)) {
-                        item.childs.forEach((child) => {
-                            const id = String(child.dep_id || child.id || '')
-                            const name = String(child.dep_name || child.name || '')
-                            if (id && name) items.push({ id, name })
-                        })
-                    } else {
-                        const id = String(item.dep_id || item.id || '')
-                        const name = String(item.dep_name || item.name || '')
-                        if (id && name) items.push({ id, name })
-                    }
-                })
-            }
+            const data = await GetDepsFlat(String(unitIdVal), cityPinyin, selectedCity.value)
+            const items = Array.isArray(data)
+                ? data.map(dep => ({
+                    id: String(dep.dep_id || ''),
+                    name: String(dep.dep_name || ''),
+                    category: String(dep.category_name || '')
+                })).filter(dep => dep.id && dep.name)
+                : []
             deps.value = items
             applySelection(depId, deps.value)
             if (items.length === 0) {
@@ -0,0 +1,126 @@
+//! Schedule API abstraction for QuickDoctor
+//! Lets the grabber run against HealthClient or a scripted test double
+
+use std::future::Future;
+
+use chrono::{DateTime, Local};
+
+use super::client::HealthClient;
+use super::errors::AppResult;
+use super::types::{AddressRecord, Appointment, DepartmentCategory, DoctorSchedule, SubmitOrderParams, SubmitOrderResult, TicketDetail};
+
+/// One server clock sample: (local_send, local_recv, server_time)
+pub type TimeSample = (DateTime<Local>, DateTime<Local>, DateTime<Local>);
+
+/// Endpoints the grabber needs from the 91160 backend
+pub trait ScheduleApi: Send + Sync {
+    /// Get schedule for a department on a date
+    fn get_schedule(
+        &self,
+        unit_id: &str,
+        dep_id: &str,
+        date: &str,
+    ) -> impl Future<Output = AppResult<Vec<DoctorSchedule>>> + Send;
+
+    /// Get schedule during a grab, under the tighter query budget
+    /// `timeout_secs` is the grab config's override (0 = the client's setting).
+    fn get_grab_schedule(
+        &self,
+        unit_id: &str,
+        dep_id: &str,
+        date: &str,
+        timeout_secs: u64,
+    ) -> impl Future<Output = AppResult<Vec<DoctorSchedule>>> + Send {
+        let _ = timeout_secs;
+        self.get_schedule(unit_id, dep_id, date)
+    }
+
+    /// Department tree of a hospital, for expanding a category into departments
+    fn get_deps_by_unit(
+        &self,
+        unit_id: &str,
+        city_pinyin: &str,
+    ) -> impl Future<Output = AppResult<Vec<DepartmentCategory>>> + Send;
+
+    /// Get ticket detail for a schedule
+    fn get_ticket_detail(
+        &self,
+        unit_id: &str,
+        dep_id: &str,
+        schedule_id: &str,
+        member_id: &str,
+        city_pinyin: Option<&str>,
+    ) -> impl Future<Output = AppResult<TicketDetail>> + Send;
+
+    /// Submit an order with optional proxy
+    fn submit_order(
+        &self,
+        params: &SubmitOrderParams,
+        proxy_url: Option<String>,
+    ) -> impl Future<Output = AppResult<SubmitOrderResult>> + Send;
+
+    /// Get server datetime
+    fn get_server_datetime(&self) -> impl Future<Output = AppResult<DateTime<Local>>> + Send;
+
+    /// Take one server clock sample
+    fn sample_server_time(&self) -> impl Future<Output = AppResult<TimeSample>> + Send;
+
+    /// Warm pooled connections to the hosts used during a grab
+    fn warm_up_connections(&self) -> impl Future<Output = AppResult<()>> + Send;
+
+    /// Get saved addresses from the account's address book
+    fn get_addresses(&self) -> impl Future<Output = AppResult<Vec<AddressRecord>>> + Send;
+
+    /// Get the account's order list, for the existing-appointment precheck
+    fn get_appointments(&self) -> impl Future<Output = AppResult<Vec<Appointment>>> + Send;
+}
+
+impl ScheduleApi for HealthClient {
+    async fn get_schedule(&self, unit_id: &str, dep_id: &str, date: &str) -> AppResult<Vec<DoctorSchedule>> {
+        HealthClient::get_schedule(self, unit_id, dep_id, date).await
+    }
+
+    async fn get_grab_schedule(&self, unit_id: &str, dep_id: &str, date: &str, timeout_secs: u64) -> AppResult<Vec<DoctorSchedule>> {
+        let timeout = self.network().grab_query_timeout(Some(timeout_secs));
+        HealthClient::get_schedule_within(self, unit_id, dep_id, date, Some(timeout)).await
+    }
+
+    async fn get_deps_by_unit(&self, unit_id: &str, city_pinyin: &str) -> AppResult<Vec<DepartmentCategory>> {
+        HealthClient::get_deps_by_unit(self, unit_id, city_pinyin).await
+    }
+
+    async fn get_ticket_detail(
+        &self,
+        unit_id: &str,
+        dep_id: &str,
+        schedule_id: &str,
+        member_id: &str,
+        city_pinyin: Option<&str>,
+    ) -> AppResult<TicketDetail> {
+        HealthClient::get_ticket_detail(self, unit_id, dep_id, schedule_id, member_id, city_pinyin).await
+    }
+
+    async fn submit_order(&self, params: &SubmitOrderParams, proxy_url: Option<String>) -> AppResult<SubmitOrderResult> {
+        HealthClient::submit_order(self, params, proxy_url).await
+    }
+
+    async fn get_server_datetime(&self) -> AppResult<DateTime<Local>> {
+        HealthClient::get_server_datetime(self).await
+    }
+
+    async fn sample_server_time(&self) -> AppResult<TimeSample> {
+        HealthClient::sample_server_time(self).await
+    }
+
+    async fn warm_up_connections(&self) -> AppResult<()> {
+        HealthClient::warm_up_connections(self).await
+    }
+
+    async fn get_addresses(&self) -> AppResult<Vec<AddressRecord>> {
+        HealthClient::get_addresses(self).await
+    }
+
+    async fn get_appointments(&self) -> AppResult<Vec<Appointment>> {
+        HealthClient::get_appointments(self).await
+    }
+}
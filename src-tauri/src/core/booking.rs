@@ -0,0 +1,232 @@
+//! Booking rule extraction for QuickDoctor
+//! Reads the release time and booking window from hospital page text
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use regex::Regex;
+
+use super::textutil::truncate_utf8;
+use super::types::{BookingRules, GrabConfig};
+
+/// Longest rule text kept in `BookingRules::raw_text`
+const RAW_TEXT_BYTES: usize = 300;
+/// Advance windows beyond this are taken as a misread
+const MAX_ADVANCE_DAYS: u32 = 90;
+
+/// Words that mark a line as describing the release rule
+const RULE_KEYWORDS: &[&str] = &["放号", "号源", "预约周期", "开放预约", "可预约", "提前"];
+
+/// Pull the release time and advance days out of page text, one rule line at a time
+/// Lines are tried in order; each value comes from the first line that has it.
+pub fn parse_booking_rules(text: &str) -> BookingRules {
+    let mut rules = BookingRules::default();
+    let mut raw_lines: Vec<&str> = Vec::new();
+
+    for line in text.lines().map(str::trim).filter(|l| RULE_KEYWORDS.iter().any(|k| l.contains(k))) {
+        let mut used = false;
+        if rules.release_time.is_none() {
+            if let Some(time) = release_time(line) {
+                rules.release_time = Some(time.format("%H:%M:%S").to_string());
+                used = true;
+            }
+        }
+        if rules.advance_days.is_none() {
+            if let Some(days) = advance_days(line) {
+                rules.advance_days = Some(days);
+                used = true;
+            }
+        }
+        if used && !raw_lines.contains(&line) {
+            raw_lines.push(line);
+        }
+        if rules.release_time.is_some() && rules.advance_days.is_some() {
+            break;
+        }
+    }
+
+    rules.raw_text = truncate_utf8(&raw_lines.join(" / "), RAW_TEXT_BYTES);
+    rules
+}
+
+/// Clock time in a rule line: "07:30", "7：30", "8点", "7点半", "下午3点"
+fn release_time(line: &str) -> Option<NaiveTime> {
+    let re = Regex::new(r"(凌晨|早上|上午|中午|下午|晚上)?\s*(\d{1,2})\s*(?:[:：]\s*(\d{2})|点\s*(半|\d{1,2}\s*分?)?)").ok()?;
+    let caps = re.captures(line)?;
+    let mut hour: u32 = caps[2].parse().ok()?;
+    let minute: u32 = match (caps.get(3), caps.get(4)) {
+        (Some(m), _) => m.as_str().parse().ok()?,
+        (None, Some(m)) if m.as_str() == "半" => 30,
+        (None, Some(m)) => m.as_str().trim_end_matches('分').trim().parse().ok()?,
+        (None, None) => 0,
+    };
+    if matches!(caps.get(1).map(|m| m.as_str()), Some("下午" | "晚上")) && hour < 12 {
+        hour += 12;
+    }
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+/// Days ahead in a rule line: "7天后", "未来14天", "预约周期为7天", "提前七天", "两周内"
+fn advance_days(line: &str) -> Option<u8> {
+    const NUM: &str = r"(\d{1,3}|[一二两三四五六七八九十]{1,3})";
+    let days = Regex::new(&format!(
+        r"(?:提前|未来|预约周期[为是]?|可预约|放)\s*{NUM}\s*[天日]|{NUM}\s*[天日](?:后|内|以内|之内)"
+    ))
+    .ok()?;
+    let weeks = Regex::new(&format!(r"{NUM}\s*(?:周|个?星期)")).ok()?;
+
+    let days = if let Some(caps) = days.captures(line) {
+        parse_count(caps.get(1).or_else(|| caps.get(2))?.as_str())?
+    } else {
+        let caps = weeks.captures(line)?;
+        parse_count(&caps[1])? * 7
+    };
+    (1..=MAX_ADVANCE_DAYS).contains(&days).then_some(days as u8)
+}
+
+/// Arabic digits or a small Chinese numeral (一 .. 九十九)
+fn parse_count(text: &str) -> Option<u32> {
+    if let Ok(n) = text.parse() {
+        return Some(n);
+    }
+    let digit = |c: char| "零一二三四五六七八九".chars().position(|d| d == c).map(|n| n as u32).or((c == '两').then_some(2));
+    let chars: Vec<char> = text.chars().collect();
+    match chars.as_slice() {
+        [c] if *c == '十' => Some(10),
+        [c] => digit(*c),
+        ['十', ones] => Some(10 + digit(*ones)?),
+        [tens, '十'] => Some(digit(*tens)? * 10),
+        [tens, '十', ones] => Some(digit(*tens)? * 10 + digit(*ones)?),
+        _ => None,
+    }
+}
+
+/// `config` with start_time set to the release time and, when it has no
+/// target dates yet, the date the next release opens
+/// A release later today opens today + advance_days; one already past
+/// opens tomorrow's. Unknown parts of the rules leave the config alone.
+pub fn apply_rules_to_config(config: &GrabConfig, rules: &BookingRules, now: NaiveDateTime) -> GrabConfig {
+    let mut config = config.clone();
+    let release = rules
+        .release_time
+        .as_deref()
+        .and_then(|t| NaiveTime::parse_from_str(t, "%H:%M:%S").ok());
+    if let Some(release) = release {
+        config.start_time = release.format("%H:%M:%S").to_string();
+    }
+
+    if let (Some(days), true) = (rules.advance_days, config.target_dates.is_empty()) {
+        let today = now.date();
+        let release_day = match release {
+            Some(release) if now.time() >= release => today + Duration::days(1),
+            _ => today,
+        };
+        let target: NaiveDate = release_day + Duration::days(i64::from(days));
+        config.target_dates = vec![target.format("%Y-%m-%d").to_string()];
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(text: &str) -> (Option<String>, Option<u8>) {
+        let parsed = parse_booking_rules(text);
+        (parsed.release_time, parsed.advance_days)
+    }
+
+    #[test]
+    fn test_parse_collected_rule_samples() {
+        let samples: &[(&str, Option<&str>, Option<u8>)] = &[
+            ("每日 07:30 放 7 天后号源", Some("07:30:00"), Some(7)),
+            ("放号时间：每天15:00，可预约未来14天的号源", Some("15:00:00"), Some(14)),
+            ("每天早上8点放号，预约周期为7天", Some("08:00:00"), Some(7)),
+            ("本院每日7点半开放预约，可预约7日内号源", Some("07:30:00"), Some(7)),
+            ("每天下午3点放号", Some("15:00:00"), None),
+            ("号源提前七天放出", None, Some(7)),
+            ("预约周期：两周内的号源", None, Some(14)),
+            ("每天 8：00 更新第二天起十四天内号源", Some("08:00:00"), Some(14)),
+            ("每日20:00放号，提前十天预约", Some("20:00:00"), Some(10)),
+        ];
+        for (text, time, days) in samples {
+            assert_eq!(rules(text), (time.map(str::to_string), *days), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_ignores_lines_without_rule_keywords() {
+        let page = "门诊时间 08:00-17:30\n咨询电话 0755-12345678\n7天无理由退款\n每天 07:30 放号\n可预约 7 天后号源";
+        let parsed = parse_booking_rules(page);
+        assert_eq!(parsed.release_time.as_deref(), Some("07:30:00"));
+        assert_eq!(parsed.advance_days, Some(7));
+        assert_eq!(parsed.raw_text, "每天 07:30 放号 / 可预约 7 天后号源");
+
+        assert_eq!(parse_booking_rules("医院简介\n门诊时间 08:00"), BookingRules::default());
+    }
+
+    #[test]
+    fn test_parse_rejects_out_of_range_values() {
+        assert_eq!(rules("每天25:00放号，提前365天"), (None, None));
+    }
+
+    #[test]
+    fn test_parse_count_handles_chinese_numerals() {
+        assert_eq!(parse_count("7"), Some(7));
+        assert_eq!(parse_count("十"), Some(10));
+        assert_eq!(parse_count("十四"), Some(14));
+        assert_eq!(parse_count("二十"), Some(20));
+        assert_eq!(parse_count("三十一"), Some(31));
+        assert_eq!(parse_count("两"), Some(2));
+        assert_eq!(parse_count("十十"), None);
+    }
+
+    fn config() -> GrabConfig {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": "u1",
+            "dep_id": "d1",
+            "member_id": "m1",
+        }))
+        .unwrap()
+    }
+
+    fn at(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_apply_rules_picks_next_release() {
+        let rules = BookingRules {
+            release_time: Some("07:30:00".into()),
+            advance_days: Some(7),
+            raw_text: String::new(),
+        };
+
+        let before = apply_rules_to_config(&config(), &rules, at("2024-03-13 06:00:00"));
+        assert_eq!(before.start_time, "07:30:00");
+        assert_eq!(before.target_dates, ["2024-03-20"]);
+
+        let after = apply_rules_to_config(&config(), &rules, at("2024-03-13 09:00:00"));
+        assert_eq!(after.target_dates, ["2024-03-21"]);
+
+        // Dates the user already chose are kept
+        let mut chosen = config();
+        chosen.target_dates = vec!["2024-03-18".into()];
+        assert_eq!(apply_rules_to_config(&chosen, &rules, at("2024-03-13 06:00:00")).target_dates, ["2024-03-18"]);
+    }
+
+    #[test]
+    fn test_apply_rules_leaves_unknown_parts() {
+        let mut base = config();
+        base.start_time = "08:00:00".into();
+        let unknown = apply_rules_to_config(&base, &BookingRules::default(), at("2024-03-13 06:00:00"));
+        assert_eq!(unknown.start_time, "08:00:00");
+        assert!(unknown.target_dates.is_empty());
+
+        let days_only = BookingRules {
+            advance_days: Some(3),
+            ..BookingRules::default()
+        };
+        let applied = apply_rules_to_config(&base, &days_only, at("2024-03-13 23:00:00"));
+        assert_eq!(applied.start_time, "08:00:00");
+        assert_eq!(applied.target_dates, ["2024-03-16"]);
+    }
+}
@@ -0,0 +1,368 @@
+//! Hospital/department catalog cache and keyword search for QuickDoctor
+//! Keeps list responses for a while so typing in the search box does not
+//! turn into one request per keystroke
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::client::HealthClient;
+use super::errors::AppResult;
+use super::types::{Department, DepartmentCategory, DepartmentDoctor, Hospital};
+
+/// How long a fetched hospital or department list stays fresh
+pub const CATALOG_TTL: Duration = Duration::from_secs(30 * 60);
+/// Doctor lists carry ticket counts, so they go stale much sooner
+pub const DOCTORS_TTL: Duration = Duration::from_secs(5 * 60);
+/// Cap on search results returned to the UI
+pub const MAX_SEARCH_RESULTS: usize = 50;
+
+#[derive(Debug, Clone)]
+struct Cached<T> {
+    value: T,
+    fetched_at: Instant,
+}
+
+/// In-memory cache of hospital lists (per city), department trees (per unit)
+/// and department doctor lists (per department and lookahead)
+#[derive(Debug)]
+pub struct CatalogCache {
+    ttl: Duration,
+    doctors_ttl: Duration,
+    hospitals: RwLock<HashMap<String, Cached<Vec<Hospital>>>>,
+    deps: RwLock<HashMap<String, Cached<Vec<DepartmentCategory>>>>,
+    doctors: RwLock<HashMap<String, Cached<Vec<DepartmentDoctor>>>>,
+}
+
+impl Default for CatalogCache {
+    fn default() -> Self {
+        Self::new(CATALOG_TTL)
+    }
+}
+
+impl CatalogCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            doctors_ttl: ttl.min(DOCTORS_TTL),
+            hospitals: RwLock::new(HashMap::new()),
+            deps: RwLock::new(HashMap::new()),
+            doctors: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Hospitals in a city, fetched through `client` when missing or stale
+    pub async fn hospitals(&self, client: &HealthClient, city_id: &str) -> AppResult<Vec<Hospital>> {
+        self.hospitals_with(city_id, || client.get_hospitals_by_city(city_id)).await
+    }
+
+    /// Department tree of a hospital, fetched through `client` when missing or stale
+    pub async fn deps(&self, client: &HealthClient, unit_id: &str, city_pinyin: &str) -> AppResult<Vec<DepartmentCategory>> {
+        self.deps_with(unit_id, || client.get_deps_by_unit(unit_id, city_pinyin)).await
+    }
+
+    /// Doctors of a department over the next `lookahead_days`, fetched through `client` when missing or stale
+    pub async fn department_doctors(&self, client: &Arc<HealthClient>, unit_id: &str, dep_id: &str, lookahead_days: u8) -> AppResult<Vec<DepartmentDoctor>> {
+        let key = format!("{}/{}/{}", unit_id, dep_id, lookahead_days);
+        self.department_doctors_with(&key, || client.get_department_doctors(unit_id, dep_id, lookahead_days)).await
+    }
+
+    pub async fn hospitals_with<F, Fut>(&self, city_id: &str, fetch: F) -> AppResult<Vec<Hospital>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AppResult<Vec<Hospital>>>,
+    {
+        get_or_fetch(&self.hospitals, self.ttl, city_id, fetch).await
+    }
+
+    pub async fn deps_with<F, Fut>(&self, unit_id: &str, fetch: F) -> AppResult<Vec<DepartmentCategory>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AppResult<Vec<DepartmentCategory>>>,
+    {
+        get_or_fetch(&self.deps, self.ttl, unit_id, fetch).await
+    }
+
+    pub async fn department_doctors_with<F, Fut>(&self, key: &str, fetch: F) -> AppResult<Vec<DepartmentDoctor>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AppResult<Vec<DepartmentDoctor>>>,
+    {
+        get_or_fetch(&self.doctors, self.doctors_ttl, key, fetch).await
+    }
+
+    /// Fresh department trees already cached for the given hospitals; never fetches
+    pub async fn cached_deps(&self, unit_ids: &[&str]) -> HashMap<String, Vec<DepartmentCategory>> {
+        let deps = self.deps.read().await;
+        unit_ids
+            .iter()
+            .filter_map(|id| {
+                let cached = deps.get(*id)?;
+                (cached.fetched_at.elapsed() < self.ttl).then(|| (id.to_string(), cached.value.clone()))
+            })
+            .collect()
+    }
+
+    /// Drop everything (e.g. after the site changed its catalog)
+    pub async fn clear(&self) {
+        self.hospitals.write().await.clear();
+        self.deps.write().await.clear();
+        self.doctors.write().await.clear();
+    }
+}
+
+async fn get_or_fetch<T, F, Fut>(map: &RwLock<HashMap<String, Cached<T>>>, ttl: Duration, key: &str, fetch: F) -> AppResult<T>
+where
+    T: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = AppResult<T>>,
+{
+    if let Some(cached) = map.read().await.get(key) {
+        if cached.fetched_at.elapsed() < ttl {
+            return Ok(cached.value.clone());
+        }
+    }
+    let value = fetch().await?;
+    map.write().await.insert(
+        key.to_string(),
+        Cached {
+            value: value.clone(),
+            fetched_at: Instant::now(),
+        },
+    );
+    Ok(value)
+}
+
+/// How a search result matched the keyword, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    /// Name starts with the keyword
+    Prefix,
+    /// Name contains the keyword
+    Contains,
+    /// Keyword matches the pinyin or its initials
+    Pinyin,
+}
+
+/// One hospital or department hit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogMatch {
+    pub unit_id: String,
+    pub unit_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dep_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dep_name: Option<String>,
+    pub match_kind: MatchKind,
+}
+
+/// Match `name` (and optional pinyin) against a lowercased keyword
+fn match_name(name: &str, pinyin: Option<&str>, keyword: &str) -> Option<MatchKind> {
+    let name = name.trim().to_lowercase();
+    if name.starts_with(keyword) {
+        return Some(MatchKind::Prefix);
+    }
+    if name.contains(keyword) {
+        return Some(MatchKind::Contains);
+    }
+    let pinyin = pinyin.map(|p| p.trim().to_lowercase()).filter(|p| !p.is_empty())?;
+    let initials: String = pinyin
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '_' || c == '\'')
+        .filter_map(|word| word.chars().next())
+        .collect();
+    let compact: String = pinyin.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    if initials.starts_with(keyword) || compact.starts_with(keyword) {
+        Some(MatchKind::Pinyin)
+    } else {
+        None
+    }
+}
+
+/// Rank hospitals and departments against a keyword
+/// Prefix matches beat substring matches, which beat pinyin matches; within
+/// the same kind a department hit ranks above a hospital hit.
+pub fn search_catalog(
+    hospitals: &[Hospital],
+    deps: &HashMap<String, Vec<DepartmentCategory>>,
+    keyword: &str,
+) -> Vec<CatalogMatch> {
+    let keyword = keyword.trim().to_lowercase();
+    if keyword.is_empty() {
+        return Vec::new();
+    }
+
+    let mut results = Vec::new();
+    for hospital in hospitals {
+        if let Some(kind) = match_name(&hospital.unit_name, hospital.pinyin.as_deref(), &keyword) {
+            results.push(CatalogMatch {
+                unit_id: hospital.unit_id.clone(),
+                unit_name: hospital.unit_name.clone(),
+                dep_id: None,
+                dep_name: None,
+                match_kind: kind,
+            });
+        }
+        if let Some(categories) = deps.get(&hospital.unit_id) {
+            for dep in Department::flatten(categories) {
+                if let Some(kind) = match_name(&dep.dep_name, None, &keyword) {
+                    results.push(CatalogMatch {
+                        unit_id: hospital.unit_id.clone(),
+                        unit_name: hospital.unit_name.clone(),
+                        dep_id: Some(dep.dep_id),
+                        dep_name: Some(dep.dep_name),
+                        match_kind: kind,
+                    });
+                }
+            }
+        }
+    }
+
+    // Stable sort keeps the site's hospital order among equal ranks
+    results.sort_by_key(|m| {
+        let name = m.dep_name.as_deref().unwrap_or(&m.unit_name);
+        (m.match_kind, m.dep_id.is_none(), name.chars().count())
+    });
+    results.truncate(MAX_SEARCH_RESULTS);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn hospital(id: &str, name: &str, pinyin: Option<&str>) -> Hospital {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": id,
+            "unit_name": name,
+            "pinyin": pinyin,
+        }))
+        .unwrap()
+    }
+
+    fn deps(json: serde_json::Value) -> Vec<DepartmentCategory> {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_search_ranks_prefix_and_department_hits_first() {
+        let hospitals = vec![
+            hospital("1", "深圳市人民医院", Some("shen zhen shi ren min yi yuan")),
+            hospital("2", "北京大学深圳医院", None),
+            hospital("3", "深圳儿童医院", None),
+        ];
+        let mut tree = HashMap::new();
+        tree.insert(
+            "2".to_string(),
+            deps(serde_json::json!([
+                {"pubcat": "内科", "childs": [{"dep_id": 200, "dep_name": "深圳专家门诊"}]}
+            ])),
+        );
+
+        let results = search_catalog(&hospitals, &tree, "深圳");
+        let summary: Vec<(&str, Option<&str>, MatchKind)> = results
+            .iter()
+            .map(|m| (m.unit_id.as_str(), m.dep_id.as_deref(), m.match_kind))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("2", Some("200"), MatchKind::Prefix),
+                ("3", None, MatchKind::Prefix),
+                ("1", None, MatchKind::Prefix),
+                ("2", None, MatchKind::Contains),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_matches_nested_departments_case_insensitive() {
+        let hospitals = vec![hospital("1", "深圳市人民医院", None)];
+        let mut tree = HashMap::new();
+        tree.insert(
+            "1".to_string(),
+            deps(serde_json::json!([
+                {"pubcat": "外科", "childs": [
+                    {"dep_id": "10", "dep_name": "骨科", "childs": [
+                        {"dep_id": "11", "dep_name": "ICU骨科病区"}
+                    ]}
+                ]}
+            ])),
+        );
+
+        let results = search_catalog(&hospitals, &tree, "icu");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].dep_id.as_deref(), Some("11"));
+        assert_eq!(results[0].match_kind, MatchKind::Prefix);
+        assert!(search_catalog(&hospitals, &tree, "  ").is_empty());
+    }
+
+    #[test]
+    fn test_search_matches_pinyin_initials() {
+        let hospitals = vec![
+            hospital("1", "深圳市人民医院", Some("shen zhen shi ren min yi yuan")),
+            hospital("2", "北京大学深圳医院", Some("beijingdaxueshenzhenyiyuan")),
+            hospital("3", "深圳儿童医院", None),
+        ];
+        let results = search_catalog(&hospitals, &HashMap::new(), "SZS");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].unit_id, "1");
+        assert_eq!(results[0].match_kind, MatchKind::Pinyin);
+
+        let results = search_catalog(&hospitals, &HashMap::new(), "beijing");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].unit_id, "2");
+    }
+
+    #[tokio::test]
+    async fn test_cache_fetches_once_until_stale() {
+        let cache = CatalogCache::new(Duration::from_millis(50));
+        let calls = AtomicUsize::new(0);
+        let fetch = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![hospital("1", "深圳市人民医院", None)])
+        };
+
+        assert_eq!(cache.hospitals_with("5", fetch).await.unwrap().len(), 1);
+        assert_eq!(cache.hospitals_with("5", fetch).await.unwrap().len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        cache.hospitals_with("5", fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_doctor_lists_expire_before_the_catalog() {
+        let cache = CatalogCache::new(Duration::from_millis(200));
+        assert_eq!(cache.doctors_ttl, Duration::from_millis(200));
+        assert_eq!(CatalogCache::default().doctors_ttl, DOCTORS_TTL);
+
+        let calls = AtomicUsize::new(0);
+        let fetch = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Vec::new())
+        };
+        cache.department_doctors_with("21/200/7", fetch).await.unwrap();
+        cache.department_doctors_with("21/200/7", fetch).await.unwrap();
+        cache.department_doctors_with("21/200/14", fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        cache.clear().await;
+        cache.department_doctors_with("21/200/7", fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cached_deps_never_fetches() {
+        let cache = CatalogCache::default();
+        cache.deps_with("1", || async { Ok(Vec::new()) }).await.unwrap();
+        let cached = cache.cached_deps(&["1", "2"]).await;
+        assert!(cached.contains_key("1"));
+        assert!(!cached.contains_key("2"));
+    }
+}
@@ -0,0 +1,148 @@
+//! City list loading for QuickDoctor
+//! The on-disk cities.json wins so users can edit it; a copy bundled into the
+//! binary covers first runs where the file does not exist yet
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use super::errors::{AppError, AppResult};
+use super::fsutil::write_atomic;
+use super::paths::cities_path;
+use super::types::City;
+
+/// Default city list shipped with the app
+const BUNDLED_CITIES: &str = include_str!("../../config/cities.json");
+
+/// Load the city list from config, seeding it from the bundled copy if missing
+pub fn load_cities() -> AppResult<Vec<City>> {
+    load_cities_from(&cities_path()?)
+}
+
+/// Load cities from `path`; a missing file is replaced by the bundled list
+pub fn load_cities_from(path: &Path) -> AppResult<Vec<City>> {
+    if path.exists() {
+        let data = fs::read_to_string(path)?;
+        return parse_cities(&data).map_err(|e| {
+            AppError::ConfigError(format!("{} is invalid: {}", path.display(), e))
+        });
+    }
+
+    let cities = bundled_cities()?;
+    if let Err(e) = write_atomic(path, BUNDLED_CITIES.as_bytes()) {
+        tracing::warn!(path = %path.display(), error = %e, "failed to write default cities.json");
+    }
+    Ok(cities)
+}
+
+/// The city list compiled into the binary
+pub fn bundled_cities() -> AppResult<Vec<City>> {
+    parse_cities(BUNDLED_CITIES)
+        .map_err(|e| AppError::ConfigError(format!("bundled cities.json is invalid: {}", e)))
+}
+
+/// Parse and validate a cities.json document, returning a readable reason on failure
+fn parse_cities(data: &str) -> Result<Vec<City>, String> {
+    let cities: Vec<City> = serde_json::from_str(data).map_err(|e| {
+        format!(
+            "expected a list of {{\"name\", \"cityId\"}} objects ({} at line {}, column {})",
+            describe_json_error(&e),
+            e.line(),
+            e.column()
+        )
+    })?;
+    validate_cities(&cities)?;
+    Ok(cities)
+}
+
+fn describe_json_error(e: &serde_json::Error) -> &'static str {
+    match e.classify() {
+        serde_json::error::Category::Eof => "file is truncated",
+        serde_json::error::Category::Syntax => "syntax error",
+        serde_json::error::Category::Data => "unexpected value",
+        serde_json::error::Category::Io => "read error",
+    }
+}
+
+fn validate_cities(cities: &[City]) -> Result<(), String> {
+    if cities.is_empty() {
+        return Err("city list is empty".into());
+    }
+
+    let mut seen = HashSet::new();
+    for (index, city) in cities.iter().enumerate() {
+        let id = city.city_id.trim();
+        if id.is_empty() {
+            return Err(format!("entry {} ({}) has an empty cityId", index, city.name));
+        }
+        if !seen.insert(id) {
+            return Err(format!("duplicate cityId {} ({})", id, city.name));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_cities_are_valid() {
+        let cities = bundled_cities().unwrap();
+        assert!(cities.iter().any(|c| c.city_id == "5" && c.name == "深圳"));
+    }
+
+    #[test]
+    fn test_missing_file_falls_back_and_writes_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cities.json");
+
+        let cities = load_cities_from(&path).unwrap();
+
+        assert_eq!(cities.len(), bundled_cities().unwrap().len());
+        assert_eq!(fs::read_to_string(&path).unwrap(), BUNDLED_CITIES);
+    }
+
+    #[test]
+    fn test_valid_file_takes_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cities.json");
+        fs::write(&path, r#"[{"name":"测试","cityId":99,"pinyin":"cs"}]"#).unwrap();
+
+        let cities = load_cities_from(&path).unwrap();
+
+        assert_eq!(cities.len(), 1);
+        assert_eq!(cities[0].city_id, "99");
+    }
+
+    #[test]
+    fn test_corrupt_file_is_a_config_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cities.json");
+
+        fs::write(&path, r#"[{"name":"深圳","cityId":"5"},"#).unwrap();
+        match load_cities_from(&path) {
+            Err(AppError::ConfigError(msg)) => {
+                assert!(msg.contains("cities.json is invalid"), "{}", msg);
+                assert!(msg.contains("truncated"), "{}", msg);
+            }
+            other => panic!("expected ConfigError, got {:?}", other),
+        }
+        // The user's file is left alone for them to fix
+        assert!(fs::read_to_string(&path).unwrap().ends_with(','));
+    }
+
+    #[test]
+    fn test_validate_cities() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cities.json");
+
+        fs::write(&path, "[]").unwrap();
+        let err = load_cities_from(&path).unwrap_err().to_string();
+        assert!(err.contains("empty"), "{}", err);
+
+        fs::write(&path, r#"[{"name":"A","cityId":"1"},{"name":"B","cityId":" 1 "}]"#).unwrap();
+        let err = load_cities_from(&path).unwrap_err().to_string();
+        assert!(err.contains("duplicate cityId 1 (B)"), "{}", err);
+    }
+}
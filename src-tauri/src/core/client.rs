@@ -1,67 +1,330 @@
 //! HTTP Client for QuickDoctor
 //! Corresponds to core/client.go - HTTP client with cookie management and API methods
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
-
-use reqwest::cookie::Jar;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, ORIGIN, REFERER, USER_AGENT};
-use reqwest::Client;
-use scraper::{Html, Selector};
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use reqwest::cookie::CookieStore;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, CONTENT_TYPE, ORIGIN, REFERER, RETRY_AFTER, USER_AGENT};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use scraper::{ElementRef, Html, Selector};
+use tokio::sync::{watch, RwLock, Semaphore};
+use tokio::task::JoinSet;
 use url::Url;
 
-use super::cookies::{has_access_hash, load_cookie_file, save_cookie_file, unique_strings};
-use super::errors::{AppError, AppResult};
-use super::types::{CookieRecord, DepartmentCategory, DoctorSchedule, Member, ScheduleSlot, SubmitOrderResult, TicketDetail, TimeSlot, AddressOption, Hospital};
+use super::api::TimeSample;
+use super::booking::parse_booking_rules;
+use super::cities::{bundled_cities, load_cities};
+use super::cookies::{
+    apply_cookie_attrs, has_access_hash, load_cookie_file, merge_cookie_header, save_cookie_file, save_cookie_file_to, session_expiry, unique_strings,
+    CookieAttrs, RecordingJar,
+};
+use super::errors::{body_snippet, AppError, AppResult, BODY_SNIPPET_BYTES};
+use super::metrics::Metrics;
+use super::health;
+use super::httpcache::HttpCache;
+use super::paths::cookies_path;
+use super::proxy::ProxyEntry;
+use super::ratelimit::{Lane, RateLimiter};
+use super::sessions::SessionRanker;
+use super::submittrace::{SubmitTrace, SubmitTraces};
+use super::textutil::truncate_utf8;
+use super::types::{AccountInfo, AddressRecord, Appointment, BookingRules, City, ClientProfile, CookieRecord, DayAvailability, DepartmentCategory, DepartmentDoctor, HealthReport, LoginStatus, DoctorSchedule, Member, NetworkSettings, NetworkStats, NewMemberParams, OrderConfirmation, ScheduleSlot, SessionInfo, SubmitOrderParams, SubmitOrderResult, TicketDetail, TicketPageKind, TimeSlot, AddressOption, Hospital};
+
+/// Endpoint names carried by `AppError::Api` and the request metrics
+const SCHEDULE_ENDPOINT: &str = "schedule";
+const SUBMIT_ENDPOINT: &str = "submit";
+/// Longest a request waits in-line for a host's Retry-After to expire
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(30);
+/// Backoff applied to a 429 that carries no usable Retry-After header
+const RATE_LIMIT_DEFAULT_BACKOFF: Duration = Duration::from_secs(5);
+/// Parallel schedule queries when fetching a range of dates
+const SCHEDULE_RANGE_CONCURRENCY: usize = 3;
+/// Longest availability calendar, in days
+pub const MAX_CALENDAR_DAYS: u8 = 31;
+
+/// Base URLs for the 91160 hosts used by HealthClient
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// Main site (ajax catalog, ticket detail, submit)
+    pub www: String,
+    /// Schedule gateway
+    pub gate: String,
+    /// User center (login check, members)
+    pub user: String,
+    /// City site template; `{city}` is replaced with the city pinyin
+    pub city: String,
+    /// WeChat QR login host, only probed by health checks
+    pub weixin: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            www: "https://www.91160.com".into(),
+            gate: "https://gate.91160.com".into(),
+            user: "https://user.91160.com".into(),
+            city: "https://{city}.91160.com".into(),
+            weixin: "https://open.weixin.qq.com".into(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Use one base URL for every host (mock servers, staging)
+    pub fn single(base: &str) -> Self {
+        let base = base.trim_end_matches('/').to_string();
+        Self {
+            www: base.clone(),
+            gate: base.clone(),
+            user: base.clone(),
+            city: base.clone(),
+            weixin: base,
+        }
+    }
 
-const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+    /// Base URL of a city site, falling back to www when no pinyin is given
+    pub fn city_base(&self, city_pinyin: &str) -> String {
+        if city_pinyin.is_empty() {
+            self.www.clone()
+        } else {
+            self.city.replace("{city}", city_pinyin)
+        }
+    }
+}
 
 /// Health client for 91160 API
 pub struct HealthClient {
     client: Client,
-    cookie_jar: Arc<Jar>,
+    endpoints: Endpoints,
+    profile: ClientProfile,
+    network: NetworkSettings,
+    cookie_jar: Arc<SessionJar>,
     cookies: RwLock<Vec<CookieRecord>>,
-    last_error: RwLock<String>,
-    last_status_code: RwLock<i32>,
+    /// Most recent failure, for callers that only look at the client;
+    /// each call's own error travels in its return value
+    last_error: std::sync::Mutex<Option<String>>,
+    rate_limits: std::sync::Mutex<RateLimitGate>,
+    /// Per-endpoint request counts and latencies
+    metrics: Metrics,
+    /// Token buckets every outbound request draws from
+    limiter: RateLimiter,
+    /// Which access_hash to try first on schedule queries
+    sessions: std::sync::Mutex<SessionRanker>,
+    /// Told whenever the client learns the session is alive or gone
+    login_status: Option<watch::Sender<bool>>,
+    /// city_id -> city site subdomain, filled lazily from cities.json
+    city_subdomains: RwLock<HashMap<String, String>>,
+    /// Disk cache for the hospital and department lists
+    http_cache: Option<HttpCache>,
+    /// Recent submits, kept only while capture is enabled
+    submit_traces: Arc<SubmitTraces>,
+}
+
+/// Cookie store that can be emptied on logout
+/// reqwest's Jar has no clear(), so the inner jar is swapped for a fresh one;
+/// clients built on this store keep working and simply see no cookies
+#[derive(Default)]
+struct SessionJar {
+    inner: std::sync::RwLock<Arc<RecordingJar>>,
+}
+
+impl SessionJar {
+    fn jar(&self) -> Arc<RecordingJar> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn attrs(&self) -> HashMap<String, CookieAttrs> {
+        self.jar().attrs()
+    }
+
+    fn add_cookie_str(&self, cookie: &str, url: &Url) {
+        self.jar().add_cookie_str(cookie, url);
+    }
+
+    fn reset(&self) {
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(RecordingJar::default());
+    }
+}
+
+impl CookieStore for SessionJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        self.jar().set_cookies(cookie_headers, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.jar().cookies(url)
+    }
+}
+
+/// Per-host "not before" instants recorded from 429 / Retry-After responses
+#[derive(Debug, Default)]
+struct RateLimitGate {
+    not_before: HashMap<String, Instant>,
+}
+
+impl RateLimitGate {
+    /// Hold requests to a host until the given instant (never shortens an existing hold)
+    fn block(&mut self, host: &str, until: Instant) {
+        let entry = self.not_before.entry(host.to_string()).or_insert(until);
+        if until > *entry {
+            *entry = until;
+        }
+    }
+
+    /// Time left before a host may be contacted again; expired holds are dropped
+    fn remaining(&mut self, host: &str, now: Instant) -> Option<Duration> {
+        match self.not_before.get(host) {
+            Some(until) if *until > now => Some(*until - now),
+            Some(_) => {
+                self.not_before.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
 }
 
 impl HealthClient {
     /// Create a new health client
     pub fn new() -> AppResult<Self> {
-        let cookie_jar = Arc::new(Jar::default());
+        Self::with_endpoints(Endpoints::default())
+    }
+
+    /// Create a health client with a custom request fingerprint
+    pub fn with_profile(profile: ClientProfile) -> AppResult<Self> {
+        Self::with_settings(Endpoints::default(), profile, NetworkSettings::default())
+    }
+
+    /// Create a health client against custom base URLs
+    pub fn with_endpoints(endpoints: Endpoints) -> AppResult<Self> {
+        Self::with_settings(endpoints, ClientProfile::default(), NetworkSettings::default())
+    }
+
+    /// Create a health client against custom base URLs with a custom
+    /// fingerprint and timeouts
+    pub fn with_settings(endpoints: Endpoints, profile: ClientProfile, network: NetworkSettings) -> AppResult<Self> {
+        let cookie_jar = Arc::new(SessionJar::default());
+        let network = network.clamped();
 
         let client = Client::builder()
-            .user_agent(DEFAULT_USER_AGENT)
+            .user_agent(&profile.user_agent)
             .cookie_provider(cookie_jar.clone())
-            .timeout(Duration::from_secs(30))
-            .connect_timeout(Duration::from_secs(10))
+            .timeout(network.request_timeout())
+            .connect_timeout(network.connect_timeout())
             .gzip(true)
             .brotli(true)
             .build()
             .map_err(|e| AppError::HttpError(e))?;
 
+        let gate_host = Url::parse(&endpoints.gate)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let limiter = RateLimiter::new(network.rate_limit, &gate_host);
+
         Ok(Self {
             client,
+            endpoints,
+            profile,
+            network,
             cookie_jar,
             cookies: RwLock::new(Vec::new()),
-            last_error: RwLock::new(String::new()),
-            last_status_code: RwLock::new(0),
+            last_error: std::sync::Mutex::new(None),
+            rate_limits: std::sync::Mutex::new(RateLimitGate::default()),
+            metrics: Metrics::default(),
+            limiter,
+            sessions: std::sync::Mutex::new(SessionRanker::default()),
+            login_status: None,
+            city_subdomains: RwLock::new(HashMap::new()),
+            http_cache: None,
+            submit_traces: Arc::new(SubmitTraces::default()),
         })
     }
 
+    /// Record submits into `traces`, which outlives client rebuilds
+    pub fn with_submit_traces(mut self, traces: Arc<SubmitTraces>) -> Self {
+        self.submit_traces = traces;
+        self
+    }
+
+    pub fn submit_traces(&self) -> &SubmitTraces {
+        &self.submit_traces
+    }
+
+    /// Keep hospital and department lists in `dir` for the configured
+    /// `http_cache_ttl_secs`, revalidating them with ETag/Last-Modified after
+    pub fn with_http_cache(mut self, dir: PathBuf) -> Self {
+        self.http_cache = Some(HttpCache::new(dir, self.network.http_cache_ttl()));
+        self
+    }
+
+    /// Delete the cached catalog responses; returns how many were removed
+    pub fn clear_http_cache(&self) -> AppResult<usize> {
+        self.http_cache.as_ref().map_or(Ok(0), HttpCache::clear)
+    }
+
+    /// Cached body still within the TTL
+    fn fresh_cached(&self, endpoint: &str, params: &[(&str, &str)]) -> Option<String> {
+        let body = self.http_cache.as_ref()?.fresh(endpoint, params, chrono::Utc::now().timestamp())?;
+        tracing::debug!(endpoint, "served from http cache");
+        Some(body)
+    }
+
+    /// If-None-Match/If-Modified-Since for a stale cached response, if any
+    fn cache_validators(&self, endpoint: &str, params: &[(&str, &str)]) -> HeaderMap {
+        self.http_cache
+            .as_ref()
+            .and_then(|cache| cache.get(endpoint, params))
+            .map(|entry| entry.validators())
+            .unwrap_or_default()
+    }
+
+    /// Cached body confirmed unchanged by a 304
+    fn revalidated(&self, endpoint: &str, params: &[(&str, &str)]) -> Option<String> {
+        let body = self.http_cache.as_ref()?.revalidated(endpoint, params, chrono::Utc::now().timestamp())?;
+        tracing::debug!(endpoint, "http cache revalidated");
+        Some(body)
+    }
+
+    fn store_cached(&self, endpoint: &str, params: &[(&str, &str)], body: &str, headers: &HeaderMap) {
+        if let Some(cache) = &self.http_cache {
+            cache.store(endpoint, params, body, headers, chrono::Utc::now().timestamp());
+        }
+    }
+
+    /// Publish login state changes into `tx`; receivers only wake when the
+    /// value actually flips
+    pub fn with_login_status(mut self, tx: watch::Sender<bool>) -> Self {
+        self.login_status = Some(tx);
+        self
+    }
+
+    fn publish_login(&self, logged_in: bool) {
+        if let Some(tx) = &self.login_status {
+            tx.send_if_modified(|current| std::mem::replace(current, logged_in) != logged_in);
+        }
+    }
+
+    /// Publish whether an access_hash is present after the cookies changed
+    async fn publish_cookie_state(&self) {
+        self.publish_login(self.has_access_hash().await);
+    }
+
     /// Load cookies from file and apply to client
     pub async fn load_cookies(&self) -> bool {
-        match load_cookie_file() {
+        let loaded = match load_cookie_file() {
             Ok(records) if !records.is_empty() => {
                 self.apply_cookies(&records).await;
-                let mut cookies = self.cookies.write().await;
-                *cookies = records;
+                *self.cookies.write().await = records;
                 true
             }
             _ => false,
-        }
+        };
+        self.publish_cookie_state().await;
+        loaded
     }
 
     /// Ensure cookies are loaded
@@ -107,235 +370,688 @@ impl HealthClient {
         }
     }
 
+    /// Apply cookie records in memory without touching the cookie file
+    pub async fn set_cookies(&self, records: Vec<CookieRecord>) {
+        self.apply_cookies(&records).await;
+        *self.cookies.write().await = records;
+        self.publish_cookie_state().await;
+    }
+
+    /// Forget the session: empty the cookie jar and the in-memory records
+    /// The cookie file is left alone; see `cookies::delete_cookie_file`
+    pub async fn reset_cookies(&self) {
+        self.cookie_jar.reset();
+        self.cookies.write().await.clear();
+        *self.session_ranker() = SessionRanker::default();
+        self.publish_login(false);
+    }
+
+    fn session_ranker(&self) -> std::sync::MutexGuard<'_, SessionRanker> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Known access_hash values (masked) in the order schedule queries try them
+    pub async fn sessions(&self) -> Vec<SessionInfo> {
+        let keys = self.get_access_hash_values().await;
+        self.session_ranker().sessions(&keys)
+    }
+
+    /// Try the access_hash with this masked id first; `None` or "" goes back to
+    /// the one that last worked
+    pub fn prefer_session(&self, id: Option<&str>) {
+        self.session_ranker().prefer(id);
+    }
+
+    /// Remove demoted access_hash cookies from memory, the jar and the cookie file
+    /// Returns how many keys were removed.
+    pub async fn prune_sessions(&self) -> AppResult<usize> {
+        let dead: Vec<String> = {
+            let keys = self.get_access_hash_values().await;
+            let ranker = self.session_ranker();
+            keys.into_iter().filter(|key| ranker.is_dead(key)).collect()
+        };
+        if dead.is_empty() {
+            return Ok(0);
+        }
+
+        let mut records = self.current_cookies().await;
+        records.retain(|c| !(c.name == "access_hash" && dead.contains(&c.value)));
+        save_cookie_file(&records)?;
+        self.cookie_jar.reset();
+        self.set_cookies(records).await;
+
+        let mut ranker = self.session_ranker();
+        for key in &dead {
+            ranker.forget(key);
+        }
+        Ok(dead.len())
+    }
+
+    /// Soonest expiry (unix seconds) of the session cookies, including
+    /// attributes the server sent since the cookies were last saved
+    pub async fn session_expires_at(&self) -> Option<i64> {
+        session_expiry(&self.current_cookies().await)
+    }
+
+    /// Saved records updated with values and attributes the server set in the jar
+    /// The flag is true when anything differs from the saved records.
+    async fn jar_records(&self) -> (Vec<CookieRecord>, bool) {
+        let mut records = self.cookies.read().await.clone();
+        let mut values_changed = false;
+        for base in [&self.endpoints.www, &self.endpoints.user] {
+            let Ok(url) = Url::parse(base) else {
+                continue;
+            };
+            let Some(header) = self.cookie_jar.cookies(&url) else {
+                continue;
+            };
+            if let Some(updated) = merge_cookie_header(&records, header.to_str().unwrap_or_default()) {
+                records = updated;
+                values_changed = true;
+            }
+        }
+
+        // Expiry changes count as an update even when the values did not change
+        let refreshed = apply_cookie_attrs(&mut records, &self.cookie_jar.attrs());
+        (records, values_changed || refreshed)
+    }
+
+    /// Current session cookies, including anything the server refreshed
+    pub async fn current_cookies(&self) -> Vec<CookieRecord> {
+        self.jar_records().await.0
+    }
+
+    /// Write cookies the server refreshed in the jar back to the cookie file
+    /// Returns true when the file was updated
+    pub async fn sync_cookies_from_jar(&self) -> AppResult<bool> {
+        match self.jar_records().await {
+            (records, true) if has_access_hash(&records) => {
+                save_cookie_file(&records)?;
+                *self.cookies.write().await = records;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     /// Save cookies from current jar to file
-    #[allow(dead_code)]
     pub async fn save_cookies_from_records(&self, records: Vec<CookieRecord>) -> AppResult<()> {
+        self.save_cookies_to(&cookies_path()?, records).await
+    }
+
+    /// Save cookies to `path`, then switch the client over to them; when the
+    /// write fails the client keeps its current cookies
+    pub async fn save_cookies_to(&self, path: &Path, records: Vec<CookieRecord>) -> AppResult<()> {
         if records.is_empty() {
             return Err(AppError::ConfigError("No cookies to save".into()));
         }
-        save_cookie_file(&records)?;
+        save_cookie_file_to(path, &records)?;
         self.apply_cookies(&records).await;
-        let mut cookies = self.cookies.write().await;
-        *cookies = records;
+        *self.cookies.write().await = records;
+        self.publish_cookie_state().await;
         Ok(())
     }
 
-    /// Set last error
-    async fn set_last_error(&self, message: &str) {
-        let mut error = self.last_error.write().await;
-        *error = message.to_string();
+    /// Remember a failure for `last_error` and hand it back
+    /// A LoginRequired failure also marks the session as gone.
+    fn record_error(&self, err: AppError) -> AppError {
+        if let AppError::LoginRequired(_) = err {
+            self.publish_login(false);
+        }
+        *self.last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(err.to_string());
+        err
     }
 
-    /// Set last status code
-    async fn set_last_status_code(&self, code: i32) {
-        let mut status = self.last_status_code.write().await;
-        *status = code;
+    /// Most recent failure of any call on this client, formatted
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap_or_else(|e| e.into_inner()).clone()
     }
 
-    /// Get last error
-    pub async fn last_error(&self) -> String {
-        self.last_error.read().await.clone()
+    /// Send a request through the per-host rate-limit gate.
+    /// Waits out a short Retry-After hold, fails with RateLimited on a long one,
+    /// and records new holds from 429 (or 503 + Retry-After) responses.
+    /// The request is timed and counted under `endpoint`.
+    async fn send(&self, endpoint: &'static str, request: RequestBuilder) -> AppResult<Response> {
+        self.send_in(endpoint, request, Lane::Normal).await
     }
 
-    /// Get last status code
-    #[allow(dead_code)]
-    pub async fn last_status_code(&self) -> i32 {
-        *self.last_status_code.read().await
+    /// `send` through the given rate limiter lane
+    async fn send_in(&self, endpoint: &'static str, request: RequestBuilder, lane: Lane) -> AppResult<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+
+        let remaining = self.rate_limits.lock().unwrap_or_else(|e| e.into_inner()).remaining(&host, Instant::now());
+        if let Some(wait) = remaining {
+            if wait > RATE_LIMIT_MAX_WAIT {
+                return Err(AppError::RateLimited {
+                    retry_after_ms: wait.as_millis() as u64,
+                });
+            }
+            tokio::time::sleep(wait).await;
+        }
+        self.limiter.acquire(&host, lane).await;
+
+        let started = Instant::now();
+        let resp = client.execute(request).await;
+        let ok = resp.as_ref().is_ok_and(|r| !r.status().is_client_error() && !r.status().is_server_error());
+        self.metrics.record(endpoint, started.elapsed(), ok);
+        let resp = resp?;
+
+        let status = resp.status();
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| parse_retry_after(v, chrono::Utc::now()));
+        let delay = match (status, retry_after) {
+            (StatusCode::TOO_MANY_REQUESTS, delay) => Some(delay.unwrap_or(RATE_LIMIT_DEFAULT_BACKOFF)),
+            (StatusCode::SERVICE_UNAVAILABLE, Some(delay)) => Some(delay),
+            _ => None,
+        };
+        if let Some(delay) = delay {
+            self.rate_limits.lock().unwrap_or_else(|e| e.into_inner()).block(&host, Instant::now() + delay);
+            return Err(AppError::RateLimited {
+                retry_after_ms: delay.as_millis() as u64,
+            });
+        }
+
+        Ok(resp)
     }
 
-    /// Build default headers
-    fn default_headers() -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"));
-        headers.insert(ACCEPT, HeaderValue::from_static("application/json, text/javascript, */*; q=0.01"));
-        headers.insert("Accept-Language", HeaderValue::from_static("zh-CN,zh;q=0.9,en;q=0.8"));
-        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
-        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
-        headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
-        headers.insert("sec-ch-ua", HeaderValue::from_static("\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"120\", \"Google Chrome\";v=\"120\""));
-        headers.insert("sec-ch-ua-mobile", HeaderValue::from_static("?0"));
-        headers.insert("sec-ch-ua-platform", HeaderValue::from_static("\"Windows\""));
-        headers
+    /// Header builder seeded from the client profile; every request starts here
+    pub fn headers(&self) -> HeaderBuilder<'_> {
+        HeaderBuilder::new(&self.profile)
+    }
+
+    /// Headers of a plain same-origin request
+    pub fn default_headers(&self) -> HeaderMap {
+        self.headers().build()
+    }
+
+    /// Request fingerprint this client was built with
+    pub fn profile(&self) -> &ClientProfile {
+        &self.profile
+    }
+
+    /// Timeouts this client was built with
+    pub fn network(&self) -> &NetworkSettings {
+        &self.network
+    }
+
+    /// Timeouts, rate limits, the per-host limiter counters and per-endpoint metrics
+    pub fn network_stats(&self) -> NetworkStats {
+        NetworkStats {
+            settings: self.network,
+            hosts: self.limiter.stats(),
+            endpoints: self.metrics.snapshot(),
+        }
     }
 
-    /// Check login status
+    /// Check login status; when the site cannot be asked (network failure,
+    /// rate limit) the last published state stands
     pub async fn check_login(&self) -> bool {
+        let status = self.probe_login(false).await;
+        match (status.reason.as_deref(), &self.login_status) {
+            (Some("network"), Some(tx)) => *tx.borrow(),
+            _ => status.logged_in,
+        }
+    }
+
+    /// Check login status with the account name, member count and failure reason
+    pub async fn get_login_status(&self) -> LoginStatus {
+        self.probe_login(true).await
+    }
+
+    /// Probe the user center; a network failure says nothing about the
+    /// session, so only a definite answer is published
+    async fn probe_login(&self, count_members: bool) -> LoginStatus {
+        let status = self.probe_login_page(count_members).await;
+        if status.reason.as_deref() != Some("network") {
+            self.publish_login(status.logged_in);
+        }
+        status
+    }
+
+    async fn probe_login_page(&self, count_members: bool) -> LoginStatus {
         if !self.has_access_hash().await {
-            return false;
+            return LoginStatus::failed("no_cookie");
         }
 
-        // Try to access user page
-        let mut headers = Self::default_headers();
-        headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
-        // For page requests, Accept should include html
-        headers.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"));
-        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("document"));
-        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("navigate"));
-        headers.insert("Sec-Fetch-Site", HeaderValue::from_static("none")); // Initial navigation
-        headers.insert("Sec-Fetch-User", HeaderValue::from_static("?1"));
-        headers.insert("Upgrade-Insecure-Requests", HeaderValue::from_static("1"));
+        // Try to access user page, as an initial navigation
+        let headers = self.headers().document_navigation().fetch_site("none").ajax().build();
 
         let result = self
-            .client
-            .get("https://user.91160.com/user/index.html")
-            .headers(headers)
-            .send()
+            .send("login", self.client.get(format!("{}/user/index.html", self.endpoints.user)).headers(headers))
             .await;
 
-        match result {
-            Ok(resp) if resp.status().is_success() => true,
-            _ => {
-                // Fallback: try to get members
-                self.get_members().await.map(|m| !m.is_empty()).unwrap_or(false)
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => return LoginStatus::from_error(&e),
+        };
+        if resp.url().as_str().to_lowercase().contains("login") {
+            return LoginStatus::failed("expired");
+        }
+
+        if resp.status().is_success() {
+            let body = read_html_body(resp).await.unwrap_or_default();
+            let mut status = LoginStatus::ok(parse_username(&body));
+            status.session_expires_at = self.session_expires_at().await;
+            if count_members {
+                status.member_count = self.get_members().await.ok().map(|m| m.len());
             }
+            return status;
+        }
+
+        // Fallback: try to get members
+        match self.get_members().await {
+            Ok(members) if !members.is_empty() => LoginStatus {
+                member_count: Some(members.len()),
+                session_expires_at: self.session_expires_at().await,
+                ..LoginStatus::ok(None)
+            },
+            Ok(_) => LoginStatus::failed("expired"),
+            Err(e) => LoginStatus::from_error(&e),
         }
     }
 
     /// Get hospitals by city
     pub async fn get_hospitals_by_city(&self, city_id: &str) -> AppResult<Vec<Hospital>> {
         let city = if city_id.is_empty() { "5" } else { city_id };
+        let params = [("c", city)];
+        if let Some(text) = self.fresh_cached("hospitals", &params) {
+            return Ok(serde_json::from_str(&text)?);
+        }
 
-        let mut headers = Self::default_headers();
-        headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded; charset=UTF-8"));
-        headers.insert(REFERER, HeaderValue::from_static("https://www.91160.com/"));
-        headers.insert(ORIGIN, HeaderValue::from_static("https://www.91160.com"));
+        let mut headers = self
+            .headers()
+            .ajax()
+            .form_post(&self.endpoints.www)
+            .referer(&format!("{}/", self.endpoints.www))
+            .build();
+        headers.extend(self.cache_validators("hospitals", &params));
 
         let resp = self
-            .client
-            .post("https://www.91160.com/ajax/getunitbycity.html")
-            .headers(headers)
-            .form(&[("c", city)])
-            .send()
+            .send(
+                "hospitals",
+                self.client
+                    .post(format!("{}/ajax/getunitbycity.html", self.endpoints.www))
+                    .headers(headers)
+                    .form(&params),
+            )
             .await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            if let Some(text) = self.revalidated("hospitals", &params) {
+                return Ok(serde_json::from_str(&text)?);
+            }
+        }
 
-        let text = resp.text().await?;
+        let resp_headers = resp.headers().clone();
+        let text = read_json_body(resp).await?;
         let data: Vec<Hospital> = serde_json::from_str(&text)?;
+        self.store_cached("hospitals", &params, &text, &resp_headers);
         Ok(data)
     }
 
+    /// Scrape a hospital's homepage for the metadata the list payload lacks
+    pub async fn get_hospital_detail(&self, unit_id: &str) -> AppResult<Hospital> {
+        let unit_id = unit_id.trim();
+        if unit_id.is_empty() {
+            return Err(AppError::ConfigError("unit_id is required".into()));
+        }
+        let headers = self.page_headers(&format!("{}/", self.endpoints.www));
+        let resp = self
+            .send(
+                "hospital_detail",
+                self.client
+                    .get(format!("{}/unit/show/uid-{}.html", self.endpoints.www, unit_id))
+                    .headers(headers),
+            )
+            .await?;
+        let body = resp.text().await?;
+        Ok(parse_hospital_detail(unit_id, &body))
+    }
+
+    /// Release time and advance days for a department, read from its booking
+    /// page (city site first, then www) or else the hospital homepage
+    pub async fn get_booking_rules(&self, unit_id: &str, dep_id: &str, city_pinyin: &str) -> AppResult<BookingRules> {
+        let (unit_id, dep_id, city_pinyin) = (unit_id.trim(), dep_id.trim(), city_pinyin.trim());
+        if unit_id.is_empty() || dep_id.is_empty() {
+            return Err(AppError::ConfigError("unit_id and dep_id are required".into()));
+        }
+
+        let dep_page = |base: &str| format!("{}/guahao/ystep1/uid-{}/depid-{}.html", base, unit_id, dep_id);
+        let mut pages = Vec::new();
+        if !city_pinyin.is_empty() {
+            pages.push(dep_page(&self.endpoints.city_base(city_pinyin)));
+        }
+        pages.push(dep_page(&self.endpoints.www));
+        pages.push(format!("{}/unit/show/uid-{}.html", self.endpoints.www, unit_id));
+
+        let mut last_err = None;
+        let mut loaded = false;
+        for url in pages {
+            match self.fetch_booking_rules(&url).await {
+                Ok(rules) if rules.release_time.is_some() || rules.advance_days.is_some() => return Ok(rules),
+                Ok(_) => {
+                    tracing::debug!(url = %url, "no booking rules on page");
+                    loaded = true;
+                }
+                Err(e @ (AppError::RateLimited { .. } | AppError::Blocked(_))) => return Err(e),
+                Err(e) => {
+                    tracing::warn!(url = %url, error = %e, "booking rules page failed");
+                    last_err = Some(e);
+                }
+            }
+        }
+        // A page that loaded without rules means the hospital publishes none
+        match last_err {
+            Some(e) if !loaded => Err(e),
+            _ => Ok(BookingRules::default()),
+        }
+    }
+
+    async fn fetch_booking_rules(&self, url: &str) -> AppResult<BookingRules> {
+        let headers = self.page_headers(&format!("{}/", self.endpoints.www));
+        let resp = self.send("booking_rules", self.client.get(url).headers(headers)).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(AppError::Api {
+                endpoint: "booking_rules".into(),
+                status: Some(status.as_u16()),
+                code: None,
+                message: "unexpected http status".into(),
+                body_snippet: None,
+            });
+        }
+        let body = read_html_body(resp).await?;
+        Ok(parse_booking_rules(&flatten_page_text(&Html::parse_document(&body))))
+    }
+
     /// Get departments by unit
     /// city_pinyin is used to construct the correct subdomain (e.g., "sz" -> "sz.91160.com")
     pub async fn get_deps_by_unit(&self, unit_id: &str, city_pinyin: &str) -> AppResult<Vec<DepartmentCategory>> {
         // Use city pinyin as subdomain, fallback to "www" if empty
-        let subdomain = if city_pinyin.is_empty() { "www" } else { city_pinyin };
-        let url = format!("https://{}.91160.com/ajax/getdepbyunit.html", subdomain);
-        
-        println!(">>> [get_deps_by_unit] Request URL: {}", url);
-        println!(">>> [get_deps_by_unit] Request body: keyValue={}", unit_id);
-        
-        let mut headers = Self::default_headers();
-        headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded; charset=UTF-8"));
-        
-        // Dynamic Referer and Origin based on subdomain
-        let referer = format!("https://{}.91160.com/", subdomain);
-        let origin = format!("https://{}.91160.com", subdomain);
-        headers.insert(REFERER, HeaderValue::from_str(&referer).unwrap_or(HeaderValue::from_static("https://www.91160.com/")));
-        headers.insert(ORIGIN, HeaderValue::from_str(&origin).unwrap_or(HeaderValue::from_static("https://www.91160.com")));
+        let city_pinyin = city_pinyin.trim();
+        let params = [("keyValue", unit_id), ("city", city_pinyin)];
+        if let Some(text) = self.fresh_cached("deps", &params) {
+            return Ok(serde_json::from_str(&text)?);
+        }
+        let validators = self.cache_validators("deps", &params);
+        let mut resp = self.post_deps(&self.endpoints.city_base(city_pinyin), unit_id, validators.clone()).await?;
 
-        let resp = self
-            .client
-            .post(&url)
-            .headers(headers)
-            .form(&[("keyValue", unit_id)])
-            .send()
-            .await?;
+        // Some cities have no subdomain site (or a stale pinyin); www serves every unit
+        if resp.status() == StatusCode::NOT_FOUND && !city_pinyin.is_empty() {
+            tracing::warn!(unit_id = %unit_id, city = %city_pinyin, "city subdomain returned 404, retrying on www");
+            resp = self.post_deps(&self.endpoints.www, unit_id, validators).await?;
+        }
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            if let Some(text) = self.revalidated("deps", &params) {
+                return Ok(serde_json::from_str(&text)?);
+            }
+        }
 
         let status = resp.status();
-        println!(">>> [get_deps_by_unit] Response status: {}", status);
-        
-        let text = resp.text().await?;
-        // Print first 500 chars of response for debugging
-        let preview = if text.len() > 500 { &text[..500] } else { &text };
-        println!(">>> [get_deps_by_unit] Response body (preview): {}", preview);
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            let err = AppError::Api {
+                endpoint: "deps".into(),
+                status: Some(status.as_u16()),
+                code: None,
+                message: "unexpected http status".into(),
+                body_snippet: body_snippet(&body),
+            };
+            return Err(self.record_error(err));
+        }
+        let resp_headers = resp.headers().clone();
+        let text = read_json_body(resp).await?;
+        tracing::debug!(unit_id = %unit_id, status = status.as_u16(), bytes = text.len(), "get_deps_by_unit response");
         
         // API returns: [{pubcat, yuyue_num, childs: [departments]}]
         // We return the raw category structure so frontend can handle hierarchy
         match serde_json::from_str::<Vec<DepartmentCategory>>(&text) {
             Ok(categories) => {
-                println!(">>> [get_deps_by_unit] Parsed {} categories successfully", categories.len());
+                tracing::debug!(unit_id = %unit_id, categories = categories.len(), "get_deps_by_unit parsed");
+                self.store_cached("deps", &params, &text, &resp_headers);
                 Ok(categories)
             }
             Err(e) => {
-                println!(">>> [get_deps_by_unit] JSON parse error: {}", e);
-                println!(">>> [get_deps_by_unit] Full response: {}", text);
-                Err(AppError::JsonError(e))
+                let preview = truncate_utf8(&text, 500);
+                tracing::warn!(unit_id = %unit_id, error = %e, body = %preview, "get_deps_by_unit parse failed");
+                let err = AppError::Api {
+                    endpoint: "deps".into(),
+                    status: Some(status.as_u16()),
+                    code: None,
+                    message: format!("decode failed: {}", e),
+                    body_snippet: body_snippet(&text),
+                };
+                Err(self.record_error(err))
+            }
+        }
+    }
+
+    async fn post_deps(&self, base: &str, unit_id: &str, validators: HeaderMap) -> AppResult<Response> {
+        let url = format!("{}/ajax/getdepbyunit.html", base);
+        tracing::debug!(unit_id = %unit_id, url = %url, "get_deps_by_unit request");
+
+        // Dynamic Referer and Origin based on subdomain
+        let mut headers = self.headers().ajax().form_post(base).referer(&format!("{}/", base)).build();
+        headers.extend(validators);
+
+        self.send("deps", self.client.post(&url).headers(headers).form(&[("keyValue", unit_id)]))
+            .await
+    }
+
+    /// City site subdomain (pinyin) for a city id, from the configured city list
+    /// Returns None for unknown cities or cities without a site, meaning www
+    pub async fn resolve_subdomain(&self, city_id: &str) -> Option<String> {
+        if self.city_subdomains.read().await.is_empty() {
+            match load_cities().or_else(|_| bundled_cities()) {
+                Ok(cities) => self.set_cities(&cities).await,
+                Err(e) => tracing::warn!(error = %e, "city list unavailable for subdomain lookup"),
             }
         }
+        self.city_subdomains.read().await.get(city_id.trim()).cloned()
+    }
+
+    /// Replace the city list used by `resolve_subdomain`
+    pub async fn set_cities(&self, cities: &[City]) {
+        let map = cities
+            .iter()
+            .filter(|c| !c.pinyin.trim().is_empty())
+            .map(|c| (c.city_id.trim().to_string(), c.pinyin.trim().to_string()))
+            .collect();
+        *self.city_subdomains.write().await = map;
+    }
+
+    /// Headers for a top-level page navigation (no XMLHttpRequest)
+    fn page_headers(&self, referer: &str) -> HeaderMap {
+        self.headers().document_navigation().referer(referer).build()
+    }
+
+    /// Phone, balance and pending counts from the user center homepage
+    pub async fn get_account_info(&self) -> AppResult<AccountInfo> {
+        let headers = self.page_headers(&format!("{}/", self.endpoints.user));
+        let resp = self
+            .send("account", self.client.get(format!("{}/user/index.html", self.endpoints.user)).headers(headers))
+            .await?;
+        if resp.url().as_str().to_lowercase().contains("login") {
+            return Err(self.record_error(AppError::LoginRequired("redirected to login".into())));
+        }
+        let body = resp.text().await?;
+        Ok(parse_account_info(&body))
     }
 
     /// Get members (patients)
     pub async fn get_members(&self) -> AppResult<Vec<Member>> {
-        let mut headers = Self::default_headers();
-        // Page request - no XMLHttpRequest
-        headers.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"));
-        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("document"));
-        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("navigate"));
-        headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
-        headers.insert("Sec-Fetch-User", HeaderValue::from_static("?1"));
-        headers.insert("Upgrade-Insecure-Requests", HeaderValue::from_static("1"));
-        headers.insert(REFERER, HeaderValue::from_static("https://user.91160.com/user/index.html"));
-
+        let headers = self.page_headers(&format!("{}/user/index.html", self.endpoints.user));
         let resp = self
-            .client
-            .get("https://user.91160.com/member.html")
-            .headers(headers)
-            .send()
+            .send("members", self.client.get(format!("{}/member.html", self.endpoints.user)).headers(headers))
             .await?;
 
         let url = resp.url().to_string();
-        let body = resp.text().await?;
+        let body = read_html_body(resp).await?;
 
         // Check if redirected to login
         if url.to_lowercase().contains("login") || body.contains("登录") {
             return Ok(Vec::new());
         }
 
-        // Parse HTML
-        let document = Html::parse_document(&body);
-        let row_selector = Selector::parse("tbody#mem_list tr").unwrap();
-        let td_selector = Selector::parse("td").unwrap();
-
-        let mut members = Vec::new();
+        parse_members(&body)
+    }
 
-        for row in document.select(&row_selector) {
-            let id = row
-                .value()
-                .attr("id")
-                .unwrap_or("")
-                .trim_start_matches("mem")
-                .to_string();
+    /// Orders in the user center order list
+    pub async fn get_appointments(&self) -> AppResult<Vec<Appointment>> {
+        let headers = self.page_headers(&format!("{}/user/index.html", self.endpoints.user));
+        let resp = self
+            .send("orders", self.client.get(format!("{}/order.html", self.endpoints.user)).headers(headers))
+            .await?;
+        if resp.url().as_str().to_lowercase().contains("login") {
+            return Err(self.record_error(AppError::LoginRequired("redirected to login".into())));
+        }
+        let body = read_html_body(resp).await?;
+        Ok(parse_appointments(&body))
+    }
 
-            let tds: Vec<_> = row.select(&td_selector).collect();
-            if tds.is_empty() {
-                continue;
-            }
+    /// Active appointment of the member in the department on `date`, if any
+    pub async fn has_existing_appointment(&self, member_id: &str, unit_id: &str, dep_id: &str, date: &str) -> AppResult<Option<Appointment>> {
+        let orders = self.get_appointments().await?;
+        Ok(orders.into_iter().find(|order| order.matches(member_id, unit_id, dep_id, date)))
+    }
 
-            let mut name = tds[0].text().collect::<String>().trim().to_string();
-            name = name.replace("默认", "");
+    /// Register a new member (patient) and return the refreshed member list
+    pub async fn add_member(&self, name: &str, id_card: &str, phone: &str, relation: &str) -> AppResult<Vec<Member>> {
+        let params = NewMemberParams {
+            name: name.trim().to_string(),
+            id_card: id_card.trim().to_uppercase(),
+            phone: phone.trim().to_string(),
+            relation: relation.trim().to_string(),
+        };
+        params.validate().map_err(AppError::ConfigError)?;
 
-            let certified = tds.iter().any(|td| td.text().collect::<String>().contains("认证"));
+        // The form page carries hidden tokens that must be posted back
+        let form_url = format!("{}/member/add.html", self.endpoints.user);
+        let headers = self.page_headers(&format!("{}/member.html", self.endpoints.user));
+        let resp = self.send("add_member", self.client.get(&form_url).headers(headers)).await?;
+        let page_url = resp.url().clone();
+        let body = resp.text().await?;
+        if page_url.as_str().to_lowercase().contains("login") {
+            return Err(self.record_error(AppError::LoginRequired("redirected to login".into())));
+        }
+        let form = parse_hidden_form(&body, &page_url, "cardno");
+        // A same-name member that was already there must not pass for the new one
+        let existing: HashSet<String> = self.get_members().await?.into_iter().map(|m| m.id).collect();
+
+        let mut fields = form.hidden;
+        fields.extend([
+            ("truename".to_string(), params.name.clone()),
+            ("cardno".to_string(), params.id_card.clone()),
+            ("mobile".to_string(), params.phone.clone()),
+            ("relation".to_string(), params.relation.clone()),
+        ]);
+
+        let headers = self.headers().form_post(&self.endpoints.user).referer(page_url.as_str()).build();
+        let resp = self
+            .send("add_member", self.client.post(form.action).headers(headers).form(&fields))
+            .await?;
+        let body = resp.text().await?;
+        let message = self.extract_submit_message(&body);
+
+        // The response wording varies, so confirm against the member list itself
+        let members = self.get_members().await?;
+        let suffix = &params.id_card[14..];
+        let added = members.iter().any(|m| {
+            !existing.contains(&m.id)
+                && m.name == params.name
+                && (m.id_card_masked.is_empty() || m.id_card_masked.to_uppercase().ends_with(suffix))
+        });
+        if added {
+            return Ok(members);
+        }
 
-            if id.is_empty() && name.is_empty() {
-                continue;
-            }
+        let message = if message.is_empty() { "add member failed".to_string() } else { message };
+        Err(self.record_error(AppError::api("add_member", message)))
+    }
 
-            members.push(Member { id, name, certified });
+    /// Fetch the address book page (list, region options and the add form)
+    async fn get_address_page(&self) -> AppResult<(Url, String)> {
+        let headers = self.page_headers(&format!("{}/user/index.html", self.endpoints.user));
+        let resp = self
+            .send("addresses", self.client.get(format!("{}/address.html", self.endpoints.user)).headers(headers))
+            .await?;
+        let url = resp.url().clone();
+        let body = resp.text().await?;
+        if url.as_str().to_lowercase().contains("login") {
+            return Err(self.record_error(AppError::LoginRequired("redirected to login".into())));
         }
+        Ok((url, body))
+    }
 
-        Ok(members)
+    /// Get saved addresses from the user-center address book
+    pub async fn get_addresses(&self) -> AppResult<Vec<AddressRecord>> {
+        let (_, body) = self.get_address_page().await?;
+        Ok(parse_addresses(&body))
     }
 
-    /// Get schedule for a department on a date
-    pub async fn get_schedule(
-        &self,
+    /// Add an address under `region_id` and return the refreshed address book
+    pub async fn add_address(&self, region_id: &str, detail_text: &str) -> AppResult<Vec<AddressRecord>> {
+        let region_id = region_id.trim();
+        let detail_text = detail_text.trim();
+        if region_id.is_empty() {
+            return Err(AppError::ConfigError("region_id is required".into()));
+        }
+        if detail_text.is_empty() {
+            return Err(AppError::ConfigError("address detail is required".into()));
+        }
+
+        // The region select lists the ids the site accepts
+        let (page_url, body) = self.get_address_page().await?;
+        let regions = parse_address_regions(&body);
+        if !regions.is_empty() && !regions.iter().any(|r| r.id == region_id) {
+            return Err(AppError::ConfigError(format!("unknown region_id {}", region_id)));
+        }
+        let form = parse_hidden_form(&body, &page_url, "region_id");
+
+        let mut fields = form.hidden;
+        fields.extend([
+            ("region_id".to_string(), region_id.to_string()),
+            ("address".to_string(), detail_text.to_string()),
+        ]);
+
+        let headers = self.headers().form_post(&self.endpoints.user).referer(page_url.as_str()).build();
+        let resp = self
+            .send("add_address", self.client.post(form.action).headers(headers).form(&fields))
+            .await?;
+        let body = resp.text().await?;
+        let message = self.extract_submit_message(&body);
+
+        let addresses = self.get_addresses().await?;
+        if addresses.iter().any(|a| a.region_id == region_id && a.detail == detail_text) {
+            return Ok(addresses);
+        }
+
+        let message = if message.is_empty() { "add address failed".to_string() } else { message };
+        Err(self.record_error(AppError::api("add_address", message)))
+    }
+
+    /// Get schedule for a department on a date
+    pub async fn get_schedule(
+        &self,
+        unit_id: &str,
+        dep_id: &str,
+        date: &str,
+    ) -> AppResult<Vec<DoctorSchedule>> {
+        self.get_schedule_within(unit_id, dep_id, date, None).await
+    }
+
+    /// Get schedule with a per-request timeout overriding the client-wide one
+    pub async fn get_schedule_within(
+        &self,
         unit_id: &str,
         dep_id: &str,
         date: &str,
+        timeout: Option<Duration>,
     ) -> AppResult<Vec<DoctorSchedule>> {
-        self.set_last_error("").await;
-        self.set_last_status_code(0).await;
 
         let date = if date.is_empty() {
             chrono::Local::now().format("%Y-%m-%d").to_string()
@@ -345,45 +1061,58 @@ impl HealthClient {
 
         let user_keys = self.get_access_hash_values().await;
         if user_keys.is_empty() {
-            self.set_last_error("missing access_hash").await;
-            return Err(AppError::LoginRequired("missing access_hash".into()));
+            return Err(self.record_error(AppError::LoginRequired("missing access_hash".into())));
         }
+        let user_keys = self.session_ranker().rank(&user_keys);
 
         let mut login_expired = false;
+        let mut failure: Option<AppError> = None;
 
         for key in &user_keys {
-            let url = format!(
-                "https://gate.91160.com/guahao/v1/pc/sch/dep?unit_id={}&dep_id={}&date={}&p=0&user_key={}",
-                unit_id, dep_id, date, key
-            );
-
-            let mut headers = Self::default_headers();
-            headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
-            headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
-            let referer = format!("https://www.91160.com/guahao/ystep1/uid-{}/depid-{}.html", unit_id, dep_id);
-            if let Ok(v) = HeaderValue::from_str(&referer) {
-                headers.insert(REFERER, v);
-            }
-
-            let resp = match self.client.get(&url).headers(headers).send().await {
+            let query = ScheduleQuery {
+                unit_id,
+                dep_id,
+                date: &date,
+                key,
+                timeout,
+            };
+            let resp = match self.send(SCHEDULE_ENDPOINT, self.schedule_request(&query, 0)).await {
                 Ok(r) => r,
+                Err(e @ AppError::RateLimited { .. }) => return Err(self.record_error(e)),
                 Err(e) => {
-                    self.set_last_error(&format!("schedule request failed: {}", e)).await;
+                    failure = Some(AppError::api(SCHEDULE_ENDPOINT, format!("request failed: {}", e)));
                     continue;
                 }
             };
 
-            self.set_last_status_code(resp.status().as_u16() as i32).await;
+            let status = resp.status();
+            let api_error = |message: String, code: Option<String>, body: &str| AppError::Api {
+                endpoint: SCHEDULE_ENDPOINT.into(),
+                status: Some(status.as_u16()),
+                code,
+                message,
+                body_snippet: body_snippet(body),
+            };
 
-            if !resp.status().is_success() {
-                self.set_last_error(&format!("schedule http {}", resp.status())).await;
+            if !status.is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                failure = Some(api_error("unexpected http status".into(), None, &body));
                 continue;
             }
 
-            let payload: serde_json::Value = match resp.json().await {
+            let text = match read_json_body(resp).await {
+                Ok(t) => t,
+                Err(e @ AppError::Blocked(_)) => return Err(self.record_error(e)),
+                Err(e) => {
+                    failure = Some(api_error(format!("read failed: {}", e), None, ""));
+                    continue;
+                }
+            };
+
+            let payload: serde_json::Value = match serde_json::from_str(&text) {
                 Ok(v) => v,
                 Err(e) => {
-                    self.set_last_error(&format!("schedule decode failed: {}", e)).await;
+                    failure = Some(api_error(format!("decode failed: {}", e), None, &text));
                     continue;
                 }
             };
@@ -391,103 +1120,37 @@ impl HealthClient {
             let result_code = payload.get("result_code").and_then(|v| v.as_str()).unwrap_or("");
 
             if result_code == "1" {
-                let data = payload.get("data");
-                let doc_list = data
-                    .and_then(|d| d.get("doc"))
-                    .and_then(|d| d.as_array())
-                    .cloned()
-                    .unwrap_or_default();
-                let sch_map = data
-                    .and_then(|d| d.get("sch"))
-                    .and_then(|s| s.as_object())
-                    .cloned()
-                    .unwrap_or_default();
-
-                let mut valid_docs = Vec::new();
-
-                for doc_value in &doc_list {
-                    let doctor_id = if let Some(s) = doc_value.get("doctor_id").and_then(|v| v.as_str()) {
-                        s.to_string()
-                    } else if let Some(n) = doc_value.get("doctor_id").and_then(|v| v.as_i64()) {
-                        n.to_string()
-                    } else {
-                        String::new()
-                    };
-
-                    if doctor_id.is_empty() {
-                        continue;
+                self.session_ranker().record_success(key, chrono::Local::now().timestamp());
+                self.publish_login(true);
+                let mut pages = SchedulePages::default();
+                let mut data = payload.get("data").cloned();
+                let mut next_page = 1;
+                loop {
+                    let (page_docs, added) = pages.merge(data.as_ref());
+                    // Nothing new means the list ended or the gateway ignored `p`
+                    if added == 0
+                        || next_page >= self.network.schedule_max_pages
+                        || !has_more_schedule_pages(data.as_ref(), next_page, page_docs)
+                    {
+                        break;
                     }
-
-                    let raw_schedule = sch_map.get(&doctor_id);
-                    if raw_schedule.is_none() {
-                        continue;
-                    }
-
-                    let mut schedules = Vec::new();
-
-                    if let Some(sch_data) = raw_schedule.and_then(|s| s.as_object()) {
-                        for time_type in ["am", "pm"] {
-                            if let Some(type_data) = sch_data.get(time_type) {
-                                let slots: Vec<&serde_json::Value> = if type_data.is_object() {
-                                    type_data.as_object().unwrap().values().collect()
-                                } else if type_data.is_array() {
-                                    type_data.as_array().unwrap().iter().collect()
-                                } else {
-                                    continue;
-                                };
-
-                                for slot in slots {
-                                    let schedule_id = if let Some(s) = slot.get("schedule_id").and_then(|v| v.as_str()) {
-                                        s.to_string()
-                                    } else if let Some(n) = slot.get("schedule_id").and_then(|v| v.as_i64()) {
-                                        n.to_string()
-                                    } else {
-                                        String::new()
-                                    };
-
-                                    if !schedule_id.is_empty() {
-                                        schedules.push(ScheduleSlot {
-                                            schedule_id,
-                                            time_type: slot.get("time_type").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                                            time_type_desc: slot.get("time_type_desc").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                                            left_num: slot.get("left_num").and_then(|v| v.as_i64()).unwrap_or(0) as i32,
-                                            sch_date: slot.get("sch_date").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    if schedules.is_empty() {
-                        continue;
+                    data = self.fetch_schedule_page(&query, next_page).await;
+                    if data.is_none() {
+                        break;
                     }
-
-                    let total_left: i32 = schedules.iter().map(|s| s.left_num).sum();
-
-                    valid_docs.push(DoctorSchedule {
-                        doctor_id,
-                        doctor_name: doc_value.get("doctor_name").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                        reg_fee: doc_value.get("reg_fee").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                        total_left_num: total_left,
-                        his_doc_id: doc_value.get("his_doc_id").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                        his_dep_id: doc_value.get("his_dep_id").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                        schedule_id: schedules.first().map(|s| s.schedule_id.clone()).unwrap_or_default(),
-                        time_type_desc: schedules.first().map(|s| s.time_type_desc.clone()).unwrap_or_default(),
-                        schedules,
-                    });
+                    next_page += 1;
                 }
 
+                let valid_docs = pages.doctors();
                 if !valid_docs.is_empty() {
-                    self.set_last_error("").await;
                     return Ok(valid_docs);
                 }
 
-                if !doc_list.is_empty() {
-                    self.set_last_error("").await;
+                if !pages.docs.is_empty() {
                     return Ok(Vec::new());
                 }
             } else if payload.get("error_code").and_then(|v| v.as_str()) == Some("10022") {
+                self.session_ranker().record_expired(key);
                 login_expired = true;
                 continue;
             } else {
@@ -502,229 +1165,328 @@ impl HealthClient {
                     .get("error_code")
                     .or_else(|| payload.get("result_code"))
                     .and_then(|v| v.as_str())
-                    .unwrap_or("");
-                self.set_last_error(&format!("schedule api error: code={} msg={}", error_code, error_msg)).await;
+                    .filter(|code| !code.is_empty())
+                    .map(str::to_string);
+                let message = if error_msg.is_empty() { "api error" } else { error_msg };
+                failure = Some(api_error(message.to_string(), error_code, &text));
             }
         }
 
         if login_expired {
-            self.set_last_error("login expired or insufficient permissions (error_code=10022)").await;
-            return Err(AppError::LoginRequired("error_code=10022".into()));
+            return Err(self.record_error(AppError::LoginRequired("error_code=10022".into())));
         }
 
-        let err = self.last_error().await;
-        if err.is_empty() {
-            self.set_last_error("schedule query failed").await;
+        let failure = failure.unwrap_or_else(|| AppError::api(SCHEDULE_ENDPOINT, "schedule query failed"));
+        Err(self.record_error(failure))
+    }
+
+    /// Gateway request for one page of a schedule query
+    fn schedule_request(&self, query: &ScheduleQuery<'_>, page: u32) -> RequestBuilder {
+        let url = format!(
+            "{}/guahao/v1/pc/sch/dep?unit_id={}&dep_id={}&date={}&p={}&user_key={}",
+            self.endpoints.gate, query.unit_id, query.dep_id, query.date, page, query.key
+        );
+
+        let referer = format!("{}/guahao/ystep1/uid-{}/depid-{}.html", self.endpoints.www, query.unit_id, query.dep_id);
+        let headers = self.headers().ajax().fetch_site("same-site").referer(&referer).build();
+
+        let request = self.client.get(&url).headers(headers);
+        match query.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
         }
-        Err(AppError::ApiError(self.last_error().await))
     }
 
-    /// Get ticket detail for a schedule
-    pub async fn get_ticket_detail(
-        &self,
+    /// `data` of a later schedule page; any failure just ends the paging,
+    /// keeping the doctors already read
+    async fn fetch_schedule_page(&self, query: &ScheduleQuery<'_>, page: u32) -> Option<serde_json::Value> {
+        let payload = async {
+            let resp = self.send(SCHEDULE_ENDPOINT, self.schedule_request(query, page)).await?;
+            if !resp.status().is_success() {
+                return Err(AppError::api(SCHEDULE_ENDPOINT, format!("unexpected http status {}", resp.status())));
+            }
+            let text = read_json_body(resp).await?;
+            Ok::<serde_json::Value, AppError>(serde_json::from_str(&text)?)
+        }
+        .await;
+
+        match payload {
+            Ok(payload) if payload.get("result_code").and_then(|v| v.as_str()) == Some("1") => payload.get("data").cloned(),
+            Ok(_) => {
+                tracing::debug!(page, "schedule page rejected, stopping");
+                None
+            }
+            Err(e) => {
+                tracing::debug!(page, error = %e, "schedule page failed, stopping");
+                None
+            }
+        }
+    }
+
+
+    /// Query schedules for several dates with bounded concurrency
+    /// Results come back in the order of `dates`.
+    pub async fn get_schedule_range(
+        self: &Arc<Self>,
         unit_id: &str,
         dep_id: &str,
-        schedule_id: &str,
-        _member_id: &str,
-    ) -> AppResult<TicketDetail> {
-        let url = format!(
-            "https://www.91160.com/guahao/ystep1/uid-{}/depid-{}/schid-{}.html",
-            unit_id, dep_id, schedule_id
-        );
+        dates: &[String],
+    ) -> Vec<(String, AppResult<Vec<DoctorSchedule>>)> {
+        let limit = Arc::new(Semaphore::new(SCHEDULE_RANGE_CONCURRENCY));
+        let mut queries = JoinSet::new();
+        for (index, date) in dates.iter().enumerate() {
+            let client = self.clone();
+            let limit = limit.clone();
+            let (unit_id, dep_id, date) = (unit_id.to_string(), dep_id.to_string(), date.clone());
+            queries.spawn(async move {
+                let _permit = limit.acquire_owned().await;
+                let result = client.get_schedule(&unit_id, &dep_id, &date).await;
+                (index, date, result)
+            });
+        }
 
-        let resp = self
-            .client
-            .get(&url)
-            .headers(Self::default_headers())
-            .send()
-            .await?;
+        let mut results = Vec::with_capacity(dates.len());
+        while let Some(joined) = queries.join_next().await {
+            if let Ok(entry) = joined {
+                results.push(entry);
+            }
+        }
+        results.sort_by_key(|(index, _, _)| *index);
+        results.into_iter().map(|(_, date, result)| (date, result)).collect()
+    }
 
-        let body = resp.text().await?;
-        let document = Html::parse_document(&body);
-
-        // Parse time slots
-        let li_selector = Selector::parse("#delts li").unwrap();
-        let time_slots: Vec<TimeSlot> = document
-            .select(&li_selector)
-            .filter_map(|el| {
-                let name = el.text().collect::<String>().trim().to_string();
-                let value = el.value().attr("val").unwrap_or("").to_string();
-                if value.is_empty() {
-                    None
-                } else {
-                    Some(TimeSlot { name, value })
-                }
-            })
+    /// Ticket availability for the next `days` days starting today
+    /// An expired login fails the whole call; other per-day failures leave
+    /// that day unknown.
+    pub async fn get_availability_calendar(self: &Arc<Self>, unit_id: &str, dep_id: &str, days: u8) -> AppResult<Vec<DayAvailability>> {
+        let today = chrono::Local::now().date_naive();
+        let dates: Vec<String> = (0..days.clamp(1, MAX_CALENDAR_DAYS))
+            .map(|offset| (today + chrono::Duration::days(i64::from(offset))).format("%Y-%m-%d").to_string())
             .collect();
 
-        // Helper to get input value
-        let get_input_value = |selectors: &[&str]| -> String {
-            for selector in selectors {
-                if let Ok(sel) = Selector::parse(selector) {
-                    if let Some(el) = document.select(&sel).next() {
-                        if let Some(val) = el.value().attr("value") {
-                            return val.trim().to_string();
-                        }
-                    }
+        let mut calendar = Vec::with_capacity(dates.len());
+        for (date, result) in self.get_schedule_range(unit_id, dep_id, &dates).await {
+            calendar.push(match result {
+                Ok(docs) => summarize_availability(&date, &docs),
+                Err(e @ AppError::LoginRequired(_)) => return Err(e),
+                Err(e) => DayAvailability {
+                    date,
+                    total_left: None,
+                    doctors_with_slots: None,
+                    error: Some(e.to_string()),
+                },
+            });
+        }
+        Ok(calendar)
+    }
+
+    /// Doctors scheduled in a department over the next `lookahead_days` days
+    /// starting today. Days that fail are skipped; an expired login, or every
+    /// day failing, fails the call.
+    pub async fn get_department_doctors(self: &Arc<Self>, unit_id: &str, dep_id: &str, lookahead_days: u8) -> AppResult<Vec<DepartmentDoctor>> {
+        let today = chrono::Local::now().date_naive();
+        let dates: Vec<String> = (0..lookahead_days.clamp(1, MAX_CALENDAR_DAYS))
+            .map(|offset| (today + chrono::Duration::days(i64::from(offset))).format("%Y-%m-%d").to_string())
+            .collect();
+
+        let mut days = Vec::with_capacity(dates.len());
+        let mut first_error = None;
+        for (date, result) in self.get_schedule_range(unit_id, dep_id, &dates).await {
+            match result {
+                Ok(docs) => days.push((date, docs)),
+                Err(e @ AppError::LoginRequired(_)) => return Err(e),
+                Err(e) => {
+                    tracing::warn!(unit_id = %unit_id, dep_id = %dep_id, date = %date, error = %e, "doctor lookup skipped a day");
+                    first_error.get_or_insert(e);
                 }
             }
-            String::new()
-        };
+        }
+        match first_error {
+            Some(e) if days.is_empty() => Err(e),
+            _ => Ok(aggregate_department_doctors(&days)),
+        }
+    }
 
-        // Parse addresses from select
-        let mut addresses = Vec::new();
-        let address_selectors = ["select[name='addressId']", "#addressId", "#useraddress_area"];
-        for selector in address_selectors {
-            if let Ok(sel) = Selector::parse(selector) {
-                if let Some(select_el) = document.select(&sel).next() {
-                    if let Ok(option_sel) = Selector::parse("option") {
-                        for option in select_el.select(&option_sel) {
-                            let id = option.value().attr("value").unwrap_or("").trim().to_string();
-                            let text = option.text().collect::<String>().trim().to_string();
-                            if !id.is_empty() && id != "0" && id != "-1" && !text.is_empty() {
-                                addresses.push(AddressOption { id, text });
-                            }
-                        }
-                    }
-                    break;
+    /// Get ticket detail for a schedule
+    /// With a city pinyin the city site is tried first; some cities only
+    /// render the booking step there, while www serves an empty shell.
+    pub async fn get_ticket_detail(
+        &self,
+        unit_id: &str,
+        dep_id: &str,
+        schedule_id: &str,
+        _member_id: &str,
+        city_pinyin: Option<&str>,
+    ) -> AppResult<TicketDetail> {
+        if let Some(city) = city_pinyin.map(str::trim).filter(|c| !c.is_empty()) {
+            let base = self.endpoints.city_base(city);
+            match self.fetch_ticket_detail(&base, unit_id, dep_id, schedule_id).await {
+                Ok((status, detail)) if status.is_success() && detail.has_required_fields() => return Ok(detail),
+                // Session and risk-control pages are the same on www
+                Ok((_, detail)) if matches!(detail.page_kind, TicketPageKind::LoginRedirect | TicketPageKind::Captcha) => return Ok(detail),
+                Ok((status, _)) => {
+                    tracing::warn!(unit_id = %unit_id, city = %city, status = status.as_u16(), "city ticket detail incomplete, retrying on www");
+                }
+                Err(e @ (AppError::RateLimited { .. } | AppError::Blocked(_))) => return Err(e),
+                Err(e) => {
+                    tracing::warn!(unit_id = %unit_id, city = %city, error = %e, "city ticket detail failed, retrying on www");
                 }
             }
         }
 
-        let mut address_id = get_input_value(&["input[name='addressId']", "#addressId"]);
-        let mut address = get_input_value(&["input[name='address']", "#address"]);
+        let (_, detail) = self.fetch_ticket_detail(&self.endpoints.www, unit_id, dep_id, schedule_id).await?;
+        Ok(detail)
+    }
 
-        // Fallback to first address
-        if (address_id.is_empty() || address.is_empty()) && !addresses.is_empty() {
-            if address_id.is_empty() {
-                address_id = addresses[0].id.clone();
-            }
-            if address.is_empty() {
-                address = addresses[0].text.clone();
-            }
+    /// Load and parse the booking step page from one host
+    async fn fetch_ticket_detail(
+        &self,
+        base: &str,
+        unit_id: &str,
+        dep_id: &str,
+        schedule_id: &str,
+    ) -> AppResult<(StatusCode, TicketDetail)> {
+        let url = format!("{}/guahao/ystep1/uid-{}/depid-{}/schid-{}.html", base, unit_id, dep_id, schedule_id);
+        let headers = self
+            .headers()
+            .referer(&format!("{}/guahao/ystep1/uid-{}/depid-{}.html", base, unit_id, dep_id))
+            .build();
+
+        let resp = self.send("ticket_detail", self.client.get(&url).headers(headers)).await?;
+        let status = resp.status();
+        let page_url = resp.url().to_string();
+        let redirected_to_login = page_url.to_lowercase().contains("login");
+        let body = read_html_body(resp).await?;
+        let mut detail = parse_ticket_detail(&body);
+        detail.page_url = page_url;
+        if redirected_to_login && !detail.has_required_fields() {
+            detail.page_kind = TicketPageKind::LoginRedirect;
         }
-
-        Ok(TicketDetail {
-            times: time_slots.clone(),
-            time_slots,
-            sch_data: get_input_value(&["input[name='sch_data']"]),
-            detlid_realtime: get_input_value(&["#detlid_realtime"]),
-            level_code: get_input_value(&["#level_code"]),
-            sch_date: get_input_value(&["input[name='sch_date']", "#sch_date"]),
-            order_no: get_input_value(&["input[name='order_no']", "#order_no"]),
-            disease_content: get_input_value(&["input[name='disease_content']", "#disease_content"]),
-            disease_input: get_input_value(&["textarea[name='disease_input']", "#disease_input"]),
-            is_hot: get_input_value(&["input[name='is_hot']", "#is_hot"]),
-            his_mem_id: get_input_value(&["input[name='hisMemId']", "#hismemid"]),
-            address_id,
-            address,
-            addresses,
-        })
+        Ok((status, detail))
     }
 
     /// Submit an order with optional proxy
-    pub async fn submit_order(&self, params: &HashMap<String, String>, proxy_url: Option<String>) -> AppResult<SubmitOrderResult> {
-        let mut data: HashMap<String, String> = HashMap::new();
-        
-        // Map parameters
-        data.insert("sch_data".into(), params.get("sch_data").cloned().unwrap_or_default());
-        data.insert("mid".into(), params.get("member_id").cloned().unwrap_or_default());
-        data.insert("addressId".into(), params.get("addressId").cloned().unwrap_or_default());
-        data.insert("address".into(), params.get("address").cloned().unwrap_or_default());
-        data.insert("hisMemId".into(), params.get("hisMemId").or(params.get("his_mem_id")).cloned().unwrap_or_default());
-        data.insert("disease_input".into(), params.get("disease_input").cloned().unwrap_or_default());
-        data.insert("order_no".into(), params.get("order_no").cloned().unwrap_or_default());
-        data.insert("disease_content".into(), params.get("disease_content").cloned().unwrap_or_default());
-        data.insert("accept".into(), "1".into());
-        data.insert("unit_id".into(), params.get("unit_id").cloned().unwrap_or_default());
-        data.insert("schedule_id".into(), params.get("schedule_id").cloned().unwrap_or_default());
-        data.insert("dep_id".into(), params.get("dep_id").cloned().unwrap_or_default());
-        data.insert("his_dep_id".into(), params.get("his_dep_id").cloned().unwrap_or_default());
-        data.insert("sch_date".into(), params.get("sch_date").cloned().unwrap_or_default());
-        data.insert("time_type".into(), params.get("time_type").cloned().unwrap_or_default());
-        data.insert("doctor_id".into(), params.get("doctor_id").cloned().unwrap_or_default());
-        data.insert("his_doc_id".into(), params.get("his_doc_id").cloned().unwrap_or_default());
-        data.insert("detlid".into(), params.get("detlid").cloned().unwrap_or_default());
-        data.insert("detlid_realtime".into(), params.get("detlid_realtime").cloned().unwrap_or_default());
-        data.insert("level_code".into(), params.get("level_code").cloned().unwrap_or_default());
-        data.insert("is_hot".into(), params.get("is_hot").cloned().unwrap_or_default());
-
-        let unit_id = data.get("unit_id").cloned().unwrap_or_default();
-        let dep_id = data.get("dep_id").cloned().unwrap_or_default();
-        let schedule_id = data.get("schedule_id").cloned().unwrap_or_default();
-
-        let mut headers = Self::default_headers();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
-        headers.insert(ORIGIN, HeaderValue::from_static("https://www.91160.com"));
-        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("document"));
-        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("navigate"));
-        headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
-        headers.insert("Sec-Fetch-User", HeaderValue::from_static("?1"));
-        headers.insert("Upgrade-Insecure-Requests", HeaderValue::from_static("1"));
-        
+    pub async fn submit_order(&self, params: &SubmitOrderParams, proxy_url: Option<String>) -> AppResult<SubmitOrderResult> {
+        params.validate().map_err(AppError::ConfigError)?;
+
+        let unit_id = &params.unit_id;
+        let dep_id = &params.dep_id;
+        let schedule_id = &params.schedule_id;
+
         let referer = format!(
-            "https://www.91160.com/guahao/ystep1/uid-{}/depid-{}/schid-{}.html",
-            unit_id, dep_id, schedule_id
+            "{}/guahao/ystep1/uid-{}/depid-{}/schid-{}.html",
+            self.endpoints.www, unit_id, dep_id, schedule_id
         );
-        if let Ok(v) = HeaderValue::from_str(&referer) {
-            headers.insert(REFERER, v);
-        }
+        // The booking form is a navigation, but it has always gone out with the XHR Accept
+        let headers = self
+            .headers()
+            .form_post(&self.endpoints.www)
+            .document_navigation()
+            .accept(ACCEPT_XHR)
+            .referer(&referer)
+            .build();
 
         let client = if let Some(url) = proxy_url {
-            let proxy = reqwest::Proxy::all(&url).map_err(|e| AppError::ProxyError(e.to_string()))?;
+            let entry = ProxyEntry::parse(&url, "http")?;
+            tracing::debug!(proxy = %entry, "submitting through proxy");
+            let proxy = entry.to_proxy()?;
             reqwest::Client::builder()
-                .user_agent(DEFAULT_USER_AGENT)
+                .user_agent(&self.profile.user_agent)
                 .cookie_provider(self.cookie_jar.clone())
                 .proxy(proxy)
-                .timeout(Duration::from_secs(30))
+                .timeout(self.network.request_timeout())
+                .connect_timeout(self.network.connect_timeout())
                 .build()?
         } else {
             self.client.clone()
         };
 
-        let resp = client
-            .post("https://www.91160.com/guahao/ysubmit.html")
-            .headers(headers)
-            .form(&data)
-            .send()
-            .await?;
+        let submit_url = format!("{}/guahao/ysubmit.html", self.endpoints.www);
+        let trace = self.submit_traces.is_enabled().then(|| SubmitTrace::request(&submit_url, &headers, params));
+        let result = self
+            .send_in(SUBMIT_ENDPOINT, client.post(&submit_url).headers(headers).form(params), Lane::Priority)
+            .await;
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                if let Some(trace) = trace {
+                    self.submit_traces.record(trace.failed(&e.to_string()));
+                }
+                return Err(e);
+            }
+        };
 
         let status = resp.status();
         let url = resp.url().to_string();
+        let resp_headers = resp.headers().clone();
+        let body = read_html_body(resp).await;
+        if let Some(trace) = trace {
+            let trace = match &body {
+                Ok(body) => trace.respond(status.as_u16(), &url, &resp_headers, body, params),
+                Err(e) => trace.respond(status.as_u16(), &url, &resp_headers, "", params).failed(&e.to_string()),
+            };
+            self.submit_traces.record(trace);
+        }
 
-        // Check for redirect to success
+        // Check for redirect to success; the client follows redirects, so the
+        // body is already the confirmation page
         if url.to_lowercase().contains("success") {
+            let body = body.unwrap_or_default();
             return Ok(SubmitOrderResult {
                 success: true,
                 status: true,
                 message: "OK".into(),
                 url: Some(url),
+                confirmation: parse_order_confirmation(&body),
             });
         }
 
-        let body = resp.text().await?;
+        let body = body?;
+
+        // A captcha injected into the booking step; the user solves it on the
+        // step page, which shows the same challenge in a browser
+        if let Some(marker) = captcha_marker(&body) {
+            tracing::warn!(marker = %marker, "captcha in submit response");
+            return Err(self.record_error(AppError::CaptchaRequired(referer)));
+        }
 
         // Extract error message from response
+        let snippet = body_snippet(&body);
         let msg = self.extract_submit_message(&body);
         if !msg.is_empty() {
-            self.set_last_error(&msg).await;
+            let err = AppError::Api {
+                endpoint: SUBMIT_ENDPOINT.into(),
+                status: Some(status.as_u16()),
+                code: None,
+                message: msg.clone(),
+                body_snippet: snippet,
+            };
+            self.record_error(err);
             return Ok(SubmitOrderResult {
                 success: false,
                 status: false,
                 message: format!("submit failed: {}", msg),
                 url: None,
+                confirmation: None,
             });
         }
 
-        let snippet = if body.len() > 200 { &body[..200] } else { &body };
-        let msg = format!("submit failed code={}, resp={}", status, snippet);
-        self.set_last_error(&msg).await;
+        let msg = format!("submit failed code={}, resp={}", status, snippet.as_deref().unwrap_or_default());
+        let err = AppError::Api {
+            endpoint: SUBMIT_ENDPOINT.into(),
+            status: Some(status.as_u16()),
+            code: None,
+            message: "no result in response".into(),
+            body_snippet: snippet,
+        };
+        self.record_error(err);
 
         Ok(SubmitOrderResult {
             success: false,
             status: false,
             message: msg,
             url: None,
+            confirmation: None,
         })
     }
 
@@ -755,12 +1517,80 @@ impl HealthClient {
         String::new()
     }
 
+    /// Wait for a rate limiter token for a request that bypasses `send`
+    async fn throttle(&self, base: &str) {
+        if let Some(host) = Url::parse(base).ok().as_ref().and_then(Url::host_str) {
+            self.limiter.acquire(host, Lane::Normal).await;
+        }
+    }
+
+    /// Issue lightweight requests to the hosts used during a grab so their
+    /// connections sit warm in the pool
+    pub async fn warm_up_connections(&self) -> AppResult<()> {
+        self.throttle(&self.endpoints.www).await;
+        self.throttle(&self.endpoints.gate).await;
+        let (www, gate) = tokio::join!(
+            self.client.head(format!("{}/favicon.ico", self.endpoints.www)).headers(self.headers().build()).send(),
+            self.client.head(format!("{}/favicon.ico", self.endpoints.gate)).headers(self.headers().build()).send(),
+        );
+        www?;
+        gate?;
+        Ok(())
+    }
+
+    /// Probe the www, gate, user and WeChat hosts concurrently and judge the
+    /// network from the results
+    pub async fn health_check(&self) -> HealthReport {
+        let probe = |base: &str| {
+            let request = self.client.head(format!("{}/favicon.ico", base)).headers(self.headers().build());
+            let base = base.to_string();
+            async move {
+                self.throttle(&base).await;
+                health::probe_host(&base, request).await
+            }
+        };
+        let (www, gate, user, weixin) = tokio::join!(
+            probe(&self.endpoints.www),
+            probe(&self.endpoints.gate),
+            probe(&self.endpoints.user),
+            probe(&self.endpoints.weixin)
+        );
+        let results = [www, gate, user, weixin];
+        let clock_skew = results.iter().find_map(|(_, skew)| *skew);
+        health::build_report(results.into_iter().map(|(host, _)| host).collect(), clock_skew)
+    }
+
+    /// Take one server clock sample: (local_send, local_recv, server_time)
+    /// Fails when the response carries no parseable Date header
+    pub async fn sample_server_time(&self) -> AppResult<TimeSample> {
+        self.throttle(&self.endpoints.www).await;
+        let send = chrono::Local::now();
+        let resp = self
+            .client
+            .head(format!("{}/favicon.ico", self.endpoints.www))
+            .headers(self.headers().build())
+            .send()
+            .await?;
+        let recv = chrono::Local::now();
+
+        let server = resp
+            .headers()
+            .get("date")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|s| chrono::DateTime::parse_from_rfc2822(s).ok())
+            .map(|t| t.with_timezone(&chrono::Local))
+            .ok_or_else(|| AppError::ParseError("missing Date header".into()))?;
+
+        Ok((send, recv, server))
+    }
+
     /// Get server datetime
     pub async fn get_server_datetime(&self) -> AppResult<chrono::DateTime<chrono::Local>> {
+        self.throttle(&self.endpoints.www).await;
         let resp = self
             .client
-            .get("https://www.91160.com/favicon.ico")
-            .headers(Self::default_headers())
+            .get(format!("{}/favicon.ico", self.endpoints.www))
+            .headers(self.headers().build())
             .send()
             .await?;
 
@@ -781,3 +1611,1582 @@ impl Default for HealthClient {
         Self::new().expect("Failed to create HealthClient")
     }
 }
+
+/// Insert a header built from a runtime string, skipping invalid values
+fn insert_header(headers: &mut HeaderMap, name: reqwest::header::HeaderName, value: &str) {
+    if let Ok(v) = HeaderValue::from_str(value) {
+        headers.insert(name, v);
+    }
+}
+
+/// Accept sent by jQuery ajax calls
+const ACCEPT_XHR: &str = "application/json, text/javascript, */*; q=0.01";
+/// Accept sent by Chrome for page navigations
+const ACCEPT_DOCUMENT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+
+/// Header set for one request, starting from a same-origin fetch with the
+/// profile's fingerprint; each method switches on one coherent group of
+/// headers, so call order does not matter
+#[derive(Debug, Clone)]
+pub struct HeaderBuilder<'a> {
+    profile: &'a ClientProfile,
+    accept: Option<&'static str>,
+    fetch_site: &'static str,
+    navigation: bool,
+    ajax: bool,
+    origin: Option<String>,
+    referer: Option<String>,
+}
+
+impl<'a> HeaderBuilder<'a> {
+    pub fn new(profile: &'a ClientProfile) -> Self {
+        Self {
+            profile,
+            accept: None,
+            fetch_site: "same-origin",
+            navigation: false,
+            ajax: false,
+            origin: None,
+            referer: None,
+        }
+    }
+
+    /// jQuery-style XMLHttpRequest
+    pub fn ajax(mut self) -> Self {
+        self.ajax = true;
+        self
+    }
+
+    /// Top-level page load: HTML Accept and navigate fetch metadata
+    pub fn document_navigation(mut self) -> Self {
+        self.navigation = true;
+        self
+    }
+
+    /// Urlencoded form body posted from `origin`; ajax posts carry the charset
+    pub fn form_post(mut self, origin: &str) -> Self {
+        self.origin = Some(origin.to_string());
+        self
+    }
+
+    pub fn referer(mut self, url: &str) -> Self {
+        self.referer = Some(url.to_string());
+        self
+    }
+
+    /// Sec-Fetch-Site: "same-origin" (default), "same-site" or "none"
+    pub fn fetch_site(mut self, site: &'static str) -> Self {
+        self.fetch_site = site;
+        self
+    }
+
+    /// Replace the Accept implied by the request kind
+    pub fn accept(mut self, accept: &'static str) -> Self {
+        self.accept = Some(accept);
+        self
+    }
+
+    pub fn build(self) -> HeaderMap {
+        let profile = self.profile;
+        let (dest, mode, accept) = if self.navigation {
+            ("document", "navigate", ACCEPT_DOCUMENT)
+        } else {
+            ("empty", "cors", ACCEPT_XHR)
+        };
+        let mut headers = HeaderMap::new();
+        insert_header(&mut headers, USER_AGENT, &profile.user_agent);
+        headers.insert(ACCEPT, HeaderValue::from_static(self.accept.unwrap_or(accept)));
+        insert_header(&mut headers, ACCEPT_LANGUAGE, &profile.accept_language);
+        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static(dest));
+        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static(mode));
+        headers.insert("Sec-Fetch-Site", HeaderValue::from_static(self.fetch_site));
+        insert_header(&mut headers, HeaderName::from_static("sec-ch-ua"), &profile.sec_ch_ua);
+        headers.insert("sec-ch-ua-mobile", HeaderValue::from_static("?0"));
+        insert_header(&mut headers, HeaderName::from_static("sec-ch-ua-platform"), &profile.sec_ch_ua_platform);
+        if self.ajax {
+            headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+        }
+        if let Some(origin) = &self.origin {
+            let content_type = if self.ajax {
+                "application/x-www-form-urlencoded; charset=UTF-8"
+            } else {
+                "application/x-www-form-urlencoded"
+            };
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            insert_header(&mut headers, ORIGIN, origin);
+        }
+        if self.navigation {
+            headers.insert("Sec-Fetch-User", HeaderValue::from_static("?1"));
+            headers.insert("Upgrade-Insecure-Requests", HeaderValue::from_static("1"));
+        }
+        if let Some(referer) = &self.referer {
+            insert_header(&mut headers, REFERER, referer);
+        }
+        headers
+    }
+}
+
+/// Parse a Retry-After header: delta seconds or an HTTP-date.
+/// Dates in the past yield a zero delay.
+pub fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Markers of WAF / rate-limit interstitials served in place of JSON
+const CHALLENGE_MARKERS: [&str; 10] = [
+    "访问过于频繁",
+    "访问频率过快",
+    "安全验证",
+    "人机验证",
+    "滑动验证",
+    "cf-challenge",
+    "challenge-form",
+    "nc_1_wrapper",
+    "waf_verify",
+    "__jsl_clearance",
+];
+
+/// Read the body of a JSON endpoint, turning challenge pages into AppError::Blocked
+async fn read_json_body(resp: reqwest::Response) -> AppResult<String> {
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let text = resp.text().await?;
+    if let Some(reason) = detect_challenge_page(&content_type, &text) {
+        return Err(AppError::Blocked(reason));
+    }
+    Ok(text)
+}
+
+/// Read an HTML page, decoding it as GBK when the Content-Type charset or a
+/// `<meta>` tag names a GBK-family encoding; everything else is read as UTF-8
+async fn read_html_body(resp: reqwest::Response) -> AppResult<String> {
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let bytes = resp.bytes().await?;
+    Ok(decode_html(&bytes, &content_type))
+}
+
+/// Decode page bytes using the charset from the Content-Type header, falling
+/// back to a `<meta charset>` / `<meta http-equiv>` tag near the top of the page
+fn decode_html(bytes: &[u8], content_type: &str) -> String {
+    let header_charset = content_type
+        .split(';')
+        .filter_map(|part| part.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string());
+    let charset = header_charset.or_else(|| meta_charset(bytes));
+
+    let encoding = charset
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+        .filter(|encoding| *encoding == encoding_rs::GBK || *encoding == encoding_rs::GB18030)
+        .unwrap_or(encoding_rs::UTF_8);
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+/// Charset declared by a `<meta>` tag in the first KB of a page
+fn meta_charset(bytes: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    regex::Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?([a-z0-9_-]+)"#)
+        .ok()?
+        .captures(&head)
+        .map(|caps| caps[1].to_string())
+}
+
+/// Detect a WAF / challenge page returned where JSON was expected.
+/// Returns a short description of what matched, or None for JSON-looking bodies.
+fn detect_challenge_page(content_type: &str, body: &str) -> Option<String> {
+    let trimmed = body.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        return None;
+    }
+
+    if let Some(marker) = CHALLENGE_MARKERS.iter().find(|m| body.contains(*m)) {
+        return Some(format!("challenge marker \"{}\"", marker));
+    }
+
+    // An HTML page on a JSON endpoint is an interstitial even without a known marker
+    if content_type.to_lowercase().contains("html") || trimmed.starts_with('<') {
+        let title = regex::Regex::new(r"(?is)<title[^>]*>(.*?)</title>")
+            .ok()
+            .and_then(|re| re.captures(body))
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str().trim().to_string())
+            .unwrap_or_default();
+        return Some(if title.is_empty() {
+            "unexpected html response".to_string()
+        } else {
+            format!("unexpected html response: {}", title)
+        });
+    }
+
+    None
+}
+
+/// Check whether a URL points at a 91160 host over http(s)
+pub fn is_91160_url(raw: &str) -> bool {
+    let Ok(url) = Url::parse(raw.trim()) else {
+        return false;
+    };
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return false;
+    }
+    match url.host_str() {
+        Some(host) => {
+            let host = host.to_lowercase();
+            host == "91160.com" || host.ends_with(".91160.com")
+        }
+        None => false,
+    }
+}
+
+/// Parse order confirmation details from the success page
+/// Returns None when no known field can be found
+/// Member table columns located by header text, so reordered columns still parse
+#[derive(Debug, Default)]
+struct MemberColumns {
+    name: Option<usize>,
+    id_card: Option<usize>,
+    phone: Option<usize>,
+    relation: Option<usize>,
+    certified: Option<usize>,
+}
+
+impl MemberColumns {
+    fn from_headers(headers: &[String]) -> Self {
+        let find = |keys: &[&str]| headers.iter().position(|h| keys.iter().any(|k| h.contains(k)));
+        Self {
+            name: find(&["姓名"]),
+            id_card: find(&["证件", "身份证"]),
+            phone: find(&["手机", "电话"]),
+            relation: find(&["关系"]),
+            certified: find(&["认证", "状态"]),
+        }
+    }
+}
+
+/// Status flag keys seen on gate schedule doctors and slots
+const SCHEDULE_STATUS_KEYS: [&str; 5] = ["doc_status", "y_state", "sch_status", "state_desc", "status"];
+
+/// Doctor or schedule id, which the gateway sends as either a string or a number
+fn json_id(value: &serde_json::Value, key: &str) -> String {
+    match value.get(key) {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(v) => v.as_i64().map(|n| n.to_string()).unwrap_or_default(),
+        None => String::new(),
+    }
+}
+
+/// Non-negative count the gateway may send as a number or a numeric string
+fn json_count(value: &serde_json::Value, key: &str) -> Option<u64> {
+    match value.get(key)? {
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        v => v.as_u64(),
+    }
+}
+
+/// Doctors per schedule page when the gateway does not say; a page this full
+/// is taken to mean there may be another one
+const DEFAULT_SCHEDULE_PAGE_SIZE: usize = 10;
+
+/// Whether page `next_page` (zero-based) should exist, judged from the page
+/// just read: its total/page count fields when present, otherwise a full page
+fn has_more_schedule_pages(data: Option<&serde_json::Value>, next_page: u32, page_docs: usize) -> bool {
+    let Some(data) = data else {
+        return false;
+    };
+    let next_page = u64::from(next_page);
+    if let Some(pages) = ["total_page", "page_count", "pages"].iter().find_map(|key| json_count(data, key)) {
+        return next_page < pages;
+    }
+    let page_size = json_count(data, "page_size").filter(|size| *size > 0);
+    if let (Some(total), Some(page_size)) = (json_count(data, "total"), page_size) {
+        return next_page * page_size < total;
+    }
+    let page_size = page_size.map_or(DEFAULT_SCHEDULE_PAGE_SIZE, |size| size as usize);
+    page_docs >= page_size
+}
+
+/// One schedule query, repeated for each page
+struct ScheduleQuery<'a> {
+    unit_id: &'a str,
+    dep_id: &'a str,
+    date: &'a str,
+    key: &'a str,
+    timeout: Option<Duration>,
+}
+
+/// Doctor and schedule data merged across gateway pages
+/// A doctor repeated on a later page keeps its first-page entry.
+#[derive(Default)]
+struct SchedulePages {
+    docs: Vec<serde_json::Value>,
+    sch: serde_json::Map<String, serde_json::Value>,
+    seen: std::collections::HashSet<String>,
+}
+
+impl SchedulePages {
+    /// Add one page's `data`; returns the doctors on the page and how many were new
+    fn merge(&mut self, data: Option<&serde_json::Value>) -> (usize, usize) {
+        let Some(data) = data else {
+            return (0, 0);
+        };
+        let page_docs = data.get("doc").and_then(|d| d.as_array()).map(Vec::as_slice).unwrap_or_default();
+        let mut added = 0;
+        for doc in page_docs {
+            let doctor_id = json_id(doc, "doctor_id");
+            if doctor_id.is_empty() || self.seen.insert(doctor_id) {
+                self.docs.push(doc.clone());
+                added += 1;
+            }
+        }
+        if let Some(sch) = data.get("sch").and_then(|s| s.as_object()) {
+            for (doctor_id, schedule) in sch {
+                self.sch.entry(doctor_id.clone()).or_insert_with(|| schedule.clone());
+            }
+        }
+        (page_docs.len(), added)
+    }
+
+    fn doctors(&self) -> Vec<DoctorSchedule> {
+        parse_schedule_doctors(&self.docs, &self.sch)
+    }
+}
+
+/// Doctors that have at least one schedule slot in `sch`
+fn parse_schedule_doctors(
+    docs: &[serde_json::Value],
+    sch: &serde_json::Map<String, serde_json::Value>,
+) -> Vec<DoctorSchedule> {
+    let mut valid_docs = Vec::new();
+
+    for doc_value in docs {
+
+        let doctor_id = json_id(doc_value, "doctor_id");
+
+        if doctor_id.is_empty() {
+            continue;
+        }
+
+        let raw_schedule = sch.get(&doctor_id);
+        if raw_schedule.is_none() {
+            continue;
+        }
+
+        let mut schedules = Vec::new();
+
+        if let Some(sch_data) = raw_schedule.and_then(|s| s.as_object()) {
+            for time_type in ["am", "pm"] {
+                if let Some(type_data) = sch_data.get(time_type) {
+                    let slots: Vec<&serde_json::Value> = if type_data.is_object() {
+                        type_data.as_object().unwrap().values().collect()
+                    } else if type_data.is_array() {
+                        type_data.as_array().unwrap().iter().collect()
+                    } else {
+                        continue;
+                    };
+
+                    for slot in slots {
+                        let schedule_id = json_id(slot, "schedule_id");
+
+                        if !schedule_id.is_empty() {
+                            schedules.push(ScheduleSlot {
+                                schedule_id,
+                                time_type: slot.get("time_type").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                                time_type_desc: slot.get("time_type_desc").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                                left_num: slot.get("left_num").and_then(|v| v.as_i64()).unwrap_or(0) as i32,
+                                sch_date: slot.get("sch_date").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                                status: schedule_status(slot),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if schedules.is_empty() {
+            continue;
+        }
+
+        let total_left: i32 = schedules.iter().map(|s| s.left_num).sum();
+
+        valid_docs.push(DoctorSchedule {
+            doctor_id,
+            doctor_name: doc_value.get("doctor_name").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            reg_fee: doc_value.get("reg_fee").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            total_left_num: total_left,
+            his_doc_id: doc_value.get("his_doc_id").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            his_dep_id: doc_value.get("his_dep_id").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            schedule_id: schedules.first().map(|s| s.schedule_id.clone()).unwrap_or_default(),
+            time_type_desc: schedules.first().map(|s| s.time_type_desc.clone()).unwrap_or_default(),
+            status: schedule_status(doc_value),
+            schedules,
+        });
+    }
+
+    valid_docs
+}
+
+/// First non-empty status flag of a doctor or slot object
+fn schedule_status(value: &serde_json::Value) -> String {
+    SCHEDULE_STATUS_KEYS
+        .iter()
+        .filter_map(|key| match value.get(*key)? {
+            serde_json::Value::String(s) => Some(s.trim().to_string()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+        .find(|s| !s.is_empty())
+        .unwrap_or_default()
+}
+
+/// Reduce one day's schedules to remaining tickets and doctors with slots
+/// Suspended (停诊) doctors and slots count as having no tickets.
+pub fn summarize_availability(date: &str, docs: &[DoctorSchedule]) -> DayAvailability {
+    let mut total_left = 0;
+    let mut doctors_with_slots = 0;
+    for doc in docs {
+        let left = bookable_left(doc);
+        if left > 0 {
+            total_left += left;
+            doctors_with_slots += 1;
+        }
+    }
+    DayAvailability {
+        date: date.to_string(),
+        total_left: Some(total_left),
+        doctors_with_slots: Some(doctors_with_slots),
+        error: None,
+    }
+}
+
+/// Tickets a doctor still has that can be booked, ignoring 停诊 slots
+fn bookable_left(doc: &DoctorSchedule) -> i32 {
+    if doc.is_suspended() {
+        return 0;
+    }
+    doc.schedules.iter().filter(|s| !s.is_suspended()).map(|s| s.left_num.max(0)).sum()
+}
+
+/// Unique doctors across per-day schedules (in date order), each with the
+/// nearest date that still has tickets. Doctors with tickets come first,
+/// soonest first; the rest keep the order they were first seen in.
+pub fn aggregate_department_doctors(days: &[(String, Vec<DoctorSchedule>)]) -> Vec<DepartmentDoctor> {
+    let mut doctors: Vec<DepartmentDoctor> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (date, docs) in days {
+        for doc in docs {
+            let key = if doc.doctor_id.is_empty() { format!("name:{}", doc.doctor_name) } else { doc.doctor_id.clone() };
+            if key == "name:" {
+                continue;
+            }
+            let slot = *index.entry(key).or_insert_with(|| {
+                doctors.push(DepartmentDoctor {
+                    doctor_id: doc.doctor_id.clone(),
+                    doctor_name: doc.doctor_name.clone(),
+                    reg_fee: String::new(),
+                    next_available_date: None,
+                    left_num: 0,
+                    schedule_dates: Vec::new(),
+                });
+                doctors.len() - 1
+            });
+            let doctor = &mut doctors[slot];
+            if doctor.reg_fee.is_empty() {
+                doctor.reg_fee = doc.reg_fee.clone();
+            }
+            if !doctor.schedule_dates.contains(date) {
+                doctor.schedule_dates.push(date.clone());
+            }
+            let left = bookable_left(doc);
+            match &doctor.next_available_date {
+                None if left > 0 => {
+                    doctor.next_available_date = Some(date.clone());
+                    doctor.left_num = left;
+                }
+                // Morning and afternoon can come back as separate entries
+                Some(next) if next == date => doctor.left_num += left,
+                _ => {}
+            }
+        }
+    }
+    // Stable: doctors without tickets keep their first-seen order
+    doctors.sort_by(|a, b| match (&a.next_available_date, &b.next_available_date) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    doctors
+}
+
+/// Parse the booking step page (ystep1) into the fields needed to submit
+fn parse_ticket_detail(body: &str) -> TicketDetail {
+    let document = Html::parse_document(body);
+
+    // Parse time slots
+    let li_selector = Selector::parse("#delts li").unwrap();
+    let time_slots: Vec<TimeSlot> = document
+        .select(&li_selector)
+        .filter_map(|el| {
+            let name = el.text().collect::<String>().trim().to_string();
+            let value = el.value().attr("val").unwrap_or("").to_string();
+            if value.is_empty() {
+                None
+            } else {
+                Some(TimeSlot { name, value })
+            }
+        })
+        .collect();
+
+    // Helper to get input value
+    let get_input_value = |selectors: &[&str]| -> String {
+        for selector in selectors {
+            if let Ok(sel) = Selector::parse(selector) {
+                if let Some(el) = document.select(&sel).next() {
+                    if let Some(val) = el.value().attr("value") {
+                        return val.trim().to_string();
+                    }
+                }
+            }
+        }
+        String::new()
+    };
+
+    // Parse addresses from select
+    let mut addresses = Vec::new();
+    let address_selectors = ["select[name='addressId']", "#addressId", "#useraddress_area"];
+    for selector in address_selectors {
+        if let Ok(sel) = Selector::parse(selector) {
+            if let Some(select_el) = document.select(&sel).next() {
+                if let Ok(option_sel) = Selector::parse("option") {
+                    for option in select_el.select(&option_sel) {
+                        let id = option.value().attr("value").unwrap_or("").trim().to_string();
+                        let text = option.text().collect::<String>().trim().to_string();
+                        if !id.is_empty() && id != "0" && id != "-1" && !text.is_empty() {
+                            addresses.push(AddressOption { id, text });
+                        }
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    let mut address_id = get_input_value(&["input[name='addressId']", "#addressId"]);
+    let mut address = get_input_value(&["input[name='address']", "#address"]);
+
+    // Fallback to first address
+    if (address_id.is_empty() || address.is_empty()) && !addresses.is_empty() {
+        if address_id.is_empty() {
+            address_id = addresses[0].id.clone();
+        }
+        if address.is_empty() {
+            address = addresses[0].text.clone();
+        }
+    }
+
+    let mut detail = TicketDetail {
+        times: time_slots.clone(),
+        time_slots,
+        sch_data: get_input_value(&["input[name='sch_data']"]),
+        detlid_realtime: get_input_value(&["#detlid_realtime"]),
+        level_code: get_input_value(&["#level_code"]),
+        sch_date: get_input_value(&["input[name='sch_date']", "#sch_date"]),
+        order_no: get_input_value(&["input[name='order_no']", "#order_no"]),
+        disease_content: get_input_value(&["input[name='disease_content']", "#disease_content"]),
+        disease_input: get_input_value(&["textarea[name='disease_input']", "#disease_input"]),
+        is_hot: get_input_value(&["input[name='is_hot']", "#is_hot"]),
+        his_mem_id: get_input_value(&["input[name='hisMemId']", "#hismemid"]),
+        address_id,
+        address,
+        addresses,
+        ..TicketDetail::default()
+    };
+    detail.missing_fields = detail.missing_required_fields();
+    if !detail.missing_fields.is_empty() {
+        detail.page_kind = ticket_page_kind(&document, body);
+    }
+    detail
+}
+
+/// Login form markers on a page that should have been the booking form
+const LOGIN_PAGE_SELECTORS: [&str; 3] = ["input[type='password']", "form[action*='login']", "#loginForm"];
+
+/// Captcha widgets the site's risk control injects into booking pages:
+/// GeeTest, the Aliyun slider, Tencent TCaptcha, generic captcha boxes and
+/// their instructions
+const CAPTCHA_PATTERNS: [&str; 5] = [
+    r"(?i)geetest",
+    r#"(?i)\bid\s*=\s*["']nc(_1_wrapper)?["']|nc-container"#,
+    r"(?i)tcaptcha",
+    r#"(?i)\b(id|class)\s*=\s*["'][^"']*captcha"#,
+    r"滑动验证|拖动滑块|安全验证|请完成验证|图形验证码",
+];
+
+/// Text of the first captcha marker found in a page
+fn captcha_marker(body: &str) -> Option<String> {
+    CAPTCHA_PATTERNS
+        .iter()
+        .filter_map(|pattern| regex::Regex::new(pattern).ok())
+        .find_map(|re| re.find(body).map(|m| m.as_str().to_string()))
+}
+
+/// Error banners and tip boxes shown instead of the booking form
+const ERROR_BANNER_SELECTORS: [&str; 6] = [".error", ".err_tip", ".error-tip", ".tips-error", ".alert-danger", ".tip p"];
+
+/// Why a booking step page lacks its hidden inputs; Normal when it is
+/// none of the known interstitials (a page variant the parser misses)
+fn ticket_page_kind(document: &Html, body: &str) -> TicketPageKind {
+    let matches_any = |selectors: &[&str]| {
+        selectors
+            .iter()
+            .filter_map(|css| Selector::parse(css).ok())
+            .any(|selector| document.select(&selector).next().is_some())
+    };
+    if matches_any(&LOGIN_PAGE_SELECTORS) {
+        return TicketPageKind::LoginRedirect;
+    }
+    if captcha_marker(body).is_some() {
+        return TicketPageKind::Captcha;
+    }
+    let banner = ERROR_BANNER_SELECTORS
+        .iter()
+        .filter_map(|css| Selector::parse(css).ok())
+        .flat_map(|selector| {
+            document
+                .select(&selector)
+                .map(|el| el.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+        })
+        .find(|text| !text.is_empty());
+    match banner {
+        Some(text) => TicketPageKind::Error(truncate_utf8(&text, BODY_SNIPPET_BYTES)),
+        None => TicketPageKind::Normal,
+    }
+}
+
+/// Account name on user.91160.com/user/index.html
+fn parse_username(body: &str) -> Option<String> {
+    let document = Html::parse_document(body);
+    let selector = Selector::parse(".user-name, .username, .user_name, #username, .uname").unwrap();
+    let clean = |text: &str| {
+        let name = text.trim().trim_start_matches(['，', ',', '：', ':', ' ']).trim().trim_end_matches(['！', '!']);
+        (!name.is_empty()).then(|| name.to_string())
+    };
+
+    if let Some(name) = document
+        .select(&selector)
+        .find_map(|el| clean(&el.text().collect::<String>()))
+    {
+        return Some(name);
+    }
+
+    // Older layouts only have a "欢迎您，xxx" greeting
+    let text = document.root_element().text().collect::<String>();
+    let rest = text.split_once("欢迎您")?.1;
+    clean(rest.split_whitespace().next().unwrap_or(""))
+}
+
+/// Text of the element labelled by one of `labels`
+/// Matches an element whose own text starts with the label, then takes the rest of
+/// its text or, when that is empty, the next sibling element's text.
+fn labeled_value(document: &Html, labels: &[&str]) -> Option<String> {
+    let all = Selector::parse("body *").unwrap();
+    let squash = |text: String| text.split_whitespace().collect::<String>();
+    for el in document.select(&all) {
+        let own = squash(el.children().filter_map(|n| n.value().as_text().map(|t| t.to_string())).collect());
+        let Some(label) = labels.iter().find(|l| own.starts_with(*l)) else {
+            continue;
+        };
+        let full = squash(el.text().collect());
+        let rest = full.strip_prefix(*label).unwrap_or_default().trim_start_matches(['：', ':']);
+        if !rest.is_empty() {
+            return Some(rest.to_string());
+        }
+        let sibling = el.next_siblings().find_map(ElementRef::wrap).map(|s| squash(s.text().collect()));
+        if let Some(value) = sibling.filter(|v| !v.is_empty()) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// First number in `text`, e.g. "¥12.50" -> 12.5
+fn leading_number(text: &str) -> Option<&str> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let rest = &text[start..];
+    let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+    Some(rest[..end].trim_end_matches('.'))
+}
+
+/// Account fields on user.91160.com/user/index.html
+fn parse_account_info(body: &str) -> AccountInfo {
+    let document = Html::parse_document(body);
+    let number = |labels: &[&str]| labeled_value(&document, labels).and_then(|v| leading_number(&v).map(str::to_string));
+    AccountInfo {
+        username: parse_username(body),
+        phone_masked: labeled_value(&document, &["手机号码", "绑定手机", "手机号", "手机"])
+            .filter(|v| v.chars().any(|c| c.is_ascii_digit())),
+        balance: number(&["账户余额", "余额"]).and_then(|v| v.parse().ok()),
+        pending_payment: number(&["待支付", "待付款"]).and_then(|v| v.parse().ok()),
+        unread_notifications: number(&["未读消息", "消息通知", "消息"]).and_then(|v| v.parse().ok()),
+    }
+}
+
+/// Ids the member list body has had across site versions, current first
+const MEMBER_LIST_SELECTORS: [&str; 4] = ["tbody#mem_list", "tbody#memberList", "tbody#member_list", "tbody#memList"];
+
+/// Wording of the member page when the account has no members yet
+const NO_MEMBER_MARKERS: [&str; 3] = ["暂无就诊人", "还没有就诊人", "尚未添加就诊人"];
+
+/// Parse the member table of user.91160.com/member.html
+/// Tries the known list ids, then a table headed "就诊人", then any `mem<id>`
+/// elements. A page none of them matches is an error rather than an empty
+/// list, so a site redesign is not mistaken for an account without members.
+fn parse_members(body: &str) -> AppResult<Vec<Member>> {
+    let document = Html::parse_document(body);
+    let thead_selector = Selector::parse("thead th, thead td").unwrap();
+    let th_selector = Selector::parse("th").unwrap();
+    let row_selector = Selector::parse("tr").unwrap();
+    let table_selector = Selector::parse("table").unwrap();
+    // Header cells in <thead>, or <th> cells anywhere when there is none
+    let headers_of = |table: &ElementRef| -> Vec<String> {
+        let headers: Vec<String> = table.select(&thead_selector).map(|th| cell_text(&th)).collect();
+        if !headers.is_empty() {
+            return headers;
+        }
+        table.select(&th_selector).map(|th| cell_text(&th)).collect()
+    };
+
+    for (tier, css) in MEMBER_LIST_SELECTORS.iter().enumerate() {
+        let selector = Selector::parse(css).unwrap();
+        if let Some(tbody) = document.select(&selector).next() {
+            if tier > 0 {
+                tracing::warn!(selector = css, "member list found under an alternate id");
+            }
+            let headers = tbody.parent().and_then(ElementRef::wrap).map(|table| headers_of(&table)).unwrap_or_default();
+            return Ok(member_rows(tbody.select(&row_selector), &MemberColumns::from_headers(&headers)));
+        }
+    }
+
+    if let Some((table, headers)) = document
+        .select(&table_selector)
+        .map(|table| (table, headers_of(&table)))
+        .find(|(_, headers)| headers.iter().any(|h| h.contains("就诊人")))
+    {
+        tracing::warn!("member list found by table header");
+        return Ok(member_rows(table.select(&row_selector), &MemberColumns::from_headers(&headers)));
+    }
+
+    let id_re = regex::Regex::new(r#"\bid\s*=\s*["']mem(\d+)["']"#).unwrap();
+    let rows: Vec<ElementRef> = id_re
+        .captures_iter(body)
+        .filter_map(|caps| Selector::parse(&format!("[id=\"mem{}\"]", &caps[1])).ok())
+        .filter_map(|selector| document.select(&selector).next())
+        .collect();
+    if !rows.is_empty() {
+        tracing::warn!(count = rows.len(), "member list found by mem ids");
+        return Ok(member_rows(rows.into_iter(), &MemberColumns::default()));
+    }
+
+    if NO_MEMBER_MARKERS.iter().any(|marker| body.contains(marker)) {
+        return Ok(Vec::new());
+    }
+    Err(AppError::ParseError("member page layout changed".into()))
+}
+
+/// Whitespace-free text of a cell
+fn cell_text(el: &ElementRef) -> String {
+    el.text().collect::<String>().split_whitespace().collect()
+}
+
+/// Members from list rows; cells are the `td`s, or the child elements of a
+/// row that is not a table row
+fn member_rows<'a>(rows: impl Iterator<Item = ElementRef<'a>>, columns: &MemberColumns) -> Vec<Member> {
+    let td_selector = Selector::parse("td").unwrap();
+    let mut members = Vec::new();
+    for row in rows {
+        let id = row
+            .value()
+            .attr("id")
+            .unwrap_or("")
+            .trim_start_matches("mem")
+            .to_string();
+
+        let mut cells: Vec<String> = row.select(&td_selector).map(|td| cell_text(&td)).collect();
+        if cells.is_empty() && row.value().name() != "tr" {
+            cells = row.children().filter_map(ElementRef::wrap).map(|el| cell_text(&el)).collect();
+        }
+        if cells.is_empty() {
+            continue;
+        }
+        let cell = |index: Option<usize>| index.and_then(|i| cells.get(i)).cloned().unwrap_or_default();
+
+        let raw_name = cell(columns.name.or(Some(0)));
+        let is_default = raw_name.contains("默认") || cells.iter().any(|c| c == "默认");
+        let name = raw_name.replace("默认", "");
+        let certified = match columns.certified {
+            Some(i) => cells.get(i).map(|c| is_certified_text(c)).unwrap_or(false),
+            None => cells.iter().any(|c| is_certified_text(c)),
+        };
+
+        if id.is_empty() && name.is_empty() {
+            continue;
+        }
+
+        members.push(Member {
+            id,
+            name,
+            certified,
+            id_card_masked: cell(columns.id_card),
+            phone_masked: cell(columns.phone),
+            relation: cell(columns.relation),
+            is_default,
+        });
+    }
+
+    members
+}
+
+/// Order table columns located by header text
+#[derive(Debug, Default)]
+struct OrderColumns {
+    order_no: Option<usize>,
+    member: Option<usize>,
+    unit: Option<usize>,
+    dep: Option<usize>,
+    doctor: Option<usize>,
+    visit_time: Option<usize>,
+    status: Option<usize>,
+}
+
+impl OrderColumns {
+    fn from_headers(headers: &[String]) -> Self {
+        let find = |keys: &[&str]| headers.iter().position(|h| keys.iter().any(|k| h.contains(k)));
+        Self {
+            order_no: find(&["订单号", "订单编号", "预约单号"]),
+            member: find(&["就诊人", "患者"]),
+            unit: find(&["医院"]),
+            dep: find(&["科室"]),
+            doctor: find(&["医生", "专家"]),
+            visit_time: find(&["就诊时间", "就诊日期", "预约时间"]),
+            status: find(&["状态"]),
+        }
+    }
+}
+
+/// Order statuses that no longer hold a booking
+const INACTIVE_ORDER_WORDS: [&str; 9] = ["取消", "退号", "已退", "退款", "已就诊", "已完成", "过期", "爽约", "失败"];
+
+/// Parse the order list on user.91160.com/order.html
+/// Member, hospital and department ids come from the row's data attributes.
+fn parse_appointments(body: &str) -> Vec<Appointment> {
+    let document = Html::parse_document(body);
+    let tbody_selector = Selector::parse("tbody#order_list").unwrap();
+    let header_selector = Selector::parse("thead th, thead td").unwrap();
+    let row_selector = Selector::parse("tr").unwrap();
+    let td_selector = Selector::parse("td").unwrap();
+    let cell_text = |el: &ElementRef| el.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ");
+    let date_re = regex::Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap();
+
+    let Some(tbody) = document.select(&tbody_selector).next() else {
+        return Vec::new();
+    };
+    let headers: Vec<String> = tbody
+        .parent()
+        .and_then(ElementRef::wrap)
+        .map(|table| table.select(&header_selector).map(|th| cell_text(&th)).collect())
+        .unwrap_or_default();
+    let columns = OrderColumns::from_headers(&headers);
+
+    let mut orders = Vec::new();
+    for row in tbody.select(&row_selector) {
+        let attr = |name: &str| row.value().attr(name).unwrap_or("").trim().to_string();
+        let cells: Vec<String> = row.select(&td_selector).map(|td| cell_text(&td)).collect();
+        if cells.is_empty() {
+            continue;
+        }
+        let cell = |index: Option<usize>| index.and_then(|i| cells.get(i)).cloned().unwrap_or_default();
+
+        let mut order_no = cell(columns.order_no);
+        if order_no.is_empty() {
+            order_no = attr("id").trim_start_matches("order").to_string();
+        }
+        let visit_time = cell(columns.visit_time);
+        let status = cell(columns.status);
+        orders.push(Appointment {
+            order_no,
+            member_id: attr("data-mid"),
+            member_name: cell(columns.member),
+            unit_id: attr("data-unit-id"),
+            unit_name: cell(columns.unit),
+            dep_id: attr("data-dep-id"),
+            dep_name: cell(columns.dep),
+            doctor_name: cell(columns.doctor),
+            date: date_re.find(&visit_time).map(|m| m.as_str().to_string()).unwrap_or_default(),
+            visit_time,
+            active: !status.is_empty() && !INACTIVE_ORDER_WORDS.iter().any(|word| status.contains(word)),
+            status,
+        });
+    }
+
+    orders
+}
+
+/// Parse the saved addresses on user.91160.com/address.html
+fn parse_addresses(body: &str) -> Vec<AddressRecord> {
+    let document = Html::parse_document(body);
+    let item_selector = Selector::parse("#address_list [data-id]").unwrap();
+    let region_selector = Selector::parse(".region").unwrap();
+    let detail_selector = Selector::parse(".detail").unwrap();
+    let text_of = |el: ElementRef| el.text().collect::<String>().trim().to_string();
+
+    document
+        .select(&item_selector)
+        .filter_map(|item| {
+            let id = item.value().attr("data-id").unwrap_or("").trim().to_string();
+            let region = item.select(&region_selector).next().map(text_of).unwrap_or_default();
+            let detail = item.select(&detail_selector).next().map(text_of).unwrap_or_default();
+            let text = if region.is_empty() && detail.is_empty() {
+                text_of(item)
+            } else {
+                format!("{}{}", region, detail)
+            };
+            if id.is_empty() || text.is_empty() {
+                return None;
+            }
+            Some(AddressRecord {
+                id,
+                text,
+                region_id: item.value().attr("data-region").unwrap_or("").trim().to_string(),
+                detail,
+                is_default: item.value().classes().any(|c| c == "default"),
+            })
+        })
+        .collect()
+}
+
+/// Region options offered by the address form
+fn parse_address_regions(body: &str) -> Vec<AddressOption> {
+    let document = Html::parse_document(body);
+    let option_selector = Selector::parse("select[name='region_id'] option").unwrap();
+    document
+        .select(&option_selector)
+        .filter_map(|option| {
+            let id = option.value().attr("value").unwrap_or("").trim().to_string();
+            let text = option.text().collect::<String>().trim().to_string();
+            (!id.is_empty() && id != "0" && id != "-1").then_some(AddressOption { id, text })
+        })
+        .collect()
+}
+
+/// User-center form: where to post and the hidden fields to echo back
+#[derive(Debug)]
+struct HiddenForm {
+    action: Url,
+    hidden: Vec<(String, String)>,
+}
+
+/// Locate the form holding `field` (or the first form) in a page
+fn parse_hidden_form(body: &str, page_url: &Url, field: &str) -> HiddenForm {
+    let document = Html::parse_document(body);
+    let form_selector = Selector::parse("form").unwrap();
+    let hidden_selector = Selector::parse("input[type='hidden'][name]").unwrap();
+
+    let form = document
+        .select(&form_selector)
+        .find(|f| f.html().contains(field))
+        .or_else(|| document.select(&form_selector).next());
+
+    let action = form
+        .and_then(|f| f.value().attr("action"))
+        .map(str::trim)
+        .filter(|a| !a.is_empty() && !a.starts_with("javascript"))
+        .and_then(|a| page_url.join(a).ok())
+        .unwrap_or_else(|| page_url.clone());
+
+    let hidden = form
+        .map(|f| {
+            f.select(&hidden_selector)
+                .filter_map(|input| {
+                    let name = input.value().attr("name")?.trim();
+                    let value = input.value().attr("value").unwrap_or("").trim();
+                    (!name.is_empty()).then(|| (name.to_string(), value.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    HiddenForm { action, hidden }
+}
+
+fn is_certified_text(text: &str) -> bool {
+    text.contains("认证") && !text.contains("未认证")
+}
+
+/// Flatten a page to one text node per line so label/value pairs split
+/// across table cells still match
+fn flatten_page_text(document: &Html) -> String {
+    document
+        .root_element()
+        .text()
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Value following the first matching `label：` in flattened page text
+fn find_labeled_value(text: &str, labels: &[&str]) -> String {
+    for label in labels {
+        let pattern = format!(r"{}\s*[：:]?\s*([^\n]+)", regex::escape(label));
+        if let Ok(re) = regex::Regex::new(&pattern) {
+            if let Some(m) = re.captures(text).and_then(|c| c.get(1)) {
+                let value = m.as_str().trim();
+                if !value.is_empty() {
+                    return value.to_string();
+                }
+            }
+        }
+    }
+    String::new()
+}
+
+/// Parse grade, district, address and phone from a hospital homepage
+fn parse_hospital_detail(unit_id: &str, body: &str) -> Hospital {
+    let document = Html::parse_document(body);
+    let text = flatten_page_text(&document);
+    let non_empty = |s: String| (!s.is_empty()).then_some(s);
+
+    let name_selector = Selector::parse("h1").unwrap();
+    let unit_name = document
+        .select(&name_selector)
+        .next()
+        .map(|h| h.text().collect::<String>().trim().to_string())
+        .unwrap_or_default();
+
+    let level = non_empty(find_labeled_value(&text, &["医院等级", "等级"])).or_else(|| {
+        regex::Regex::new(r"三级甲等|三级乙等|三级|二级甲等|二级乙等|二级|一级|三甲|二甲")
+            .ok()
+            .and_then(|re| re.find(&text).map(|m| m.as_str().to_string()))
+    });
+    let address = non_empty(find_labeled_value(&text, &["医院地址", "地址"]));
+    let district = non_empty(find_labeled_value(&text, &["所在区域", "所属区域", "区域"])).or_else(|| {
+        // Derive from the address, e.g. 深圳市罗湖区东门北路 -> 罗湖区
+        let re = regex::Regex::new(r"(?:市|^)([^市省\s]{1,6}?[区县])").ok()?;
+        re.captures(address.as_deref()?).map(|c| c[1].to_string())
+    });
+    let phone = non_empty(find_labeled_value(&text, &["联系电话", "电话"]));
+
+    Hospital {
+        unit_id: unit_id.to_string(),
+        unit_name,
+        level,
+        address,
+        district,
+        phone,
+        pinyin: None,
+    }
+}
+
+fn parse_order_confirmation(body: &str) -> Option<OrderConfirmation> {
+    if body.trim().is_empty() {
+        return None;
+    }
+
+    let text = flatten_page_text(&Html::parse_document(body));
+    let find = |labels: &[&str]| find_labeled_value(&text, labels);
+
+    let confirmation = OrderConfirmation {
+        order_no: find(&["订单号", "订单编号", "预约单号"]),
+        queue_no: find(&["排队号", "就诊序号", "序号"]),
+        visit_time: find(&["就诊时间", "预约时间"]),
+        payment_deadline: find(&["支付截止时间", "支付截止", "最晚支付时间"]),
+    };
+
+    if confirmation.order_no.is_empty()
+        && confirmation.queue_no.is_empty()
+        && confirmation.visit_time.is_empty()
+        && confirmation.payment_deadline.is_empty()
+    {
+        return None;
+    }
+
+    Some(confirmation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::CLIENT_PROFILE_PRESETS;
+
+    #[test]
+    fn test_has_more_schedule_pages() {
+        assert!(has_more_schedule_pages(Some(&serde_json::json!({"total": 25, "page_size": 10})), 2, 10));
+        assert!(!has_more_schedule_pages(Some(&serde_json::json!({"total": "20", "page_size": "10"})), 2, 10));
+        assert!(has_more_schedule_pages(Some(&serde_json::json!({"total_page": 3})), 2, 0));
+        assert!(!has_more_schedule_pages(Some(&serde_json::json!({"total_page": "3"})), 3, 10));
+        // Without counts, only a full page suggests another one
+        assert!(has_more_schedule_pages(Some(&serde_json::json!({})), 1, DEFAULT_SCHEDULE_PAGE_SIZE));
+        assert!(!has_more_schedule_pages(Some(&serde_json::json!({})), 1, DEFAULT_SCHEDULE_PAGE_SIZE - 1));
+        assert!(has_more_schedule_pages(Some(&serde_json::json!({"page_size": 4})), 1, 4));
+        assert!(!has_more_schedule_pages(None, 1, 50));
+    }
+
+    #[test]
+    fn test_schedule_pages_dedupe_by_doctor_id() {
+        let mut pages = SchedulePages::default();
+        let first = serde_json::json!({
+            "doc": [{"doctor_id": "1"}, {"doctor_id": 2}],
+            "sch": {"1": {"am": [{"schedule_id": "a"}]}, "2": {"am": [{"schedule_id": "b"}]}},
+        });
+        let second = serde_json::json!({
+            "doc": [{"doctor_id": "2"}, {"doctor_id": "3"}],
+            "sch": {"2": {"am": [{"schedule_id": "dup"}]}, "3": {"pm": [{"schedule_id": "c"}]}},
+        });
+        assert_eq!(pages.merge(Some(&first)), (2, 2));
+        assert_eq!(pages.merge(Some(&second)), (2, 1));
+        assert_eq!(pages.merge(Some(&second)), (2, 0));
+
+        let doctors = pages.doctors();
+        let ids: Vec<&str> = doctors.iter().map(|d| d.doctor_id.as_str()).collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        assert_eq!(doctors[1].schedule_id, "b");
+    }
+
+    #[test]
+    fn test_default_headers_follow_profile() {
+        for name in CLIENT_PROFILE_PRESETS {
+            let profile = ClientProfile::preset(name).unwrap();
+            let client = HealthClient::with_profile(profile.clone()).unwrap();
+            let headers = client.default_headers();
+            assert_eq!(headers[USER_AGENT], profile.user_agent.as_str());
+            assert_eq!(headers["sec-ch-ua"], profile.sec_ch_ua.as_str());
+            assert_eq!(headers["sec-ch-ua-platform"], profile.sec_ch_ua_platform.as_str());
+            assert_eq!(headers[ACCEPT_LANGUAGE], profile.accept_language.as_str());
+            assert_eq!(headers["sec-ch-ua-mobile"], "?0");
+        }
+
+        let edge = HealthClient::with_profile(ClientProfile::preset("edge_win").unwrap()).unwrap();
+        assert!(edge.default_headers()[USER_AGENT].to_str().unwrap().contains("Edg/"));
+        let mac = HealthClient::with_profile(ClientProfile::preset("chrome_mac").unwrap()).unwrap();
+        assert_eq!(mac.default_headers()["sec-ch-ua-platform"], "\"macOS\"");
+        assert_eq!(mac.page_headers("https://user.91160.com/")[USER_AGENT], mac.profile().user_agent.as_str());
+        assert!(ClientProfile::preset("netscape").is_none());
+    }
+
+    /// Header maps as each request site built them by hand before HeaderBuilder
+    fn legacy_default(profile: &ClientProfile) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        insert_header(&mut headers, USER_AGENT, &profile.user_agent);
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json, text/javascript, */*; q=0.01"));
+        insert_header(&mut headers, ACCEPT_LANGUAGE, &profile.accept_language);
+        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+        headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
+        insert_header(&mut headers, HeaderName::from_static("sec-ch-ua"), &profile.sec_ch_ua);
+        headers.insert("sec-ch-ua-mobile", HeaderValue::from_static("?0"));
+        insert_header(&mut headers, HeaderName::from_static("sec-ch-ua-platform"), &profile.sec_ch_ua_platform);
+        headers
+    }
+
+    fn legacy_navigation(headers: &mut HeaderMap, site: &'static str) {
+        headers.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"));
+        headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("document"));
+        headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("navigate"));
+        headers.insert("Sec-Fetch-Site", HeaderValue::from_static(site));
+        headers.insert("Sec-Fetch-User", HeaderValue::from_static("?1"));
+        headers.insert("Upgrade-Insecure-Requests", HeaderValue::from_static("1"));
+    }
+
+    #[test]
+    fn test_header_builder_reproduces_request_sites() {
+        const WWW: &str = "https://www.91160.com";
+        const USER: &str = "https://user.91160.com";
+        const STEP: &str = "https://www.91160.com/guahao/ystep1/uid-21/depid-100.html";
+        for name in CLIENT_PROFILE_PRESETS {
+            let profile = ClientProfile::preset(name).unwrap();
+            let builder = || HeaderBuilder::new(&profile);
+            let legacy = || legacy_default(&profile);
+
+            // probe_login_page
+            let mut login = legacy();
+            login.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+            legacy_navigation(&mut login, "none");
+            assert_eq!(builder().document_navigation().fetch_site("none").ajax().build(), login);
+
+            // get_hospitals_by_city, post_deps
+            let mut ajax_form = legacy();
+            ajax_form.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+            ajax_form.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded; charset=UTF-8"));
+            insert_header(&mut ajax_form, REFERER, &format!("{}/", WWW));
+            insert_header(&mut ajax_form, ORIGIN, WWW);
+            assert_eq!(builder().ajax().form_post(WWW).referer(&format!("{}/", WWW)).build(), ajax_form);
+
+            // page_headers
+            let mut page = legacy();
+            legacy_navigation(&mut page, "same-origin");
+            insert_header(&mut page, REFERER, USER);
+            assert_eq!(builder().document_navigation().referer(USER).build(), page);
+
+            // add_member, add_address
+            let mut member_form = legacy();
+            member_form.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
+            insert_header(&mut member_form, ORIGIN, USER);
+            insert_header(&mut member_form, REFERER, "https://user.91160.com/member.html");
+            assert_eq!(builder().form_post(USER).referer("https://user.91160.com/member.html").build(), member_form);
+
+            // schedule_request
+            let mut schedule = legacy();
+            schedule.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+            schedule.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
+            insert_header(&mut schedule, REFERER, STEP);
+            assert_eq!(builder().ajax().fetch_site("same-site").referer(STEP).build(), schedule);
+
+            // fetch_ticket_detail
+            let mut detail = legacy();
+            insert_header(&mut detail, REFERER, STEP);
+            assert_eq!(builder().referer(STEP).build(), detail);
+
+            // submit_order keeps the XHR Accept on its navigation
+            let mut submit = legacy();
+            submit.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
+            insert_header(&mut submit, ORIGIN, WWW);
+            legacy_navigation(&mut submit, "same-origin");
+            submit.insert(ACCEPT, HeaderValue::from_static("application/json, text/javascript, */*; q=0.01"));
+            insert_header(&mut submit, REFERER, STEP);
+            assert_eq!(builder().accept(ACCEPT_XHR).referer(STEP).document_navigation().form_post(WWW).build(), submit);
+
+            // favicon HEADs and server time
+            assert_eq!(builder().build(), legacy());
+        }
+    }
+
+    #[test]
+    fn test_parse_appointments() {
+        let orders = parse_appointments(include_str!("../../tests/fixtures/orders.html"));
+        assert_eq!(orders.len(), 3);
+        assert_eq!(
+            orders[0],
+            Appointment {
+                order_no: "YY20240315123456".into(),
+                member_id: "1001".into(),
+                member_name: "张三".into(),
+                unit_id: "21".into(),
+                unit_name: "深圳市人民医院".into(),
+                dep_id: "100".into(),
+                dep_name: "心血管内科".into(),
+                doctor_name: "王医生".into(),
+                date: "2024-03-20".into(),
+                visit_time: "2024-03-20 上午 09:00-09:30".into(),
+                status: "预约成功".into(),
+                active: true,
+            }
+        );
+        assert!(orders[1].active);
+        assert!(!orders[2].active);
+
+        assert!(orders[0].matches("1001", "21", "100", "2024-03-20"));
+        assert!(!orders[0].matches("1001", "21", "100", "2024-03-21"));
+        assert!(!orders[0].matches("1002", "21", "100", "2024-03-20"));
+        assert!(!orders[0].matches("1001", "22", "100", "2024-03-20"));
+        // Cancelled orders never match
+        assert!(!orders[2].matches("1001", "21", "100", "2024-03-21"));
+        assert!(parse_appointments("<html><body>暂无预约</body></html>").is_empty());
+    }
+
+    #[test]
+    fn test_decode_html_gbk() {
+        let login = include_bytes!("../../tests/fixtures/login_gbk.html");
+        // Charset only in the meta tag
+        let body = decode_html(login, "text/html");
+        assert!(body.contains("请先登录"));
+        assert!(!String::from_utf8_lossy(login).contains("登录"));
+
+        let members = include_bytes!("../../tests/fixtures/members_gbk.html");
+        assert!(decode_html(members, "text/html; charset=GBK").contains("张三"));
+        assert!(decode_html(members, "text/html").contains("张三"));
+
+        assert_eq!(decode_html("健康160".as_bytes(), "text/html; charset=utf-8"), "健康160");
+        assert_eq!(decode_html("健康160".as_bytes(), ""), "健康160");
+        assert_eq!(meta_charset(b"<meta charset='GB18030'>").as_deref(), Some("GB18030"));
+    }
+
+    #[test]
+    fn test_parse_members_layout_tiers() {
+        let old = parse_members(include_str!("../../tests/fixtures/members.html")).unwrap();
+        assert_eq!(old.len(), 3);
+        assert_eq!((old[0].id.as_str(), old[0].relation.as_str()), ("1001", "本人"));
+
+        // Redesign: list body renamed and columns reordered
+        let new_layout = include_str!("../../tests/fixtures/members_new_layout.html");
+        let renamed = parse_members(new_layout).unwrap();
+        assert_eq!(renamed.len(), 2);
+        assert_eq!(renamed[0].name, "张三");
+        assert!(renamed[0].is_default && renamed[0].certified);
+        assert_eq!(renamed[0].id_card_masked, "4403**********1234");
+        assert_eq!(renamed[1].phone_masked, "139****0000");
+        assert_eq!(renamed[1].relation, "子女");
+        assert!(!renamed[1].certified);
+
+        // No known id left: found by the "就诊人" header
+        let by_header = parse_members(&new_layout.replace(r#" id="memberList""#, "")).unwrap();
+        assert_eq!(format!("{:?}", by_header), format!("{:?}", renamed));
+
+        // No table at all: rows found by their mem ids
+        let list = r#"<ul class="members">
+            <li id="mem2001"><span>赵六</span><span>本人</span><span>已认证</span></li>
+            <li id='mem2002'><span>钱七</span><span>父母</span><span>未认证</span></li>
+        </ul>"#;
+        let by_id = parse_members(list).unwrap();
+        assert_eq!(by_id.iter().map(|m| (m.id.as_str(), m.name.as_str(), m.certified)).collect::<Vec<_>>(), [("2001", "赵六", true), ("2002", "钱七", false)]);
+    }
+
+    #[test]
+    fn test_parse_members_empty_vs_unrecognized() {
+        let empty_table = include_str!("../../tests/fixtures/members.html");
+        let start = empty_table.find("<tr id=\"mem1001\">").unwrap();
+        let end = empty_table.find("</tbody>").unwrap();
+        let no_rows = format!("{}{}", &empty_table[..start], &empty_table[end..]);
+        assert!(parse_members(&no_rows).unwrap().is_empty());
+        assert!(parse_members("<div class=\"empty\">暂无就诊人，请先添加</div>").unwrap().is_empty());
+
+        let err = parse_members("<div class=\"patients\"><p>张三</p></div>").unwrap_err();
+        assert!(matches!(&err, AppError::ParseError(msg) if msg == "member page layout changed"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_account_info_tolerates_missing_fields() {
+        let body = "<html><body><div>手机：<span>139****0000</span></div><div>消息</div></body></html>";
+        let info = parse_account_info(body);
+        assert_eq!(info.phone_masked.as_deref(), Some("139****0000"));
+        assert_eq!(info.balance, None);
+        assert_eq!(info.pending_payment, None);
+        assert_eq!(info.unread_notifications, None);
+        assert_eq!(parse_account_info(""), AccountInfo::default());
+    }
+
+    #[test]
+    fn test_parse_username_greeting_fallback() {
+        let body = "<html><body><div class=\"top\">欢迎您，李四！ <a>退出登录</a></div></body></html>";
+        assert_eq!(parse_username(body).as_deref(), Some("李四"));
+        assert_eq!(parse_username("<html><body>首页</body></html>"), None);
+    }
+
+    const ORDER_SUCCESS_HTML: &str = r#"<html><body>
+        <div class="success-box">
+            <h2>预约成功</h2>
+            <table>
+                <tr><th>订单号：</th><td>YY20240315123456</td></tr>
+                <tr><th>就诊序号：</th><td>12</td></tr>
+                <tr><th>就诊时间：</th><td>2024-03-20 上午 09:30-10:00</td></tr>
+            </table>
+            <p>支付截止时间：2024-03-15 12:30:00</p>
+        </div>
+    </body></html>"#;
+
+    #[test]
+    fn test_parse_order_confirmation() {
+        let confirmation = parse_order_confirmation(ORDER_SUCCESS_HTML).unwrap();
+        assert_eq!(confirmation.order_no, "YY20240315123456");
+        assert_eq!(confirmation.queue_no, "12");
+        assert_eq!(confirmation.visit_time, "2024-03-20 上午 09:30-10:00");
+        assert_eq!(confirmation.payment_deadline, "2024-03-15 12:30:00");
+    }
+
+    fn schedule(id: &str, left: &[i32]) -> DoctorSchedule {
+        DoctorSchedule {
+            doctor_id: id.into(),
+            doctor_name: String::new(),
+            reg_fee: String::new(),
+            total_left_num: left.iter().sum(),
+            his_doc_id: String::new(),
+            his_dep_id: String::new(),
+            schedules: left
+                .iter()
+                .enumerate()
+                .map(|(i, n)| ScheduleSlot {
+                    schedule_id: format!("{}-{}", id, i),
+                    time_type: "am".into(),
+                    time_type_desc: String::new(),
+                    left_num: *n,
+                    sch_date: "2024-03-20".into(),
+                    status: String::new(),
+                })
+                .collect(),
+            schedule_id: String::new(),
+            time_type_desc: String::new(),
+            status: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_summarize_availability() {
+        let docs = vec![schedule("1", &[3, 0]), schedule("2", &[0, 0]), schedule("3", &[1, -1])];
+        let day = summarize_availability("2024-03-20", &docs);
+        assert_eq!(day.date, "2024-03-20");
+        assert_eq!(day.total_left, Some(4));
+        assert_eq!(day.doctors_with_slots, Some(2));
+        assert!(day.error.is_none());
+
+        let mut suspended = schedule("4", &[5]);
+        suspended.status = "停诊".into();
+        let mut slot_suspended = schedule("5", &[2, 2]);
+        slot_suspended.schedules[0].status = "stop".into();
+        let day = summarize_availability("2024-03-20", &[suspended, slot_suspended]);
+        assert_eq!((day.total_left, day.doctors_with_slots), (Some(2), Some(1)));
+
+        let empty = summarize_availability("2024-03-21", &[]);
+        assert_eq!((empty.total_left, empty.doctors_with_slots), (Some(0), Some(0)));
+    }
+
+    #[test]
+    fn test_aggregate_department_doctors() {
+        let named = |id: &str, name: &str, fee: &str, left: &[i32]| DoctorSchedule {
+            doctor_name: name.into(),
+            reg_fee: fee.into(),
+            ..schedule(id, left)
+        };
+        let mut stopped = named("3", "王医生", "30", &[4]);
+        stopped.status = "停诊".into();
+        let days = vec![
+            ("2024-03-20".to_string(), vec![named("1", "张医生", "", &[0]), stopped, named("2", "李医生", "50", &[0, 0])]),
+            // 张医生 twice: morning and afternoon entries
+            ("2024-03-21".to_string(), vec![named("1", "张医生", "80", &[2]), named("1", "张医生", "80", &[1, -1])]),
+            ("2024-03-22".to_string(), vec![named("2", "李医生", "50", &[0]), named("3", "王医生", "30", &[5]), named("1", "张医生", "80", &[9])]),
+            ("2024-03-23".to_string(), vec![named("", "", "", &[1]), named("", "赵医生", "20", &[0])]),
+        ];
+
+        let doctors = aggregate_department_doctors(&days);
+        let summary: Vec<(&str, &str, Option<&str>, i32, usize)> = doctors
+            .iter()
+            .map(|d| (d.doctor_name.as_str(), d.reg_fee.as_str(), d.next_available_date.as_deref(), d.left_num, d.schedule_dates.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("张医生", "80", Some("2024-03-21"), 3, 3),
+                ("王医生", "30", Some("2024-03-22"), 5, 2),
+                ("李医生", "50", None, 0, 2),
+                ("赵医生", "20", None, 0, 1),
+            ]
+        );
+        assert_eq!(doctors[0].schedule_dates, ["2024-03-20", "2024-03-21", "2024-03-22"]);
+        assert!(aggregate_department_doctors(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_endpoints_city_base() {
+        let endpoints = Endpoints::default();
+        assert_eq!(endpoints.city_base("sz"), "https://sz.91160.com");
+        assert_eq!(endpoints.city_base(""), "https://www.91160.com");
+
+        let mock = Endpoints::single("http://127.0.0.1:8080/");
+        assert_eq!(mock.city_base("sz"), "http://127.0.0.1:8080");
+        assert_eq!(mock.gate, "http://127.0.0.1:8080");
+    }
+
+    #[test]
+    fn test_is_91160_url() {
+        assert!(is_91160_url("https://www.91160.com/order/success.html?id=1"));
+        assert!(is_91160_url("http://user.91160.com/"));
+        assert!(!is_91160_url("https://91160.com.evil.cn/"));
+        assert!(!is_91160_url("https://evil91160.com/"));
+        assert!(!is_91160_url("file:///etc/passwd"));
+        assert!(!is_91160_url("not a url"));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 20 Mar 2024 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 20 Mar 2024 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after("Wed, 20 Mar 2024 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-5", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_rate_limit_gate() {
+        let mut gate = RateLimitGate::default();
+        let now = Instant::now();
+        assert!(gate.remaining("gate.91160.com", now).is_none());
+
+        gate.block("gate.91160.com", now + Duration::from_secs(10));
+        assert_eq!(gate.remaining("gate.91160.com", now), Some(Duration::from_secs(10)));
+        assert!(gate.remaining("www.91160.com", now).is_none());
+
+        // A shorter hold never shortens an existing one
+        gate.block("gate.91160.com", now + Duration::from_secs(2));
+        assert_eq!(gate.remaining("gate.91160.com", now), Some(Duration::from_secs(10)));
+
+        // Expired holds are dropped
+        assert!(gate.remaining("gate.91160.com", now + Duration::from_secs(11)).is_none());
+        assert!(gate.not_before.is_empty());
+    }
+
+    #[test]
+    fn test_captcha_marker() {
+        let cases = [
+            (r#"<div class="geetest_holder geetest_wind"></div>"#, Some("geetest")),
+            (r#"<div id="nc_1_wrapper" class="nc-container"></div>"#, Some("id=\"nc_1_wrapper\"")),
+            (r#"<span id='nc'></span>"#, Some("id='nc'")),
+            (r#"<script src="https://ssl.captcha.qq.com/TCaptcha.js"></script>"#, Some("TCaptcha")),
+            (r#"<img id="imgCaptcha" src="/captcha.png">"#, Some("id=\"imgCaptcha")),
+            ("<p>请按住滑块，拖动滑块到最右边</p>", Some("拖动滑块")),
+            ("<h3>请完成安全验证后继续</h3>", Some("安全验证")),
+            // Ordinary booking and error pages
+            (include_str!("../../tests/fixtures/ticket_detail.html"), None),
+            (include_str!("../../tests/fixtures/order_error.html"), None),
+            (r#"<div class="nc-item">内科</div><a href="/captcha-help">帮助</a>"#, None),
+        ];
+        for (page, marker) in cases {
+            assert_eq!(captcha_marker(page).as_deref(), marker, "{}", page);
+        }
+    }
+
+    #[test]
+    fn test_detect_challenge_page() {
+        let rate_limited = "<html><head><title>提示</title></head><body>访问过于频繁，请稍后再试</body></html>";
+        assert!(detect_challenge_page("text/html", rate_limited).unwrap().contains("访问过于频繁"));
+
+        let slider = r#"<div id="nc_1_wrapper"></div>"#;
+        assert!(detect_challenge_page("", slider).is_some());
+
+        let unknown = "<html><head><title>Service Unavailable</title></head></html>";
+        assert_eq!(
+            detect_challenge_page("text/html", unknown).unwrap(),
+            "unexpected html response: Service Unavailable"
+        );
+
+        assert!(detect_challenge_page("text/html", r#"{"result_code":"1"}"#).is_none());
+        assert!(detect_challenge_page("application/json", "[]").is_none());
+        assert!(detect_challenge_page("application/json", "not json").is_none());
+    }
+
+    #[test]
+    fn test_parse_order_confirmation_unknown_page() {
+        assert!(parse_order_confirmation("<html><body>ok</body></html>").is_none());
+        assert!(parse_order_confirmation("").is_none());
+    }
+}
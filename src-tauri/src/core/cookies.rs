@@ -3,35 +3,40 @@
 
 use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, NaiveDateTime};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::HeaderValue;
+use url::Url;
 
 use super::errors::{AppError, AppResult};
+use super::fsutil::{backup_path, read_with_backup, write_with_backup};
 use super::paths::cookies_path;
 use super::types::CookieRecord;
 
 /// Load cookies from file
 pub fn load_cookie_file() -> AppResult<Vec<CookieRecord>> {
-    let path = cookies_path()?;
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
+    load_cookie_file_from(&cookies_path()?)
+}
 
-    let data = fs::read_to_string(&path)?;
+/// Load cookies from `path`, recovering from the backup copy if it is corrupt
+pub fn load_cookie_file_from(path: &Path) -> AppResult<Vec<CookieRecord>> {
+    Ok(read_with_backup(path, parse_cookie_data)?.unwrap_or_default())
+}
 
+fn parse_cookie_data(data: &str) -> AppResult<Vec<CookieRecord>> {
     // Try parsing as array first
-    if let Ok(list) = serde_json::from_str::<Vec<CookieRecord>>(&data) {
+    if let Ok(list) = serde_json::from_str::<Vec<CookieRecord>>(data) {
         return Ok(normalize_cookie_records(list));
     }
 
     // Try parsing as dict (legacy format)
-    if let Ok(dict) = serde_json::from_str::<HashMap<String, String>>(&data) {
+    if let Ok(dict) = serde_json::from_str::<HashMap<String, String>>(data) {
         let list: Vec<CookieRecord> = dict
             .into_iter()
-            .map(|(name, value)| CookieRecord {
-                name,
-                value,
-                domain: ".91160.com".into(),
-                path: "/".into(),
-            })
+            .map(|(name, value)| CookieRecord::root(name, value))
             .collect();
         return Ok(normalize_cookie_records(list));
     }
@@ -41,18 +46,34 @@ pub fn load_cookie_file() -> AppResult<Vec<CookieRecord>> {
 
 /// Save cookies to file
 pub fn save_cookie_file(records: &[CookieRecord]) -> AppResult<()> {
+    save_cookie_file_to(&cookies_path()?, records)
+}
+
+/// Save cookies to `path` atomically, keeping a `.bak` copy
+pub fn save_cookie_file_to(path: &Path, records: &[CookieRecord]) -> AppResult<()> {
     let normalized = normalize_cookie_records(records.to_vec());
     if normalized.is_empty() {
         return Err(AppError::ConfigError("No cookies to save".into()));
     }
 
-    let path = cookies_path()?;
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
     let data = serde_json::to_string_pretty(&normalized)?;
-    fs::write(&path, data)?;
+    write_with_backup(path, data.as_bytes())
+}
+
+/// Delete the cookie file (and its backup) of the active profile
+pub fn delete_cookie_file() -> AppResult<()> {
+    delete_cookie_file_at(&cookies_path()?)
+}
+
+/// Delete the cookie file at `path` and its backup; missing files are fine
+pub fn delete_cookie_file_at(path: &Path) -> AppResult<()> {
+    for file in [path.to_path_buf(), backup_path(path)] {
+        match fs::remove_file(&file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
     Ok(())
 }
 
@@ -77,12 +98,168 @@ pub fn normalize_cookie_records(records: Vec<CookieRecord>) -> Vec<CookieRecord>
             record.path,
             record.name
         );
-        unique.insert(key, record);
+        // Of two copies keep the one that lives longer; the later one wins a tie
+        match unique.get(&key) {
+            Some(kept) if kept.expires > record.expires => {}
+            _ => {
+                unique.insert(key, record);
+            }
+        }
     }
 
     unique.into_values().collect()
 }
 
+/// Fold a `Cookie:` header read back from the jar into saved records
+/// Values the server refreshed are updated and new cookies are added on the
+/// 91160 root domain. Returns None when nothing changed.
+pub fn merge_cookie_header(existing: &[CookieRecord], header: &str) -> Option<Vec<CookieRecord>> {
+    let mut merged = existing.to_vec();
+    let mut changed = false;
+
+    for part in header.split(';') {
+        let Some((name, value)) = part.split_once('=') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || value.is_empty() {
+            continue;
+        }
+        match merged.iter_mut().find(|r| r.name == name) {
+            Some(record) if record.value == value => {}
+            Some(record) => {
+                record.value = value.to_string();
+                changed = true;
+            }
+            None => {
+                merged.push(CookieRecord::root(name, value));
+                changed = true;
+            }
+        }
+    }
+
+    changed.then_some(merged)
+}
+
+/// Cookies whose expiry ends the login session
+const SESSION_COOKIES: [&str; 2] = ["access_hash", "PHPSESSID"];
+
+/// Expiry and flags from a `Set-Cookie` header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CookieAttrs {
+    /// Unix seconds; None for session cookies
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+/// Parse the cookie name and attributes of one `Set-Cookie` header
+/// Max-Age takes precedence over Expires as in RFC 6265.
+pub fn parse_set_cookie(header: &str, now: i64) -> Option<(String, CookieAttrs)> {
+    let mut parts = header.split(';');
+    let (name, _) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut attrs = CookieAttrs::default();
+    let mut max_age = None;
+    for part in parts {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        match key.trim().to_ascii_lowercase().as_str() {
+            "expires" => attrs.expires = attrs.expires.or(parse_cookie_date(value.trim())),
+            "max-age" => max_age = value.trim().parse::<i64>().ok(),
+            "secure" => attrs.secure = true,
+            "httponly" => attrs.http_only = true,
+            _ => {}
+        }
+    }
+    if let Some(secs) = max_age {
+        attrs.expires = Some(now + secs);
+    }
+    Some((name.to_string(), attrs))
+}
+
+/// Parse an Expires date, e.g. "Thu, 17 Oct 2026 06:00:00 GMT" or the
+/// dashed "Thu, 17-Oct-2026 06:00:00 GMT" PHP sends
+fn parse_cookie_date(value: &str) -> Option<i64> {
+    let value = value.replace('-', " ");
+    if let Ok(date) = DateTime::parse_from_rfc2822(&value) {
+        return Some(date.timestamp());
+    }
+    let trimmed = value.trim_end_matches("GMT").trim_end_matches("UTC").trim();
+    let without_day = trimmed.split_once(',').map(|(_, rest)| rest.trim()).unwrap_or(trimmed);
+    NaiveDateTime::parse_from_str(without_day, "%d %b %Y %H:%M:%S")
+        .ok()
+        .map(|dt| dt.and_utc().timestamp())
+}
+
+/// Copy recorded attributes onto records with the same name; true when any changed
+pub fn apply_cookie_attrs(records: &mut [CookieRecord], attrs: &HashMap<String, CookieAttrs>) -> bool {
+    let mut changed = false;
+    for record in records.iter_mut() {
+        let Some(a) = attrs.get(&record.name) else {
+            continue;
+        };
+        if record.expires != a.expires || record.secure != a.secure || record.http_only != a.http_only {
+            record.expires = a.expires;
+            record.secure = a.secure;
+            record.http_only = a.http_only;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Soonest expiry among the cookies that carry the login session
+pub fn session_expiry(records: &[CookieRecord]) -> Option<i64> {
+    records
+        .iter()
+        .filter(|r| SESSION_COOKIES.contains(&r.name.as_str()) && !r.value.is_empty())
+        .filter_map(|r| r.expires)
+        .min()
+}
+
+/// Cookie jar that also remembers the attributes of every `Set-Cookie` it stores
+/// reqwest's Jar only hands back `name=value` pairs, so expiry would be lost otherwise.
+#[derive(Debug, Default)]
+pub struct RecordingJar {
+    jar: Jar,
+    attrs: Mutex<HashMap<String, CookieAttrs>>,
+}
+
+impl RecordingJar {
+    /// Attributes seen so far, keyed by cookie name
+    pub fn attrs(&self) -> HashMap<String, CookieAttrs> {
+        self.attrs.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn add_cookie_str(&self, cookie: &str, url: &Url) {
+        self.jar.add_cookie_str(cookie, url);
+    }
+}
+
+impl CookieStore for RecordingJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let headers: Vec<&HeaderValue> = cookie_headers.collect();
+        let now = chrono::Utc::now().timestamp();
+        {
+            let mut attrs = self.attrs.lock().unwrap_or_else(|e| e.into_inner());
+            for header in &headers {
+                if let Some((name, parsed)) = header.to_str().ok().and_then(|h| parse_set_cookie(h, now)) {
+                    attrs.insert(name, parsed);
+                }
+            }
+        }
+        self.jar.set_cookies(&mut headers.into_iter(), url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.jar.cookies(url)
+    }
+}
+
 /// Check if access_hash cookie exists
 pub fn has_access_hash(records: &[CookieRecord]) -> bool {
     records.iter().any(|r| r.name == "access_hash" && !r.value.is_empty())
@@ -116,12 +293,14 @@ mod tests {
                 value: "value1".into(),
                 domain: "".into(),
                 path: "".into(),
+                ..Default::default()
             },
             CookieRecord {
                 name: "test".into(),
                 value: "value2".into(),
                 domain: ".91160.com".into(),
                 path: "/".into(),
+                ..Default::default()
             },
         ];
 
@@ -130,6 +309,82 @@ mod tests {
         assert_eq!(normalized[0].domain, ".91160.com");
     }
 
+    #[test]
+    fn test_normalize_keeps_later_expiry() {
+        let record = |value: &str, expires: Option<i64>| CookieRecord {
+            expires,
+            ..CookieRecord::root("access_hash", value)
+        };
+
+        let normalized = normalize_cookie_records(vec![record("new", Some(2_000)), record("old", Some(1_000))]);
+        assert_eq!(normalized.len(), 1);
+        assert_eq!(normalized[0].value, "new");
+
+        let normalized = normalize_cookie_records(vec![record("session", None), record("persisted", Some(1_000))]);
+        assert_eq!(normalized[0].value, "persisted");
+
+        // Equal expiry: the later record wins as before
+        let normalized = normalize_cookie_records(vec![record("first", None), record("second", None)]);
+        assert_eq!(normalized[0].value, "second");
+    }
+
+    #[test]
+    fn test_cookie_record_serde_compat() {
+        let old: Vec<CookieRecord> =
+            serde_json::from_str(r#"[{"name":"access_hash","value":"abc","domain":".91160.com","path":"/"}]"#).unwrap();
+        assert_eq!(old[0].expires, None);
+        assert!(!old[0].secure && !old[0].http_only);
+
+        let record = CookieRecord {
+            expires: Some(1_792_303_200),
+            http_only: true,
+            ..CookieRecord::root("access_hash", "abc")
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["expires"], 1_792_303_200);
+        let back: CookieRecord = serde_json::from_value(json).unwrap();
+        assert_eq!(back.expires, Some(1_792_303_200));
+        assert!(back.http_only);
+    }
+
+    #[test]
+    fn test_parse_set_cookie_attributes() {
+        let now = 1_700_000_000;
+        let (name, attrs) =
+            parse_set_cookie("access_hash=abc; expires=Thu, 17-Oct-2026 06:00:00 GMT; path=/; domain=.91160.com; HttpOnly", now)
+                .unwrap();
+        assert_eq!(name, "access_hash");
+        assert_eq!(attrs.expires, Some(1_792_216_800));
+        assert!(attrs.http_only);
+        assert!(!attrs.secure);
+
+        let (_, attrs) = parse_set_cookie("sid=1; Expires=Thu, 17 Oct 2026 06:00:00 GMT; Max-Age=60; Secure", now).unwrap();
+        assert_eq!(attrs.expires, Some(now + 60));
+        assert!(attrs.secure);
+
+        assert_eq!(parse_set_cookie("sid=1", now).unwrap().1, CookieAttrs::default());
+        assert!(parse_set_cookie("garbage", now).is_none());
+    }
+
+    #[test]
+    fn test_session_expiry_uses_soonest_auth_cookie() {
+        let mut records = vec![
+            CookieRecord::root("access_hash", "abc"),
+            CookieRecord::root("PHPSESSID", "s1"),
+            CookieRecord::root("tracking", "t"),
+        ];
+        assert_eq!(session_expiry(&records), None);
+
+        let attrs = HashMap::from([
+            ("access_hash".to_string(), CookieAttrs { expires: Some(3_000), ..Default::default() }),
+            ("PHPSESSID".to_string(), CookieAttrs { expires: Some(2_000), ..Default::default() }),
+            ("tracking".to_string(), CookieAttrs { expires: Some(1_000), ..Default::default() }),
+        ]);
+        assert!(apply_cookie_attrs(&mut records, &attrs));
+        assert!(!apply_cookie_attrs(&mut records, &attrs));
+        assert_eq!(session_expiry(&records), Some(2_000));
+    }
+
     #[test]
     fn test_has_access_hash() {
         let records = vec![CookieRecord {
@@ -137,7 +392,94 @@ mod tests {
             value: "abc123".into(),
             domain: ".91160.com".into(),
             path: "/".into(),
+            ..Default::default()
         }];
         assert!(has_access_hash(&records));
     }
+
+    #[test]
+    fn test_cookie_file_recovers_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+        let records = vec![CookieRecord {
+            name: "access_hash".into(),
+            value: "abc123".into(),
+            domain: ".91160.com".into(),
+            path: "/".into(),
+            ..Default::default()
+        }];
+
+        save_cookie_file_to(&path, &records).unwrap();
+        assert!(backup_path(&path).exists());
+
+        // Simulate a write cut off by a crash
+        let full = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, &full[..full.len() / 2]).unwrap();
+
+        let loaded = load_cookie_file_from(&path).unwrap();
+        assert!(has_access_hash(&loaded));
+    }
+
+    #[test]
+    fn test_cookie_file_corrupt_without_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+
+        assert!(load_cookie_file_from(&path).unwrap().is_empty());
+
+        std::fs::write(&path, "[{\"name\":").unwrap();
+        assert!(matches!(load_cookie_file_from(&path), Err(AppError::ParseError(_))));
+    }
+
+    #[test]
+    fn test_delete_cookie_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+        let records = vec![CookieRecord {
+            name: "access_hash".into(),
+            value: "abc123".into(),
+            domain: ".91160.com".into(),
+            path: "/".into(),
+            ..Default::default()
+        }];
+        save_cookie_file_to(&path, &records).unwrap();
+
+        delete_cookie_file_at(&path).unwrap();
+        assert!(!path.exists());
+        assert!(!backup_path(&path).exists());
+        assert!(load_cookie_file_from(&path).unwrap().is_empty());
+
+        // Logging out twice is not an error
+        delete_cookie_file_at(&path).unwrap();
+    }
+
+    #[test]
+    fn test_merge_cookie_header() {
+        let existing = vec![
+            CookieRecord {
+                name: "access_hash".into(),
+                value: "old".into(),
+                domain: ".91160.com".into(),
+                path: "/".into(),
+                ..Default::default()
+            },
+            CookieRecord {
+                name: "kept".into(),
+                value: "1".into(),
+                domain: "user.91160.com".into(),
+                path: "/".into(),
+                ..Default::default()
+            },
+        ];
+
+        assert!(merge_cookie_header(&existing, "access_hash=old; kept=1").is_none());
+        assert!(merge_cookie_header(&existing, "").is_none());
+
+        let merged = merge_cookie_header(&existing, "access_hash=new; kept=1; sid=s1; junk").unwrap();
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].value, "new");
+        assert_eq!(merged[1].domain, "user.91160.com");
+        assert_eq!(merged[2].name, "sid");
+        assert_eq!(merged[2].domain, ".91160.com");
+    }
 }
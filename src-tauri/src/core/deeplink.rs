@@ -0,0 +1,171 @@
+//! Shared booking links for QuickDoctor
+//! Derives hospital and department ids from a 91160 booking URL
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::client::is_91160_url;
+use super::errors::{AppError, AppResult};
+use super::types::GrabConfig;
+
+/// 91160 subdomains that are not city sites
+const NON_CITY_HOSTS: [&str; 10] = ["www", "m", "wap", "h5", "user", "gate", "wx", "weixin", "api", "static"];
+
+/// Ids found in a booking link
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BookingLink {
+    pub unit_id: String,
+    /// Empty for a hospital page link
+    pub dep_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule_id: Option<String>,
+    /// City site subdomain the link points at, empty for www
+    pub city_pinyin: String,
+}
+
+/// A link with the grab config derived from it, for the UI to confirm
+#[derive(Debug, Clone, Serialize)]
+pub struct BookingLinkConfig {
+    pub link: BookingLink,
+    pub config: GrabConfig,
+}
+
+/// Parse a 91160 link: `uid-`/`depid-`/`schid-` path segments (booking and
+/// hospital pages) or `unit_id`/`dep_id`/`schedule_id` query parameters
+/// (gateway links). A missing scheme is read as https.
+pub fn parse_booking_url(raw: &str) -> AppResult<BookingLink> {
+    let raw = raw.trim();
+    let url = Url::parse(raw)
+        .or_else(|_| Url::parse(&format!("https://{}", raw)))
+        .map_err(|_| AppError::ConfigError(format!("not a link: {}", raw)))?;
+    if !is_91160_url(url.as_str()) {
+        return Err(AppError::ConfigError(format!("not a 91160 link: {}", raw)));
+    }
+
+    let mut link = BookingLink {
+        city_pinyin: city_subdomain(url.host_str().unwrap_or_default()),
+        ..BookingLink::default()
+    };
+    for segment in url.path_segments().into_iter().flatten() {
+        let segment = segment.trim_end_matches(".html").trim_end_matches(".htm");
+        let Some((key, value)) = segment.split_once('-') else { continue };
+        if !is_id(value) {
+            continue;
+        }
+        match key {
+            "uid" => link.unit_id = value.to_string(),
+            "depid" => link.dep_id = value.to_string(),
+            "schid" => link.schedule_id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    for (key, value) in url.query_pairs() {
+        let value = value.trim();
+        if !is_id(value) {
+            continue;
+        }
+        match key.as_ref() {
+            "unit_id" | "unitid" if link.unit_id.is_empty() => link.unit_id = value.to_string(),
+            "dep_id" | "depid" if link.dep_id.is_empty() => link.dep_id = value.to_string(),
+            "schedule_id" | "sch_id" | "schid" if link.schedule_id.is_none() => link.schedule_id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if link.unit_id.is_empty() {
+        return Err(AppError::ConfigError(format!("no hospital id in link: {}", raw)));
+    }
+    Ok(link)
+}
+
+fn is_id(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// "sz" for sz.91160.com; empty for www and the other service hosts
+fn city_subdomain(host: &str) -> String {
+    let host = host.to_lowercase();
+    match host.strip_suffix(".91160.com") {
+        Some(sub) if !sub.contains('.') && !NON_CITY_HOSTS.contains(&sub) => sub.to_string(),
+        _ => String::new(),
+    }
+}
+
+impl BookingLink {
+    /// Grab config with the link's ids and everything else at its defaults
+    pub fn to_config(&self) -> AppResult<GrabConfig> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "unit_id": self.unit_id,
+            "dep_id": self.dep_id,
+            "city_pinyin": self.city_pinyin,
+            "member_id": "",
+        }))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(unit_id: &str, dep_id: &str, schedule_id: Option<&str>, city_pinyin: &str) -> BookingLink {
+        BookingLink {
+            unit_id: unit_id.into(),
+            dep_id: dep_id.into(),
+            schedule_id: schedule_id.map(str::to_string),
+            city_pinyin: city_pinyin.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse_booking_url_shapes() {
+        let cases = [
+            ("https://www.91160.com/guahao/ystep1/uid-75/depid-1234.html", link("75", "1234", None, "")),
+            ("https://sz.91160.com/guahao/ystep1/uid-75/depid-1234.html", link("75", "1234", None, "sz")),
+            ("https://www.91160.com/guahao/ystep1/uid-75/depid-1234/schid-98765.html", link("75", "1234", Some("98765"), "")),
+            ("https://gz.91160.com/guahao/ystep1/uid-75/depid-1234/schid-a1b2c3.html", link("75", "1234", Some("a1b2c3"), "gz")),
+            ("https://www.91160.com/guahao/ystep1/uid-75/depid-1234/", link("75", "1234", None, "")),
+            ("https://www.91160.com/guahao/ystep1/uid-75/depid-1234.html/", link("75", "1234", None, "")),
+            (
+                "https://www.91160.com/guahao/ystep1/uid-75/depid-1234.html?from=timeline&isappinstalled=0#wechat_redirect",
+                link("75", "1234", None, ""),
+            ),
+            ("  www.91160.com/guahao/ystep1/uid-75/depid-1234.html  ", link("75", "1234", None, "")),
+            ("HTTP://SZ.91160.COM/guahao/ystep1/uid-75/depid-1234.html", link("75", "1234", None, "sz")),
+            ("https://m.91160.com/guahao/ystep1/uid-75/depid-1234.html", link("75", "1234", None, "")),
+            ("https://www.91160.com/unit/show/uid-75.html", link("75", "", None, "")),
+            (
+                "https://gate.91160.com/guahao/v1/pc/sch/dep?unit_id=75&dep_id=1234&date=2024-03-20&p=0",
+                link("75", "1234", None, ""),
+            ),
+            ("https://www.91160.com/guahao/ystep1/uid-75/depid-1234.html?dep_id=999", link("75", "1234", None, "")),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(parse_booking_url(raw).unwrap(), expected, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_parse_booking_url_rejects_other_links() {
+        for raw in [
+            "https://www.91160.com.evil.cn/guahao/ystep1/uid-75/depid-1234.html",
+            "https://evil.com/guahao/ystep1/uid-75/depid-1234.html?next=www.91160.com",
+            "ftp://www.91160.com/guahao/ystep1/uid-75/depid-1234.html",
+            "https://www.91160.com/doctors/index/docid-100.html",
+            "https://www.91160.com/guahao/ystep1/uid-/depid-1234.html",
+            "",
+            "挂号链接",
+        ] {
+            assert!(matches!(parse_booking_url(raw), Err(AppError::ConfigError(_))), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_link_to_config() {
+        let config = link("75", "1234", Some("98765"), "sz").to_config().unwrap();
+        assert_eq!(config.unit_id, "75");
+        assert_eq!(config.dep_id, "1234");
+        assert_eq!(config.city_pinyin, "sz");
+        assert!(config.member_id.is_empty() && config.target_dates.is_empty());
+        assert!(config.proxy_fallback_direct);
+    }
+}
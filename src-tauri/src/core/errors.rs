@@ -1,8 +1,11 @@
 //! Error types for QuickDoctor
 //! Corresponds to core/errors.go
 
+use serde::ser::SerializeStruct;
 use thiserror::Error;
 
+use super::textutil::truncate_utf8;
+
 /// Application error types
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -24,8 +27,17 @@ pub enum AppError {
     #[error("Parse error: {0}")]
     ParseError(String),
 
-    #[error("API error: {0}")]
-    ApiError(String),
+    #[error("API error ({endpoint}): {message}{}", api_context(.status, .code))]
+    Api {
+        /// Short name of the failing call, e.g. "schedule" or "deps"
+        endpoint: String,
+        status: Option<u16>,
+        /// Error code from the response payload
+        code: Option<String>,
+        message: String,
+        /// Start of the response body
+        body_snippet: Option<String>,
+    },
 
     #[allow(dead_code)]
     #[error("Timeout: {0}")]
@@ -34,14 +46,50 @@ pub enum AppError {
     #[error("Cancelled")]
     Cancelled,
 
+    #[error("Blocked by WAF: {0}")]
+    Blocked(String),
+
+    #[error("Rate limited, retry after {retry_after_ms}ms")]
+    RateLimited { retry_after_ms: u64 },
+
+    /// URL of the page showing the captcha
+    #[error("Captcha required: {0}")]
+    CaptchaRequired(String),
+
     #[allow(dead_code)]
     #[error("Proxy error: {0}")]
     ProxyError(String),
 
+    #[error("Another instance is running (pid {pid})")]
+    AlreadyRunning { pid: u32 },
+
     #[error("{0}")]
     Other(String),
 }
 
+/// Bytes of a response body kept in `AppError::Api`
+pub const BODY_SNIPPET_BYTES: usize = 200;
+
+/// " [HTTP 502, code=10001]" style suffix; empty when neither is known
+fn api_context(status: &Option<u16>, code: &Option<String>) -> String {
+    let parts: Vec<String> = status
+        .map(|s| format!("HTTP {}", s))
+        .into_iter()
+        .chain(code.as_ref().map(|c| format!("code={}", c)))
+        .collect();
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", parts.join(", "))
+    }
+}
+
+/// Start of a body with whitespace squashed, cut at `BODY_SNIPPET_BYTES`
+pub fn body_snippet(body: &str) -> Option<String> {
+    let squashed = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!squashed.is_empty()).then(|| truncate_utf8(&squashed, BODY_SNIPPET_BYTES))
+}
+
 impl From<String> for AppError {
     fn from(s: String) -> Self {
         AppError::Other(s)
@@ -56,6 +104,17 @@ impl From<&str> for AppError {
 
 /// Convert AppError to a user-friendly string for frontend
 impl AppError {
+    /// API failure known only by endpoint and message
+    pub fn api(endpoint: &str, message: impl Into<String>) -> Self {
+        AppError::Api {
+            endpoint: endpoint.to_string(),
+            status: None,
+            code: None,
+            message: message.into(),
+            body_snippet: None,
+        }
+    }
+
     pub fn to_frontend_string(&self) -> String {
         match self {
             AppError::LoginRequired(_) => "登录已失效，请重新扫码".to_string(),
@@ -64,24 +123,170 @@ impl AppError {
             AppError::IoError(e) => format!("文件操作失败: {}", e),
             AppError::ConfigError(msg) => format!("配置错误: {}", msg),
             AppError::ParseError(msg) => format!("解析错误: {}", msg),
-            AppError::ApiError(msg) => format!("API 错误: {}", msg),
+            AppError::Api { endpoint, status, code, message, .. } => {
+                format!("API 错误: {} ({}){}", message, endpoint, api_context(status, code))
+            }
             AppError::Timeout(msg) => format!("超时: {}", msg),
             AppError::Cancelled => "操作已取消".to_string(),
+            AppError::Blocked(_) => "被风控拦截，请稍后重试或更换网络".to_string(),
+            AppError::RateLimited { retry_after_ms } => {
+                format!("请求过于频繁，请 {} 秒后重试", retry_after_ms.div_ceil(1000))
+            }
+            AppError::CaptchaRequired(_) => "触发验证码，请在浏览器中打开挂号页完成验证".to_string(),
             AppError::ProxyError(msg) => format!("代理错误: {}", msg),
+            AppError::AlreadyRunning { pid } => format!("程序已在运行 (PID {})，请勿重复打开", pid),
             AppError::Other(msg) => msg.clone(),
         }
     }
+
+    /// Stable machine-readable code for the frontend
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::LoginRequired(_) => "LOGIN_REQUIRED",
+            AppError::HttpError(_) => "HTTP",
+            AppError::JsonError(_) => "JSON",
+            AppError::IoError(_) => "IO",
+            AppError::ConfigError(_) => "CONFIG",
+            AppError::ParseError(_) => "PARSE",
+            AppError::Api { .. } => "API",
+            AppError::Timeout(_) => "TIMEOUT",
+            AppError::Cancelled => "CANCELLED",
+            AppError::Blocked(_) => "BLOCKED",
+            AppError::RateLimited { .. } => "RATE_LIMITED",
+            AppError::CaptchaRequired(_) => "CAPTCHA_REQUIRED",
+            AppError::ProxyError(_) => "PROXY",
+            AppError::AlreadyRunning { .. } => "ALREADY_RUNNING",
+            AppError::Other(_) => "OTHER",
+        }
+    }
+
+    /// Whether repeating the same operation later may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            AppError::HttpError(_)
+                | AppError::Api { .. }
+                | AppError::Timeout(_)
+                | AppError::Blocked(_)
+                | AppError::RateLimited { .. }
+                | AppError::CaptchaRequired(_)
+                | AppError::ProxyError(_)
+        )
+    }
 }
 
 /// Result type alias for the application
 pub type AppResult<T> = Result<T, AppError>;
 
-/// Serialize error for Tauri commands
+/// Serialize error for Tauri commands as `{ code, message, retryable }`,
+/// plus `detail` with every field of an API failure
 impl serde::Serialize for AppError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&self.to_frontend_string())
+        let detail = match self {
+            AppError::Api {
+                endpoint,
+                status,
+                code,
+                message,
+                body_snippet,
+            } => Some(serde_json::json!({
+                "endpoint": endpoint,
+                "status": status,
+                "code": code,
+                "message": message,
+                "body_snippet": body_snippet,
+            })),
+            _ => None,
+        };
+        let mut state = serializer.serialize_struct("AppError", if detail.is_some() { 4 } else { 3 })?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_frontend_string())?;
+        state.serialize_field("retryable", &self.is_retryable())?;
+        if let Some(detail) = detail {
+            state.serialize_field("detail", &detail)?;
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(err: AppError) -> serde_json::Value {
+        serde_json::to_value(err).unwrap()
+    }
+
+    #[test]
+    fn test_serialize_every_variant() {
+        let http = reqwest::Client::new().get("not a url").build().unwrap_err();
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+
+        let cases = vec![
+            (AppError::LoginRequired("x".into()), "LOGIN_REQUIRED", false),
+            (AppError::HttpError(http), "HTTP", true),
+            (AppError::JsonError(json), "JSON", false),
+            (AppError::IoError(io), "IO", false),
+            (AppError::ConfigError("x".into()), "CONFIG", false),
+            (AppError::ParseError("x".into()), "PARSE", false),
+            (AppError::api("schedule", "x"), "API", true),
+            (AppError::Timeout("x".into()), "TIMEOUT", true),
+            (AppError::Cancelled, "CANCELLED", false),
+            (AppError::Blocked("x".into()), "BLOCKED", true),
+            (AppError::RateLimited { retry_after_ms: 1500 }, "RATE_LIMITED", true),
+            (AppError::CaptchaRequired("x".into()), "CAPTCHA_REQUIRED", true),
+            (AppError::ProxyError("x".into()), "PROXY", true),
+            (AppError::AlreadyRunning { pid: 42 }, "ALREADY_RUNNING", false),
+            (AppError::Other("x".into()), "OTHER", false),
+        ];
+
+        for (err, code, retryable) in cases {
+            let message = err.to_frontend_string();
+            let value = payload(err);
+            assert_eq!(value["code"], code);
+            assert_eq!(value["retryable"], retryable, "{}", code);
+            assert_eq!(value["message"], message.as_str());
+            let fields = if code == "API" { 4 } else { 3 };
+            assert_eq!(value.as_object().unwrap().len(), fields);
+        }
+    }
+
+    #[test]
+    fn test_api_error_rendering() {
+        let err = AppError::Api {
+            endpoint: "schedule".into(),
+            status: Some(502),
+            code: Some("10001".into()),
+            message: "系统繁忙".into(),
+            body_snippet: body_snippet("<html>\n  <body>Bad   Gateway</body>\n</html>"),
+        };
+        assert_eq!(err.to_string(), "API error (schedule): 系统繁忙 [HTTP 502, code=10001]");
+        assert_eq!(err.to_frontend_string(), "API 错误: 系统繁忙 (schedule) [HTTP 502, code=10001]");
+
+        let value = payload(err);
+        assert_eq!(value["detail"]["endpoint"], "schedule");
+        assert_eq!(value["detail"]["status"], 502);
+        assert_eq!(value["detail"]["code"], "10001");
+        assert_eq!(value["detail"]["body_snippet"], "<html> <body>Bad Gateway</body> </html>");
+
+        let bare = AppError::api("deps", "parse failed");
+        assert_eq!(bare.to_frontend_string(), "API 错误: parse failed (deps)");
+        assert!(payload(bare)["detail"]["status"].is_null());
+
+        assert_eq!(body_snippet("  \n "), None);
+        let snippet = body_snippet(&"字".repeat(500)).unwrap();
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.len() <= BODY_SNIPPET_BYTES + '…'.len_utf8());
+    }
+
+    #[test]
+    fn test_serialize_messages() {
+        assert_eq!(payload(AppError::LoginRequired("x".into()))["message"], "登录已失效，请重新扫码");
+        assert_eq!(payload(AppError::RateLimited { retry_after_ms: 1500 })["message"], "请求过于频繁，请 2 秒后重试");
+        assert_eq!(payload(AppError::Other("自定义".into()))["message"], "自定义");
     }
 }
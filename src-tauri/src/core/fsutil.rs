@@ -0,0 +1,155 @@
+//! Crash-safe file helpers for SkylineMed
+//! Config files are written to a temp file, fsynced and renamed into place so
+//! a crash mid-write never leaves a truncated file; each save also refreshes a
+//! `.bak` copy that loads fall back to when the primary file is unreadable
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::errors::AppResult;
+
+/// Path of the backup copy kept next to `path`, e.g. cookies.json.bak
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+/// Atomically replace `path` with `data`
+pub fn write_atomic(path: &Path, data: &[u8]) -> AppResult<()> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)?;
+
+    let tmp = with_suffix(path, ".tmp");
+    let result = (|| -> AppResult<()> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+
+    sync_dir(parent);
+    Ok(())
+}
+
+/// Atomically write `path`, then refresh its `.bak` copy
+pub fn write_with_backup(path: &Path, data: &[u8]) -> AppResult<()> {
+    write_atomic(path, data)?;
+    write_atomic(&backup_path(path), data)
+}
+
+/// Read and parse `path`, falling back to its `.bak` copy when the primary
+/// file cannot be read or parsed. Returns `Ok(None)` if the primary file does
+/// not exist; the error from the primary file wins if both copies are bad.
+pub fn read_with_backup<T, F>(path: &Path, parse: F) -> AppResult<Option<T>>
+where
+    F: Fn(&str) -> AppResult<T>,
+{
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let primary = fs::read_to_string(path)
+        .map_err(Into::into)
+        .and_then(|data| parse(&data));
+    let err = match primary {
+        Ok(value) => return Ok(Some(value)),
+        Err(e) => e,
+    };
+
+    let backup = backup_path(path);
+    match fs::read_to_string(&backup)
+        .map_err(Into::into)
+        .and_then(|data| parse(&data))
+    {
+        Ok(value) => {
+            tracing::warn!(
+                path = %path.display(),
+                error = %err,
+                "primary file unreadable, recovered from backup"
+            );
+            Ok(Some(value))
+        }
+        Err(_) => Err(err),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Persist the rename itself; directories cannot be opened for sync on Windows
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Ok(handle) = File::open(dir) {
+        let _ = handle.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::errors::AppError;
+
+    fn parse_number(data: &str) -> AppResult<u32> {
+        data.trim()
+            .parse()
+            .map_err(|_| AppError::ParseError(format!("not a number: {:?}", data)))
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_file_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+
+        write_atomic(&path, b"1").unwrap();
+        write_atomic(&path, b"2").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "2");
+        assert!(!with_suffix(&path, ".tmp").exists());
+    }
+
+    #[test]
+    fn test_write_with_backup_refreshes_bak() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+
+        write_with_backup(&path, b"7").unwrap();
+
+        assert_eq!(backup_path(&path), dir.path().join("cookies.json.bak"));
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "7");
+    }
+
+    #[test]
+    fn test_read_with_backup_recovers_from_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        assert_eq!(read_with_backup(&path, parse_number).unwrap(), None);
+
+        write_with_backup(&path, b"42").unwrap();
+        fs::write(&path, "4").unwrap();
+        assert_eq!(read_with_backup(&path, parse_number).unwrap(), Some(4));
+
+        fs::write(&path, "{trunc").unwrap();
+        assert_eq!(read_with_backup(&path, parse_number).unwrap(), Some(42));
+
+        fs::write(backup_path(&path), "also bad").unwrap();
+        let err = read_with_backup(&path, parse_number).unwrap_err();
+        assert!(err.to_string().contains("{trunc"));
+    }
+}
@@ -1,39 +1,408 @@
 //! Grabber engine for QuickDoctor
 //! Corresponds to core/grabber.go - appointment grabbing logic
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use chrono::Local;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
 use rand::Rng;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use tokio_util::sync::CancellationToken;
 
+use super::api::ScheduleApi;
 use super::client::HealthClient;
 use super::errors::{AppError, AppResult};
-use super::proxy::ProxyPool;
-use super::types::{GrabConfig, GrabResult, GrabSuccess, TicketDetail, TimeSlot};
+use super::proxy::{mask_proxy_url, ProbeConfig, ProxyPool, ProxySource, RotationStrategy};
+use super::tasks::{CaptchaGate, CaptchaWait, GrabStatus};
+use super::timesync;
+use super::types::{parse_clock_time, parse_slot_range, AddressRecord, Appointment, Department, DepartmentCategory, DoctorSchedule, FlatDepartment, GrabConfig, GrabResult, GrabSuccess, StartTimePolicy, MIN_BURST_INTERVAL_MS, SubmitOrderParams, TicketDetail, TicketPageKind, TimeSlot};
 
 const DATE_QUERY_JITTER_MAX_MS: u64 = 40;
 const SUBMIT_MIN_INTERVAL_MS: u64 = 1800;
 const SUBMIT_BACKOFF_MIN_MS: u64 = 2500;
 const SUBMIT_BACKOFF_MAX_MS: u64 = 4200;
+const BLOCKED_BACKOFF_MIN_MS: u64 = 8000;
+const BLOCKED_BACKOFF_MAX_MS: u64 = 15000;
+/// How long a grab stays paused for the user to solve a captcha
+const CAPTCHA_TIMEOUT: Duration = Duration::from_secs(600);
+const TIME_RESYNC_POINTS_SECS: [i64; 2] = [60, 5];
+const PREWARM_LEAD_SECS: i64 = 10;
+const PREWARM_INTERVAL_SECS: u64 = 3;
+const DAILY_WAKE_LEAD_SECS: i64 = 120;
+const EMPTY_SCHEDULE_HEARTBEAT: u32 = 20;
+/// Extra detail fetch + submit rounds when the chosen detlid was just taken
+const MAX_DETLID_REFETCHES: u32 = 2;
+/// Countdown event cadence while waiting for start_time, and the faster
+/// cadence used inside the final window
+const COUNTDOWN_INTERVAL_MS: u64 = 1000;
+const COUNTDOWN_FAST_INTERVAL_MS: u64 = 100;
+const COUNTDOWN_FAST_WINDOW_MS: i64 = 3000;
+/// Final-wait phases: 10 ms sleeps until FINE_WAIT_MS remain, then 1 ms
+/// sleeps, and only a busy yield for the last SPIN_WAIT_MS
+const COARSE_WAIT_STEP_MS: u64 = 10;
+const FINE_WAIT_MS: i64 = 50;
+const SPIN_WAIT_MS: i64 = 2;
+/// Server offsets beyond this are treated as bogus and clamped
+const MAX_SERVER_OFFSET_SECS: i64 = 300;
+
+/// How a submit goes out once a slot is picked
+#[derive(Debug, Clone, PartialEq)]
+enum SubmitRoute {
+    Proxy(String),
+    Direct,
+    /// No proxy and direct fallback disabled
+    Skip,
+}
 
 /// Appointment grabber
-pub struct Grabber {
-    client: Arc<HealthClient>,
+pub struct Grabber<C = HealthClient> {
+    client: Arc<C>,
     proxy_pool: Arc<ProxyPool>,
     last_submit_at: RwLock<Option<std::time::Instant>>,
+    exhausted: RwLock<ExhaustedSlots>,
+    schedule_log: RwLock<EmptyScheduleLog>,
+    /// Address book fetched when neither config nor page has an address
+    address_book: RwLock<Option<Vec<AddressRecord>>>,
+    /// Order list fetched for the existing-appointment precheck
+    appointments: RwLock<Option<Vec<Appointment>>>,
+    /// Set while a submit request is out; a timed-out attempt waits for it
+    submit_in_flight: AtomicBool,
+    /// Schedule queries sent during the current run
+    schedule_queries: AtomicU32,
+    /// Attempt counter published for the tray
+    status: Arc<GrabStatus>,
+    /// Released by the app when the user has solved a captcha
+    captcha_gate: Arc<CaptchaGate>,
+    events: Option<mpsc::UnboundedSender<GrabEvent>>,
+}
+
+/// Per-date "no schedule" log state so pre-release polling doesn't flood the log
+#[derive(Debug, Default)]
+struct EmptyScheduleLog {
+    empty_streaks: HashMap<String, u32>,
+}
+
+impl EmptyScheduleLog {
+    /// Forget everything (called at the start of each run)
+    fn reset(&mut self) {
+        self.empty_streaks.clear();
+    }
+
+    /// Record an empty schedule for a date. Warns on the first empty result
+    /// (or the first after slots were seen), then only emits a debug
+    /// heartbeat every EMPTY_SCHEDULE_HEARTBEAT attempts.
+    fn on_empty(&mut self, date: &str) -> Option<(&'static str, String)> {
+        let streak = self.empty_streaks.entry(date.to_string()).or_insert(0);
+        *streak += 1;
+        if *streak == 1 {
+            Some(("warn", format!("no schedule on {}", date)))
+        } else if streak.is_multiple_of(EMPTY_SCHEDULE_HEARTBEAT) {
+            Some(("debug", format!("still no schedule on {} ({} attempts)", date, streak)))
+        } else {
+            None
+        }
+    }
+
+    /// Record that a date returned doctors again
+    fn on_available(&mut self, date: &str) {
+        self.empty_streaks.remove(date);
+    }
+}
+
+/// Named event forwarded to the frontend alongside log messages
+#[derive(Debug, Clone)]
+pub struct GrabEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+/// What the final wait does next, given the time left to the trigger
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WaitStep {
+    Sleep(Duration),
+    Spin,
+    Done,
+}
+
+fn final_wait_step(remaining: chrono::Duration) -> WaitStep {
+    let ms = remaining.num_milliseconds();
+    if remaining <= chrono::Duration::zero() {
+        WaitStep::Done
+    } else if ms > FINE_WAIT_MS {
+        // Never sleep past the start of the 1 ms phase
+        WaitStep::Sleep(Duration::from_millis((ms - FINE_WAIT_MS).clamp(1, COARSE_WAIT_STEP_MS as i64) as u64))
+    } else if ms > SPIN_WAIT_MS {
+        WaitStep::Sleep(Duration::from_millis(1))
+    } else {
+        WaitStep::Spin
+    }
+}
+
+/// Clamp a server clock offset to ±MAX_SERVER_OFFSET_SECS; true when it was clamped
+fn clamp_server_offset(offset: chrono::Duration) -> (chrono::Duration, bool) {
+    let max = chrono::Duration::seconds(MAX_SERVER_OFFSET_SECS);
+    if offset > max {
+        (max, true)
+    } else if offset < -max {
+        (-max, true)
+    } else {
+        (offset, false)
+    }
+}
+
+/// Time-type filter built once per attempt cycle; doctors depend on the
+/// date, see `order_doctors`
+struct SlotFilter {
+    time_types: HashSet<String>,
+}
+
+impl SlotFilter {
+    fn from_config(config: &GrabConfig) -> Self {
+        let time_types = if config.time_types.is_empty() {
+            vec!["am".into(), "pm".into()].into_iter().collect()
+        } else {
+            config.time_types.iter().cloned().collect()
+        };
+        Self { time_types }
+    }
+}
+
+/// `docs` in the order of `doctors` (best first), dropping doctors not
+/// listed; an empty list keeps every doctor in page order
+fn order_doctors<'a>(docs: &'a [DoctorSchedule], doctors: &[String]) -> Vec<&'a DoctorSchedule> {
+    if doctors.is_empty() {
+        return docs.iter().collect();
+    }
+    let mut ranked: Vec<(usize, &DoctorSchedule)> = docs
+        .iter()
+        .filter_map(|doc| doctors.iter().position(|id| *id == doc.doctor_id).map(|rank| (rank, doc)))
+        .collect();
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked.into_iter().map(|(_, doc)| doc).collect()
+}
+
+/// One department a run cycles through on each date
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrabDepartment {
+    pub id: String,
+    /// Display name; the id when the name is unknown
+    pub name: String,
+}
+
+/// One schedule query of an attempt: a date in a department
+struct DateQuery<'a> {
+    date: &'a str,
+    dep: &'a GrabDepartment,
+    /// Several departments are running, so logs name the department
+    show_dep: bool,
+}
+
+impl<'a> DateQuery<'a> {
+    /// One cycle of queries: every department on the first date, then the next date
+    fn cycle(dates: &'a [String], departments: &'a [GrabDepartment]) -> Vec<Self> {
+        let show_dep = departments.len() > 1;
+        dates
+            .iter()
+            .flat_map(|date| departments.iter().map(move |dep| DateQuery { date, dep, show_dep }))
+            .collect()
+    }
+
+    /// Date, plus the department when several run
+    fn label(&self) -> String {
+        if self.show_dep {
+            format!("{} / {}", self.date, self.dep.name)
+        } else {
+            self.date.to_string()
+        }
+    }
+}
+
+/// Departments of the category matched by id or name, in page order
+pub fn category_departments(categories: &[DepartmentCategory], category_id: &str) -> Vec<FlatDepartment> {
+    let category_id = category_id.trim();
+    categories
+        .iter()
+        .find(|c| c.id.as_deref().map(str::trim) == Some(category_id) || c.pubcat.trim() == category_id)
+        .map(|category| Department::flatten(std::slice::from_ref(category)))
+        .unwrap_or_default()
+}
+
+/// Decides when the next `grab-countdown` event is due
+#[derive(Debug, Default)]
+struct CountdownTicker {
+    last_emit: Option<std::time::Instant>,
+}
+
+impl CountdownTicker {
+    /// True when an event should go out now, given the time left to the trigger
+    fn due(&mut self, remaining: chrono::Duration, now: std::time::Instant) -> bool {
+        let interval = if remaining.num_milliseconds() <= COUNTDOWN_FAST_WINDOW_MS {
+            COUNTDOWN_FAST_INTERVAL_MS
+        } else {
+            COUNTDOWN_INTERVAL_MS
+        };
+        let due = !matches!(self.last_emit, Some(last) if now.duration_since(last) < Duration::from_millis(interval));
+        if due {
+            self.last_emit = Some(now);
+        }
+        due
+    }
+}
+
+/// Schedule/detlid combinations that came back fully booked within a run
+#[derive(Debug, Default)]
+struct ExhaustedSlots {
+    combos: HashSet<(String, String)>,
+    left_nums: HashMap<String, i32>,
+}
+
+impl ExhaustedSlots {
+    /// Forget everything (called at the start of each run)
+    fn reset(&mut self) {
+        self.combos.clear();
+        self.left_nums.clear();
+    }
+
+    /// Check whether a schedule/detlid combination is known to be gone
+    fn is_exhausted(&self, schedule_id: &str, detlid: &str) -> bool {
+        self.combos.contains(&(schedule_id.to_string(), detlid.to_string()))
+    }
+
+    /// Time slots of a schedule that are not known to be gone
+    fn available(&self, schedule_id: &str, times: &[TimeSlot]) -> Vec<TimeSlot> {
+        times
+            .iter()
+            .filter(|t| !self.is_exhausted(schedule_id, &t.value))
+            .cloned()
+            .collect()
+    }
+
+    /// Mark a schedule/detlid combination as gone
+    fn mark(&mut self, schedule_id: &str, detlid: &str) {
+        self.combos.insert((schedule_id.to_string(), detlid.to_string()));
+    }
+
+    /// Record left_num from a schedule query; when it increased since the
+    /// last observation, exhausted entries for that schedule are dropped.
+    /// Returns true if entries were dropped.
+    fn observe_left_num(&mut self, schedule_id: &str, left_num: i32) -> bool {
+        let previous = self.left_nums.insert(schedule_id.to_string(), left_num);
+        let increased = matches!(previous, Some(prev) if left_num > prev);
+        if !increased {
+            return false;
+        }
+        let before = self.combos.len();
+        self.combos.retain(|(sid, _)| sid != schedule_id);
+        self.combos.len() != before
+    }
 }
 
-impl Grabber {
+impl<C: ScheduleApi + 'static> Grabber<C> {
     /// Create a new grabber
-    pub fn new(client: Arc<HealthClient>) -> Self {
+    pub fn new(client: Arc<C>) -> Self {
         Self {
             client,
             proxy_pool: Arc::new(ProxyPool::new()),
             last_submit_at: RwLock::new(None),
+            exhausted: RwLock::new(ExhaustedSlots::default()),
+            schedule_log: RwLock::new(EmptyScheduleLog::default()),
+            address_book: RwLock::new(None),
+            appointments: RwLock::new(None),
+            submit_in_flight: AtomicBool::new(false),
+            schedule_queries: AtomicU32::new(0),
+            status: Arc::new(GrabStatus::default()),
+            captcha_gate: Arc::new(CaptchaGate::default()),
+            events: None,
+        }
+    }
+
+    /// Forward grab events (summaries, countdowns, ...) to this channel
+    pub fn with_events(mut self, events: mpsc::UnboundedSender<GrabEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Share a proxy pool (e.g. one holding the user's custom proxies)
+    pub fn with_proxy_pool(mut self, proxy_pool: Arc<ProxyPool>) -> Self {
+        self.proxy_pool = proxy_pool;
+        self
+    }
+
+    /// Publish attempt counts to a status shared with the app
+    pub fn with_status(mut self, status: Arc<GrabStatus>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Gate the app opens once the user has solved a captcha
+    pub fn with_captcha_gate(mut self, gate: Arc<CaptchaGate>) -> Self {
+        self.captcha_gate = gate;
+        self
+    }
+
+    /// Schedule queries sent by the current (or last) run
+    pub fn schedule_queries(&self) -> u32 {
+        self.schedule_queries.load(Ordering::Relaxed)
+    }
+
+    /// Emit a named event if an event channel is attached
+    fn emit_event(&self, name: &str, payload: serde_json::Value) {
+        if let Some(tx) = &self.events {
+            let _ = tx.send(GrabEvent {
+                name: name.to_string(),
+                payload,
+            });
+        }
+    }
+
+    /// Report the time left before the trigger
+    fn emit_countdown(&self, remaining: chrono::Duration, target_time: &str, offset: chrono::Duration) {
+        self.emit_event(
+            "grab-countdown",
+            serde_json::json!({
+                "remaining_ms": remaining.num_milliseconds().max(0),
+                "target_time": target_time,
+                "server_offset_ms": offset.num_milliseconds(),
+            }),
+        );
+    }
+
+    /// Pick a proxy for the next submit; when none is available either fall
+    /// back to a direct submit or skip, per `proxy_fallback_direct`
+    async fn submit_route<P, F>(&self, proxies: &P, config: &GrabConfig, on_log: &mut F) -> SubmitRoute
+    where
+        P: ProxySource,
+        F: FnMut(&str, &str) + Send,
+    {
+        if !config.use_proxy_submit {
+            return SubmitRoute::Direct;
+        }
+
+        match proxies.rotate_proxy("https", "CN").await {
+            Ok(url) => {
+                let masked = mask_proxy_url(&url);
+                emit_log(on_log, "info", &format!("using proxy: {}", masked));
+                self.emit_event("proxy-status", serde_json::json!({"status": "ok", "proxy": masked}));
+                SubmitRoute::Proxy(url)
+            }
+            Err(e) if config.proxy_fallback_direct => {
+                emit_log(on_log, "warn", &format!("代理不可用，使用直连提交: {}", e));
+                self.emit_event(
+                    "proxy-status",
+                    serde_json::json!({"status": "degraded", "fallback": "direct", "message": e.to_string()}),
+                );
+                SubmitRoute::Direct
+            }
+            Err(e) => {
+                emit_log(on_log, "warn", &format!("代理不可用，跳过本次提交: {}", e));
+                self.emit_event(
+                    "proxy-status",
+                    serde_json::json!({"status": "unavailable", "fallback": "skip", "message": e.to_string()}),
+                );
+                SubmitRoute::Skip
+            }
         }
     }
 
@@ -48,7 +417,8 @@ impl Grabber {
         F: FnMut(&str, &str) + Send,
     {
         // Validate config
-        if let Err(e) = config.validate() {
+        if let Err(errors) = config.validate() {
+            let e = errors.join("; ");
             emit_log(&mut on_log, "error", &e);
             return GrabResult {
                 success: false,
@@ -57,20 +427,57 @@ impl Grabber {
             };
         }
 
-        emit_log(&mut on_log, "info", "grab engine started");
+        self.exhausted.write().await.reset();
+        self.schedule_log.write().await.reset();
+        self.schedule_queries.store(0, Ordering::Relaxed);
+        *self.address_book.write().await = None;
+        *self.appointments.write().await = None;
+
+        let departments = self.resolve_departments(&config, &cancel_token, &mut on_log).await;
+        if departments.is_empty() {
+            let message = "no departments to grab".to_string();
+            emit_log(&mut on_log, "error", &message);
+            return GrabResult {
+                success: false,
+                message,
+                detail: None,
+            };
+        }
+        if let Some(result) = self.precheck_existing(&config, &departments, &mut on_log).await {
+            return result;
+        }
+
+        // Keep validated proxies ready while this grab runs
+        if config.use_proxy_submit {
+            self.proxy_pool
+                .set_probe(ProbeConfig::from_options(&config.proxy_probe_target, &config.proxy_probe_method))
+                .await;
+            self.proxy_pool
+                .set_strategy(RotationStrategy::from_option(&config.proxy_rotation))
+                .await;
+        }
+        let _maintenance = config.use_proxy_submit.then(|| {
+            let stop = cancel_token.child_token();
+            self.proxy_pool.start_maintenance(stop.clone());
+            stop.drop_guard()
+        });
+
+        emit_log(&mut on_log, "state", "grab engine started");
         emit_log(
             &mut on_log,
             "info",
             &format!(
-                "grab config: dates={} doctor_ids={} time_types={} preferred={}",
+                "grab config: dates={} doctor_ids={} exclude={} time_types={} preferred={}",
                 config.target_dates.join(","),
                 config.doctor_ids.join(","),
+                config.exclude_doctor_ids.join(","),
                 config.time_types.join(","),
                 config.preferred_hours.join(",")
             ),
         );
 
         let is_precise = !config.doctor_ids.is_empty()
+            || config.date_doctor_map.values().any(|doctors| !doctors.is_empty())
             || !config.preferred_hours.is_empty()
             || !config.time_types.is_empty();
 
@@ -83,98 +490,345 @@ impl Grabber {
         if config.time_types.is_empty() {
             emit_log(&mut on_log, "info", "time_types 未设置，默认 am/pm");
         }
+        for warning in config.warnings() {
+            emit_log(&mut on_log, "warn", &warning);
+        }
 
-        // Wait for start time if specified
-        if !config.start_time.is_empty() {
-            self.wait_until(&config.start_time, config.use_server_time, cancel_token.clone(), &mut on_log).await;
-            if cancel_token.is_cancelled() {
+        if !config.recur_daily {
+            return self.run_window(&config, &departments, cancel_token, &mut on_log).await.result;
+        }
+
+        emit_log(&mut on_log, "state", &format!("daily mode: {} - {}", config.start_time, config.stop_time));
+        let mut config = config;
+        loop {
+            let WindowOutcome { result, attempts, deadline_hit } = self.run_window(&config, &departments, cancel_token.clone(), &mut on_log).await;
+            self.emit_event(
+                "grab-day-summary",
+                serde_json::json!({
+                    "day": Local::now().format("%Y-%m-%d").to_string(),
+                    "attempts": attempts,
+                    "success": result.success,
+                    "message": result.message,
+                    "target_dates": config.target_dates,
+                }),
+            );
+
+            if result.success || cancel_token.is_cancelled() || !deadline_hit {
+                return result;
+            }
+
+            // Sleep until shortly before tomorrow's window so wait_until can calibrate
+            let Some(next_start) = parse_clock_time(&config.start_time)
+                .map(|t| today_at(t) + chrono::Duration::days(1) - chrono::Duration::seconds(DAILY_WAKE_LEAD_SECS))
+            else {
+                return result;
+            };
+            let sleep = (next_start - Local::now()).to_std().unwrap_or(Duration::ZERO);
+            emit_log(&mut on_log, "state", &format!("window closed, sleeping until {}", next_start.format("%Y-%m-%d %H:%M:%S")));
+            if !sleep_with_cancel(sleep, cancel_token.clone()).await {
                 return GrabResult {
                     success: false,
                     message: "stopped".into(),
                     detail: None,
                 };
             }
+
+            let rolled = roll_target_dates(&config.target_dates, Local::now().date_naive());
+            if rolled != config.target_dates {
+                emit_log(&mut on_log, "state", &format!("target dates rolled: {}", rolled.join(",")));
+                config.target_dates = rolled;
+                if let Some(result) = self.precheck_existing(&config, &departments, &mut on_log).await {
+                    return result;
+                }
+            }
+            self.exhausted.write().await.reset();
+            self.schedule_log.write().await.reset();
         }
+    }
 
-        let retry_interval = if config.retry_interval <= 0.0 { 0.5 } else { config.retry_interval };
+    /// Run one grab window: wait for start_time, then attempt until success,
+    /// stop, deadline or retry limit
+    async fn run_window<F>(
+        &self,
+        config: &GrabConfig,
+        departments: &[GrabDepartment],
+        cancel_token: CancellationToken,
+        on_log: &mut F,
+    ) -> WindowOutcome
+    where
+        F: FnMut(&str, &str) + Send,
+    {
         let mut attempt = 0;
 
+        // Wait for start time if specified
+        let mut offset = chrono::Duration::zero();
+        let mut day = Local::now().date_naive();
+        if !config.start_time.is_empty() {
+            let start = self.wait_until(config, cancel_token.clone(), on_log).await;
+            if cancel_token.is_cancelled() {
+                return WindowOutcome::ended(
+                    GrabResult {
+                        success: false,
+                        message: "stopped".into(),
+                        detail: None,
+                    },
+                    attempt,
+                );
+            }
+            if start.aborted {
+                return WindowOutcome::ended(
+                    GrabResult {
+                        success: false,
+                        message: "start_time already passed".into(),
+                        detail: None,
+                    },
+                    attempt,
+                );
+            }
+            offset = start.offset;
+            day = start.day;
+        } else if config.use_server_time && !config.stop_time.is_empty() {
+            if let Some(estimate) = self.calibrate_offset(on_log).await {
+                offset = estimate;
+            }
+        }
+
+        // max_duration_secs counts from the trigger, not from the start_time wait
+        let run_started = tokio::time::Instant::now();
+
+        // Deadline: stop_time is interpreted on the same clock and day as start_time
+        let stop_at = parse_clock_time(&config.stop_time).map(|t| local_at(day.and_time(t)) - offset);
+        let max_duration = (config.max_duration_secs > 0).then(|| Duration::from_secs(config.max_duration_secs));
+
+        let retry_interval = if config.retry_interval <= 0.0 { 0.5 } else { config.retry_interval };
+        let retry_interval = Duration::from_secs_f64(retry_interval);
+        let triggered_at = std::time::Instant::now();
+        let mut in_burst = config.burst_duration_ms > 0;
+        // Explicit target dates take precedence over the rolling window
+        let rolling_days = config.rolling_days.filter(|_| config.target_dates.is_empty());
+        let mut rolling = RollingWindow::default();
+
+        if in_burst {
+            emit_log(
+                on_log,
+                "state",
+                &format!("burst mode on: {}ms @ {}ms", config.burst_duration_ms, config.burst_interval_ms),
+            );
+        }
+
         loop {
             if cancel_token.is_cancelled() {
-                return GrabResult {
-                    success: false,
-                    message: "stopped".into(),
-                    detail: None,
+                return WindowOutcome::ended(
+                    GrabResult {
+                        success: false,
+                        message: "stopped".into(),
+                        detail: None,
+                    },
+                    attempt,
+                );
+            }
+
+            if deadline_reached(Local::now(), stop_at, run_started.elapsed(), max_duration) {
+                emit_log(on_log, "deadline", "deadline reached, stopping");
+                return WindowOutcome {
+                    result: GrabResult {
+                        success: false,
+                        message: "deadline reached".into(),
+                        detail: None,
+                    },
+                    attempts: attempt,
+                    deadline_hit: true,
                 };
             }
 
             attempt += 1;
-            emit_log(&mut on_log, "info", &format!("attempt {}", attempt));
+            self.status.set_attempt(attempt as u32);
+            emit_log(on_log, "info", &format!("attempt {}", attempt));
+
+            if let Some(days) = rolling_days {
+                if rolling.refresh(Local::now().date_naive(), config.release_offset_days, days) {
+                    emit_log(on_log, "info", &format!("rolling dates: {}", rolling.dates.join(",")));
+                }
+            }
+            let dates = if rolling_days.is_some() { &rolling.dates } else { &config.target_dates };
+            let queries = DateQuery::cycle(dates, departments);
+
+            let deadline = (config.attempt_timeout_secs > 0)
+                .then(|| tokio::time::Instant::now() + Duration::from_secs(config.attempt_timeout_secs));
+            let outcome = {
+                let cycle = self.try_grab_once(config, &queries, in_burst, deadline, cancel_token.clone(), on_log);
+                tokio::pin!(cycle);
+                match deadline {
+                    None => cycle.await,
+                    Some(deadline) => tokio::select! {
+                        result = &mut cycle => result,
+                        _ = tokio::time::sleep_until(deadline) => {
+                            // A submit already on the wire is allowed to finish
+                            if self.submit_in_flight.load(Ordering::SeqCst) {
+                                cycle.await
+                            } else {
+                                Err(attempt_timeout())
+                            }
+                        }
+                    },
+                }
+            };
 
-            match self.try_grab_once(&config, cancel_token.clone(), &mut on_log).await {
+            match outcome {
+                Err(AppError::Timeout(_)) if attempt_expired(deadline) => {
+                    emit_log(on_log, "warn", "attempt timed out, restarting cycle");
+                }
                 Ok(Some(success)) => {
-                    emit_log(&mut on_log, "success", "grab success");
-                    return GrabResult {
-                        success: true,
-                        message: "success".into(),
-                        detail: Some(success),
+                    emit_log(on_log, "success", "grab success");
+                    let message = match &success.note {
+                        Some(note) => format!("success ({})", note),
+                        None => "success".into(),
                     };
+                    return WindowOutcome::ended(
+                        GrabResult {
+                            success: true,
+                            message,
+                            detail: Some(success),
+                        },
+                        attempt,
+                    );
                 }
                 Ok(None) => {}
-                Err(e) => {
-                    if matches!(e, AppError::LoginRequired(_)) {
-                        return GrabResult {
+                Err(e) if !e.is_retryable() => {
+                    let message = match e {
+                        AppError::Cancelled => "stopped".into(),
+                        e => e.to_frontend_string(),
+                    };
+                    return WindowOutcome::ended(
+                        GrabResult {
                             success: false,
-                            message: e.to_frontend_string(),
+                            message,
                             detail: None,
-                        };
+                        },
+                        attempt,
+                    );
+                }
+                Err(AppError::CaptchaRequired(url)) => {
+                    emit_log(on_log, "warn", &format!("captcha required, paused until solved: {}", url));
+                    self.emit_event(
+                        "grab-captcha-required",
+                        serde_json::json!({ "url": url, "timeout_secs": CAPTCHA_TIMEOUT.as_secs() }),
+                    );
+                    match self.captcha_gate.wait(CAPTCHA_TIMEOUT, &cancel_token).await {
+                        CaptchaWait::Resumed => emit_log(on_log, "info", "captcha solved, resuming"),
+                        CaptchaWait::Cancelled => {
+                            return WindowOutcome::ended(
+                                GrabResult {
+                                    success: false,
+                                    message: "stopped".into(),
+                                    detail: None,
+                                },
+                                attempt,
+                            );
+                        }
+                        CaptchaWait::TimedOut => {
+                            let message = format!("captcha not solved within {} minutes", CAPTCHA_TIMEOUT.as_secs() / 60);
+                            emit_log(on_log, "warn", &message);
+                            return WindowOutcome::ended(
+                                GrabResult {
+                                    success: false,
+                                    message,
+                                    detail: None,
+                                },
+                                attempt,
+                            );
+                        }
+                    }
+                }
+                Err(e) => {
+                    let backoff = match &e {
+                        AppError::Blocked(reason) => {
+                            let backoff = Duration::from_millis(random_backoff_ms(BLOCKED_BACKOFF_MIN_MS, BLOCKED_BACKOFF_MAX_MS));
+                            emit_log(on_log, "warn", &format!("blocked by WAF ({}), backoff {}ms", reason, backoff.as_millis()));
+                            Some(backoff)
+                        }
+                        AppError::RateLimited { retry_after_ms } => {
+                            emit_log(on_log, "warn", &format!("rate limited, retry after {}ms", retry_after_ms));
+                            Some(Duration::from_millis(*retry_after_ms))
+                        }
+                        _ => None,
+                    };
+                    if let Some(backoff) = backoff {
+                        if !sleep_with_cancel(backoff, cancel_token.clone()).await {
+                            return WindowOutcome::ended(
+                                GrabResult {
+                                    success: false,
+                                    message: "stopped".into(),
+                                    detail: None,
+                                },
+                                attempt,
+                            );
+                        }
                     }
                 }
             }
 
             if config.max_retries > 0 && attempt >= config.max_retries {
-                emit_log(&mut on_log, "warn", &format!("max retries reached ({})", config.max_retries));
-                return GrabResult {
-                    success: false,
-                    message: "max retries reached".into(),
-                    detail: None,
-                };
+                emit_log(on_log, "warn", &format!("max retries reached ({})", config.max_retries));
+                return WindowOutcome::ended(
+                    GrabResult {
+                        success: false,
+                        message: "max retries reached".into(),
+                        detail: None,
+                    },
+                    attempt,
+                );
             }
 
-            if !sleep_with_cancel(Duration::from_secs_f64(retry_interval), cancel_token.clone()).await {
-                return GrabResult {
-                    success: false,
-                    message: "stopped".into(),
-                    detail: None,
-                };
+            let (interval, still_burst) = attempt_interval(
+                triggered_at.elapsed(),
+                retry_interval,
+                config.burst_duration_ms,
+                config.burst_interval_ms,
+            );
+            if in_burst && !still_burst {
+                emit_log(on_log, "state", "burst mode off, normal cadence");
+            }
+            in_burst = still_burst;
+
+            if !sleep_with_cancel(interval, cancel_token.clone()).await {
+                return WindowOutcome::ended(
+                    GrabResult {
+                        success: false,
+                        message: "stopped".into(),
+                        detail: None,
+                    },
+                    attempt,
+                );
             }
         }
     }
 
-    /// Try to grab once (one complete cycle through all dates)
+    /// Try to grab once (one complete cycle through all dates, each in every department)
     async fn try_grab_once<F>(
         &self,
         config: &GrabConfig,
+        queries: &[DateQuery<'_>],
+        in_burst: bool,
+        deadline: Option<tokio::time::Instant>,
         cancel_token: CancellationToken,
         on_log: &mut F,
     ) -> AppResult<Option<GrabSuccess>>
     where
         F: FnMut(&str, &str) + Send,
     {
-        let doctor_set: HashSet<String> = config.doctor_ids.iter().cloned().collect();
-        let time_set: HashSet<String> = if config.time_types.is_empty() {
-            vec!["am".into(), "pm".into()].into_iter().collect()
-        } else {
-            config.time_types.iter().cloned().collect()
-        };
+        let filter = SlotFilter::from_config(config);
 
-        for date in &config.target_dates {
+        for query in queries {
             if cancel_token.is_cancelled() {
                 return Err(AppError::Cancelled);
             }
+            if attempt_expired(deadline) {
+                return Err(attempt_timeout());
+            }
 
-            // Add jitter
-            if DATE_QUERY_JITTER_MAX_MS > 0 {
+            // Add jitter (skipped during burst)
+            if DATE_QUERY_JITTER_MAX_MS > 0 && !in_burst {
                 let jitter = {
                     let mut rng = rand::thread_rng();
                     rng.gen_range(0..DATE_QUERY_JITTER_MAX_MS)
@@ -182,13 +836,19 @@ impl Grabber {
                 tokio::time::sleep(Duration::from_millis(jitter)).await;
             }
 
-            match self.try_grab_date(config, date, &doctor_set, &time_set, cancel_token.clone(), on_log).await {
+            match self.try_grab_date(config, query, &filter, deadline, cancel_token.clone(), on_log).await {
                 Ok(Some(success)) => return Ok(Some(success)),
                 Ok(None) => continue,
+                Err(e @ AppError::Timeout(_)) if attempt_expired(deadline) => return Err(e),
                 Err(e) => {
-                    if matches!(e, AppError::LoginRequired(_)) {
+                    // Fatal errors abort the run; WAF blocks, rate limits and captchas
+                    // end this cycle so run_window can back off
+                    if !e.is_retryable() || matches!(e, AppError::Blocked(_) | AppError::RateLimited { .. } | AppError::CaptchaRequired(_)) {
                         return Err(e);
                     }
+                    if let AppError::Api { .. } = e {
+                        emit_log(on_log, "warn", &format!("{}: {}", query.label(), e));
+                    }
                     continue;
                 }
             }
@@ -201,33 +861,59 @@ impl Grabber {
     async fn try_grab_date<F>(
         &self,
         config: &GrabConfig,
-        date: &str,
-        doctor_set: &HashSet<String>,
-        time_set: &HashSet<String>,
+        query: &DateQuery<'_>,
+        filter: &SlotFilter,
+        deadline: Option<tokio::time::Instant>,
         cancel_token: CancellationToken,
         on_log: &mut F,
     ) -> AppResult<Option<GrabSuccess>>
     where
         F: FnMut(&str, &str) + Send,
     {
-        emit_log(on_log, "info", &format!("schedule query: {}", date));
-
-        let docs = self.client.get_schedule(&config.unit_id, &config.dep_id, date).await?;
+        let (date, dep) = (query.date, query.dep);
+        let label = query.label();
+        emit_log(on_log, "info", &format!("schedule query: {}", label));
+
+        self.schedule_queries.fetch_add(1, Ordering::Relaxed);
+        let started = tokio::time::Instant::now();
+        let docs = until_cancelled(
+            &cancel_token,
+            self.client.get_grab_schedule(&config.unit_id, &dep.id, date, config.query_timeout_secs),
+        )
+        .await;
+        emit_log(on_log, "latency", &format!("schedule {}: {}ms", label, started.elapsed().as_millis()));
+        let docs = docs?;
 
         if docs.is_empty() {
-            emit_log(on_log, "warn", &format!("no schedule on {}", date));
+            if let Some((level, message)) = self.schedule_log.write().await.on_empty(&label) {
+                emit_log(on_log, level, &message);
+            }
             return Ok(None);
         }
+        self.schedule_log.write().await.on_available(&label);
 
         emit_log(on_log, "info", &format!("schedule result: docs={}", docs.len()));
 
-        for doc in &docs {
+        let doctors = config.doctors_for(date);
+        if config.date_doctor_map.contains_key(date) {
+            let listed = if doctors.is_empty() { "any".to_string() } else { doctors.join(",") };
+            emit_log(on_log, "debug", &format!("doctors for {}: {}", date, listed));
+        }
+
+        for doc in order_doctors(&docs, doctors) {
             if cancel_token.is_cancelled() {
                 return Err(AppError::Cancelled);
             }
 
-            // Filter by doctor
-            if !doctor_set.is_empty() && !doctor_set.contains(&doc.doctor_id) {
+            // Skip blacklisted doctors regardless of mode
+            if config.exclude_doctor_ids.contains(&doc.doctor_id) {
+                emit_log(on_log, "debug", &format!("skipped blacklisted doctor {}", doc.doctor_name));
+                continue;
+            }
+
+            // Suspended doctors show tickets but every submit fails
+            if doc.is_suspended() {
+                emit_log(on_log, "info", &format!("医生停诊，跳过: {}", doc.doctor_name));
                 continue;
             }
 
@@ -236,8 +922,19 @@ impl Grabber {
                     return Err(AppError::Cancelled);
                 }
 
+                // Every count is recorded, 0 included, so a 1 -> 0 -> 1 reopen
+                // clears the slot's exhausted entries
+                if !slot.schedule_id.is_empty() && self.exhausted.write().await.observe_left_num(&slot.schedule_id, slot.left_num) {
+                    emit_log(on_log, "info", &format!("left_num increased for {}, retrying exhausted slots", slot.schedule_id));
+                }
+
                 // Filter by time type
-                if !time_set.is_empty() && !time_set.contains(&slot.time_type) {
+                if !filter.time_types.is_empty() && !filter.time_types.contains(&slot.time_type) {
+                    continue;
+                }
+
+                if slot.is_suspended() {
+                    emit_log(on_log, "info", &format!("医生停诊，跳过: {} {}", doc.doctor_name, slot.time_type_desc));
                     continue;
                 }
 
@@ -250,118 +947,217 @@ impl Grabber {
                     continue;
                 }
 
-                emit_log(
-                    on_log,
-                    "success",
-                    &format!("found slot: {} - {} (left {})", doc.doctor_name, slot.time_type_desc, slot.left_num),
+                let found = if query.show_dep {
+                    format!("found slot: {} / {} - {} (left {})", dep.name, doc.doctor_name, slot.time_type_desc, slot.left_num)
+                } else {
+                    format!("found slot: {} - {} (left {})", doc.doctor_name, slot.time_type_desc, slot.left_num)
+                };
+                emit_log(on_log, "success", &found);
+                self.emit_event(
+                    "grab-slot-found",
+                    serde_json::json!({
+                        "dep_name": dep.name,
+                        "doctor_name": doc.doctor_name,
+                        "time_type_desc": slot.time_type_desc,
+                        "left_num": slot.left_num,
+                        "date": date,
+                        "reg_fee": doc.reg_fee,
+                    }),
                 );
 
-                // Get ticket detail
-                let detail = match self.client.get_ticket_detail(&config.unit_id, &config.dep_id, &slot.schedule_id, &config.member_id).await {
-                    Ok(d) => d,
-                    Err(_) => {
-                        emit_log(on_log, "warn", "ticket detail unavailable");
-                        continue;
+                // A detlid can sell out between the detail fetch and the submit;
+                // while the schedule still shows tickets, re-fetch and try the
+                // next time slot a couple of times within this attempt
+                let mut refetches = 0;
+                loop {
+                    // Get ticket detail
+                    if attempt_expired(deadline) {
+                        return Err(attempt_timeout());
                     }
-                };
+                    let started = tokio::time::Instant::now();
+                    let detail = until_cancelled(
+                        &cancel_token,
+                        self.client.get_ticket_detail(
+                            &config.unit_id,
+                            &dep.id,
+                            &slot.schedule_id,
+                            &config.member_id,
+                            Some(config.city_pinyin.as_str()),
+                        ),
+                    )
+                    .await;
+                    emit_log(on_log, "latency", &format!("ticket detail {}: {}ms", slot.schedule_id, started.elapsed().as_millis()));
+                    let detail = match detail {
+                        Ok(d) => d,
+                        Err(e @ (AppError::RateLimited { .. } | AppError::Cancelled)) => return Err(e),
+                        Err(_) => {
+                            emit_log(on_log, "warn", "ticket detail unavailable");
+                            break;
+                        }
+                    };
 
-                let times = if detail.times.is_empty() { &detail.time_slots } else { &detail.times };
-                if times.is_empty() {
-                    continue;
-                }
+                    match &detail.page_kind {
+                        TicketPageKind::Normal => {}
+                        TicketPageKind::LoginRedirect => {
+                            return Err(AppError::LoginRequired("ticket page redirected to login".into()));
+                        }
+                        TicketPageKind::Captcha => {
+                            return Err(AppError::CaptchaRequired(detail.page_url.clone()));
+                        }
+                        TicketPageKind::Error(text) => {
+                            emit_log(on_log, "warn", &format!("ticket page error: {}", text));
+                            break;
+                        }
+                    }
 
-                if detail.sch_data.is_empty() || detail.detlid_realtime.is_empty() || detail.level_code.is_empty() {
-                    emit_log(on_log, "warn", "ticket detail missing fields");
-                    continue;
-                }
+                    let times = if detail.times.is_empty() { &detail.time_slots } else { &detail.times };
+                    if times.is_empty() {
+                        break;
+                    }
 
-                // Select time slot
-                let selected = pick_time_slot(times, &config.preferred_hours);
-                emit_log(on_log, "info", &format!("selected time slot: {}", selected.name));
+                    // Drop time slots that already came back as fully booked in this run
+                    let times = self.exhausted.read().await.available(&slot.schedule_id, times);
+                    if times.is_empty() {
+                        emit_log(on_log, "info", &format!("all time slots exhausted for {}", slot.schedule_id));
+                        break;
+                    }
 
-                // Resolve address
-                let (address_id, address_text) = resolve_address(config, &detail, on_log);
-                if address_id.is_empty() || address_text.is_empty() {
-                    emit_log(on_log, "error", "missing address info");
-                    continue;
-                }
+                    let missing = detail.missing_required_fields();
+                    if !missing.is_empty() {
+                        emit_log(on_log, "warn", &format!("ticket detail missing fields: {}", missing.join(", ")));
+                        break;
+                    }
 
-                // Build submit params
-                let mut submit_params = std::collections::HashMap::new();
-                submit_params.insert("unit_id".into(), config.unit_id.clone());
-                submit_params.insert("dep_id".into(), config.dep_id.clone());
-                submit_params.insert("schedule_id".into(), slot.schedule_id.clone());
-                submit_params.insert("time_type".into(), slot.time_type.clone());
-                submit_params.insert("doctor_id".into(), doc.doctor_id.clone());
-                submit_params.insert("his_doc_id".into(), doc.his_doc_id.clone());
-                submit_params.insert("his_dep_id".into(), doc.his_dep_id.clone());
-                submit_params.insert("detlid".into(), selected.value.clone());
-                submit_params.insert("member_id".into(), config.member_id.clone());
-                submit_params.insert("addressId".into(), address_id.clone());
-                submit_params.insert("address".into(), address_text.clone());
-                submit_params.insert("sch_data".into(), detail.sch_data.clone());
-                submit_params.insert("level_code".into(), detail.level_code.clone());
-                submit_params.insert("detlid_realtime".into(), detail.detlid_realtime.clone());
-                submit_params.insert("sch_date".into(), detail.sch_date.clone());
-                submit_params.insert("hisMemId".into(), detail.his_mem_id.clone());
-                submit_params.insert("order_no".into(), detail.order_no.clone());
-                submit_params.insert("disease_input".into(), detail.disease_input.clone());
-                submit_params.insert("disease_content".into(), detail.disease_content.clone());
-                submit_params.insert("is_hot".into(), detail.is_hot.clone());
-
-                // Apply throttle
-                self.apply_submit_throttle(on_log).await;
-
-                // Proxy rotation
-                let proxy_url = if config.use_proxy_submit {
-                    match self.proxy_pool.rotate_proxy("https", "CN").await {
-                        Ok(url) => {
-                            emit_log(on_log, "info", &format!("using proxy: {}", url));
-                            Some(url)
-                        }
-                        Err(e) => {
-                            emit_log(on_log, "warn", &format!("proxy rotation failed: {}, using direct connection", e));
-                            None
-                        }
+                    // Select time slot
+                    let selected = pick_time_slot(&times, &config.preferred_hours);
+                    emit_log(on_log, "info", &format!("selected time slot: {}", selected.name));
+
+                    // Resolve address
+                    let (address_id, address_text) = self.resolve_address(config, &detail, on_log).await;
+                    if address_id.is_empty() || address_text.is_empty() {
+                        emit_log(on_log, "error", "missing address info");
+                        break;
                     }
-                } else {
-                    None
-                };
 
-                // Submit
-                match self.client.submit_order(&submit_params, proxy_url).await {
-                    Ok(result) if result.success || result.status => {
-                        let unit_name = if config.unit_name.is_empty() { &config.unit_id } else { &config.unit_name };
-                        let dep_name = if config.dep_name.is_empty() { &config.dep_id } else { &config.dep_name };
-                        let member_name = if config.member_name.is_empty() { &config.member_id } else { &config.member_name };
-
-                        let success = GrabSuccess {
-                            unit_name: unit_name.clone(),
-                            dep_name: dep_name.clone(),
-                            doctor_name: doc.doctor_name.clone(),
-                            date: date.to_string(),
-                            time_slot: selected.name.clone(),
-                            member_name: member_name.clone(),
-                            url: result.url,
-                        };
-
-                        emit_log(on_log, "success", &format!("success: {} / {} / {}", unit_name, dep_name, doc.doctor_name));
-                        return Ok(Some(success));
+                    // Build submit params
+                    let submit_params = SubmitOrderParams {
+                        sch_data: detail.sch_data.clone(),
+                        member_id: config.member_id.clone(),
+                        address_id: address_id.clone(),
+                        address: address_text.clone(),
+                        his_mem_id: detail.his_mem_id.clone(),
+                        disease_input: detail.disease_input.clone(),
+                        order_no: detail.order_no.clone(),
+                        disease_content: detail.disease_content.clone(),
+                        accept: "1".into(),
+                        unit_id: config.unit_id.clone(),
+                        schedule_id: slot.schedule_id.clone(),
+                        dep_id: dep.id.clone(),
+                        his_dep_id: doc.his_dep_id.clone(),
+                        sch_date: detail.sch_date.clone(),
+                        time_type: slot.time_type.clone(),
+                        doctor_id: doc.doctor_id.clone(),
+                        his_doc_id: doc.his_doc_id.clone(),
+                        detlid: selected.value.clone(),
+                        detlid_realtime: detail.detlid_realtime.clone(),
+                        level_code: detail.level_code.clone(),
+                        is_hot: detail.is_hot.clone(),
+                    };
+
+                    // Apply throttle
+                    self.apply_submit_throttle(on_log).await;
+
+                    // Proxy rotation
+                    let proxy_url = match self.submit_route(&*self.proxy_pool, config, on_log).await {
+                        SubmitRoute::Proxy(url) => Some(url),
+                        SubmitRoute::Direct => None,
+                        SubmitRoute::Skip => break,
+                    };
+
+                    // Submit (not started once the attempt deadline has passed)
+                    if attempt_expired(deadline) {
+                        return Err(attempt_timeout());
                     }
-                    Ok(result) => {
-                        let msg = if result.message.is_empty() { "submit failed".to_string() } else { result.message };
-                        
-                        if is_too_fast_message(&msg) {
-                            emit_log(on_log, "warn", &format!("submit throttled, backoff"));
-                            let backoff = Duration::from_millis(random_backoff_ms(SUBMIT_BACKOFF_MIN_MS, SUBMIT_BACKOFF_MAX_MS));
-                            tokio::time::sleep(backoff).await;
-                        } else {
-                            emit_log(on_log, "error", &msg);
+                    emit_log(on_log, "submit", &format!("submitting: {} / {} / {}", doc.doctor_name, date, selected.name));
+                    self.emit_event(
+                        "grab-submitting",
+                        serde_json::json!({
+                            "doctor_name": doc.doctor_name,
+                            "date": date,
+                            "time_slot": selected.name,
+                        }),
+                    );
+                    self.submit_in_flight.store(true, Ordering::SeqCst);
+                    let started = tokio::time::Instant::now();
+                    let submitted =
+                        until_cancelled(&cancel_token, self.client.submit_order(&submit_params, proxy_url.clone())).await;
+                    self.submit_in_flight.store(false, Ordering::SeqCst);
+                    emit_log(on_log, "latency", &format!("submit: {}ms", started.elapsed().as_millis()));
+                    match submitted {
+                        Ok(result) if result.success || result.status => {
+                            let mut success = build_grab_success(config, dep, &doc.doctor_name, date, &selected.name, &submit_params);
+                            success.url = result.url;
+                            success.confirmation = result.confirmation;
+
+                            emit_log(on_log, "success", &format!("success: {} / {} / {}", success.unit_name, success.dep_name, doc.doctor_name));
+                            match &success.confirmation {
+                                Some(c) if !c.order_no.is_empty() => {
+                                    emit_log(on_log, "success", &format!("order no: {}", c.order_no));
+                                }
+                                _ => {
+                                    emit_log(on_log, "warn", "order confirmation unavailable, see success url");
+                                }
+                            }
+                            return Ok(Some(success));
+                        }
+                        Ok(result) => {
+                            let msg = if result.message.is_empty() { "submit failed".to_string() } else { result.message };
+
+                            if is_already_booked_message(&msg) {
+                                let mut success = build_grab_success(config, dep, &doc.doctor_name, date, &selected.name, &submit_params);
+                                success.note = Some("detected via duplicate-order response".into());
+                                emit_log(
+                                    on_log,
+                                    "success",
+                                    &format!("already booked (duplicate-order response): {} / {} / {}", success.unit_name, success.dep_name, doc.doctor_name),
+                                );
+                                return Ok(Some(success));
+                            }
+
+                            if is_detlid_taken_message(&msg) {
+                                self.exhausted.write().await.mark(&slot.schedule_id, &selected.value);
+                                if refetches < MAX_DETLID_REFETCHES {
+                                    refetches += 1;
+                                    emit_log(
+                                        on_log,
+                                        "warn",
+                                        &format!("time slot taken: {} / {}, re-fetching ticket detail ({}/{})", slot.schedule_id, selected.name, refetches, MAX_DETLID_REFETCHES),
+                                    );
+                                    continue;
+                                }
+                                emit_log(on_log, "warn", &format!("time slot taken: {} / {}", slot.schedule_id, selected.name));
+                            } else if is_too_fast_message(&msg) {
+                                emit_log(on_log, "warn", &format!("submit throttled, backoff"));
+                                let backoff = Duration::from_millis(random_backoff_ms(SUBMIT_BACKOFF_MIN_MS, SUBMIT_BACKOFF_MAX_MS));
+                                if !sleep_with_cancel(backoff, cancel_token.clone()).await {
+                                    return Err(AppError::Cancelled);
+                                }
+                            } else if is_slot_full_message(&msg) {
+                                self.exhausted.write().await.mark(&slot.schedule_id, &selected.value);
+                                emit_log(on_log, "warn", &format!("slot exhausted: {} / {}", slot.schedule_id, selected.name));
+                            } else {
+                                emit_log(on_log, "error", &msg);
+                            }
+                        }
+                        Err(e @ (AppError::RateLimited { .. } | AppError::CaptchaRequired(_) | AppError::Cancelled)) => return Err(e),
+                        Err(e) => {
+                            if let Some(url) = proxy_url.as_deref().filter(|_| is_proxy_failure(&e)) {
+                                self.proxy_pool.report_failure(url).await;
+                            }
+                            emit_log(on_log, "error", &format!("submit error: {}", e));
                         }
                     }
-                    Err(e) => {
-                        emit_log(on_log, "error", &format!("submit error: {}", e));
-                    }
+                    break;
                 }
             }
         }
@@ -369,72 +1165,349 @@ impl Grabber {
         Ok(None)
     }
 
-    /// Wait until specified time
-    async fn wait_until<F>(
+    /// Departments this run cycles: `dep_id`, `dep_ids`, then the children of
+    /// `dep_category_id`. A category that cannot be expanded is logged and
+    /// skipped; the run only fails when nothing is left.
+    async fn resolve_departments<F>(
         &self,
-        target_time: &str,
-        use_server_time: bool,
-        cancel_token: CancellationToken,
+        config: &GrabConfig,
+        cancel_token: &CancellationToken,
         on_log: &mut F,
-    ) where
+    ) -> Vec<GrabDepartment>
+    where
         F: FnMut(&str, &str) + Send,
     {
-        let parts: Vec<&str> = target_time.split(':').collect();
-        if parts.len() < 3 {
-            emit_log(on_log, "error", &format!("invalid time format: {}", target_time));
-            return;
+        let dep_name = config.dep_name.trim();
+        let mut departments: Vec<GrabDepartment> = config
+            .dep_id_list()
+            .into_iter()
+            .map(|id| {
+                let name = if id == config.dep_id.trim() && !dep_name.is_empty() { dep_name.to_string() } else { id.clone() };
+                GrabDepartment { id, name }
+            })
+            .collect();
+
+        let category = config.dep_category_id.trim();
+        if !category.is_empty() {
+            match until_cancelled(cancel_token, self.client.get_deps_by_unit(&config.unit_id, &config.city_pinyin)).await {
+                Ok(categories) => {
+                    let children = category_departments(&categories, category);
+                    if children.is_empty() {
+                        emit_log(on_log, "warn", &format!("department category {} not found or empty", category));
+                    } else {
+                        emit_log(on_log, "info", &format!("department category {}: {} departments", category, children.len()));
+                    }
+                    for child in children {
+                        if !departments.iter().any(|d| d.id == child.dep_id) {
+                            departments.push(GrabDepartment {
+                                id: child.dep_id,
+                                name: child.dep_name,
+                            });
+                        }
+                    }
+                }
+                Err(e) => emit_log(on_log, "warn", &format!("department list unavailable, category {} skipped: {}", category, e)),
+            }
         }
 
-        let hour: u32 = parts[0].parse().unwrap_or(0);
-        let min: u32 = parts[1].parse().unwrap_or(0);
-        let sec: u32 = parts[2].parse().unwrap_or(0);
-
-        let now = Local::now();
-        let target = now.date_naive().and_hms_opt(hour, min, sec)
-            .map(|t| t.and_local_timezone(Local).unwrap())
-            .unwrap_or(now);
-
-        let mut offset = chrono::Duration::zero();
-        if use_server_time {
-            if let Ok(server_time) = self.client.get_server_datetime().await {
-                offset = server_time - Local::now();
-                emit_log(on_log, "info", &format!("time offset {:.3}s", offset.num_milliseconds() as f64 / 1000.0));
-            }
+        if departments.len() > 1 {
+            let names: Vec<&str> = departments.iter().map(|d| d.name.as_str()).collect();
+            emit_log(on_log, "info", &format!("departments: {}", names.join(", ")));
         }
+        departments
+    }
 
-        let adjusted = target - offset;
-        let now = Local::now();
+    /// With `precheck_existing`, the failed result to stop with when the member
+    /// already holds an active appointment in one of `departments` on a date
+    /// this run targets. The order list is fetched at most once per run; a
+    /// failed lookup is logged and does not block the grab.
+    async fn precheck_existing<F>(&self, config: &GrabConfig, departments: &[GrabDepartment], on_log: &mut F) -> Option<GrabResult>
+    where
+        F: FnMut(&str, &str) + Send,
+    {
+        if !config.precheck_existing {
+            return None;
+        }
+        if self.appointments.read().await.is_none() {
+            match self.client.get_appointments().await {
+                Ok(orders) => *self.appointments.write().await = Some(orders),
+                Err(e) => {
+                    emit_log(on_log, "warn", &format!("existing appointment check failed: {}", e));
+                    return None;
+                }
+            }
+        }
+
+        let today = Local::now().date_naive();
+        let dates = upcoming_target_dates(&effective_target_dates(config, today), today);
+        let (existing, without_ids) = {
+            let orders = self.appointments.read().await;
+            let orders = orders.as_deref().unwrap_or_default();
+            let existing = orders
+                .iter()
+                .find(|order| {
+                    dates.iter().any(|date| {
+                        departments.iter().any(|dep| {
+                            order.matches(&config.member_id, &config.unit_id, &dep.id, date)
+                                || (order.lacks_ids() && order.matches_names(&config.member_name, &config.unit_id, &dep.name, date))
+                        })
+                    })
+                })
+                .cloned();
+            (existing, orders.iter().filter(|order| order.active && order.lacks_ids()).count())
+        };
+        if without_ids > 0 {
+            let how = if config.member_name.trim().is_empty() {
+                "not checked, member name unknown"
+            } else {
+                "matched by member and department name"
+            };
+            emit_log(on_log, "warn", &format!("{} orders listed without member/department ids: {}", without_ids, how));
+        }
+        let Some(order) = existing else {
+            emit_log(on_log, "info", "no existing appointment on the target dates");
+            return None;
+        };
+
+        let details = format!(
+            "{} {} {} {} (order {}, {})",
+            order.member_name, order.dep_name, order.visit_time, order.doctor_name, order.order_no, order.status
+        );
+        if config.force {
+            emit_log(on_log, "warn", &format!("existing appointment: {}; force set, grabbing anyway", details));
+            return None;
+        }
+        let message = format!("existing appointment: {}; set force to grab anyway", details);
+        emit_log(on_log, "error", &message);
+        Some(GrabResult {
+            success: false,
+            message,
+            detail: None,
+        })
+    }
+
+    /// Wait until specified time, or decide per `start_time_policy` when it
+    /// has already passed
+    async fn wait_until<F>(
+        &self,
+        config: &GrabConfig,
+        cancel_token: CancellationToken,
+        on_log: &mut F,
+    ) -> StartWait
+    where
+        F: FnMut(&str, &str) + Send,
+    {
+        let target_time = config.start_time.as_str();
+        let use_server_time = config.use_server_time;
+        let mut offset = chrono::Duration::zero();
 
-        if adjusted <= now {
-            emit_log(on_log, "warn", &format!("target time already passed: {}", target_time));
-            return;
+        let Some(start) = parse_clock_time(target_time) else {
+            emit_log(on_log, "error", &format!("invalid time format: {}", target_time));
+            return StartWait::started(offset, Local::now().date_naive());
+        };
+
+        if use_server_time {
+            if let Some(estimate) = self.calibrate_offset(on_log).await {
+                offset = estimate;
+            }
         }
 
-        let wait = adjusted - now;
-        emit_log(on_log, "info", &format!("waiting {:.1}s to start", wait.num_seconds() as f64));
+        let server_now = Local::now().naive_local() + offset;
+        let target = match plan_start(start, server_now, config.start_time_policy) {
+            StartPlan::At(at) => {
+                if at.date() != server_now.date() {
+                    emit_log(
+                        on_log,
+                        "warn",
+                        &format!("target time already passed: {}, waiting until {}", target_time, at.format("%Y-%m-%d %H:%M:%S")),
+                    );
+                }
+                at
+            }
+            StartPlan::Now => {
+                emit_log(on_log, "warn", &format!("target time already passed: {}", target_time));
+                return StartWait::started(offset, server_now.date());
+            }
+            StartPlan::Abort => {
+                emit_log(on_log, "error", &format!("target time already passed: {}, not starting (start_time_policy abort)", target_time));
+                return StartWait {
+                    offset,
+                    day: server_now.date(),
+                    aborted: true,
+                };
+            }
+        };
+        let day = target.date();
+        let target = local_at(target);
+
+        let wait = target - offset - Local::now();
+        emit_log(on_log, "state", &format!("waiting {:.1}s to start", wait.num_milliseconds() as f64 / 1000.0));
+
+        // Re-sync checkpoints (seconds before trigger) still ahead of us
+        let mut resync_points: Vec<i64> = if use_server_time {
+            TIME_RESYNC_POINTS_SECS.iter().copied().filter(|s| wait > chrono::Duration::seconds(*s)).collect()
+        } else {
+            Vec::new()
+        };
+
+        // Warm-up requests run detached so they never delay the trigger;
+        // failures come back over this channel and are logged as warnings
+        let (warm_tx, mut warm_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let mut next_warm_at: Option<std::time::Instant> = None;
+        let mut countdown = CountdownTicker::default();
 
         // Wait with periodic checks
-        while Local::now() < adjusted {
+        loop {
             if cancel_token.is_cancelled() {
-                return;
+                return StartWait::started(offset, day);
+            }
+            while let Ok(msg) = warm_rx.try_recv() {
+                emit_log(on_log, "warn", &format!("connection warm-up failed: {}", msg));
+            }
+            let remaining = (target - offset) - Local::now();
+            if countdown.due(remaining, std::time::Instant::now()) {
+                self.emit_countdown(remaining, target_time, offset);
             }
-            let remaining = adjusted - Local::now();
             if remaining.num_seconds() <= 2 {
                 break;
             }
+            if config.prewarm_connections
+                && remaining <= chrono::Duration::seconds(PREWARM_LEAD_SECS)
+                && !matches!(next_warm_at, Some(t) if std::time::Instant::now() < t)
+            {
+                if next_warm_at.is_none() {
+                    emit_log(on_log, "state", "warming up connections");
+                }
+                next_warm_at = Some(std::time::Instant::now() + Duration::from_secs(PREWARM_INTERVAL_SECS));
+                let client = self.client.clone();
+                let tx = warm_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = client.warm_up_connections().await {
+                        let _ = tx.send(e.to_string());
+                    }
+                });
+            }
+            if let Some(pos) = resync_points.iter().position(|s| remaining <= chrono::Duration::seconds(*s)) {
+                resync_points.drain(..=pos);
+                if let Some(estimate) = self.calibrate_offset(on_log).await {
+                    offset = estimate;
+                }
+                continue;
+            }
             let sleep = std::cmp::min(remaining.num_milliseconds() as u64, 1000);
-            tokio::time::sleep(Duration::from_millis(sleep)).await;
+            if !sleep_with_cancel(Duration::from_millis(sleep), cancel_token.clone()).await {
+                return StartWait::started(offset, day);
+            }
         }
 
-        // Spin wait for precision
-        while Local::now() < adjusted {
+        // Close in on the refined instant: short sleeps, busy-yielding only
+        // for the last couple of milliseconds
+        let adjusted = target - offset;
+        loop {
+            let remaining = adjusted - Local::now();
+            let step = final_wait_step(remaining);
+            if step == WaitStep::Done {
+                break;
+            }
             if cancel_token.is_cancelled() {
-                return;
+                return StartWait::started(offset, day);
+            }
+            if countdown.due(remaining, std::time::Instant::now()) {
+                self.emit_countdown(remaining, target_time, offset);
+            }
+            match step {
+                WaitStep::Sleep(duration) => tokio::time::sleep(duration).await,
+                WaitStep::Spin => tokio::task::yield_now().await,
+                WaitStep::Done => {}
+            }
+        }
+
+        self.emit_countdown(chrono::Duration::zero(), target_time, offset);
+        emit_log(on_log, "state", "start trigger");
+        StartWait::started(offset, day)
+    }
+
+    /// Sample the server clock several times and estimate its offset from local time
+    async fn calibrate_offset<F>(&self, on_log: &mut F) -> Option<chrono::Duration>
+    where
+        F: FnMut(&str, &str) + Send,
+    {
+        let (estimate, errors) = timesync::measure(&*self.client).await;
+        for e in errors {
+            emit_log(on_log, "warn", &format!("server time sample failed: {}", e));
+        }
+
+        match estimate {
+            Some(estimate) => {
+                emit_log(
+                    on_log,
+                    "info",
+                    &format!(
+                        "time offset {:+.3}s (±{}ms, {} samples)",
+                        estimate.offset.num_milliseconds() as f64 / 1000.0,
+                        estimate.confidence.num_milliseconds(),
+                        estimate.samples
+                    ),
+                );
+                let (offset, clamped) = clamp_server_offset(estimate.offset);
+                if clamped {
+                    emit_log(
+                        on_log,
+                        "warn",
+                        &format!("time offset too large, capped at {:+}s", offset.num_seconds()),
+                    );
+                }
+                Some(offset)
+            }
+            None => {
+                emit_log(on_log, "warn", "server time unavailable, using local clock");
+                None
             }
-            tokio::task::yield_now().await;
         }
+    }
 
-        emit_log(on_log, "info", "start trigger");
+    /// Resolve address from config or detail, then from the address book
+    async fn resolve_address<F>(&self, config: &GrabConfig, detail: &TicketDetail, on_log: &mut F) -> (String, String)
+    where
+        F: FnMut(&str, &str) + Send,
+    {
+        let (address_id, address_text, fallback) = match unoffered_address(config, detail) {
+            Some(configured) => {
+                let picked = pick_page_address(detail);
+                emit_log(
+                    on_log,
+                    "warn",
+                    &format!("configured address {} not offered for this schedule, using {}", configured, picked.1),
+                );
+                (picked.0, picked.1, false)
+            }
+            None => pick_address(config, detail),
+        };
+        if fallback {
+            emit_log(on_log, "warn", &format!("fallback address: {}", address_text));
+        }
+        if !address_id.is_empty() && !address_text.is_empty() {
+            return (address_id, address_text);
+        }
+
+        // Fetched at most once per run; a failed lookup is retried next time
+        if self.address_book.read().await.is_none() {
+            match self.client.get_addresses().await {
+                Ok(book) => *self.address_book.write().await = Some(book),
+                Err(e) => {
+                    emit_log(on_log, "warn", &format!("address book lookup failed: {}", e));
+                    return (address_id, address_text);
+                }
+            }
+        }
+        let book = self.address_book.read().await;
+        match book.as_deref().and_then(pick_address_record) {
+            Some((id, text)) => {
+                emit_log(on_log, "warn", &format!("address book fallback: {}", text));
+                (id, text)
+            }
+            None => (address_id, address_text),
+        }
     }
 
     /// Apply submit throttle
@@ -457,18 +1530,156 @@ impl Grabber {
     }
 }
 
+/// Shift target dates forward when they have passed, keeping their spacing:
+/// every date moves by the number of days the earliest one is behind `today`.
+/// Unparseable dates are dropped.
+pub fn roll_target_dates(dates: &[String], today: NaiveDate) -> Vec<String> {
+    let parsed: Vec<NaiveDate> = dates
+        .iter()
+        .filter_map(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok())
+        .collect();
+    let Some(earliest) = parsed.iter().min().copied() else {
+        return Vec::new();
+    };
+    let shift = if earliest < today { today - earliest } else { chrono::Duration::zero() };
+
+    let mut seen = HashSet::new();
+    parsed
+        .into_iter()
+        .map(|d| (d + shift).format("%Y-%m-%d").to_string())
+        .filter(|d| seen.insert(d.clone()))
+        .collect()
+}
+
+/// Today's date at the given local clock time
+fn today_at(time: NaiveTime) -> DateTime<Local> {
+    local_at(Local::now().date_naive().and_time(time))
+}
+
+fn local_at(at: NaiveDateTime) -> DateTime<Local> {
+    at.and_local_timezone(Local).earliest().unwrap_or_else(Local::now)
+}
+
+/// How one `run_window` call ended
+#[derive(Debug)]
+struct WindowOutcome {
+    result: GrabResult,
+    attempts: i32,
+    /// Stopped by stop_time or max_duration_secs; daily mode waits for the next window
+    deadline_hit: bool,
+}
+
+impl WindowOutcome {
+    fn ended(result: GrabResult, attempts: i32) -> Self {
+        Self {
+            result,
+            attempts,
+            deadline_hit: false,
+        }
+    }
+}
+
+/// How `wait_until` ended
+#[derive(Debug, Clone, Copy)]
+struct StartWait {
+    /// Server clock offset in use (zero when not using server time)
+    offset: chrono::Duration,
+    /// Server-clock day the window opens on; stop_time is read on this day
+    day: NaiveDate,
+    /// start_time had passed and the policy is `Abort`
+    aborted: bool,
+}
+
+impl StartWait {
+    fn started(offset: chrono::Duration, day: NaiveDate) -> Self {
+        Self { offset, day, aborted: false }
+    }
+}
+
+/// When to fire for start_time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartPlan {
+    /// Wait for this instant on the server clock
+    At(NaiveDateTime),
+    /// Already passed; start now
+    Now,
+    /// Already passed and the config says not to run
+    Abort,
+}
+
+/// Where `start` falls relative to `server_now` (local wall time plus the
+/// server clock offset). Plain wall-clock arithmetic: 91160 runs on
+/// Asia/Shanghai, which has no DST, so tomorrow's start is exactly 24h on.
+pub fn plan_start(start: NaiveTime, server_now: NaiveDateTime, policy: StartTimePolicy) -> StartPlan {
+    let today = server_now.date().and_time(start);
+    if today > server_now {
+        return StartPlan::At(today);
+    }
+    match policy {
+        StartTimePolicy::RunNow => StartPlan::Now,
+        StartTimePolicy::NextDay => StartPlan::At(today + chrono::Duration::days(1)),
+        StartTimePolicy::Abort => StartPlan::Abort,
+    }
+}
+
+/// Check whether the stop time or the maximum run duration has passed
+fn deadline_reached(
+    now: DateTime<Local>,
+    stop_at: Option<DateTime<Local>>,
+    elapsed: Duration,
+    max_duration: Option<Duration>,
+) -> bool {
+    matches!(stop_at, Some(stop) if now >= stop) || matches!(max_duration, Some(max) if elapsed >= max)
+}
+
+/// Sleep before the next attempt: the burst interval while still inside the
+/// burst window after the trigger (never below `MIN_BURST_INTERVAL_MS`),
+/// else the normal retry interval. Returns (interval, still_in_burst).
+fn attempt_interval(
+    since_trigger: Duration,
+    retry_interval: Duration,
+    burst_duration_ms: u64,
+    burst_interval_ms: u64,
+) -> (Duration, bool) {
+    if burst_duration_ms > 0 && since_trigger < Duration::from_millis(burst_duration_ms) {
+        (Duration::from_millis(burst_interval_ms.max(MIN_BURST_INTERVAL_MS)), true)
+    } else {
+        (retry_interval, false)
+    }
+}
+
+/// Build a GrabSuccess from config names, falling back to ids, and the ids
+/// that were submitted
+fn build_grab_success(config: &GrabConfig, dep: &GrabDepartment, doctor_name: &str, date: &str, time_slot: &str, params: &SubmitOrderParams) -> GrabSuccess {
+    let pick = |name: &str, id: &str| if name.is_empty() { id.to_string() } else { name.to_string() };
+    GrabSuccess {
+        unit_name: pick(&config.unit_name, &config.unit_id),
+        dep_name: dep.name.clone(),
+        doctor_name: doctor_name.to_string(),
+        date: date.to_string(),
+        time_slot: time_slot.to_string(),
+        member_name: pick(&config.member_name, &config.member_id),
+        dep_id: params.dep_id.clone(),
+        doctor_id: params.doctor_id.clone(),
+        address_id: params.address_id.clone(),
+        address: params.address.clone(),
+        url: None,
+        confirmation: None,
+        note: None,
+    }
+}
+
 /// Pick time slot based on preference
 fn pick_time_slot(slots: &[TimeSlot], preferred: &[String]) -> TimeSlot {
     if slots.is_empty() {
         return TimeSlot { name: String::new(), value: String::new() };
     }
 
-    if !preferred.is_empty() {
-        for p in preferred {
-            for slot in slots {
-                if &slot.name == p {
-                    return slot.clone();
-                }
+    for p in preferred {
+        let wanted = parse_slot_range(p);
+        for slot in slots {
+            if &slot.name == p || (wanted.is_some() && parse_slot_range(&slot.name) == wanted) {
+                return slot.clone();
             }
         }
     }
@@ -476,33 +1687,109 @@ fn pick_time_slot(slots: &[TimeSlot], preferred: &[String]) -> TimeSlot {
     slots[0].clone()
 }
 
-/// Resolve address from config or detail
-fn resolve_address<F>(config: &GrabConfig, detail: &TicketDetail, on_log: &mut F) -> (String, String)
-where
-    F: FnMut(&str, &str) + Send,
-{
-    let mut address_id = normalize_address_id(&config.address_id);
-    let mut address_text = normalize_address_text(&config.address);
-
+/// Pick the address to submit: config first, then the page defaults, then
+/// the first usable option. Returns (id, text, used_option_fallback).
+pub fn pick_address(config: &GrabConfig, detail: &TicketDetail) -> (String, String, bool) {
+    let address_id = normalize_address_id(&config.address_id);
+    let address_text = normalize_address_text(&config.address);
     if address_id.is_empty() || address_text.is_empty() {
-        address_id = normalize_address_id(&detail.address_id);
-        address_text = normalize_address_text(&detail.address);
+        return pick_page_address(detail);
     }
+    (address_id, address_text, false)
+}
 
-    if (address_id.is_empty() || address_text.is_empty()) && !detail.addresses.is_empty() {
+/// The page's preselected address, else its first usable option
+fn pick_page_address(detail: &TicketDetail) -> (String, String, bool) {
+    let address_id = normalize_address_id(&detail.address_id);
+    let address_text = normalize_address_text(&detail.address);
+
+    if address_id.is_empty() || address_text.is_empty() {
         for item in &detail.addresses {
             let cand_id = normalize_address_id(&item.id);
             let cand_text = normalize_address_text(&item.text);
             if !cand_id.is_empty() && !cand_text.is_empty() {
-                address_id = cand_id;
-                address_text = cand_text.clone();
-                emit_log(on_log, "warn", &format!("fallback address: {}", cand_text));
-                break;
+                return (cand_id, cand_text, true);
             }
         }
     }
 
-    (address_id, address_text)
+    (address_id, address_text, false)
+}
+
+/// The configured address id when the page lists address options and it is
+/// not one of them; the site would reject it or print another pickup point
+pub fn unoffered_address(config: &GrabConfig, detail: &TicketDetail) -> Option<String> {
+    let configured = normalize_address_id(&config.address_id);
+    let offered: Vec<String> = detail
+        .addresses
+        .iter()
+        .map(|item| normalize_address_id(&item.id))
+        .filter(|id| !id.is_empty())
+        .collect();
+    (!configured.is_empty() && !offered.is_empty() && !offered.contains(&configured)).then_some(configured)
+}
+
+/// First usable address book entry, preferring the default one
+fn pick_address_record(book: &[AddressRecord]) -> Option<(String, String)> {
+    let mut usable = book.iter().filter_map(|record| {
+        let id = normalize_address_id(&record.id);
+        let text = normalize_address_text(&record.text);
+        (!id.is_empty() && !text.is_empty()).then_some((record.is_default, id, text))
+    });
+    let first = usable.next()?;
+    let chosen = if first.0 { first } else { usable.find(|r| r.0).unwrap_or(first) };
+    Some((chosen.1, chosen.2))
+}
+
+/// Dates `today + release_offset_days` onwards, `rolling_days` of them
+pub fn rolling_target_dates(today: NaiveDate, release_offset_days: u8, rolling_days: u8) -> Vec<String> {
+    let mut window = RollingWindow::default();
+    window.refresh(today, release_offset_days, rolling_days);
+    window.dates
+}
+
+/// Dates a run queries on `today`: explicit target dates, else the rolling window
+pub fn effective_target_dates(config: &GrabConfig, today: NaiveDate) -> Vec<String> {
+    match config.rolling_days.filter(|_| config.target_dates.is_empty()) {
+        Some(days) => rolling_target_dates(today, config.release_offset_days, days),
+        None => config.target_dates.clone(),
+    }
+}
+
+/// Rolling target-date window, rebuilt only when the day changes
+#[derive(Debug, Default)]
+struct RollingWindow {
+    day: Option<NaiveDate>,
+    dates: Vec<String>,
+}
+
+impl RollingWindow {
+    /// Recompute the window for `today`; returns true when it moved
+    fn refresh(&mut self, today: NaiveDate, release_offset_days: u8, rolling_days: u8) -> bool {
+        if self.day == Some(today) {
+            return false;
+        }
+        self.day = Some(today);
+        let first = today + chrono::Duration::days(i64::from(release_offset_days));
+        self.dates.clear();
+        self.dates.extend(
+            (0..i64::from(rolling_days)).map(|i| (first + chrono::Duration::days(i)).format("%Y-%m-%d").to_string()),
+        );
+        true
+    }
+}
+
+/// Target dates that are well-formed and not before `today`
+pub fn upcoming_target_dates(dates: &[String], today: NaiveDate) -> Vec<String> {
+    dates
+        .iter()
+        .filter(|d| {
+            NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d")
+                .map(|date| date >= today)
+                .unwrap_or(false)
+        })
+        .map(|d| d.trim().to_string())
+        .collect()
 }
 
 /// Normalize address ID
@@ -539,6 +1826,37 @@ fn is_too_fast_message(message: &str) -> bool {
     message.contains("太快") || message.contains("频繁") || message.contains("刷新")
 }
 
+/// Phrases returned when the member already holds this schedule
+const ALREADY_BOOKED_PHRASES: [&str; 2] = ["您已预约该排班", "已预约该排班"];
+
+/// Check if message indicates a duplicate order (a previous submit landed)
+fn is_already_booked_message(message: &str) -> bool {
+    let message = message.trim();
+    if message.is_empty() {
+        return false;
+    }
+    ALREADY_BOOKED_PHRASES.iter().any(|p| message.contains(p))
+}
+
+/// Check if message says the chosen time slot (detlid) was taken by someone
+/// else while other slots of the schedule may remain
+fn is_detlid_taken_message(message: &str) -> bool {
+    let message = message.trim();
+    if message.is_empty() {
+        return false;
+    }
+    ["已被抢", "已被他人预约", "已被占用", "该时段已满"].iter().any(|p| message.contains(p))
+}
+
+/// Check if message indicates the selected slot is fully booked
+fn is_slot_full_message(message: &str) -> bool {
+    let message = message.trim();
+    if message.is_empty() {
+        return false;
+    }
+    message.contains("约满") || message.contains("已满") || message.contains("号源不足")
+}
+
 /// Random backoff in milliseconds
 fn random_backoff_ms(min_ms: u64, max_ms: u64) -> u64 {
     if min_ms == 0 && max_ms == 0 {
@@ -552,6 +1870,15 @@ fn random_backoff_ms(min_ms: u64, max_ms: u64) -> u64 {
     rng.gen_range(min_ms..=max)
 }
 
+/// Submit errors that point at the proxy rather than the site
+fn is_proxy_failure(e: &AppError) -> bool {
+    match e {
+        AppError::ProxyError(_) => true,
+        AppError::HttpError(e) => e.is_connect() || e.is_timeout(),
+        _ => false,
+    }
+}
+
 /// Sleep with cancellation support
 async fn sleep_with_cancel(duration: Duration, cancel_token: CancellationToken) -> bool {
     tokio::select! {
@@ -560,6 +1887,28 @@ async fn sleep_with_cancel(duration: Duration, cancel_token: CancellationToken)
     }
 }
 
+/// True once the per-attempt deadline (if any) has passed
+fn attempt_expired(deadline: Option<tokio::time::Instant>) -> bool {
+    deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline)
+}
+
+fn attempt_timeout() -> AppError {
+    AppError::Timeout("attempt timed out".into())
+}
+
+/// Drive a client call unless the token fires first; the in-flight
+/// request is dropped (and its connection aborted) on cancellation
+async fn until_cancelled<T>(
+    cancel_token: &CancellationToken,
+    call: impl std::future::Future<Output = AppResult<T>>,
+) -> AppResult<T> {
+    tokio::select! {
+        biased;
+        _ = cancel_token.cancelled() => Err(AppError::Cancelled),
+        result = call => result,
+    }
+}
+
 /// Emit log message
 fn emit_log<F>(on_log: &mut F, level: &str, message: &str)
 where
@@ -567,3 +1916,1338 @@ where
 {
     on_log(level, message);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use crate::core::api::TimeSample;
+    use crate::core::types::{AddressOption, DoctorSchedule, ScheduleSlot, SubmitOrderResult};
+
+    /// Scripted ScheduleApi: schedule and submit responses are popped in
+    /// order; once exhausted, schedules come back empty and submits succeed
+    #[derive(Default)]
+    struct MockScheduleApi {
+        schedules: Mutex<VecDeque<AppResult<Vec<DoctorSchedule>>>>,
+        submits: Mutex<VecDeque<SubmitOrderResult>>,
+        submitted: Mutex<Vec<SubmitOrderParams>>,
+        schedule_calls: Mutex<usize>,
+        /// (dep_id, date) of every schedule query, in order
+        schedule_queries: Mutex<Vec<(String, String)>>,
+        /// Department tree returned by get_deps_by_unit
+        categories: Vec<DepartmentCategory>,
+        detail_calls: Mutex<usize>,
+        /// Ticket pages popped before the default bookable one
+        details: Mutex<VecDeque<TicketDetail>>,
+        addresses: Vec<AddressRecord>,
+        address_calls: Mutex<usize>,
+        /// Order list returned by get_appointments
+        appointments: Vec<Appointment>,
+        appointment_calls: Mutex<usize>,
+        /// Per-call artificial latency, keyed by "schedule", "detail" or "submit"
+        delays: HashMap<&'static str, Duration>,
+    }
+
+    impl MockScheduleApi {
+        fn with_schedules(schedules: Vec<Vec<DoctorSchedule>>) -> Self {
+            let mock = Self::default();
+            mock.schedules.lock().unwrap().extend(schedules.into_iter().map(Ok));
+            mock
+        }
+
+        fn push_submit(&self, result: SubmitOrderResult) {
+            self.submits.lock().unwrap().push_back(result);
+        }
+
+        fn submitted(&self) -> Vec<SubmitOrderParams> {
+            self.submitted.lock().unwrap().clone()
+        }
+
+        fn with_delay(mut self, call: &'static str, delay: Duration) -> Self {
+            self.delays.insert(call, delay);
+            self
+        }
+
+        async fn delay(&self, call: &str) {
+            if let Some(delay) = self.delays.get(call) {
+                tokio::time::sleep(*delay).await;
+            }
+        }
+    }
+
+    impl ScheduleApi for MockScheduleApi {
+        async fn get_schedule(&self, _unit_id: &str, dep_id: &str, date: &str) -> AppResult<Vec<DoctorSchedule>> {
+            *self.schedule_calls.lock().unwrap() += 1;
+            self.schedule_queries.lock().unwrap().push((dep_id.to_string(), date.to_string()));
+            self.delay("schedule").await;
+            self.schedules.lock().unwrap().pop_front().unwrap_or(Ok(Vec::new()))
+        }
+
+        async fn get_deps_by_unit(&self, _unit_id: &str, _city_pinyin: &str) -> AppResult<Vec<DepartmentCategory>> {
+            Ok(self.categories.clone())
+        }
+
+        async fn get_ticket_detail(
+            &self,
+            _unit_id: &str,
+            _dep_id: &str,
+            _schedule_id: &str,
+            _member_id: &str,
+            _city_pinyin: Option<&str>,
+        ) -> AppResult<TicketDetail> {
+            *self.detail_calls.lock().unwrap() += 1;
+            self.delay("detail").await;
+            if let Some(detail) = self.details.lock().unwrap().pop_front() {
+                return Ok(detail);
+            }
+            Ok(TicketDetail {
+                times: vec![
+                    TimeSlot { name: "09:00-09:30".into(), value: "t1".into() },
+                    TimeSlot { name: "09:30-10:00".into(), value: "t2".into() },
+                ],
+                sch_data: "sch".into(),
+                detlid_realtime: "rt".into(),
+                level_code: "lv".into(),
+                address_id: "12".into(),
+                address: "福田区".into(),
+                ..Default::default()
+            })
+        }
+
+        async fn submit_order(&self, params: &SubmitOrderParams, _proxy_url: Option<String>) -> AppResult<SubmitOrderResult> {
+            self.submitted.lock().unwrap().push(params.clone());
+            self.delay("submit").await;
+            Ok(self.submits.lock().unwrap().pop_front().unwrap_or(SubmitOrderResult {
+                success: true,
+                status: true,
+                message: "OK".into(),
+                url: Some("https://www.91160.com/order/success.html".into()),
+                confirmation: None,
+            }))
+        }
+
+        async fn get_server_datetime(&self) -> AppResult<DateTime<Local>> {
+            Ok(Local::now())
+        }
+
+        async fn sample_server_time(&self) -> AppResult<TimeSample> {
+            let now = Local::now();
+            Ok((now, now, now))
+        }
+
+        async fn warm_up_connections(&self) -> AppResult<()> {
+            Ok(())
+        }
+
+        async fn get_addresses(&self) -> AppResult<Vec<AddressRecord>> {
+            *self.address_calls.lock().unwrap() += 1;
+            Ok(self.addresses.clone())
+        }
+
+        async fn get_appointments(&self) -> AppResult<Vec<Appointment>> {
+            *self.appointment_calls.lock().unwrap() += 1;
+            Ok(self.appointments.clone())
+        }
+    }
+
+    fn doctor(id: &str, name: &str, slots: &[(&str, &str, i32)]) -> DoctorSchedule {
+        let schedules: Vec<ScheduleSlot> = slots
+            .iter()
+            .map(|(schedule_id, time_type, left_num)| ScheduleSlot {
+                schedule_id: schedule_id.to_string(),
+                time_type: time_type.to_string(),
+                time_type_desc: time_type.to_string(),
+                left_num: *left_num,
+                sch_date: "2024-03-20".into(),
+                status: String::new(),
+            })
+            .collect();
+        DoctorSchedule {
+            doctor_id: id.into(),
+            doctor_name: name.into(),
+            reg_fee: String::new(),
+            total_left_num: schedules.iter().map(|s| s.left_num).sum(),
+            his_doc_id: String::new(),
+            his_dep_id: String::new(),
+            schedule_id: String::new(),
+            time_type_desc: String::new(),
+            status: String::new(),
+            schedules,
+        }
+    }
+
+    fn failed_submit(message: &str) -> SubmitOrderResult {
+        SubmitOrderResult {
+            success: false,
+            status: false,
+            message: message.into(),
+            url: None,
+            confirmation: None,
+        }
+    }
+
+    fn test_config() -> GrabConfig {
+        let mut config = sample_config();
+        config.use_proxy_submit = false;
+        config.retry_interval = 0.2;
+        config.max_retries = 5;
+        config
+    }
+
+    async fn run_grab(mock: Arc<MockScheduleApi>, config: GrabConfig) -> (GrabResult, Vec<(String, String)>) {
+        let grabber = Grabber::new(mock);
+        let mut logs = Vec::new();
+        let result = grabber
+            .run(config, CancellationToken::new(), |level: &str, message: &str| {
+                logs.push((level.to_string(), message.to_string()));
+            })
+            .await;
+        (result, logs)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_doctor_filter() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![
+            doctor("100", "张医生", &[("s1", "am", 3)]),
+            doctor("200", "李医生", &[("s2", "am", 3)]),
+        ]]));
+        let mut config = test_config();
+        config.doctor_ids = vec!["200".into()];
+
+        let (result, _) = run_grab(mock.clone(), config).await;
+        assert!(result.success);
+        let submitted = mock.submitted();
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(submitted[0].doctor_id, "200");
+        assert_eq!(submitted[0].schedule_id, "s2");
+    }
+
+    /// Doctor submitted when 100, 200 and 300 all have tickets on the first target date
+    async fn doctor_picked(doctor_ids: &[&str], date_doctors: Option<(i64, &[&str])>) -> String {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![
+            doctor("100", "张医生", &[("s1", "am", 3)]),
+            doctor("200", "李医生", &[("s2", "am", 3)]),
+            doctor("300", "王医生", &[("s3", "am", 3)]),
+        ]]));
+        let mut config = test_config();
+        config.doctor_ids = doctor_ids.iter().map(|id| id.to_string()).collect();
+        if let Some((days, doctors)) = date_doctors {
+            let date = (Local::now() + chrono::Duration::days(days)).format("%Y-%m-%d").to_string();
+            config.date_doctor_map.insert(date, doctors.iter().map(|id| id.to_string()).collect());
+        }
+
+        let (result, _) = run_grab(mock.clone(), config).await;
+        assert!(result.success);
+        mock.submitted()[0].doctor_id.clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_date_doctor_map_overrides_doctor_ids() {
+        // Listed order wins over page order
+        assert_eq!(doctor_picked(&["100"], Some((1, &["300", "200"]))).await, "300");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_date_doctor_map_falls_back_to_doctor_ids() {
+        assert_eq!(doctor_picked(&["200"], Some((2, &["300"]))).await, "200");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_empty_date_entry_takes_any_doctor() {
+        assert_eq!(doctor_picked(&["999"], Some((1, &[]))).await, "100");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_skips_suspended_doctors_and_slots() {
+        let schedules: Vec<Vec<DoctorSchedule>> = serde_json::from_str(include_str!("../../tests/fixtures/schedule_suspended.json")).unwrap();
+        let mock = Arc::new(MockScheduleApi::with_schedules(schedules));
+
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success);
+        let submitted = mock.submitted();
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(submitted[0].doctor_id, "300");
+        assert_eq!(submitted[0].schedule_id, "s31");
+        assert!(logs.iter().any(|(_, m)| m == "医生停诊，跳过: 张医生"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_time_type_filter() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![doctor(
+            "100",
+            "张医生",
+            &[("s1", "am", 3), ("s2", "pm", 3)],
+        )]]));
+        let mut config = test_config();
+        config.time_types = vec!["pm".into()];
+
+        let (result, _) = run_grab(mock.clone(), config).await;
+        assert!(result.success);
+        let submitted = mock.submitted();
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(submitted[0].time_type, "pm");
+        assert_eq!(submitted[0].schedule_id, "s2");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_retries_after_empty_schedule() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![
+            vec![],
+            vec![doctor("100", "张医生", &[("s1", "am", 0)])],
+            vec![doctor("100", "张医生", &[("s1", "am", 2)])],
+        ]));
+
+        let status = Arc::new(GrabStatus::default());
+        status.start();
+        let grabber = Grabber::new(mock.clone()).with_status(status.clone());
+        let result = grabber.run(test_config(), CancellationToken::new(), |_: &str, _: &str| {}).await;
+        assert!(result.success);
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 3);
+        assert_eq!(mock.submitted().len(), 1);
+        assert_eq!(status.label(), "抢号中 (第 3 次尝试)");
+    }
+
+    fn categories() -> Vec<DepartmentCategory> {
+        serde_json::from_value(serde_json::json!([
+            {"pubcat_id": "c1", "pubcat": "内科", "childs": [
+                {"dep_id": "d2", "dep_name": "心内科"},
+                {"dep_id": "d3", "dep_name": "消化内科"},
+            ]},
+            {"pubcat_id": "c2", "pubcat": "外科", "childs": [
+                {"dep_id": "d4", "dep_name": "骨科"},
+            ]},
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_category_departments_matches_id_or_name() {
+        let ids = |category: &str| -> Vec<String> {
+            category_departments(&categories(), category).into_iter().map(|d| d.dep_id).collect()
+        };
+        assert_eq!(ids("c1"), ["d2", "d3"]);
+        assert_eq!(ids(" 外科 "), ["d4"]);
+        assert!(ids("c9").is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_cycles_departments_within_each_date() {
+        let mock = Arc::new(MockScheduleApi {
+            categories: categories(),
+            ..Default::default()
+        });
+        let mut config = test_config();
+        config.dep_name = "儿科".into();
+        config.dep_category_id = "c1".into();
+        config.city_pinyin = "sz".into();
+        let day = |n: i64| (Local::now() + chrono::Duration::days(n)).format("%Y-%m-%d").to_string();
+        config.target_dates = vec![day(1), day(2)];
+        config.max_retries = 1;
+
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert!(!result.success);
+        let queries = mock.schedule_queries.lock().unwrap().clone();
+        let expected: Vec<(String, String)> = [(1, "d1"), (1, "d2"), (1, "d3"), (2, "d1"), (2, "d2"), (2, "d3")]
+            .iter()
+            .map(|(n, dep)| (dep.to_string(), day(*n)))
+            .collect();
+        assert_eq!(queries, expected);
+        assert!(logs.iter().any(|(_, m)| m == "departments: 儿科, 心内科, 消化内科"));
+        assert!(logs.iter().any(|(_, m)| *m == format!("schedule query: {} / 心内科", day(2))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_success_names_the_department_with_the_slot() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![
+            vec![],
+            vec![doctor("100", "张医生", &[("s1", "am", 3)])],
+        ]));
+        let mut config = test_config();
+        config.dep_ids = vec!["d5".into()];
+
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert!(result.success);
+        assert_eq!(result.detail.unwrap().dep_name, "d5");
+        assert_eq!(mock.submitted()[0].dep_id, "d5");
+        assert!(logs.iter().any(|(_, m)| m.starts_with("found slot: d5 / 张医生")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_duration_starts_after_start_time_wait() {
+        let mock = Arc::new(MockScheduleApi::default());
+        let mut config = test_config();
+        config.prewarm_connections = false;
+        config.max_retries = 0;
+        config.max_duration_secs = 1;
+        config.start_time = (Local::now() + chrono::Duration::seconds(2)).format("%H:%M:%S").to_string();
+
+        let (result, _) = run_grab(mock.clone(), config).await;
+        assert_eq!(result.message, "deadline reached");
+        assert!(*mock.schedule_calls.lock().unwrap() > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_fails_without_departments() {
+        let mock = Arc::new(MockScheduleApi::default());
+        let mut config = test_config();
+        config.dep_id = String::new();
+        config.dep_category_id = "c9".into();
+        config.city_pinyin = "sz".into();
+
+        let (result, _) = run_grab(mock.clone(), config).await;
+        assert!(!result.success);
+        assert_eq!(result.message, "no departments to grab");
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 0);
+    }
+
+    fn booked(dep_id: &str, date: &str, status: &str, active: bool) -> Appointment {
+        Appointment {
+            order_no: "YY20240315123456".into(),
+            member_id: "m1".into(),
+            member_name: "张三".into(),
+            unit_id: "u1".into(),
+            dep_id: dep_id.into(),
+            dep_name: "心血管内科".into(),
+            doctor_name: "王医生".into(),
+            date: date.into(),
+            visit_time: format!("{} 上午 09:00-09:30", date),
+            status: status.into(),
+            active,
+            ..Appointment::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_precheck_existing_stops_before_grabbing() {
+        let config = test_config();
+        let date = config.target_dates[0].clone();
+        let mock = Arc::new(MockScheduleApi {
+            appointments: vec![booked("d1", &date, "预约成功", true)],
+            ..MockScheduleApi::default()
+        });
+        let mut config = config;
+        config.precheck_existing = true;
+
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert!(!result.success);
+        assert!(result.message.starts_with("existing appointment: 张三 心血管内科"));
+        assert!(result.message.contains("YY20240315123456") && result.message.contains("预约成功"));
+        assert!(logs.iter().any(|(level, m)| level == "error" && m == &result.message));
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 0);
+        assert_eq!(*mock.appointment_calls.lock().unwrap(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_precheck_existing_passes_other_orders_and_force() {
+        let config = test_config();
+        let date = config.target_dates[0].clone();
+        let orders = vec![
+            booked("d2", &date, "预约成功", true),
+            booked("d1", &date, "已取消", false),
+            booked("d1", "2099-01-01", "预约成功", true),
+        ];
+        let mock = Arc::new(MockScheduleApi {
+            appointments: orders,
+            ..MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]])
+        });
+        let mut config = config.clone();
+        config.precheck_existing = true;
+        let (result, logs) = run_grab(mock.clone(), config.clone()).await;
+        assert!(result.success);
+        assert!(logs.iter().any(|(_, m)| m == "no existing appointment on the target dates"));
+
+        let mock = Arc::new(MockScheduleApi {
+            appointments: vec![booked("d1", &date, "待就诊", true)],
+            ..MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]])
+        });
+        config.force = true;
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert!(result.success);
+        assert!(logs.iter().any(|(level, m)| level == "warn" && m.contains("force set, grabbing anyway")));
+        assert_eq!(*mock.appointment_calls.lock().unwrap(), 1);
+
+        // Off by default: the order list is never fetched
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]]));
+        assert!(run_grab(mock.clone(), test_config()).await.0.success);
+        assert_eq!(*mock.appointment_calls.lock().unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_precheck_existing_matches_orders_without_ids_by_name() {
+        let mut config = test_config();
+        config.precheck_existing = true;
+        config.dep_name = "心血管内科".into();
+        let date = config.target_dates[0].clone();
+        let order = Appointment {
+            member_id: String::new(),
+            dep_id: String::new(),
+            ..booked("", &date, "预约成功", true)
+        };
+        let mock = Arc::new(MockScheduleApi {
+            appointments: vec![order.clone()],
+            ..MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]])
+        });
+
+        // Without the member name the order cannot be told apart: warned, not matched
+        let (result, logs) = run_grab(mock.clone(), config.clone()).await;
+        assert!(result.success);
+        assert!(logs.iter().any(|(level, m)| level == "warn" && m == "1 orders listed without member/department ids: not checked, member name unknown"));
+
+        config.member_name = "张三".into();
+        let mock = Arc::new(MockScheduleApi {
+            appointments: vec![order],
+            ..MockScheduleApi::default()
+        });
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert!(!result.success);
+        assert!(result.message.starts_with("existing appointment: 张三 心血管内科"));
+        assert!(logs.iter().any(|(_, m)| m.ends_with("matched by member and department name")));
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_backs_off_when_too_fast() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![
+            vec![doctor("100", "张医生", &[("s1", "am", 3)])],
+            vec![doctor("100", "张医生", &[("s1", "am", 3)])],
+        ]));
+        mock.push_submit(failed_submit("submit failed: 操作太快，请稍后再试"));
+
+        let started = tokio::time::Instant::now();
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success);
+        assert_eq!(mock.submitted().len(), 2);
+        assert!(logs.iter().any(|(level, msg)| level == "warn" && msg.contains("backoff")));
+        assert!(started.elapsed() >= Duration::from_millis(SUBMIT_BACKOFF_MIN_MS));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_backs_off_when_blocked() {
+        let mock = Arc::new(MockScheduleApi::default());
+        mock.schedules.lock().unwrap().extend([
+            Err(AppError::Blocked("challenge marker \"访问过于频繁\"".into())),
+            Ok(vec![doctor("100", "张医生", &[("s1", "am", 3)])]),
+        ]);
+
+        let started = tokio::time::Instant::now();
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success);
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 2);
+        assert!(logs.iter().any(|(level, msg)| level == "warn" && msg.contains("blocked by WAF")));
+        assert!(started.elapsed() >= Duration::from_millis(BLOCKED_BACKOFF_MIN_MS));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_logs_api_failure_and_retries() {
+        let mock = Arc::new(MockScheduleApi::default());
+        mock.schedules.lock().unwrap().extend([
+            Err(AppError::Api {
+                endpoint: "schedule".into(),
+                status: Some(502),
+                code: None,
+                message: "unexpected http status".into(),
+                body_snippet: Some("Bad Gateway".into()),
+            }),
+            Ok(vec![doctor("100", "张医生", &[("s1", "am", 3)])]),
+        ]);
+
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success);
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 2);
+        assert!(logs.iter().any(|(level, msg)| level == "warn" && msg.contains("(schedule)") && msg.contains("HTTP 502")));
+    }
+
+    #[test]
+    fn test_site_api_failure_is_not_a_proxy_failure() {
+        assert!(is_proxy_failure(&AppError::ProxyError("no proxy available".into())));
+        assert!(!is_proxy_failure(&AppError::api("submit", "submit failed")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_waits_out_rate_limit() {
+        let mock = Arc::new(MockScheduleApi::default());
+        mock.schedules.lock().unwrap().extend([
+            Err(AppError::RateLimited { retry_after_ms: 20_000 }),
+            Ok(vec![doctor("100", "张医生", &[("s1", "am", 3)])]),
+        ]);
+
+        let started = tokio::time::Instant::now();
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success);
+        assert!(logs.iter().any(|(level, msg)| level == "warn" && msg.contains("rate limited")));
+        assert!(started.elapsed() >= Duration::from_secs(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_success_propagation() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![doctor(
+            "100",
+            "张医生",
+            &[("s1", "am", 3)],
+        )]]));
+        let mut config = test_config();
+        config.unit_name = "测试医院".into();
+        config.preferred_hours = vec!["09:30-10:00".into()];
+
+        let (result, _) = run_grab(mock.clone(), config).await;
+        assert!(result.success);
+        assert_eq!(result.message, "success");
+        let detail = result.detail.unwrap();
+        assert_eq!(detail.unit_name, "测试医院");
+        assert_eq!(detail.dep_name, "d1");
+        assert_eq!(detail.doctor_name, "张医生");
+        assert_eq!((detail.dep_id.as_str(), detail.doctor_id.as_str()), ("d1", "100"));
+        assert_eq!(detail.address_id, mock.submitted()[0].address_id);
+        assert_eq!(detail.time_slot, "09:30-10:00");
+        assert_eq!(detail.url.as_deref(), Some("https://www.91160.com/order/success.html"));
+        assert_eq!(mock.submitted()[0].detlid, "t2");
+    }
+
+    fn ticket_page(kind: TicketPageKind) -> TicketDetail {
+        let mut detail = TicketDetail { page_kind: kind, ..Default::default() };
+        detail.missing_fields = detail.missing_required_fields();
+        detail
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_stops_when_ticket_page_asks_for_login() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]]));
+        mock.details.lock().unwrap().push_back(ticket_page(TicketPageKind::LoginRedirect));
+
+        let (result, _) = run_grab(mock.clone(), test_config()).await;
+        assert!(!result.success);
+        assert_eq!(result.message, "登录已失效，请重新扫码");
+        assert!(mock.submitted().is_empty());
+    }
+
+    const CAPTCHA_URL: &str = "https://www.91160.com/guahao/ystep1/uid-21/depid-d1/schid-s1.html";
+
+    fn captcha_page() -> TicketDetail {
+        TicketDetail { page_url: CAPTCHA_URL.into(), ..ticket_page(TicketPageKind::Captcha) }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_pauses_on_captcha_until_resumed() {
+        let slots = || vec![doctor("100", "张医生", &[("s1", "am", 3)])];
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![slots(), slots()]));
+        mock.details.lock().unwrap().push_back(captcha_page());
+        let gate = Arc::new(CaptchaGate::default());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let grabber = Grabber::new(mock.clone()).with_events(tx).with_captcha_gate(gate.clone());
+
+        // The user solves it in the browser a minute and a half later
+        let user = {
+            let gate = gate.clone();
+            tokio::spawn(async move {
+                while !gate.is_waiting() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                tokio::time::sleep(Duration::from_secs(90)).await;
+                assert!(gate.resume());
+            })
+        };
+        let started = tokio::time::Instant::now();
+        let result = grabber.run(test_config(), CancellationToken::new(), |_: &str, _: &str| {}).await;
+        user.await.unwrap();
+        assert!(result.success, "{}", result.message);
+        assert!(started.elapsed() >= Duration::from_secs(90) && started.elapsed() < CAPTCHA_TIMEOUT);
+        assert_eq!(mock.submitted().len(), 1);
+
+        drop(grabber);
+        let mut captcha = None;
+        while let Some(event) = rx.recv().await {
+            if event.name == "grab-captcha-required" {
+                captcha = Some(event.payload);
+            }
+        }
+        let captcha = captcha.unwrap();
+        assert_eq!(captcha["url"], CAPTCHA_URL);
+        assert_eq!(captcha["timeout_secs"], CAPTCHA_TIMEOUT.as_secs());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_stops_when_captcha_is_not_solved() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]]));
+        mock.details.lock().unwrap().push_back(captcha_page());
+
+        let started = tokio::time::Instant::now();
+        let (result, events) = run_grab_with_events(mock.clone(), test_config()).await;
+        assert!(!result.success);
+        assert_eq!(result.message, "captcha not solved within 10 minutes");
+        assert!(started.elapsed() >= CAPTCHA_TIMEOUT);
+        assert!(mock.submitted().is_empty());
+        assert_eq!(events.iter().filter(|e| e.name == "grab-captcha-required").count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_logs_ticket_page_error_and_missing_fields() {
+        let slots = || vec![doctor("100", "张医生", &[("s1", "am", 3)])];
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![slots(), slots(), slots()]));
+        let mut partial = ticket_page(TicketPageKind::Normal);
+        partial.times = vec![TimeSlot { name: "09:00-09:30".into(), value: "t1".into() }];
+        partial.sch_data = "sch".into();
+        mock.details.lock().unwrap().extend([ticket_page(TicketPageKind::Error("该号源已停诊".into())), partial]);
+
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success, "{}", result.message);
+        assert!(logs.iter().any(|(level, msg)| level == "warn" && msg == "ticket page error: 该号源已停诊"));
+        assert!(logs.iter().any(|(level, msg)| level == "warn" && msg == "ticket detail missing fields: detlid_realtime, level_code"));
+        assert_eq!(mock.submitted().len(), 1);
+    }
+
+    /// Scripted outcome of one grab attempt against a single schedule
+    enum Step {
+        Schedule(i32),
+        SubmitFull(&'static str),
+    }
+
+    fn run_script(slots: &mut ExhaustedSlots, schedule_id: &str, steps: &[Step]) {
+        for step in steps {
+            match step {
+                Step::Schedule(left) => {
+                    slots.observe_left_num(schedule_id, *left);
+                }
+                Step::SubmitFull(detlid) => slots.mark(schedule_id, detlid),
+            }
+        }
+    }
+
+    #[test]
+    fn test_exhausted_slots_skip_only_failed_detlid() {
+        let mut slots = ExhaustedSlots::default();
+        run_script(&mut slots, "s1", &[Step::Schedule(3), Step::SubmitFull("t1")]);
+        assert!(slots.is_exhausted("s1", "t1"));
+        assert!(!slots.is_exhausted("s1", "t2"));
+        assert!(!slots.is_exhausted("s2", "t1"));
+
+        // Same or lower left_num keeps the entry
+        run_script(&mut slots, "s1", &[Step::Schedule(3), Step::Schedule(2)]);
+        assert!(slots.is_exhausted("s1", "t1"));
+    }
+
+    #[test]
+    fn test_exhausted_slots_cleared_when_left_num_increases() {
+        let mut slots = ExhaustedSlots::default();
+        run_script(
+            &mut slots,
+            "s1",
+            &[Step::Schedule(2), Step::SubmitFull("t1"), Step::SubmitFull("t2")],
+        );
+        slots.mark("s2", "t9");
+
+        assert!(slots.observe_left_num("s1", 5));
+        assert!(!slots.is_exhausted("s1", "t1"));
+        assert!(!slots.is_exhausted("s1", "t2"));
+        assert!(slots.is_exhausted("s2", "t9"));
+
+        slots.reset();
+        assert!(!slots.is_exhausted("s2", "t9"));
+    }
+
+    fn sample_config() -> GrabConfig {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": "u1",
+            "dep_id": "d1",
+            "member_id": "m1",
+            "target_dates": [(Local::now() + chrono::Duration::days(1)).format("%Y-%m-%d").to_string()],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pick_address_prefers_config() {
+        let mut config = sample_config();
+        config.address_id = "7".into();
+        config.address = "深圳市南山区".into();
+        let detail = TicketDetail {
+            address_id: "8".into(),
+            address: "广州市".into(),
+            ..Default::default()
+        };
+        assert_eq!(pick_address(&config, &detail), ("7".into(), "深圳市南山区".into(), false));
+    }
+
+    #[test]
+    fn test_pick_address_falls_back_to_options() {
+        let config = sample_config();
+        let detail = TicketDetail {
+            address_id: "0".into(),
+            address: "请选择城市地址".into(),
+            addresses: vec![
+                AddressOption { id: "-1".into(), text: "请选择".into() },
+                AddressOption { id: "12".into(), text: "福田区".into() },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(pick_address(&config, &detail), ("12".into(), "福田区".into(), true));
+
+        let empty = TicketDetail::default();
+        let (id, text, _) = pick_address(&config, &empty);
+        assert!(id.is_empty() && text.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_address_checks_config_against_page_options() {
+        let grabber = Grabber::new(Arc::new(MockScheduleApi::default()));
+        let mut config = sample_config();
+        config.address_id = "13".into();
+        config.address = "南山区".into();
+        let detail = TicketDetail {
+            address_id: "0".into(),
+            addresses: vec![
+                AddressOption { id: "-1".into(), text: "请选择".into() },
+                AddressOption { id: "12".into(), text: "福田区".into() },
+                AddressOption { id: "13".into(), text: "南山区".into() },
+            ],
+            ..Default::default()
+        };
+        let mut logs = Vec::new();
+        let mut on_log = |level: &str, msg: &str| logs.push((level.to_string(), msg.to_string()));
+
+        // Offered: the configured address is authoritative
+        assert_eq!(grabber.resolve_address(&config, &detail, &mut on_log).await, ("13".into(), "南山区".into()));
+        // A page without options cannot contradict it
+        assert_eq!(grabber.resolve_address(&config, &TicketDetail::default(), &mut on_log).await.0, "13");
+
+        // Not offered by this schedule: warn and use the page's own choice
+        config.address_id = "7".into();
+        assert_eq!(unoffered_address(&config, &detail).as_deref(), Some("7"));
+        assert_eq!(grabber.resolve_address(&config, &detail, &mut on_log).await, ("12".into(), "福田区".into()));
+        let warnings: Vec<_> = logs.iter().filter(|(level, _)| level == "warn").map(|(_, m)| m.as_str()).collect();
+        assert_eq!(warnings, ["configured address 7 not offered for this schedule, using 福田区"]);
+
+        config.address_id = String::new();
+        assert_eq!(unoffered_address(&config, &detail), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_address_falls_back_to_address_book() {
+        let mock = Arc::new(MockScheduleApi {
+            addresses: vec![
+                AddressRecord { id: "301".into(), text: "福田区福华三路88号".into(), ..Default::default() },
+                AddressRecord { id: "302".into(), text: "南山区科技园".into(), is_default: true, ..Default::default() },
+            ],
+            ..Default::default()
+        });
+        let grabber = Grabber::new(mock.clone());
+        let config = sample_config();
+        let mut logs = Vec::new();
+        let mut on_log = |level: &str, msg: &str| logs.push((level.to_string(), msg.to_string()));
+
+        let resolved = grabber.resolve_address(&config, &TicketDetail::default(), &mut on_log).await;
+        assert_eq!(resolved, ("302".into(), "南山区科技园".into()));
+        let resolved = grabber.resolve_address(&config, &TicketDetail::default(), &mut on_log).await;
+        assert_eq!(resolved.0, "302");
+        assert_eq!(*mock.address_calls.lock().unwrap(), 1);
+
+        // Page addresses win without touching the address book again
+        let detail = TicketDetail { address_id: "12".into(), address: "福田区".into(), ..Default::default() };
+        let resolved = grabber.resolve_address(&config, &detail, &mut on_log).await;
+        assert_eq!(resolved, ("12".into(), "福田区".into()));
+        assert_eq!(*mock.address_calls.lock().unwrap(), 1);
+        assert!(logs.iter().any(|(_, m)| m.contains("address book fallback")));
+    }
+
+    #[test]
+    fn test_upcoming_target_dates() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        let dates = vec![
+            "2024-03-14".to_string(),
+            "2024-03-15".to_string(),
+            " 2024-03-20 ".to_string(),
+            "bad".to_string(),
+        ];
+        assert_eq!(upcoming_target_dates(&dates, today), vec!["2024-03-15", "2024-03-20"]);
+        assert!(upcoming_target_dates(&dates[..1], today).is_empty());
+    }
+
+    #[test]
+    fn test_attempt_interval() {
+        let normal = Duration::from_millis(500);
+        let ms = Duration::from_millis;
+
+        assert_eq!(attempt_interval(ms(100), normal, 0, 50), (normal, false));
+        assert_eq!(attempt_interval(ms(100), normal, 3000, 50), (ms(50), true));
+        assert_eq!(attempt_interval(ms(2999), normal, 3000, 0), (ms(MIN_BURST_INTERVAL_MS), true));
+        assert_eq!(attempt_interval(ms(3000), normal, 3000, 50), (normal, false));
+    }
+
+    #[test]
+    fn test_plan_start_policies() {
+        let at = |d: u32, h: u32, m: u32, s: u32| NaiveDate::from_ymd_opt(2024, 3, d).unwrap().and_hms_opt(h, m, s).unwrap();
+        let start = NaiveTime::from_hms_opt(7, 30, 0).unwrap();
+        for policy in [StartTimePolicy::RunNow, StartTimePolicy::NextDay, StartTimePolicy::Abort] {
+            assert_eq!(plan_start(start, at(20, 7, 29, 59), policy), StartPlan::At(at(20, 7, 30, 0)));
+        }
+
+        // 07:30 typed at 19:00
+        let evening = at(20, 19, 0, 0);
+        assert_eq!(plan_start(start, evening, StartTimePolicy::RunNow), StartPlan::Now);
+        assert_eq!(plan_start(start, evening, StartTimePolicy::NextDay), StartPlan::At(at(21, 7, 30, 0)));
+        assert_eq!(plan_start(start, evening, StartTimePolicy::Abort), StartPlan::Abort);
+        // Exactly on time counts as passed
+        assert_eq!(plan_start(start, at(20, 7, 30, 0), StartTimePolicy::NextDay), StartPlan::At(at(21, 7, 30, 0)));
+        // Across a month end
+        assert_eq!(plan_start(start, at(31, 8, 0, 0), StartTimePolicy::NextDay), StartPlan::At(NaiveDate::from_ymd_opt(2024, 4, 1).unwrap().and_hms_opt(7, 30, 0).unwrap()));
+
+        // The server clock decides: 2s ahead makes 07:29:59 local already 07:30:01 there
+        let server_now = at(20, 7, 29, 59) + chrono::Duration::seconds(2);
+        assert_eq!(plan_start(start, server_now, StartTimePolicy::RunNow), StartPlan::Now);
+        assert_eq!(plan_start(start, server_now, StartTimePolicy::NextDay), StartPlan::At(at(21, 7, 30, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_passed_start_time_with_abort_policy_does_not_grab() {
+        let api = Arc::new(MockScheduleApi::with_schedules(vec![]));
+        let grabber = Grabber::new(api.clone());
+        let mut config = test_config();
+        config.start_time = "00:00:00".into();
+        config.start_time_policy = StartTimePolicy::Abort;
+
+        let mut logs = Vec::new();
+        let result = grabber
+            .run(config, CancellationToken::new(), |level: &str, message: &str| logs.push(format!("{}: {}", level, message)))
+            .await;
+        assert!(!result.success);
+        assert!(result.message.contains("start_time 00:00:00 has already passed today"), "{}", result.message);
+        assert!(logs.iter().any(|l| l.starts_with("error: start_time 00:00:00 has already passed")), "{:?}", logs);
+        assert_eq!(*api.schedule_calls.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deadline_reached() {
+        let now = Local::now();
+        let secs = Duration::from_secs;
+        assert!(!deadline_reached(now, None, secs(10_000), None));
+        assert!(deadline_reached(now, Some(now), secs(0), None));
+        assert!(!deadline_reached(now, Some(now + chrono::Duration::seconds(1)), secs(0), None));
+        assert!(deadline_reached(now, None, secs(60), Some(secs(60))));
+        assert!(!deadline_reached(now, None, secs(59), Some(secs(60))));
+    }
+
+    #[test]
+    fn test_rolling_window_moves_at_midnight() {
+        let mut window = RollingWindow::default();
+        let day = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
+        assert!(window.refresh(day, 1, 3));
+        assert_eq!(window.dates, vec!["2024-02-29", "2024-03-01", "2024-03-02"]);
+
+        // Same day: nothing is rebuilt
+        let buffer = window.dates.as_ptr();
+        assert!(!window.refresh(day, 1, 3));
+        assert_eq!(window.dates.as_ptr(), buffer);
+
+        // After midnight the window slides and reuses its buffer
+        assert!(window.refresh(day.succ_opt().unwrap(), 1, 3));
+        assert_eq!(window.dates, vec!["2024-03-01", "2024-03-02", "2024-03-03"]);
+        assert_eq!(window.dates.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn test_effective_target_dates_prefers_explicit_dates() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 19).unwrap();
+        let mut config = sample_config();
+        config.rolling_days = Some(2);
+        assert_eq!(effective_target_dates(&config, today), config.target_dates);
+
+        config.target_dates.clear();
+        assert_eq!(effective_target_dates(&config, today), vec!["2024-03-19", "2024-03-20"]);
+        config.release_offset_days = 7;
+        assert_eq!(rolling_target_dates(today, 7, 1), vec!["2024-03-26"]);
+        assert_eq!(effective_target_dates(&config, today), vec!["2024-03-26", "2024-03-27"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_queries_rolling_window() {
+        let mock = Arc::new(MockScheduleApi::default());
+        let mut config = test_config();
+        config.target_dates.clear();
+        config.rolling_days = Some(3);
+        config.max_retries = 2;
+
+        let grabber = Grabber::new(mock.clone());
+        let mut logs = Vec::new();
+        let result = grabber
+            .run(config, CancellationToken::new(), |_: &str, message: &str| logs.push(message.to_string()))
+            .await;
+        assert_eq!(result.message, "max retries reached");
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 6);
+        assert_eq!(grabber.schedule_queries(), 6);
+        assert_eq!(logs.iter().filter(|m| m.starts_with("rolling dates: ")).count(), 1);
+    }
+
+    #[test]
+    fn test_roll_target_dates_month_boundaries() {
+        let dates = vec!["2024-01-30".to_string(), "2024-02-01".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        assert_eq!(roll_target_dates(&dates, today), vec!["2024-01-31", "2024-02-02"]);
+
+        // Leap year February
+        let dates = vec!["2024-02-27".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(roll_target_dates(&dates, today), vec!["2024-02-29"]);
+
+        // Year end
+        let dates = vec!["2024-12-30".to_string(), "2025-01-02".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        assert_eq!(roll_target_dates(&dates, today), vec!["2024-12-31", "2025-01-03"]);
+    }
+
+    #[test]
+    fn test_roll_target_dates_keeps_future_dates() {
+        let dates = vec!["2024-03-20".to_string(), "bad".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        assert_eq!(roll_target_dates(&dates, today), vec!["2024-03-20"]);
+        assert!(roll_target_dates(&[], today).is_empty());
+    }
+
+    #[test]
+    fn test_empty_schedule_log_dedup() {
+        let mut log = EmptyScheduleLog::default();
+        let mut warnings = 0;
+        let mut heartbeats = 0;
+        for _ in 0..100 {
+            match log.on_empty("2024-03-20") {
+                Some(("warn", _)) => warnings += 1,
+                Some(("debug", _)) => heartbeats += 1,
+                Some(_) => unreachable!(),
+                None => {}
+            }
+        }
+        assert_eq!(warnings, 1);
+        assert_eq!(heartbeats, 100 / EMPTY_SCHEDULE_HEARTBEAT as usize);
+
+        // A different date warns independently
+        assert_eq!(log.on_empty("2024-03-21").unwrap().0, "warn");
+
+        // Slots appearing and disappearing warns again
+        log.on_available("2024-03-20");
+        assert_eq!(log.on_empty("2024-03-20").unwrap().0, "warn");
+        assert!(log.on_empty("2024-03-20").is_none());
+    }
+
+    #[test]
+    fn test_is_already_booked_message() {
+        assert!(is_already_booked_message("submit failed: 您已预约该排班，请勿重复预约"));
+        // Rejections that do not name this schedule are not a booking
+        assert!(!is_already_booked_message("重复预约"));
+        assert!(!is_already_booked_message("您在其他科室已有预约"));
+        assert!(!is_already_booked_message("该时段已约满"));
+        assert!(!is_already_booked_message("  "));
+    }
+
+    #[test]
+    fn test_is_detlid_taken_message() {
+        assert!(is_detlid_taken_message("submit failed: 号源已被抢"));
+        assert!(is_detlid_taken_message("该号源已被他人预约，请重新选择"));
+        assert!(is_detlid_taken_message("该时段已满"));
+        assert!(!is_detlid_taken_message("该时段已约满"));
+        assert!(!is_detlid_taken_message("操作太快"));
+        assert!(!is_detlid_taken_message(""));
+    }
+
+    #[test]
+    fn test_taken_detlid_excluded_from_next_pick() {
+        let times = vec![
+            TimeSlot { name: "09:00-09:30".into(), value: "t1".into() },
+            TimeSlot { name: "09:30-10:00".into(), value: "t2".into() },
+            TimeSlot { name: "10:00-10:30".into(), value: "t3".into() },
+        ];
+        let preferred = vec!["09:00-09:30".to_string(), "10:00-10:30".to_string()];
+        let mut slots = ExhaustedSlots::default();
+        assert_eq!(pick_time_slot(&slots.available("s1", &times), &preferred).value, "t1");
+
+        slots.mark("s1", "t1");
+        let remaining = slots.available("s1", &times);
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(pick_time_slot(&remaining, &preferred).value, "t3");
+        assert_eq!(slots.available("s2", &times).len(), 3);
+    }
+
+    #[test]
+    fn test_pick_time_slot_matches_equivalent_labels() {
+        let times = vec![
+            TimeSlot { name: "09:00-09:30".into(), value: "t1".into() },
+            TimeSlot { name: "09:30-10:00".into(), value: "t2".into() },
+        ];
+        assert_eq!(pick_time_slot(&times, &["9:30 - 10:00".to_string()]).value, "t2");
+        assert_eq!(pick_time_slot(&times, &["11:00-11:30".to_string()]).value, "t1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_aborts_hanging_requests() {
+        for call in ["schedule", "detail", "submit"] {
+            let mock = MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]])
+                .with_delay(call, Duration::from_secs(30));
+            let grabber = Grabber::new(Arc::new(mock));
+            let cancel = CancellationToken::new();
+            let stopper = cancel.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                stopper.cancel();
+            });
+
+            let started = tokio::time::Instant::now();
+            let result = grabber.run(test_config(), cancel, |_: &str, _: &str| {}).await;
+            assert!(!result.success, "{}", call);
+            assert_eq!(result.message, "stopped", "{}", call);
+            assert!(started.elapsed() < Duration::from_millis(300), "{} took {:?}", call, started.elapsed());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_attempt_timeout_restarts_slow_cycle() {
+        let mock = Arc::new(MockScheduleApi::default().with_delay("schedule", Duration::from_secs(30)));
+        let mut config = test_config();
+        config.attempt_timeout_secs = 1;
+        config.max_retries = 2;
+
+        let started = tokio::time::Instant::now();
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert_eq!(result.message, "max retries reached");
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+        let timeouts = logs.iter().filter(|(_, m)| m == "attempt timed out, restarting cycle").count();
+        assert_eq!(timeouts, 2);
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_attempt_timeout_lets_inflight_submit_finish() {
+        let mock = MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]])
+            .with_delay("submit", Duration::from_secs(3));
+        let mock = Arc::new(mock);
+        let mut config = test_config();
+        config.attempt_timeout_secs = 1;
+
+        let (result, logs) = run_grab(mock.clone(), config).await;
+        assert!(result.success, "{}", result.message);
+        assert_eq!(mock.submitted().len(), 1);
+        assert!(!logs.iter().any(|(_, m)| m.contains("attempt timed out")));
+    }
+
+    async fn run_grab_with_events(mock: Arc<MockScheduleApi>, config: GrabConfig) -> (GrabResult, Vec<GrabEvent>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let grabber = Grabber::new(mock).with_events(tx);
+        let result = grabber.run(config, CancellationToken::new(), |_: &str, _: &str| {}).await;
+        drop(grabber);
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        (result, events)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_daily_mode_ends_on_non_deadline_failure() {
+        let mut config = test_config();
+        config.recur_daily = true;
+        config.start_time = "00:00:00".into();
+        config.stop_time = "23:59:59".into();
+        config.max_retries = 1;
+
+        let (result, events) = run_grab_with_events(Arc::new(MockScheduleApi::default()), config).await;
+        assert_eq!(result.message, "max retries reached");
+        let summaries: Vec<&GrabEvent> = events.iter().filter(|e| e.name == "grab-day-summary").collect();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].payload["attempts"], 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_emits_slot_found_then_submitting() {
+        let mut doc = doctor("100", "张医生", &[("s1", "am", 3)]);
+        doc.reg_fee = "50".into();
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![], vec![doc]]));
+        mock.push_submit(failed_submit("号源已被抢"));
+
+        let (result, events) = run_grab_with_events(mock, test_config()).await;
+        assert!(result.success);
+        let names: Vec<&str> = events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["grab-slot-found", "grab-submitting", "grab-submitting"]);
+
+        let found = &events[0].payload;
+        assert_eq!(found["doctor_name"], "张医生");
+        assert_eq!(found["time_type_desc"], "am");
+        assert_eq!(found["left_num"], 3);
+        assert_eq!(found["reg_fee"], "50");
+        assert!(found["date"].as_str().is_some_and(|d| !d.is_empty()));
+        assert_eq!(events[1].payload["time_slot"], "09:00-09:30");
+        assert_eq!(events[2].payload["time_slot"], "09:30-10:00");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_refetches_detail_when_detlid_taken() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![vec![doctor("100", "张医生", &[("s1", "am", 3)])]]));
+        mock.push_submit(failed_submit("号源已被抢"));
+
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success);
+        let submitted = mock.submitted();
+        assert_eq!(submitted.iter().map(|p| p.detlid.as_str()).collect::<Vec<_>>(), vec!["t1", "t2"]);
+        assert_eq!(*mock.detail_calls.lock().unwrap(), 2);
+        assert_eq!(*mock.schedule_calls.lock().unwrap(), 1);
+        assert!(logs.iter().any(|(_, m)| m.contains("re-fetching ticket detail (1/2)")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grab_retries_slot_reopened_after_zero() {
+        let mock = Arc::new(MockScheduleApi::with_schedules(vec![
+            vec![doctor("100", "张医生", &[("s1", "am", 1)])],
+            vec![doctor("100", "张医生", &[("s1", "am", 0)])],
+            vec![doctor("100", "张医生", &[("s1", "am", 1)])],
+        ]));
+        let single = TicketDetail {
+            times: vec![TimeSlot { name: "09:00-09:30".into(), value: "t1".into() }],
+            sch_data: "sch".into(),
+            detlid_realtime: "rt".into(),
+            level_code: "lv".into(),
+            address_id: "12".into(),
+            address: "福田区".into(),
+            ..Default::default()
+        };
+        mock.details.lock().unwrap().extend(vec![single; 2]);
+        mock.push_submit(failed_submit("号源已被抢"));
+
+        let (result, logs) = run_grab(mock.clone(), test_config()).await;
+        assert!(result.success, "{}", result.message);
+        assert_eq!(mock.submitted().iter().map(|p| p.detlid.as_str()).collect::<Vec<_>>(), vec!["t1", "t1"]);
+        assert!(logs.iter().any(|(_, m)| m == "left_num increased for s1, retrying exhausted slots"));
+    }
+
+    #[test]
+    fn test_is_slot_full_message() {
+        assert!(is_slot_full_message("submit failed: 该时段已约满"));
+        assert!(is_slot_full_message("号源不足"));
+        assert!(!is_slot_full_message("操作太快"));
+        assert!(!is_slot_full_message(""));
+    }
+
+    /// Proxy source that never has a proxy to give
+    struct NoProxies {
+        calls: Mutex<u32>,
+    }
+
+    impl ProxySource for NoProxies {
+        async fn rotate_proxy(&self, _protocol: &str, _country: &str) -> AppResult<String> {
+            *self.calls.lock().unwrap() += 1;
+            Err(AppError::ProxyError("no proxy available".into()))
+        }
+
+        async fn report_failure(&self, _url: &str) {}
+    }
+
+    #[test]
+    fn test_countdown_ticker_speeds_up_near_trigger() {
+        let mut ticker = CountdownTicker::default();
+        let start = std::time::Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let secs = chrono::Duration::seconds;
+
+        assert!(ticker.due(secs(10), at(0)));
+        assert!(!ticker.due(secs(9), at(500)));
+        assert!(ticker.due(secs(9), at(1000)));
+        assert!(!ticker.due(chrono::Duration::milliseconds(2950), at(1050)));
+        assert!(ticker.due(chrono::Duration::milliseconds(2900), at(1100)));
+        assert!(ticker.due(chrono::Duration::milliseconds(2800), at(1200)));
+    }
+
+    #[test]
+    fn test_final_wait_step_phases() {
+        let ms = chrono::Duration::milliseconds;
+        let sleep = |n| WaitStep::Sleep(Duration::from_millis(n));
+        assert_eq!(final_wait_step(ms(2999)), sleep(10));
+        assert_eq!(final_wait_step(ms(60)), sleep(10));
+        assert_eq!(final_wait_step(ms(55)), sleep(5));
+        assert_eq!(final_wait_step(ms(51)), sleep(1));
+        assert_eq!(final_wait_step(ms(50)), sleep(1));
+        assert_eq!(final_wait_step(ms(3)), sleep(1));
+        assert_eq!(final_wait_step(ms(2)), WaitStep::Spin);
+        assert_eq!(final_wait_step(chrono::Duration::microseconds(300)), WaitStep::Spin);
+        assert_eq!(final_wait_step(ms(0)), WaitStep::Done);
+        assert_eq!(final_wait_step(ms(-5)), WaitStep::Done);
+    }
+
+    #[test]
+    fn test_clamp_server_offset() {
+        let secs = chrono::Duration::seconds;
+        assert_eq!(clamp_server_offset(secs(3)), (secs(3), false));
+        assert_eq!(clamp_server_offset(secs(-300)), (secs(-300), false));
+        assert_eq!(clamp_server_offset(secs(301)), (secs(300), true));
+        assert_eq!(clamp_server_offset(secs(-7200)), (secs(-300), true));
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_emits_countdown() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let grabber = Grabber::new(Arc::new(MockScheduleApi::with_schedules(vec![]))).with_events(tx);
+        let mut config = test_config();
+        config.prewarm_connections = false;
+        config.start_time = (Local::now() + chrono::Duration::seconds(2)).format("%H:%M:%S").to_string();
+
+        let mut logs = Vec::new();
+        grabber
+            .wait_until(&config, CancellationToken::new(), &mut |_: &str, message: &str| logs.push(message.to_string()))
+            .await;
+        drop(grabber);
+
+        let mut remaining = Vec::new();
+        while let Some(event) = rx.recv().await {
+            assert_eq!(event.name, "grab-countdown");
+            assert_eq!(event.payload["target_time"], config.start_time.as_str());
+            assert_eq!(event.payload["server_offset_ms"], 0);
+            remaining.push(event.payload["remaining_ms"].as_i64().unwrap());
+        }
+        assert!(remaining.len() >= 5, "{:?}", remaining);
+        assert_eq!(remaining.last(), Some(&0));
+        assert!(remaining.windows(2).all(|w| w[0] >= w[1]));
+        assert!(logs.iter().any(|m| m == "start trigger"));
+    }
+
+    async fn route_without_proxies(config: &GrabConfig) -> (SubmitRoute, Vec<String>, Vec<GrabEvent>, u32) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let grabber = Grabber::new(Arc::new(MockScheduleApi::with_schedules(vec![]))).with_events(tx);
+        let proxies = NoProxies { calls: Mutex::new(0) };
+        let mut logs = Vec::new();
+        let route = grabber
+            .submit_route(&proxies, config, &mut |_: &str, message: &str| logs.push(message.to_string()))
+            .await;
+        drop(grabber);
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        let calls = *proxies.calls.lock().unwrap();
+        (route, logs, events, calls)
+    }
+
+    #[tokio::test]
+    async fn test_submit_route_falls_back_to_direct() {
+        let config = sample_config();
+        assert!(config.use_proxy_submit && config.proxy_fallback_direct);
+
+        let (route, logs, events, calls) = route_without_proxies(&config).await;
+        assert_eq!(route, SubmitRoute::Direct);
+        assert_eq!(calls, 1);
+        assert!(logs.iter().any(|l| l.starts_with("代理不可用，使用直连提交")), "{:?}", logs);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "proxy-status");
+        assert_eq!(events[0].payload["status"], "degraded");
+    }
+
+    #[tokio::test]
+    async fn test_submit_route_skips_without_fallback() {
+        let mut config = sample_config();
+        config.proxy_fallback_direct = false;
+
+        let (route, _, events, _) = route_without_proxies(&config).await;
+        assert_eq!(route, SubmitRoute::Skip);
+        assert_eq!(events[0].payload["status"], "unavailable");
+
+        config.use_proxy_submit = false;
+        let (route, _, events, calls) = route_without_proxies(&config).await;
+        assert_eq!(route, SubmitRoute::Direct);
+        assert_eq!(calls, 0);
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+//! Grab log routing for QuickDoctor
+//! Filters grab log lines by verbosity and folds repeats before they reach the UI
+
+/// How much of the grab log reaches the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogVerbosity {
+    /// State changes, found slots, submits, warnings and results only
+    Quiet,
+    /// Everything except latencies, with repeats folded
+    #[default]
+    Normal,
+    /// Normal plus per-request latencies
+    Verbose,
+}
+
+impl LogVerbosity {
+    /// Parse the `log_verbosity` option; unknown values give the default
+    pub fn from_option(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "quiet" => Self::Quiet,
+            "verbose" => Self::Verbose,
+            _ => Self::Normal,
+        }
+    }
+
+    fn shows(self, level: &str) -> bool {
+        match self {
+            Self::Quiet => matches!(level, "state" | "submit" | "success" | "warn" | "error" | "deadline"),
+            Self::Normal => level != "latency",
+            Self::Verbose => true,
+        }
+    }
+}
+
+/// Distinct lines remembered as one repeating cycle
+const CYCLE_LINES: usize = 16;
+/// Folded lines after which a summary goes out even if the cycle continues
+const REPEAT_FLUSH: u32 = 100;
+
+/// Filters grab log lines and folds repeats
+/// A line repeats when the same level and text was seen since the last
+/// summary. Only the attempt counter is ignored, so the per-attempt
+/// "attempt N" / "schedule query: D" cycle folds as well.
+#[derive(Debug, Default)]
+pub struct LogCollapser {
+    verbosity: LogVerbosity,
+    /// Keys of the lines in the current cycle
+    cycle: Vec<String>,
+    /// Lines folded since the last summary
+    repeats: u32,
+}
+
+impl LogCollapser {
+    pub fn new(verbosity: LogVerbosity) -> Self {
+        Self {
+            verbosity,
+            ..Self::default()
+        }
+    }
+
+    /// Lines to emit for one grab log line, as (level, message)
+    pub fn push(&mut self, level: &str, message: &str) -> Vec<(String, String)> {
+        if !self.verbosity.shows(level) {
+            return Vec::new();
+        }
+        let key = format!("{}\u{1f}{}", level, repeat_key(message));
+        if self.cycle.contains(&key) {
+            self.repeats += 1;
+            return if self.repeats >= REPEAT_FLUSH { self.summary().into_iter().collect() } else { Vec::new() };
+        }
+
+        let mut out: Vec<(String, String)> = Vec::new();
+        if self.repeats > 0 {
+            out.extend(self.summary());
+            self.cycle.clear();
+        }
+        if self.cycle.len() >= CYCLE_LINES {
+            self.cycle.clear();
+        }
+        self.cycle.push(key);
+        out.push((ui_level(level).to_string(), message.to_string()));
+        out
+    }
+
+    /// Summary of repeats still pending when the grab ends
+    pub fn finish(&mut self) -> Option<(String, String)> {
+        self.cycle.clear();
+        self.summary()
+    }
+
+    fn summary(&mut self) -> Option<(String, String)> {
+        let repeats = std::mem::take(&mut self.repeats);
+        (repeats > 0).then(|| ("info".to_string(), format!("…(repeated {} times)", repeats)))
+    }
+}
+
+/// Level shown in the UI for a routing level: "state" (run state changes),
+/// "submit" (submit attempts) and "latency" (per-request timings) show as "info"
+fn ui_level(level: &str) -> &str {
+    match level {
+        "state" | "submit" | "latency" => "info",
+        other => other,
+    }
+}
+
+/// Text a line is compared on: "attempt N" lines all match, anything else
+/// must be identical
+fn repeat_key(message: &str) -> &str {
+    match message.strip_prefix("attempt ") {
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => "attempt #",
+        _ => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(collapser: &mut LogCollapser, lines: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut out: Vec<(String, String)> = lines.iter().flat_map(|(level, message)| collapser.push(level, message)).collect();
+        out.extend(collapser.finish());
+        out
+    }
+
+    fn lines(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(l, m)| (l.to_string(), m.to_string())).collect()
+    }
+
+    #[test]
+    fn test_collapses_identical_lines() {
+        let mut collapser = LogCollapser::default();
+        let out = run(
+            &mut collapser,
+            &[("info", "no schedule"), ("info", "no schedule"), ("info", "no schedule"), ("warn", "blocked")],
+        );
+        assert_eq!(out, lines(&[("info", "no schedule"), ("info", "…(repeated 2 times)"), ("warn", "blocked")]));
+    }
+
+    #[test]
+    fn test_collapses_repeating_cycle_with_counters() {
+        let mut collapser = LogCollapser::default();
+        let mut input = Vec::new();
+        let attempts: Vec<String> = (1..=4).map(|n| format!("attempt {}", n)).collect();
+        for attempt in &attempts {
+            input.push(("info", attempt.as_str()));
+            input.push(("info", "schedule query: 2024-03-20"));
+        }
+        input.push(("success", "found slot: 张医生 - am (left 3)"));
+        input.push(("info", "attempt 5"));
+
+        let out = run(&mut collapser, &input);
+        assert_eq!(
+            out,
+            lines(&[
+                ("info", "attempt 1"),
+                ("info", "schedule query: 2024-03-20"),
+                ("info", "…(repeated 6 times)"),
+                ("success", "found slot: 张医生 - am (left 3)"),
+                ("info", "attempt 5"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_lines_differing_in_numbers_are_kept() {
+        let mut collapser = LogCollapser::default();
+        let input = [
+            ("info", "schedule query: 2024-03-20"),
+            ("info", "schedule query: 2024-03-21"),
+            ("success", "found slot: 张医生 - am (left 3)"),
+            ("success", "found slot: 张医生 - am (left 2)"),
+        ];
+        assert_eq!(run(&mut collapser, &input), lines(&input));
+    }
+
+    #[test]
+    fn test_long_runs_report_progress() {
+        let mut collapser = LogCollapser::default();
+        let out: Vec<_> = (0..=REPEAT_FLUSH * 2).flat_map(|_| collapser.push("info", "tick")).collect();
+        assert_eq!(
+            out,
+            lines(&[("info", "tick"), ("info", "…(repeated 100 times)"), ("info", "…(repeated 100 times)")])
+        );
+        assert_eq!(collapser.finish(), None);
+    }
+
+    #[test]
+    fn test_verbosity_filters_levels() {
+        let input = [
+            ("state", "start trigger"),
+            ("info", "attempt 1"),
+            ("latency", "schedule 2024-03-20: 120ms"),
+            ("submit", "submitting: 张医生 / 2024-03-20 / 09:00-09:30"),
+            ("success", "grab success"),
+        ];
+        let quiet = run(&mut LogCollapser::new(LogVerbosity::Quiet), &input);
+        assert_eq!(
+            quiet,
+            lines(&[
+                ("info", "start trigger"),
+                ("info", "submitting: 张医生 / 2024-03-20 / 09:00-09:30"),
+                ("success", "grab success"),
+            ])
+        );
+        assert_eq!(run(&mut LogCollapser::new(LogVerbosity::Normal), &input).len(), 4);
+        let verbose = run(&mut LogCollapser::new(LogVerbosity::Verbose), &input);
+        assert_eq!(verbose[2], ("info".to_string(), "schedule 2024-03-20: 120ms".to_string()));
+    }
+
+    #[test]
+    fn test_verbosity_from_option() {
+        assert_eq!(LogVerbosity::from_option(" Quiet "), LogVerbosity::Quiet);
+        assert_eq!(LogVerbosity::from_option("verbose"), LogVerbosity::Verbose);
+        assert_eq!(LogVerbosity::from_option(""), LogVerbosity::Normal);
+    }
+}
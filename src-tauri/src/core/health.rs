@@ -0,0 +1,261 @@
+//! Connectivity health check for QuickDoctor
+//! Probes each host in stages (DNS, connect, first response) and reads the clock skew
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+use super::types::{HealthReport, HostHealth};
+
+/// Budget of each probe stage
+pub const HEALTH_TIMEOUT: Duration = Duration::from_secs(4);
+/// Local clock off by more than this is reported
+const CLOCK_SKEW_WARN_SECS: f64 = 3.0;
+/// First response slower than this is reported
+const SLOW_TTFB_MS: u64 = 2000;
+
+/// Probe `base` stage by stage, `request` being the HEAD request sent last;
+/// returns the host result and, when the server sent a Date header, how far
+/// the local clock is ahead of it in seconds
+pub async fn probe_host(base: &str, request: reqwest::RequestBuilder) -> (HostHealth, Option<f64>) {
+    let parsed = Url::parse(base).ok();
+    let host = parsed.as_ref().and_then(Url::host_str).unwrap_or(base).to_string();
+    let mut health = HostHealth {
+        host: host.clone(),
+        ..HostHealth::default()
+    };
+    let fail = |mut health: HostHealth, stage: &str, error: String| {
+        health.failed_stage = Some(stage.to_string());
+        health.error = Some(error);
+        (health, None)
+    };
+    let Some(port) = parsed.as_ref().and_then(Url::port_or_known_default) else {
+        return fail(health, "dns", format!("invalid url: {}", base));
+    };
+
+    let started = Instant::now();
+    let addr = match tokio::time::timeout(HEALTH_TIMEOUT, tokio::net::lookup_host((host.as_str(), port))).await {
+        Ok(Ok(mut addrs)) => addrs.next(),
+        Ok(Err(e)) => return fail(health, "dns", e.to_string()),
+        Err(_) => return fail(health, "dns", "timed out".into()),
+    };
+    health.dns_ms = Some(elapsed_ms(started));
+    let Some(addr) = addr else {
+        return fail(health, "dns", "no address".into());
+    };
+    health.address = Some(addr.ip().to_string());
+
+    let started = Instant::now();
+    match tokio::time::timeout(HEALTH_TIMEOUT, tokio::net::TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => health.connect_ms = Some(elapsed_ms(started)),
+        Ok(Err(e)) => return fail(health, "connect", e.to_string()),
+        Err(_) => return fail(health, "connect", "timed out".into()),
+    }
+
+    let started = Instant::now();
+    let sent_at = chrono::Utc::now();
+    let resp = match request.timeout(HEALTH_TIMEOUT).send().await {
+        Ok(resp) => resp,
+        Err(e) => return fail(health, "http", e.to_string()),
+    };
+    health.ttfb_ms = Some(elapsed_ms(started));
+    health.status = Some(resp.status().as_u16());
+    if resp.url().host_str().is_some_and(|final_host| final_host != host) {
+        health.redirected_to = resp.url().host_str().map(str::to_string);
+    }
+    health.ok = true;
+
+    // Local time halfway through the request against the server's
+    let local_mid = sent_at + chrono::Duration::milliseconds(health.ttfb_ms.unwrap_or(0) as i64 / 2);
+    let skew = resp
+        .headers()
+        .get("date")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| chrono::DateTime::parse_from_rfc2822(v).ok())
+        .map(|server| (local_mid - server.with_timezone(&chrono::Utc)).num_milliseconds() as f64 / 1000.0);
+    (health, skew)
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+fn is_91160(host: &str) -> bool {
+    host == "91160.com" || host.ends_with(".91160.com")
+}
+
+/// A public host resolving to an address that cannot be on the internet
+fn poisoned_address(health: &HostHealth) -> Option<&str> {
+    if health.host.parse::<IpAddr>().is_ok() || health.host == "localhost" {
+        return None;
+    }
+    let address = health.address.as_deref()?;
+    let private = match address.parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unspecified(),
+    };
+    private.then_some(address)
+}
+
+/// Report with its overall status code and the message the UI shows
+/// Status is the first that applies: offline / dns_failed, dns_poisoned,
+/// captive_portal, degraded, qr_unavailable, clock_skew, slow, ok
+pub fn build_report(hosts: Vec<HostHealth>, clock_skew_secs: Option<f64>) -> HealthReport {
+    let (status, verdict) = verdict(&hosts, clock_skew_secs);
+    HealthReport {
+        hosts,
+        clock_skew_secs,
+        status: status.to_string(),
+        verdict,
+    }
+}
+
+fn verdict(hosts: &[HostHealth], clock_skew_secs: Option<f64>) -> (&'static str, String) {
+    let site: Vec<&HostHealth> = hosts.iter().filter(|h| is_91160(&h.host)).collect();
+    let site_down: Vec<&str> = site.iter().filter(|h| !h.ok).map(|h| h.host.as_str()).collect();
+
+    if !site.is_empty() && site_down.len() == site.len() {
+        return if site.iter().all(|h| h.failed_stage.as_deref() == Some("dns")) {
+            ("dns_failed", "无法解析 91160 域名，请检查 DNS 设置或网络连接".into())
+        } else {
+            ("offline", "无法连接 91160，请检查网络或代理设置".into())
+        };
+    }
+    if let Some((host, address)) = site.iter().find_map(|h| poisoned_address(h).map(|a| (&h.host, a))) {
+        return ("dns_poisoned", format!("{} 解析到内网地址 {}，可能被 DNS 劫持，请更换 DNS", host, address));
+    }
+    if let Some(target) = site.iter().find_map(|h| h.redirected_to.as_deref().filter(|t| !is_91160(t))) {
+        return ("captive_portal", format!("访问 91160 被重定向到 {}，可能需要先完成 Wi-Fi 认证", target));
+    }
+    if !site_down.is_empty() {
+        return ("degraded", format!("部分 91160 服务不可达: {}", site_down.join(", ")));
+    }
+    if hosts.iter().any(|h| !is_91160(&h.host) && !h.ok) {
+        return ("qr_unavailable", "微信扫码服务不可达，扫码登录可能失败".into());
+    }
+    if let Some(skew) = clock_skew_secs.filter(|s| s.abs() > CLOCK_SKEW_WARN_SECS) {
+        let direction = if skew > 0.0 { "快" } else { "慢" };
+        return ("clock_skew", format!("本机时间比服务器{} {:.1} 秒，请校准系统时间", direction, skew.abs()));
+    }
+    if let Some(slowest) = hosts.iter().filter(|h| h.ttfb_ms.is_some_and(|ms| ms > SLOW_TTFB_MS)).max_by_key(|h| h.ttfb_ms) {
+        return ("slow", format!("网络延迟较高（{} {}ms）", slowest.host, slowest.ttfb_ms.unwrap_or(0)));
+    }
+    ("ok", "网络正常".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(host: &str, address: &str, ttfb_ms: u64) -> HostHealth {
+        HostHealth {
+            host: host.into(),
+            ok: true,
+            status: Some(200),
+            address: Some(address.into()),
+            dns_ms: Some(5),
+            connect_ms: Some(20),
+            ttfb_ms: Some(ttfb_ms),
+            ..HostHealth::default()
+        }
+    }
+
+    fn down(host: &str, stage: &str) -> HostHealth {
+        HostHealth {
+            host: host.into(),
+            failed_stage: Some(stage.into()),
+            error: Some("failed".into()),
+            ..HostHealth::default()
+        }
+    }
+
+    fn healthy() -> Vec<HostHealth> {
+        vec![
+            up("www.91160.com", "122.13.1.10", 80),
+            up("gate.91160.com", "122.13.1.11", 90),
+            up("user.91160.com", "122.13.1.12", 70),
+            up("open.weixin.qq.com", "109.244.1.1", 60),
+        ]
+    }
+
+    fn status(hosts: Vec<HostHealth>, skew: Option<f64>) -> String {
+        build_report(hosts, skew).status
+    }
+
+    #[test]
+    fn test_verdict_ok() {
+        let report = build_report(healthy(), Some(0.4));
+        assert_eq!(report.status, "ok");
+        assert_eq!(report.verdict, "网络正常");
+        assert_eq!(report.hosts.len(), 4);
+        assert_eq!(report.clock_skew_secs, Some(0.4));
+    }
+
+    #[test]
+    fn test_verdict_all_site_hosts_down() {
+        let mut hosts = healthy();
+        for host in &mut hosts[..3] {
+            *host = down(&host.host.clone(), "dns");
+        }
+        assert_eq!(status(hosts.clone(), None), "dns_failed");
+
+        hosts[1] = down("gate.91160.com", "connect");
+        assert_eq!(status(hosts, None), "offline");
+    }
+
+    #[test]
+    fn test_verdict_dns_poisoning_and_captive_portal() {
+        let mut hosts = healthy();
+        hosts[0].address = Some("10.0.0.1".into());
+        let report = build_report(hosts, None);
+        assert_eq!(report.status, "dns_poisoned");
+        assert!(report.verdict.contains("www.91160.com") && report.verdict.contains("10.0.0.1"));
+
+        let mut hosts = healthy();
+        hosts[2].redirected_to = Some("portal.hotel-wifi.cn".into());
+        let report = build_report(hosts, None);
+        assert_eq!(report.status, "captive_portal");
+        assert!(report.verdict.contains("portal.hotel-wifi.cn"));
+
+        // A redirect inside 91160 is normal
+        let mut hosts = healthy();
+        hosts[0].redirected_to = Some("sz.91160.com".into());
+        assert_eq!(status(hosts, None), "ok");
+    }
+
+    #[test]
+    fn test_verdict_partial_failures() {
+        let mut hosts = healthy();
+        hosts[1] = down("gate.91160.com", "http");
+        let report = build_report(hosts, Some(10.0));
+        assert_eq!(report.status, "degraded");
+        assert!(report.verdict.contains("gate.91160.com"));
+
+        let mut hosts = healthy();
+        hosts[3] = down("open.weixin.qq.com", "connect");
+        assert_eq!(status(hosts, None), "qr_unavailable");
+    }
+
+    #[test]
+    fn test_verdict_clock_skew_and_slow() {
+        let report = build_report(healthy(), Some(-5.25));
+        assert_eq!(report.status, "clock_skew");
+        assert!(report.verdict.contains("慢 5.2"));
+        assert_eq!(status(healthy(), Some(3.0)), "ok");
+
+        let mut hosts = healthy();
+        hosts[1].ttfb_ms = Some(2500);
+        let report = build_report(hosts, None);
+        assert_eq!(report.status, "slow");
+        assert!(report.verdict.contains("gate.91160.com 2500ms"));
+    }
+
+    #[test]
+    fn test_poisoned_address_ignores_ip_and_localhost_hosts() {
+        assert_eq!(poisoned_address(&up("127.0.0.1", "127.0.0.1", 1)), None);
+        assert_eq!(poisoned_address(&up("localhost", "127.0.0.1", 1)), None);
+        assert_eq!(poisoned_address(&up("www.91160.com", "127.0.0.1", 1)), Some("127.0.0.1"));
+        assert_eq!(poisoned_address(&up("www.91160.com", "122.13.1.10", 1)), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Grab attempt history for QuickDoctor
+//! One JSON line per finished run in grab_history.jsonl, newest last; the
+//! file is rewritten atomically on every append and trimmed to the
+//! retention limit
+
+use std::fs;
+use std::path::Path;
+
+use super::errors::AppResult;
+use super::fsutil::write_atomic;
+use super::paths::grab_history_path;
+use super::types::GrabHistoryEntry;
+
+/// Entries kept in the history file
+pub const MAX_HISTORY_ENTRIES: usize = 500;
+
+/// Append an entry to the history file
+pub fn append_grab_history(entry: &GrabHistoryEntry) -> AppResult<()> {
+    append_grab_history_to(&grab_history_path()?, entry, MAX_HISTORY_ENTRIES)
+}
+
+/// Append an entry at `path`, keeping only the newest `keep` entries
+pub fn append_grab_history_to(path: &Path, entry: &GrabHistoryEntry, keep: usize) -> AppResult<()> {
+    let mut entries = read_entries(path)?;
+    entries.push(entry.clone());
+    write_entries(path, &entries, keep)
+}
+
+/// Up to `limit` entries, newest first
+pub fn load_grab_history(limit: usize) -> AppResult<Vec<GrabHistoryEntry>> {
+    load_grab_history_from(&grab_history_path()?, limit)
+}
+
+/// Up to `limit` entries at `path`, newest first
+pub fn load_grab_history_from(path: &Path, limit: usize) -> AppResult<Vec<GrabHistoryEntry>> {
+    let mut entries = read_entries(path)?;
+    entries.reverse();
+    entries.truncate(limit);
+    Ok(entries)
+}
+
+/// Remove all history
+pub fn clear_grab_history() -> AppResult<()> {
+    clear_grab_history_at(&grab_history_path()?)
+}
+
+/// Remove the history file at `path`
+pub fn clear_grab_history_at(path: &Path) -> AppResult<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Drop unreadable lines and entries beyond the retention limit (run at startup)
+pub fn compact_grab_history() -> AppResult<()> {
+    compact_grab_history_at(&grab_history_path()?, MAX_HISTORY_ENTRIES)
+}
+
+/// Compact the history file at `path`, keeping the newest `keep` entries
+pub fn compact_grab_history_at(path: &Path, keep: usize) -> AppResult<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let entries = read_entries(path)?;
+    write_entries(path, &entries, keep)
+}
+
+/// Parse every readable line at `path`, oldest first; corrupt lines are skipped
+fn read_entries(path: &Path) -> AppResult<Vec<GrabHistoryEntry>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for (index, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!(line = index + 1, error = %e, "skipping corrupt grab history line"),
+        }
+    }
+    Ok(entries)
+}
+
+fn write_entries(path: &Path, entries: &[GrabHistoryEntry], keep: usize) -> AppResult<()> {
+    let skip = entries.len().saturating_sub(keep);
+    let mut data = String::new();
+    for entry in &entries[skip..] {
+        data.push_str(&serde_json::to_string(entry)?);
+        data.push('\n');
+    }
+    write_atomic(path, data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(n: u32) -> GrabHistoryEntry {
+        GrabHistoryEntry {
+            started_at: format!("2024-03-20 08:00:{:02}", n),
+            finished_at: format!("2024-03-20 08:01:{:02}", n),
+            unit_id: "u1".into(),
+            unit_name: String::new(),
+            dep_id: "d1".into(),
+            dep_name: String::new(),
+            target_dates: vec!["2024-03-21".into()],
+            queries: n,
+            outcome: "failure".into(),
+            message: "max retries reached".into(),
+            detail: None,
+        }
+    }
+
+    fn queries(entries: &[GrabHistoryEntry]) -> Vec<u32> {
+        entries.iter().map(|e| e.queries).collect()
+    }
+
+    #[test]
+    fn test_append_and_load_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("grab_history.jsonl");
+        assert!(load_grab_history_from(&path, 10).unwrap().is_empty());
+
+        for n in 1..=3 {
+            append_grab_history_to(&path, &entry(n), MAX_HISTORY_ENTRIES).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(queries(&load_grab_history_from(&path, 10).unwrap()), vec![3, 2, 1]);
+        assert_eq!(queries(&load_grab_history_from(&path, 2).unwrap()), vec![3, 2]);
+
+        clear_grab_history_at(&path).unwrap();
+        clear_grab_history_at(&path).unwrap();
+        assert!(load_grab_history_from(&path, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_append_enforces_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("grab_history.jsonl");
+        for n in 1..=5 {
+            append_grab_history_to(&path, &entry(n), 3).unwrap();
+        }
+        assert_eq!(queries(&load_grab_history_from(&path, 10).unwrap()), vec![5, 4, 3]);
+    }
+
+    #[test]
+    fn test_corrupt_lines_are_skipped_and_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("grab_history.jsonl");
+        let line = |n| serde_json::to_string(&entry(n)).unwrap();
+        fs::write(&path, format!("{}\n{{\"started_at\": \"trunc\n\n{}\nnot json\n{}\n", line(1), line(2), line(3))).unwrap();
+
+        assert_eq!(queries(&load_grab_history_from(&path, 10).unwrap()), vec![3, 2, 1]);
+
+        compact_grab_history_at(&path, 2).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n{}\n", line(2), line(3)));
+    }
+}
@@ -0,0 +1,258 @@
+//! Disk cache for catalog responses in QuickDoctor
+//! Hospital and department lists, revalidated with ETag/Last-Modified after a TTL
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+
+use super::errors::AppResult;
+use super::fsutil::write_atomic;
+
+/// Endpoints whose responses may be cached
+pub const CACHEABLE_ENDPOINTS: [&str; 2] = ["hospitals", "deps"];
+/// Total size the cache directory is trimmed to, oldest entries first
+pub const HTTP_CACHE_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+pub fn is_cacheable(endpoint: &str) -> bool {
+    CACHEABLE_ENDPOINTS.contains(&endpoint)
+}
+
+/// One stored response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub body: String,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Unix seconds of the last fetch or 304 revalidation
+    pub stored_at: i64,
+}
+
+impl CachedResponse {
+    /// Whether the entry may be served without asking the server
+    pub fn is_fresh(&self, ttl: Duration, now: i64) -> bool {
+        let age = now.saturating_sub(self.stored_at);
+        (0..i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX)).contains(&age)
+    }
+
+    /// Conditional request headers for revalidating this entry
+    pub fn validators(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let pairs = [(IF_NONE_MATCH, &self.etag), (IF_MODIFIED_SINCE, &self.last_modified)];
+        for (name, value) in pairs {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+}
+
+/// Catalog responses on disk, keyed by endpoint and request params
+#[derive(Debug, Clone)]
+pub struct HttpCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+}
+
+impl HttpCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self {
+            dir,
+            ttl,
+            max_bytes: HTTP_CACHE_MAX_BYTES,
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stored entry for a request, fresh or not
+    pub fn get(&self, endpoint: &str, params: &[(&str, &str)]) -> Option<CachedResponse> {
+        if !is_cacheable(endpoint) {
+            return None;
+        }
+        let text = fs::read_to_string(self.entry_path(endpoint, params)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Body of an entry still within the TTL
+    pub fn fresh(&self, endpoint: &str, params: &[(&str, &str)], now: i64) -> Option<String> {
+        self.get(endpoint, params)
+            .filter(|entry| entry.is_fresh(self.ttl, now))
+            .map(|entry| entry.body)
+    }
+
+    /// The server answered 304: restart the entry's TTL and hand back its body
+    pub fn revalidated(&self, endpoint: &str, params: &[(&str, &str)], now: i64) -> Option<String> {
+        let mut entry = self.get(endpoint, params)?;
+        entry.stored_at = now;
+        self.write(endpoint, params, &entry);
+        Some(entry.body)
+    }
+
+    /// Remember a successful response with the validators from its headers
+    pub fn store(&self, endpoint: &str, params: &[(&str, &str)], body: &str, headers: &HeaderMap, now: i64) {
+        if !is_cacheable(endpoint) {
+            return;
+        }
+        let header = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok()).map(str::to_string);
+        let entry = CachedResponse {
+            body: body.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            stored_at: now,
+        };
+        self.write(endpoint, params, &entry);
+        self.trim();
+    }
+
+    /// Delete every entry; returns how many were removed
+    pub fn clear(&self) -> AppResult<usize> {
+        let mut removed = 0;
+        for (path, _, _) in self.entries() {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// A failed write only costs a refetch later
+    fn write(&self, endpoint: &str, params: &[(&str, &str)], entry: &CachedResponse) {
+        let path = self.entry_path(endpoint, params);
+        let result = serde_json::to_vec(entry).map_err(Into::into).and_then(|data| write_atomic(&path, &data));
+        if let Err(e) = result {
+            tracing::warn!(path = %path.display(), error = %e, "http cache write failed");
+        }
+    }
+
+    /// Drop the oldest entries until the directory fits `max_bytes`
+    fn trim(&self) {
+        let mut entries = self.entries();
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, len, _) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+    }
+
+    /// Cache files with their size and modification time
+    fn entries(&self) -> Vec<(PathBuf, u64, std::time::SystemTime)> {
+        let Ok(read) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        read.filter_map(|entry| {
+            let entry = entry.ok()?;
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                return None;
+            }
+            let meta = entry.metadata().ok()?;
+            Some((path, meta.len(), meta.modified().ok()?))
+        })
+        .collect()
+    }
+
+    fn entry_path(&self, endpoint: &str, params: &[(&str, &str)]) -> PathBuf {
+        let mut name = endpoint.to_string();
+        for (key, value) in params {
+            name.push_str(&format!("-{}_{}", key, value));
+        }
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '~' })
+            .collect();
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(600);
+
+    fn cache() -> (tempfile::TempDir, HttpCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(dir.path().join("cache"), TTL);
+        (dir, cache)
+    }
+
+    fn validators(etag: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_str(etag).unwrap());
+        headers.insert(LAST_MODIFIED, HeaderValue::from_static("Wed, 20 Mar 2024 08:00:00 GMT"));
+        headers
+    }
+
+    #[test]
+    fn test_entries_expire_after_ttl() {
+        let (_dir, cache) = cache();
+        let params = [("c", "5")];
+        cache.store("hospitals", &params, "[1]", &validators("\"v1\""), 1_000);
+
+        assert_eq!(cache.fresh("hospitals", &params, 1_000 + 599).as_deref(), Some("[1]"));
+        assert_eq!(cache.fresh("hospitals", &params, 1_000 + 600), None);
+        // Other params are another entry
+        assert_eq!(cache.fresh("hospitals", &[("c", "6")], 1_000), None);
+
+        // Stale entries stay around for revalidation
+        let entry = cache.get("hospitals", &params).unwrap();
+        let headers = entry.validators();
+        assert_eq!(headers[IF_NONE_MATCH], "\"v1\"");
+        assert_eq!(headers[IF_MODIFIED_SINCE], "Wed, 20 Mar 2024 08:00:00 GMT");
+
+        // A 304 starts the TTL over
+        assert_eq!(cache.revalidated("hospitals", &params, 5_000).as_deref(), Some("[1]"));
+        assert_eq!(cache.fresh("hospitals", &params, 5_100).as_deref(), Some("[1]"));
+        assert_eq!(cache.revalidated("hospitals", &[("c", "6")], 5_000), None);
+    }
+
+    #[test]
+    fn test_only_allow_listed_endpoints_are_stored() {
+        let (_dir, cache) = cache();
+        for endpoint in ["schedule", "ticket_detail", "submit"] {
+            cache.store(endpoint, &[("unit_id", "21")], "{}", &HeaderMap::new(), 1_000);
+            assert_eq!(cache.get(endpoint, &[("unit_id", "21")]), None);
+        }
+        assert!(!cache.dir().exists());
+
+        cache.store("deps", &[("keyValue", "21"), ("city", "sz")], "[]", &HeaderMap::new(), 1_000);
+        let entry = cache.get("deps", &[("keyValue", "21"), ("city", "sz")]).unwrap();
+        assert!(entry.validators().is_empty());
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.get("deps", &[("keyValue", "21"), ("city", "sz")]), None);
+    }
+
+    #[test]
+    fn test_size_cap_drops_oldest() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(dir.path().to_path_buf(), TTL).with_max_bytes(500);
+        let body = "x".repeat(150);
+        cache.store("deps", &[("keyValue", "1")], &body, &HeaderMap::new(), 1_000);
+        std::thread::sleep(Duration::from_millis(20));
+        cache.store("deps", &[("keyValue", "2")], &body, &HeaderMap::new(), 1_000);
+        std::thread::sleep(Duration::from_millis(20));
+        cache.store("deps", &[("keyValue", "3")], &body, &HeaderMap::new(), 1_000);
+
+        assert_eq!(cache.get("deps", &[("keyValue", "1")]), None);
+        assert!(cache.get("deps", &[("keyValue", "2")]).is_some());
+        assert!(cache.get("deps", &[("keyValue", "3")]).is_some());
+    }
+}
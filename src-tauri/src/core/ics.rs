@@ -0,0 +1,192 @@
+//! iCalendar (RFC 5545) export for QuickDoctor
+//! Turns a successful grab into a single-event VCALENDAR the user can import
+//! into any calendar app
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+
+use super::errors::{AppError, AppResult};
+use super::types::{parse_slot_range, GrabSuccess};
+
+/// Length of the event when the time slot gives a start time
+pub const DEFAULT_EVENT_MINUTES: i64 = 60;
+/// Longest content line in octets before folding
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Build the calendar for `success`; `now` becomes DTSTAMP.
+/// A time slot without a readable start time yields an all-day event.
+pub fn build_appointment_ics(success: &GrabSuccess, now: DateTime<Utc>) -> AppResult<String> {
+    let date = NaiveDate::parse_from_str(success.date.trim(), "%Y-%m-%d")
+        .map_err(|_| AppError::ParseError(format!("invalid appointment date: {}", success.date)))?;
+
+    let summary = format!("就诊: {}/{}/{}", success.unit_name, success.dep_name, success.doctor_name);
+    let mut description = format!("就诊人: {}", success.member_name);
+    if !success.time_slot.trim().is_empty() {
+        description.push_str(&format!("\n时段: {}", success.time_slot.trim()));
+    }
+    if let Some(order_no) = success.confirmation.as_ref().map(|c| c.order_no.trim()).filter(|n| !n.is_empty()) {
+        description.push_str(&format!("\n订单号: {}", order_no));
+    }
+    if let Some(url) = success.url.as_deref().filter(|u| !u.trim().is_empty()) {
+        description.push_str(&format!("\n订单: {}", url.trim()));
+    }
+
+    let stamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//SkylineMed//QuickDoctor//ZH".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}-{}@quickdoctor", stamp, date.format("%Y%m%d")),
+        format!("DTSTAMP:{}", stamp),
+    ];
+    match slot_start(&success.time_slot) {
+        Some(start) => {
+            let start = date.and_time(start);
+            lines.push(format!("DTSTART:{}", format_local(start)));
+            lines.push(format!("DTEND:{}", format_local(start + Duration::minutes(DEFAULT_EVENT_MINUTES))));
+        }
+        None => {
+            lines.push(format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")));
+            lines.push(format!("DTEND;VALUE=DATE:{}", (date + Duration::days(1)).format("%Y%m%d")));
+        }
+    }
+    lines.push(format!("SUMMARY:{}", escape_text(&summary)));
+    lines.push(format!("DESCRIPTION:{}", escape_text(&description)));
+    if let Some(url) = success.url.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        lines.push(format!("URL:{}", url));
+    }
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+
+    Ok(lines.iter().map(|line| fold_line(line) + "\r\n").collect())
+}
+
+/// Start of a slot label such as "09:00-09:30" or a bare "09:00"
+fn slot_start(time_slot: &str) -> Option<chrono::NaiveTime> {
+    parse_slot_range(time_slot).map(|(start, _)| start).or_else(|| {
+        chrono::NaiveTime::parse_from_str(time_slot.trim(), "%H:%M").ok()
+    })
+}
+
+/// Floating local date-time (no zone), read in the calendar's own timezone
+fn format_local(value: NaiveDateTime) -> String {
+    value.format("%Y%m%dT%H%M%S").to_string()
+}
+
+/// Escape a TEXT value (RFC 5545 §3.3.11)
+pub fn escape_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Fold a content line at 75 octets without splitting a UTF-8 character
+fn fold_line(line: &str) -> String {
+    let mut out = String::with_capacity(line.len() + line.len() / MAX_LINE_OCTETS * 3);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            // The leading space counts toward the continuation line
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::core::types::OrderConfirmation;
+
+    fn success(time_slot: &str) -> GrabSuccess {
+        GrabSuccess {
+            unit_name: "深圳市人民医院".into(),
+            dep_name: "心内科".into(),
+            doctor_name: "张医生".into(),
+            date: "2024-03-20".into(),
+            time_slot: time_slot.into(),
+            member_name: "李四".into(),
+            dep_id: String::new(),
+            doctor_id: String::new(),
+            address_id: String::new(),
+            address: String::new(),
+            url: Some("https://www.91160.com/order/success.html?id=1".into()),
+            confirmation: None,
+            note: None,
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 18, 23, 5, 9).unwrap()
+    }
+
+    /// Unfold continuation lines back into logical lines
+    fn unfold(ics: &str) -> Vec<String> {
+        ics.replace("\r\n ", "").split("\r\n").filter(|l| !l.is_empty()).map(String::from).collect()
+    }
+
+    #[test]
+    fn test_timed_event_from_slot_start() {
+        let ics = build_appointment_ics(&success("09:30-10:00"), now()).unwrap();
+        let lines = unfold(&ics);
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(lines.contains(&"DTSTAMP:20240318T230509Z".to_string()));
+        assert!(lines.contains(&"DTSTART:20240320T093000".to_string()));
+        assert!(lines.contains(&"DTEND:20240320T103000".to_string()));
+        assert!(lines.contains(&"SUMMARY:就诊: 深圳市人民医院/心内科/张医生".to_string()));
+        assert!(lines.contains(&"URL:https://www.91160.com/order/success.html?id=1".to_string()));
+    }
+
+    #[test]
+    fn test_unparseable_slot_is_all_day() {
+        let ics = build_appointment_ics(&success("上午"), now()).unwrap();
+        let lines = unfold(&ics);
+        assert!(lines.contains(&"DTSTART;VALUE=DATE:20240320".to_string()));
+        assert!(lines.contains(&"DTEND;VALUE=DATE:20240321".to_string()));
+
+        let mut bad = success("09:00-09:30");
+        bad.date = "2024/03/20".into();
+        assert!(build_appointment_ics(&bad, now()).is_err());
+    }
+
+    #[test]
+    fn test_escaping_and_description() {
+        assert_eq!(escape_text("a,b;c\\d\r\ne"), r"a\,b\;c\\d\ne");
+
+        let mut s = success("14:00");
+        s.doctor_name = "王医生,主任".into();
+        s.confirmation = Some(OrderConfirmation { order_no: "NO123".into(), ..Default::default() });
+        let lines = unfold(&build_appointment_ics(&s, now()).unwrap());
+        assert!(lines.contains(&"DTSTART:20240320T140000".to_string()));
+        assert!(lines.iter().any(|l| l == "SUMMARY:就诊: 深圳市人民医院/心内科/王医生\\,主任"));
+        let description = lines.iter().find(|l| l.starts_with("DESCRIPTION:")).unwrap();
+        assert_eq!(
+            description,
+            "DESCRIPTION:就诊人: 李四\\n时段: 14:00\\n订单号: NO123\\n订单: https://www.91160.com/order/success.html?id=1"
+        );
+    }
+
+    #[test]
+    fn test_long_lines_fold_on_char_boundaries() {
+        let line = format!("SUMMARY:{}", "医".repeat(40));
+        let folded = fold_line(&line);
+        for part in folded.split("\r\n") {
+            assert!(part.len() <= MAX_LINE_OCTETS, "{} octets", part.len());
+        }
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+}
@@ -0,0 +1,192 @@
+//! Grab identity check for QuickDoctor
+//! Names the account a grab will use and holds it until confirmed when required
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::types::{AccountInfo, GrabConfig};
+
+/// How long a held start waits for the user's confirmation
+pub const CONFIRMATION_TTL: Duration = Duration::from_secs(120);
+
+/// The account a grab is about to run with
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GrabIdentity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// As shown on the site, e.g. 138****1234
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_masked: Option<String>,
+    /// Phone, else username, else "未知账号"
+    pub label: String,
+}
+
+impl GrabIdentity {
+    pub fn from_account(info: &AccountInfo) -> Self {
+        let label = info
+            .phone_masked
+            .clone()
+            .or_else(|| info.username.clone())
+            .unwrap_or_else(|| "未知账号".to_string());
+        Self {
+            username: info.username.clone(),
+            phone_masked: info.phone_masked.clone(),
+            label,
+        }
+    }
+
+    /// The account page could not be read; the grab may still go ahead
+    pub fn unknown() -> Self {
+        Self::from_account(&AccountInfo::default())
+    }
+}
+
+/// Reply to a start request
+#[derive(Debug, Clone, Serialize)]
+pub struct GrabStart {
+    /// "started" or "needs_confirmation"
+    pub status: &'static str,
+    pub identity: GrabIdentity,
+}
+
+impl GrabStart {
+    pub fn started(identity: GrabIdentity) -> Self {
+        Self { status: "started", identity }
+    }
+
+    pub fn needs_confirmation(identity: GrabIdentity) -> Self {
+        Self { status: "needs_confirmation", identity }
+    }
+}
+
+/// Starts shown to the user and waiting for confirmation, keyed by config
+#[derive(Debug, Default)]
+pub struct PendingStarts {
+    pending: Mutex<HashMap<String, (GrabIdentity, Instant)>>,
+}
+
+impl PendingStarts {
+    /// Remember that the user was asked to confirm `identity` for `config`
+    pub fn hold(&self, config: &GrabConfig, identity: &GrabIdentity, now: Instant) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.retain(|_, (_, held_at)| now.saturating_duration_since(*held_at) < CONFIRMATION_TTL);
+        pending.insert(config_key(config), (identity.clone(), now));
+    }
+
+    /// Take the held start for `config`. False when there is none, it expired,
+    /// or the session now belongs to another account than the one confirmed.
+    pub fn confirm(&self, config: &GrabConfig, identity: &GrabIdentity, now: Instant) -> bool {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        match pending.remove(&config_key(config)) {
+            Some((held, held_at)) => now.saturating_duration_since(held_at) < CONFIRMATION_TTL && held == *identity,
+            None => false,
+        }
+    }
+}
+
+/// Canonical text of a config: going through `Value` sorts object keys, so the
+/// HashMap fields hash to the same key however they were iterated.
+fn config_key(config: &GrabConfig) -> String {
+    serde_json::to_value(config).map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(member_id: &str) -> GrabConfig {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": "21",
+            "dep_id": "100",
+            "member_id": member_id,
+            "target_dates": ["2024-03-20"],
+            "require_identity_confirmation": true,
+        }))
+        .unwrap()
+    }
+
+    fn identity(phone: &str) -> GrabIdentity {
+        GrabIdentity::from_account(&AccountInfo {
+            username: Some("张三".into()),
+            phone_masked: Some(phone.into()),
+            ..AccountInfo::default()
+        })
+    }
+
+    #[test]
+    fn test_identity_label() {
+        assert_eq!(identity("138****1234").label, "138****1234");
+        let named = GrabIdentity::from_account(&AccountInfo {
+            username: Some("张三".into()),
+            ..AccountInfo::default()
+        });
+        assert_eq!(named.label, "张三");
+        assert_eq!(GrabIdentity::unknown().label, "未知账号");
+    }
+
+    #[test]
+    fn test_confirm_within_ttl_once() {
+        let pending = PendingStarts::default();
+        let now = Instant::now();
+        let me = identity("138****1234");
+        assert!(!pending.confirm(&config("1001"), &me, now));
+
+        pending.hold(&config("1001"), &me, now);
+        assert!(!pending.confirm(&config("1002"), &me, now));
+        assert!(pending.confirm(&config("1001"), &me, now + Duration::from_secs(5)));
+        // Used up by the start it confirmed
+        assert!(!pending.confirm(&config("1001"), &me, now + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn test_unconfirmed_start_expires() {
+        let pending = PendingStarts::default();
+        let now = Instant::now();
+        let me = identity("138****1234");
+        pending.hold(&config("1001"), &me, now);
+        assert!(!pending.confirm(&config("1001"), &me, now + CONFIRMATION_TTL));
+
+        // Expired holds are dropped when a new start is held
+        pending.hold(&config("1001"), &me, now);
+        pending.hold(&config("1002"), &me, now + CONFIRMATION_TTL);
+        assert_eq!(pending.pending.lock().unwrap().len(), 1);
+        assert!(pending.confirm(&config("1002"), &me, now + CONFIRMATION_TTL + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_confirm_rejects_switched_account() {
+        let pending = PendingStarts::default();
+        let now = Instant::now();
+        pending.hold(&config("1001"), &identity("138****1234"), now);
+        assert!(!pending.confirm(&config("1001"), &identity("139****9999"), now));
+    }
+
+    #[test]
+    fn test_config_key_ignores_map_order() {
+        let raw = serde_json::json!({
+            "unit_id": "21",
+            "dep_id": "100",
+            "member_id": "1001",
+            "target_dates": ["2024-03-20", "2024-03-21", "2024-03-22"],
+            "date_doctor_map": {
+                "2024-03-20": ["201", "202"],
+                "2024-03-21": ["203"],
+                "2024-03-22": ["204"],
+                "2024-03-23": ["205"],
+            },
+            "require_identity_confirmation": true,
+        });
+        let pending = PendingStarts::default();
+        let now = Instant::now();
+        let me = identity("138****1234");
+        for _ in 0..8 {
+            let first: GrabConfig = serde_json::from_value(raw.clone()).unwrap();
+            let second: GrabConfig = serde_json::from_value(raw.clone()).unwrap();
+            pending.hold(&first, &me, now);
+            assert!(pending.confirm(&second, &me, now));
+        }
+    }
+}
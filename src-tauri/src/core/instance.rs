@@ -0,0 +1,294 @@
+//! Single-instance lock for SkylineMed
+//! Refuses a second copy while `instance.lock` names a live owner; crash leftovers are taken over
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::errors::{AppError, AppResult};
+
+pub const LOCK_FILE_NAME: &str = "instance.lock";
+
+/// Process recorded in the lock file: a PID plus the process name, so a PID
+/// reused by an unrelated program does not keep the lock alive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub name: Option<String>,
+}
+
+impl LockOwner {
+    /// The running process
+    pub fn current() -> Self {
+        let pid = std::process::id();
+        Self { pid, name: process_name(pid) }
+    }
+
+    fn to_file(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{}\n{}\n", self.pid, name),
+            None => format!("{}\n", self.pid),
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        let mut lines = raw.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let name = lines.next().map(str::trim).filter(|n| !n.is_empty()).map(String::from);
+        Some(Self { pid, name })
+    }
+}
+
+/// Held for the lifetime of the app; the lock file goes away on release/drop
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+    owner: LockOwner,
+}
+
+impl InstanceLock {
+    /// Lock `dir` for this process
+    pub fn acquire(dir: &Path) -> AppResult<Self> {
+        Self::acquire_as(dir, LockOwner::current(), owner_alive)
+    }
+
+    /// Lock `dir` for `owner`; `is_alive` decides whether a recorded owner is stale
+    fn acquire_as(dir: &Path, owner: LockOwner, is_alive: impl Fn(&LockOwner) -> bool) -> AppResult<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE_NAME);
+        // A second round covers another instance taking over the same stale lock
+        for _ in 0..2 {
+            if publish(&path, &owner)? {
+                return Ok(Self { path, owner });
+            }
+
+            let raw = fs::read_to_string(&path).unwrap_or_default();
+            let holder = LockOwner::parse(&raw);
+            if let Some(holder) = &holder {
+                if holder.pid != owner.pid && is_alive(holder) {
+                    return Err(AppError::AlreadyRunning { pid: holder.pid });
+                }
+            }
+            tracing::warn!(holder = ?holder, "removing stale instance lock");
+            discard_stale(&path, &raw, owner.pid)?;
+        }
+        match read_owner(&path) {
+            Some(holder) => Err(AppError::AlreadyRunning { pid: holder.pid }),
+            None => Err(AppError::Other(format!("could not lock {}", path.display()))),
+        }
+    }
+
+    /// Remove the lock file if it is still ours; safe to call more than once
+    pub fn release(&self) {
+        if read_owner(&self.path).as_ref() == Some(&self.owner) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                tracing::warn!(error = %e, "failed to remove instance lock");
+            }
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Write the owner to a temp file, then link it into place. The link fails
+/// when the lock exists, and a lock that exists is always complete, so a
+/// concurrent launch never sees an empty file. Ok(false): already locked.
+fn publish(path: &Path, owner: &LockOwner) -> AppResult<bool> {
+    let tmp = path.with_extension(format!("lock.{}.tmp", owner.pid));
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(owner.to_file().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        match fs::hard_link(&tmp, path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    })();
+    let _ = fs::remove_file(&tmp);
+    Ok(result?)
+}
+
+/// Move a stale lock aside and delete it. If what was moved is no longer the
+/// content judged stale, another instance took the lock over in between: put
+/// it back and let the caller re-check it.
+fn discard_stale(path: &Path, stale: &str, pid: u32) -> AppResult<()> {
+    let aside = path.with_extension(format!("lock.{}.stale", pid));
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    if fs::read_to_string(&aside).unwrap_or_default() != stale {
+        let _ = fs::hard_link(&aside, path);
+    }
+    let _ = fs::remove_file(&aside);
+    Ok(())
+}
+
+/// Owner recorded in a lock file; None when missing or unreadable
+fn read_owner(path: &Path) -> Option<LockOwner> {
+    LockOwner::parse(&fs::read_to_string(path).ok()?)
+}
+
+/// Whether the recorded owner is still running: the PID exists and, when a
+/// name was recorded, still belongs to a process of that name
+pub fn owner_alive(owner: &LockOwner) -> bool {
+    match (process_name(owner.pid), &owner.name) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(running), Some(recorded)) => same_name(&running, recorded),
+    }
+}
+
+/// Linux truncates `comm` to 15 bytes, Windows adds ".exe"
+fn same_name(a: &str, b: &str) -> bool {
+    let short = |n: &str| n.trim_end_matches(".exe").bytes().take(15).map(|b| b.to_ascii_lowercase()).collect::<Vec<_>>();
+    short(a) == short(b)
+}
+
+/// Name of the process with this PID, None when it is not running. When the
+/// check itself fails the PID counts as gone, so a broken check never locks
+/// the user out.
+pub fn process_name(pid: u32) -> Option<String> {
+    if cfg!(target_os = "linux") {
+        let comm = fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("comm")).ok()?;
+        return Some(comm.trim().to_string()).filter(|n| !n.is_empty());
+    }
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("tasklist");
+        command.args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"]);
+        command
+    } else {
+        let mut command = std::process::Command::new("ps");
+        command.args(["-p", &pid.to_string(), "-o", "comm="]);
+        command
+    };
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW: no console flash at startup
+        command.creation_flags(0x0800_0000);
+    }
+    let output = match command.stderr(std::process::Stdio::null()).output() {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::warn!(error = %e, pid, "process liveness check failed");
+            return None;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if cfg!(target_os = "windows") {
+        // "skylinemed.exe","1234","Console","1","12,345 K"
+        let mut fields = stdout.trim().split(',').map(|f| f.trim_matches('"'));
+        let name = fields.next()?;
+        return (fields.next()? == pid.to_string()).then(|| name.to_string());
+    }
+    if !output.status.success() {
+        return None;
+    }
+    let path = stdout.trim();
+    let name = Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned());
+    name.filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OTHER_PID: u32 = 4_000_001;
+
+    fn owner(pid: u32) -> LockOwner {
+        LockOwner { pid, name: Some("skylinemed".into()) }
+    }
+
+    fn lock_owner(dir: &Path) -> Option<LockOwner> {
+        read_owner(&dir.join(LOCK_FILE_NAME))
+    }
+
+    #[test]
+    fn test_second_instance_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = InstanceLock::acquire_as(dir.path(), owner(OTHER_PID), |_| true).unwrap();
+        assert_eq!(lock_owner(dir.path()), Some(owner(OTHER_PID)));
+
+        let err = InstanceLock::acquire_as(dir.path(), owner(42), |o| o.pid == OTHER_PID).unwrap_err();
+        assert!(matches!(err, AppError::AlreadyRunning { pid: OTHER_PID }), "{}", err);
+        assert_eq!(lock_owner(dir.path()), Some(owner(OTHER_PID)));
+
+        // Once the first instance exits the lock is free again
+        drop(first);
+        assert!(!dir.path().join(LOCK_FILE_NAME).exists());
+        let second = InstanceLock::acquire_as(dir.path(), owner(42), |_| true).unwrap();
+        assert_eq!(lock_owner(dir.path()), Some(owner(42)));
+        second.release();
+        second.release();
+        assert!(!dir.path().join(LOCK_FILE_NAME).exists());
+        // No temp or stale files left next to the lock
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_stale_lock_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+        // Left by a crashed instance, a garbled write, or ourselves before a restart
+        for leftover in [OTHER_PID.to_string(), String::new(), "not a pid".into(), "42\nskylinemed".into()] {
+            fs::write(&path, &leftover).unwrap();
+            let lock = InstanceLock::acquire_as(dir.path(), owner(42), |o| o.pid != OTHER_PID).unwrap();
+            assert_eq!(lock_owner(dir.path()), Some(owner(42)), "{:?}", leftover);
+            drop(lock);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_reused_pid_does_not_hold_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let me = LockOwner::current();
+        // Our own PID recorded under another program's name: the holder is gone
+        let reused = LockOwner { pid: me.pid, name: Some("not-skylinemed".into()) };
+        assert!(!owner_alive(&reused));
+        assert!(owner_alive(&me));
+
+        fs::write(dir.path().join(LOCK_FILE_NAME), reused.to_file()).unwrap();
+        let lock = InstanceLock::acquire_as(dir.path(), owner(42), owner_alive).unwrap();
+        assert_eq!(lock_owner(dir.path()), Some(owner(42)));
+        drop(lock);
+    }
+
+    #[test]
+    fn test_release_keeps_a_lock_taken_over_by_another_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = InstanceLock::acquire_as(dir.path(), owner(42), |_| false).unwrap();
+        fs::write(dir.path().join(LOCK_FILE_NAME), owner(OTHER_PID).to_file()).unwrap();
+        drop(lock);
+        assert_eq!(lock_owner(dir.path()), Some(owner(OTHER_PID)));
+    }
+
+    #[test]
+    fn test_discard_stale_restores_a_fresh_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+        // Judged stale as "4000001", but another launch replaced it meanwhile
+        fs::write(&path, owner(43).to_file()).unwrap();
+        discard_stale(&path, &OTHER_PID.to_string(), 42).unwrap();
+        assert_eq!(lock_owner(dir.path()), Some(owner(43)));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_process_name() {
+        assert!(process_name(std::process::id()).is_some());
+        assert!(process_name(u32::MAX - 1).is_none());
+        assert!(same_name("skylinemed.exe", "skylinemed"));
+        assert!(same_name("quick_doctor_li", "quick_doctor_lib-0123abcd"));
+        assert!(!same_name("skylinemed", "bash"));
+    }
+}
@@ -0,0 +1,182 @@
+//! Login keep-alive scheduling for QuickDoctor
+//! Pings an authenticated page every few minutes so the session does not go
+//! stale unnoticed; the interval can be changed or disabled while running
+
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+pub const DEFAULT_KEEPALIVE_MINUTES: u64 = 20;
+/// Upper bound for the interval (one day)
+pub const MAX_KEEPALIVE_MINUTES: u64 = 24 * 60;
+/// Warn once the session cookies expire within this many seconds
+pub const SESSION_EXPIRY_WARN_SECS: i64 = 6 * 60 * 60;
+
+/// Ping interval for a minutes setting; 0 disables the keep-alive
+pub fn keepalive_interval(minutes: u64) -> Option<Duration> {
+    if minutes == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(minutes.min(MAX_KEEPALIVE_MINUTES) * 60))
+    }
+}
+
+/// Remembers the last observed login state to spot expiry
+#[derive(Debug, Default)]
+pub struct LoginTracker {
+    logged_in: Option<bool>,
+    /// Expiry already warned about, so each session is announced once
+    warned_expiry: Option<i64>,
+}
+
+impl LoginTracker {
+    /// Record a check result; true when a logged-in session just expired
+    pub fn observe(&mut self, logged_in: bool) -> bool {
+        let expired = self.logged_in == Some(true) && !logged_in;
+        self.logged_in = Some(logged_in);
+        expired
+    }
+
+    /// Seconds left when a session expiring at `expires_at` should be announced now
+    pub fn expiry_warning(&mut self, expires_at: Option<i64>, now: i64) -> Option<i64> {
+        let expires_at = expires_at?;
+        let left = expires_at - now;
+        if left <= 0 || left > SESSION_EXPIRY_WARN_SECS || self.warned_expiry == Some(expires_at) {
+            return None;
+        }
+        self.warned_expiry = Some(expires_at);
+        Some(left)
+    }
+}
+
+/// Expiry warning shown for a session with `left` seconds to go: whole
+/// hours, or minutes below one hour
+pub fn expiry_message(left: i64) -> String {
+    if left < 3600 {
+        format!("登录将于 {} 分钟后过期", (left + 59) / 60)
+    } else {
+        format!("登录将于 {} 小时后过期", (left + 1799) / 3600)
+    }
+}
+
+/// Call `tick` every interval read from `minutes` until `cancel` fires
+/// A new value on `minutes` restarts the wait with the new interval.
+pub async fn run_keepalive<F, Fut>(mut minutes: watch::Receiver<u64>, cancel: CancellationToken, mut tick: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    loop {
+        let interval = keepalive_interval(*minutes.borrow_and_update());
+        let wait = async {
+            match interval {
+                Some(d) => tokio::time::sleep(d).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            changed = minutes.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = wait => tick().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_keepalive_interval() {
+        assert_eq!(keepalive_interval(0), None);
+        assert_eq!(keepalive_interval(20), Some(Duration::from_secs(1200)));
+        assert_eq!(keepalive_interval(u64::MAX), Some(Duration::from_secs(MAX_KEEPALIVE_MINUTES * 60)));
+    }
+
+    #[test]
+    fn test_login_tracker_reports_expiry_once() {
+        let mut tracker = LoginTracker::default();
+        assert!(!tracker.observe(false));
+        assert!(!tracker.observe(true));
+        assert!(!tracker.observe(true));
+        assert!(tracker.observe(false));
+        assert!(!tracker.observe(false));
+    }
+
+    #[test]
+    fn test_expiry_warning_once_per_session() {
+        let mut tracker = LoginTracker::default();
+        let now = 1_000_000;
+        assert_eq!(tracker.expiry_warning(None, now), None);
+        assert_eq!(tracker.expiry_warning(Some(now + SESSION_EXPIRY_WARN_SECS + 1), now), None);
+        assert_eq!(tracker.expiry_warning(Some(now + 3600), now), Some(3600));
+        assert_eq!(tracker.expiry_warning(Some(now + 3600), now + 60), None);
+        // A refreshed session that is again close to expiry is announced anew
+        assert_eq!(tracker.expiry_warning(Some(now + 7200), now + 4000), Some(3200));
+        assert_eq!(tracker.expiry_warning(Some(now - 1), now), None);
+    }
+
+    #[test]
+    fn test_expiry_message_uses_minutes_below_an_hour() {
+        assert_eq!(expiry_message(20 * 60), "登录将于 20 分钟后过期");
+        assert_eq!(expiry_message(1), "登录将于 1 分钟后过期");
+        assert_eq!(expiry_message(3600), "登录将于 1 小时后过期");
+        assert_eq!(expiry_message(5 * 3600 + 1900), "登录将于 6 小时后过期");
+    }
+
+    fn spawn_counter(minutes: watch::Receiver<u64>, cancel: CancellationToken) -> (Arc<AtomicUsize>, tokio::task::JoinHandle<()>) {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = ticks.clone();
+        let handle = tokio::spawn(run_keepalive(minutes, cancel, move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+        (ticks, handle)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_ticks_and_reacts_to_changes() {
+        let (tx, rx) = watch::channel(20);
+        let cancel = CancellationToken::new();
+        let (ticks, handle) = spawn_counter(rx, cancel.clone());
+
+        tokio::time::sleep(Duration::from_secs(61 * 60)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 3);
+
+        // Disabled: no more ticks
+        tx.send(0).unwrap();
+        tokio::time::sleep(Duration::from_secs(5 * 60 * 60)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 3);
+
+        // Re-enabled with a shorter interval
+        tx.send(5).unwrap();
+        tokio::time::sleep(Duration::from_secs(11 * 60)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 5);
+
+        cancel.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_stops_on_cancel_while_disabled() {
+        let (_tx, rx) = watch::channel(0);
+        let cancel = CancellationToken::new();
+        let (ticks, handle) = spawn_counter(rx, cancel.clone());
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        cancel.cancel();
+        handle.await.unwrap();
+        assert_eq!(ticks.load(Ordering::SeqCst), 0);
+    }
+}
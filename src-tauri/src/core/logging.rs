@@ -0,0 +1,199 @@
+//! Tracing setup for SkylineMed
+//! Logs go to stderr and a daily rotating file under logs_dir(); the level
+//! filter can be swapped at runtime from the frontend
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use super::errors::{AppError, AppResult};
+
+const LOG_FILTER_ENV: &str = "SKYLINEMED_LOG";
+const DEFAULT_LOG_FILTER: &str = "info";
+const LOG_FILE_PREFIX: &str = "skylinemed";
+const LOG_FILE_SUFFIX: &str = "log";
+const LOG_FILES_KEEP: usize = 7;
+
+/// Live handle to the installed subscriber
+/// Dropping it flushes and stops the background file writer
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    _guard: WorkerGuard,
+}
+
+impl LogHandle {
+    /// Replace the level filter, e.g. "debug" or "info,skylinemed::core::client=trace"
+    pub fn set_level(&self, directives: &str) -> AppResult<()> {
+        let filter = parse_filter(directives)?;
+        self.filter
+            .reload(filter)
+            .map_err(|e| AppError::ConfigError(format!("log level reload failed: {}", e)))
+    }
+}
+
+/// Install the global subscriber writing to stderr and `log_dir`
+pub fn init(log_dir: &Path) -> AppResult<LogHandle> {
+    // The appender only prunes when it rolls over, which a desktop app that is
+    // closed every evening never reaches; trim leftovers here as well
+    prune_log_files(log_dir, LOG_FILES_KEEP - 1)?;
+
+    let (writer, guard) = tracing_appender::non_blocking(file_appender(log_dir)?);
+    let filter = std::env::var(LOG_FILTER_ENV)
+        .ok()
+        .and_then(|v| parse_filter(&v).ok())
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_LOG_FILTER));
+
+    let (subscriber, handle) = build_subscriber(filter, writer);
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| AppError::ConfigError(format!("log init failed: {}", e)))?;
+
+    Ok(LogHandle {
+        filter: handle,
+        _guard: guard,
+    })
+}
+
+/// Parse a filter directive string
+pub fn parse_filter(directives: &str) -> AppResult<EnvFilter> {
+    let directives = directives.trim();
+    if directives.is_empty() {
+        return Err(AppError::ConfigError("log level is empty".into()));
+    }
+    EnvFilter::try_new(directives)
+        .map_err(|e| AppError::ConfigError(format!("invalid log level {}: {}", directives, e)))
+}
+
+/// Daily rotating appender: skylinemed.YYYY-MM-DD.log
+fn file_appender(log_dir: &Path) -> AppResult<RollingFileAppender> {
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(LOG_FILES_KEEP)
+        .build(log_dir)
+        .map_err(|e| AppError::ConfigError(format!("log file init failed: {}", e)))
+}
+
+/// Registry with a reloadable filter, a stderr layer and a plain-text file layer
+fn build_subscriber<W>(filter: EnvFilter, file_writer: W) -> (impl Subscriber + Send + Sync, reload::Handle<EnvFilter, Registry>)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let (filter, handle) = reload::Layer::new(filter);
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(fmt::layer().with_ansi(false).with_writer(file_writer));
+    (subscriber, handle)
+}
+
+/// Rotated log files in `log_dir`, oldest first.
+/// File names embed the date, so name order is age order.
+pub fn log_files(log_dir: &Path) -> AppResult<Vec<PathBuf>> {
+    if !log_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{}.", LOG_FILE_PREFIX);
+    let suffix = format!(".{}", LOG_FILE_SUFFIX);
+    let mut files: Vec<_> = fs::read_dir(log_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.starts_with(&prefix) && name.ends_with(&suffix)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Delete the oldest rotated log files so at most `keep` remain
+fn prune_log_files(log_dir: &Path, keep: usize) -> AppResult<()> {
+    let files = log_files(log_dir)?;
+    if files.len() <= keep {
+        return Ok(());
+    }
+    for path in &files[..files.len() - keep] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.starts_with("skylinemed."))
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn read_logs(dir: &Path) -> String {
+        log_files(dir)
+            .iter()
+            .map(|n| fs::read_to_string(dir.join(n)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_file_layer_writes_dated_file_and_switches_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let (subscriber, handle) = build_subscriber(parse_filter("info").unwrap(), file_appender(dir.path()).unwrap());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(unit_id = "21", "visible at info");
+            tracing::debug!("hidden at info");
+
+            handle.reload(parse_filter("debug").unwrap()).unwrap();
+            tracing::debug!(status = 200, "visible at debug");
+        });
+
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        assert_eq!(log_files(dir.path()), vec![format!("skylinemed.{}.log", today)]);
+
+        let content = read_logs(dir.path());
+        assert!(content.contains("visible at info"));
+        assert!(content.contains("unit_id=\"21\""));
+        assert!(!content.contains("hidden at info"));
+        assert!(content.contains("visible at debug"));
+        assert!(content.contains("status=200"));
+    }
+
+    #[test]
+    fn test_prune_log_files_keeps_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        for day in 1..=9 {
+            fs::write(dir.path().join(format!("skylinemed.2024-03-0{}.log", day)), "x").unwrap();
+        }
+        fs::write(dir.path().join("quickdoctor_logs_20240301.txt"), "export").unwrap();
+
+        prune_log_files(dir.path(), 6).unwrap();
+
+        let remaining = log_files(dir.path());
+        assert_eq!(remaining.len(), 6);
+        assert_eq!(remaining[0], "skylinemed.2024-03-04.log");
+        assert!(dir.path().join("quickdoctor_logs_20240301.txt").exists());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert!(parse_filter("debug").is_ok());
+        assert!(parse_filter("info,skylinemed::core::client=trace").is_ok());
+        assert!(parse_filter("  ").is_err());
+        assert!(parse_filter("info,=bogus=").is_err());
+    }
+}
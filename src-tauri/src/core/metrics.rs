@@ -0,0 +1,156 @@
+//! Per-endpoint request metrics for QuickDoctor
+//! Request counts, errors and latency percentiles over the latest requests
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use super::types::EndpointStats;
+
+/// Latest latencies kept per endpoint
+pub const LATENCY_RESERVOIR_SIZE: usize = 128;
+
+/// The most recent latencies in milliseconds, oldest overwritten first
+#[derive(Debug, Clone)]
+pub struct LatencyReservoir {
+    samples: [u32; LATENCY_RESERVOIR_SIZE],
+    len: usize,
+    next: usize,
+}
+
+impl Default for LatencyReservoir {
+    fn default() -> Self {
+        Self {
+            samples: [0; LATENCY_RESERVOIR_SIZE],
+            len: 0,
+            next: 0,
+        }
+    }
+}
+
+impl LatencyReservoir {
+    pub fn record(&mut self, ms: u32) {
+        self.samples[self.next] = ms;
+        self.next = (self.next + 1) % LATENCY_RESERVOIR_SIZE;
+        self.len = (self.len + 1).min(LATENCY_RESERVOIR_SIZE);
+    }
+
+    /// Kept latencies, ascending
+    pub fn sorted(&self) -> Vec<u32> {
+        let mut sorted = self.samples[..self.len].to_vec();
+        sorted.sort_unstable();
+        sorted
+    }
+}
+
+/// Nearest-rank percentile (`p` from 0 to 100) of ascending values
+pub fn percentile(sorted: &[u32], p: u8) -> Option<u32> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (usize::from(p.min(100)) * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[derive(Debug, Default)]
+struct EndpointMetrics {
+    requests: u64,
+    errors: u64,
+    latencies: LatencyReservoir,
+}
+
+/// Request counters and latencies by endpoint name
+#[derive(Debug, Default)]
+pub struct Metrics {
+    endpoints: RwLock<HashMap<&'static str, EndpointMetrics>>,
+}
+
+impl Metrics {
+    /// Count one request; `ok` is false for transport failures and 4xx/5xx
+    pub fn record(&self, endpoint: &'static str, elapsed: Duration, ok: bool) {
+        let ms = u32::try_from(elapsed.as_millis()).unwrap_or(u32::MAX);
+        let mut endpoints = self.endpoints.write().unwrap_or_else(|e| e.into_inner());
+        let entry = endpoints.entry(endpoint).or_default();
+        entry.requests += 1;
+        if !ok {
+            entry.errors += 1;
+        }
+        entry.latencies.record(ms);
+    }
+
+    /// Counters with p50/p95 latency per endpoint, by name
+    pub fn snapshot(&self) -> Vec<EndpointStats> {
+        let endpoints = self.endpoints.read().unwrap_or_else(|e| e.into_inner());
+        let mut stats: Vec<EndpointStats> = endpoints
+            .iter()
+            .map(|(endpoint, metrics)| {
+                let sorted = metrics.latencies.sorted();
+                EndpointStats {
+                    endpoint: endpoint.to_string(),
+                    requests: metrics.requests,
+                    errors: metrics.errors,
+                    p50_ms: percentile(&sorted, 50),
+                    p95_ms: percentile(&sorted, 95),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let values: Vec<u32> = (1..=20).map(|n| n * 10).collect();
+        assert_eq!(percentile(&values, 50), Some(100));
+        assert_eq!(percentile(&values, 95), Some(190));
+        assert_eq!(percentile(&values, 100), Some(200));
+        assert_eq!(percentile(&values, 0), Some(10));
+        assert_eq!(percentile(&[80], 95), Some(80));
+        assert_eq!(percentile(&[80, 2000], 50), Some(80));
+        assert_eq!(percentile(&[], 50), None);
+    }
+
+    #[test]
+    fn test_reservoir_keeps_latest() {
+        let mut reservoir = LatencyReservoir::default();
+        assert!(reservoir.sorted().is_empty());
+        for ms in [30, 10, 20] {
+            reservoir.record(ms);
+        }
+        assert_eq!(reservoir.sorted(), [10, 20, 30]);
+
+        // A slow start scrolls out once the ring has wrapped
+        let mut reservoir = LatencyReservoir::default();
+        for _ in 0..10 {
+            reservoir.record(5000);
+        }
+        for ms in 0..LATENCY_RESERVOIR_SIZE as u32 {
+            reservoir.record(ms);
+        }
+        let sorted = reservoir.sorted();
+        assert_eq!(sorted.len(), LATENCY_RESERVOIR_SIZE);
+        assert_eq!(sorted.last(), Some(&(LATENCY_RESERVOIR_SIZE as u32 - 1)));
+    }
+
+    #[test]
+    fn test_metrics_snapshot_by_endpoint() {
+        let metrics = Metrics::default();
+        assert!(metrics.snapshot().is_empty());
+        for ms in [80, 90, 100, 2000] {
+            metrics.record("schedule", Duration::from_millis(ms), ms < 1000);
+        }
+        metrics.record("deps", Duration::from_millis(40), true);
+
+        let stats = metrics.snapshot();
+        let endpoints: Vec<&str> = stats.iter().map(|s| s.endpoint.as_str()).collect();
+        assert_eq!(endpoints, ["deps", "schedule"]);
+        assert_eq!((stats[1].requests, stats[1].errors), (4, 1));
+        assert_eq!((stats[1].p50_ms, stats[1].p95_ms), (Some(90), Some(2000)));
+        assert_eq!((stats[0].p50_ms, stats[0].p95_ms), (Some(40), Some(40)));
+    }
+}
@@ -3,12 +3,38 @@
 pub mod types;
 pub mod errors;
 pub mod paths;
+pub mod fsutil;
+pub mod textutil;
+pub mod logging;
 pub mod cookies;
+pub mod cities;
 pub mod state;
+pub mod history;
+pub mod resume;
+pub mod ics;
+pub mod settings;
+pub mod profiles;
+pub mod metrics;
+pub mod ratelimit;
+pub mod sessions;
+pub mod submittrace;
 pub mod client;
+pub mod catalog;
+pub mod httpcache;
+pub mod booking;
+pub mod api;
 pub mod proxy;
 pub mod qr_login;
 pub mod grabber;
+pub mod grablog;
+pub mod keepalive;
+pub mod diagnostics;
+pub mod health;
+pub mod deeplink;
+pub mod tasks;
+pub mod timesync;
+pub mod instance;
+pub mod identity;
 
 // Re-export common types
 pub use types::*;
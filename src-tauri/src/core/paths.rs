@@ -3,22 +3,77 @@
 
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use super::errors::{AppError, AppResult};
+use super::profiles::{active_profile, cookie_file_name};
+use super::types::ConfigPaths;
 
 const CONFIG_DIR_ENV: &str = "SKYLINEMED_CONFIG_DIR";
+const CONFIG_DIR_ENV_ALIAS: &str = "QUICKDOCTOR_CONFIG_DIR";
+const APP_DATA_DIR_NAME: &str = "QuickDoctor";
+const MIGRATION_MARKER: &str = ".migrated";
+const MIGRATED_FILES: [&str; 3] = ["cookies.json", "user_state.json", "cities.json"];
 
 /// Get the configuration directory
+/// Lives under the platform data dir (e.g. %APPDATA%\QuickDoctor\config) so it
+/// does not depend on the working directory or a writable install location
 pub fn config_dir() -> AppResult<PathBuf> {
     // Check environment variable first
-    if let Ok(dir) = env::var(CONFIG_DIR_ENV) {
+    if let Some(dir) = [CONFIG_DIR_ENV, CONFIG_DIR_ENV_ALIAS]
+        .iter()
+        .find_map(|key| env::var(key).ok().filter(|v| !v.trim().is_empty()))
+    {
         let path = PathBuf::from(&dir);
         fs::create_dir_all(&path)?;
         return Ok(path);
     }
 
-    // Try various candidate directories
+    let base = directories::BaseDirs::new().ok_or_else(|| {
+        AppError::ConfigError("Unable to resolve config directory".into())
+    })?;
+    let dir = base.data_dir().join(APP_DATA_DIR_NAME).join("config");
+    fs::create_dir_all(&dir)?;
+
+    if let Err(e) = migrate_legacy_config(&dir, &legacy_config_dirs()) {
+        tracing::warn!(error = %e, "legacy config migration failed");
+    }
+    Ok(dir)
+}
+
+/// How `config_dir()` resolves on this machine, one step per line
+pub fn config_dir_trace() -> Vec<String> {
+    let mut trace = Vec::new();
+    for key in [CONFIG_DIR_ENV, CONFIG_DIR_ENV_ALIAS] {
+        match env::var(key) {
+            Ok(value) if !value.trim().is_empty() => trace.push(format!("{}={} (used)", key, value)),
+            Ok(_) => trace.push(format!("{} is empty", key)),
+            Err(_) => trace.push(format!("{} not set", key)),
+        }
+    }
+    match directories::BaseDirs::new() {
+        Some(base) => trace.push(format!("platform data dir: {}", base.data_dir().display())),
+        None => trace.push("platform data dir: unavailable".into()),
+    }
+    match config_dir() {
+        Ok(dir) => {
+            trace.push(format!("config dir: {}", dir.display()));
+            trace.push(format!("migration marker: {}", dir.join(MIGRATION_MARKER).exists()));
+        }
+        Err(e) => trace.push(format!("config dir failed: {}", e)),
+    }
+    for dir in legacy_config_dirs() {
+        let found: Vec<&str> = MIGRATED_FILES.iter().copied().filter(|name| dir.join(name).is_file()).collect();
+        if !found.is_empty() {
+            trace.push(format!("legacy config {}: {}", dir.display(), found.join(", ")));
+        }
+    }
+    trace
+}
+
+/// Config directories used before the move to the platform data dir,
+/// relative to the working directory and the executable
+pub fn legacy_config_dirs() -> Vec<PathBuf> {
     let mut candidates = Vec::new();
 
     // Current working directory
@@ -37,26 +92,46 @@ pub fn config_dir() -> AppResult<PathBuf> {
         }
     }
 
-    // Check for existing config with cities.json
-    for dir in &candidates {
-        let cities_path = dir.join("cities.json");
-        if cities_path.exists() && cities_path.is_file() {
-            return Ok(dir.clone());
-        }
+    candidates
+}
+
+/// Copy config files from the first legacy directory that has them into
+/// `target`, once. Existing files in `target` are never overwritten.
+/// Returns the directory the files were copied from, if any.
+pub fn migrate_legacy_config(target: &Path, legacy: &[PathBuf]) -> AppResult<Option<PathBuf>> {
+    let marker = target.join(MIGRATION_MARKER);
+    if marker.exists() {
+        return Ok(None);
     }
 
-    // Create first available directory
-    for dir in &candidates {
-        if !dir.as_os_str().is_empty() {
-            if fs::create_dir_all(dir).is_ok() {
-                return Ok(dir.clone());
+    // Same preference as the old lookup: a dir with cities.json wins,
+    // otherwise any dir holding one of the files
+    let has = |dir: &PathBuf, name: &str| dir.join(name).is_file();
+    let source = legacy
+        .iter()
+        .filter(|dir| dir.as_path() != target)
+        .find(|dir| has(dir, "cities.json"))
+        .or_else(|| {
+            legacy
+                .iter()
+                .filter(|dir| dir.as_path() != target)
+                .find(|dir| MIGRATED_FILES.iter().any(|name| has(dir, name)))
+        })
+        .cloned();
+
+    fs::create_dir_all(target)?;
+    if let Some(source) = &source {
+        for name in MIGRATED_FILES {
+            let dest = target.join(name);
+            if has(source, name) && !dest.exists() {
+                fs::copy(source.join(name), &dest)?;
             }
         }
+        tracing::info!(from = %source.display(), to = %target.display(), "migrated legacy config");
     }
 
-    Err(AppError::ConfigError(
-        "Unable to resolve config directory".into(),
-    ))
+    fs::write(&marker, "")?;
+    Ok(source)
 }
 
 /// Get the logs directory
@@ -70,13 +145,18 @@ pub fn logs_dir() -> AppResult<PathBuf> {
 
 /// Check if a file exists
 #[allow(dead_code)]
-pub fn file_exists(path: &PathBuf) -> bool {
+pub fn file_exists(path: &Path) -> bool {
     path.exists() && path.is_file()
 }
 
-/// Get the cookies file path
+/// Get the cookies file path of the active profile
 pub fn cookies_path() -> AppResult<PathBuf> {
-    Ok(config_dir()?.join("cookies.json"))
+    profile_cookies_path(&active_profile())
+}
+
+/// Get the cookies file path of a profile
+pub fn profile_cookies_path(profile: &str) -> AppResult<PathBuf> {
+    Ok(config_dir()?.join(cookie_file_name(profile)))
 }
 
 /// Get the user state file path
@@ -84,11 +164,75 @@ pub fn user_state_path() -> AppResult<PathBuf> {
     Ok(config_dir()?.join("user_state.json"))
 }
 
+/// Get the proxy pool cache file path
+pub fn proxies_path() -> AppResult<PathBuf> {
+    Ok(config_dir()?.join("proxies.json"))
+}
+
+/// Get the grab attempt history file path
+pub fn grab_history_path() -> AppResult<PathBuf> {
+    Ok(config_dir()?.join("grab_history.jsonl"))
+}
+
+/// Get the active grab marker path
+pub fn active_grab_path() -> AppResult<PathBuf> {
+    Ok(config_dir()?.join("active_grab.json"))
+}
+
+/// Get the HTTP response cache directory
+pub fn http_cache_dir() -> AppResult<PathBuf> {
+    Ok(config_dir()?.join("cache"))
+}
+
 /// Get the cities file path
 pub fn cities_path() -> AppResult<PathBuf> {
     Ok(config_dir()?.join("cities.json"))
 }
 
+/// `path` canonicalized, when it is one of `roots`, lies inside one, or is
+/// one of `files` (files the app exported elsewhere)
+/// Both sides are canonicalized first, so `..` segments and symlinks that
+/// lead out of a root are refused; so is anything that does not exist.
+pub fn resolve_revealable(path: &Path, roots: &[PathBuf], files: &[PathBuf]) -> AppResult<PathBuf> {
+    let refused = || AppError::ConfigError(format!("path is outside the app directories: {}", path.display()));
+    let resolved = fs::canonicalize(path).map_err(|_| refused())?;
+    let inside_root = roots
+        .iter()
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .any(|root| resolved.starts_with(root));
+    let exported = files
+        .iter()
+        .filter_map(|file| fs::canonicalize(file).ok())
+        .any(|file| file == resolved);
+    if inside_root || exported {
+        Ok(resolved)
+    } else {
+        Err(refused())
+    }
+}
+
+/// `path` without the `\\?\` prefix canonicalize adds on Windows, which
+/// Explorer does not accept; UNC paths are left alone
+pub fn plain_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if !rest.starts_with(r"UNC\") => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// All resolved storage locations, for display in the UI
+pub fn config_paths() -> AppResult<ConfigPaths> {
+    let display = |p: PathBuf| p.to_string_lossy().to_string();
+    Ok(ConfigPaths {
+        config_dir: display(config_dir()?),
+        logs_dir: display(logs_dir()?),
+        cookies: display(cookies_path()?),
+        user_state: display(user_state_path()?),
+        cities: display(cities_path()?),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,4 +243,108 @@ mod tests {
         let result = config_dir();
         assert!(result.is_ok() || result.is_err());
     }
+
+    fn write_config(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_migrate_prefers_dir_with_cities() {
+        let root = tempfile::tempdir().unwrap();
+        let cwd_config = root.path().join("cwd").join("config");
+        let exe_config = root.path().join("exe").join("config");
+        let target = root.path().join("data").join("config");
+        write_config(&cwd_config, &[("user_state.json", "{\"city_id\":\"1\"}")]);
+        write_config(&exe_config, &[("cities.json", "[]"), ("cookies.json", "[]"), ("user_state.json", "{}")]);
+
+        let legacy = vec![cwd_config, exe_config.clone(), root.path().join("missing")];
+        let source = migrate_legacy_config(&target, &legacy).unwrap();
+
+        assert_eq!(source, Some(exe_config));
+        for name in MIGRATED_FILES {
+            assert!(target.join(name).is_file(), "{} not migrated", name);
+        }
+        assert_eq!(fs::read_to_string(target.join("user_state.json")).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_migrate_runs_once_and_keeps_existing_files() {
+        let root = tempfile::tempdir().unwrap();
+        let old = root.path().join("old");
+        let target = root.path().join("new");
+        write_config(&old, &[("cookies.json", "old"), ("user_state.json", "old")]);
+        write_config(&target, &[("cookies.json", "new")]);
+
+        let source = migrate_legacy_config(&target, std::slice::from_ref(&old)).unwrap();
+        assert_eq!(source, Some(old.clone()));
+        assert_eq!(fs::read_to_string(target.join("cookies.json")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(target.join("user_state.json")).unwrap(), "old");
+
+        // Later edits in the old location are not picked up again
+        fs::write(old.join("cities.json"), "[]").unwrap();
+        assert_eq!(migrate_legacy_config(&target, &[old]).unwrap(), None);
+        assert!(!target.join("cities.json").exists());
+    }
+
+    #[test]
+    fn test_resolve_revealable_stays_inside_roots() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config");
+        let logs = root.path().join("logs");
+        let out = root.path().join("out");
+        write_config(&config, &[("cookies.json", "[]")]);
+        write_config(&logs, &[]);
+        write_config(&out, &[("settings.json", "{}"), ("other.json", "{}")]);
+        write_config(&root.path().join("config-evil"), &[("cookies.json", "[]")]);
+        fs::write(root.path().join("secret.txt"), "x").unwrap();
+
+        let roots = vec![config.clone(), logs.clone()];
+        let files = vec![out.join("settings.json")];
+        let resolve = |path: PathBuf| resolve_revealable(&path, &roots, &files);
+
+        let cookies = resolve(config.join("cookies.json")).unwrap();
+        assert_eq!(cookies, fs::canonicalize(config.join("cookies.json")).unwrap());
+        assert!(resolve(logs.clone()).is_ok());
+        assert!(resolve(config.join(".").join("cookies.json")).is_ok());
+        assert!(resolve(out.join("settings.json")).is_ok());
+
+        // Traversal, look-alike siblings, other files and missing paths
+        assert!(resolve(config.join("..").join("secret.txt")).is_err());
+        assert!(resolve(logs.join("..").join("..").join("etc")).is_err());
+        assert!(resolve(root.path().join("config-evil").join("cookies.json")).is_err());
+        assert!(resolve(out.join("other.json")).is_err());
+        assert!(resolve(config.join("missing.json")).is_err());
+        assert!(resolve(root.path().to_path_buf()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_revealable_follows_symlinks_out() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config");
+        write_config(&config, &[]);
+        fs::write(root.path().join("secret.txt"), "x").unwrap();
+        std::os::unix::fs::symlink(root.path().join("secret.txt"), config.join("link.txt")).unwrap();
+
+        assert!(resolve_revealable(&config.join("link.txt"), std::slice::from_ref(&config), &[]).is_err());
+    }
+
+    #[test]
+    fn test_plain_path_strips_verbatim_prefix() {
+        assert_eq!(plain_path(Path::new(r"\\?\C:\Users\a\config")), PathBuf::from(r"C:\Users\a\config"));
+        assert_eq!(plain_path(Path::new(r"\\?\UNC\server\share")), PathBuf::from(r"\\?\UNC\server\share"));
+        assert_eq!(plain_path(Path::new("/home/a/config")), PathBuf::from("/home/a/config"));
+    }
+
+    #[test]
+    fn test_migrate_without_legacy_config() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("config");
+
+        assert_eq!(migrate_legacy_config(&target, &[root.path().join("nope")]).unwrap(), None);
+        assert!(target.join(MIGRATION_MARKER).exists());
+    }
 }
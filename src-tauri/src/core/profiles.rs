@@ -0,0 +1,182 @@
+//! Account profiles for QuickDoctor
+//! Each profile keeps its own 91160 login in cookies.<profile>.json next to the
+//! other config files; the default profile keeps the original cookies.json
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use serde_json::Value;
+
+use super::errors::{AppError, AppResult};
+use super::fsutil::{backup_path, write_atomic};
+use super::state::load_user_state;
+
+pub const DEFAULT_PROFILE: &str = "default";
+pub const CURRENT_PROFILE_KEY: &str = "current_profile";
+const PROFILE_NAME_MAX_CHARS: usize = 32;
+
+/// Profile whose cookies `paths::cookies_path()` points at; loaded from user
+/// state on first use
+static ACTIVE_PROFILE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Name of the active profile
+pub fn active_profile() -> String {
+    if let Some(name) = ACTIVE_PROFILE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return name.clone();
+    }
+    let name = load_user_state()
+        .map(|state| current_profile(&state))
+        .unwrap_or_else(|_| DEFAULT_PROFILE.to_string());
+    set_active_profile(&name);
+    name
+}
+
+/// Point cookie loads and saves at another profile
+pub fn set_active_profile(name: &str) {
+    *ACTIVE_PROFILE.write().unwrap_or_else(|e| e.into_inner()) = Some(name.to_string());
+}
+
+/// Profile recorded in user state
+pub fn current_profile(state: &HashMap<String, Value>) -> String {
+    state
+        .get(CURRENT_PROFILE_KEY)
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim())
+        .filter(|s| validate_profile_name(s).is_ok())
+        .unwrap_or(DEFAULT_PROFILE)
+        .to_string()
+}
+
+/// Check a profile name is usable as part of a file name
+pub fn validate_profile_name(name: &str) -> AppResult<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::ConfigError("profile name is required".into()));
+    }
+    if name.chars().count() > PROFILE_NAME_MAX_CHARS {
+        return Err(AppError::ConfigError(format!(
+            "profile name must be at most {} characters",
+            PROFILE_NAME_MAX_CHARS
+        )));
+    }
+    if !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(AppError::ConfigError(format!(
+            "profile name {} may only contain letters, digits, - and _",
+            name
+        )));
+    }
+    Ok(name.to_string())
+}
+
+/// Cookie file name for a profile
+pub fn cookie_file_name(profile: &str) -> String {
+    if profile == DEFAULT_PROFILE {
+        "cookies.json".into()
+    } else {
+        format!("cookies.{}.json", profile)
+    }
+}
+
+/// Profiles that have a cookie file in `dir`; the default profile is always listed first
+pub fn list_profiles_in(dir: &Path) -> AppResult<Vec<String>> {
+    let mut names = Vec::new();
+    if dir.exists() {
+        for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let name = file_name
+                .strip_prefix("cookies.")
+                .and_then(|rest| rest.strip_suffix(".json"));
+            if let Some(name) = name {
+                if name != DEFAULT_PROFILE && validate_profile_name(name).is_ok() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(names)
+}
+
+/// Create an empty profile in `dir`
+pub fn create_profile_in(dir: &Path, name: &str) -> AppResult<String> {
+    let name = validate_profile_name(name)?;
+    if list_profiles_in(dir)?.contains(&name) {
+        return Err(AppError::ConfigError(format!("profile {} already exists", name)));
+    }
+    write_atomic(&dir.join(cookie_file_name(&name)), b"[]")?;
+    Ok(name)
+}
+
+/// Delete a profile's cookies from `dir`; the default and active profiles are kept
+pub fn delete_profile_in(dir: &Path, name: &str, active: &str) -> AppResult<()> {
+    let name = name.trim();
+    if name == DEFAULT_PROFILE {
+        return Err(AppError::ConfigError("the default profile cannot be deleted".into()));
+    }
+    if name == active {
+        return Err(AppError::ConfigError(format!("profile {} is active, switch away first", name)));
+    }
+    if !list_profiles_in(dir)?.iter().any(|p| p == name) {
+        return Err(AppError::ConfigError(format!("profile {} not found", name)));
+    }
+
+    let path: PathBuf = dir.join(cookie_file_name(name));
+    fs::remove_file(&path)?;
+    let backup = backup_path(&path);
+    if backup.exists() {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_names() {
+        assert_eq!(validate_profile_name(" 妈妈 ").unwrap(), "妈妈");
+        assert_eq!(validate_profile_name("dad_2").unwrap(), "dad_2");
+        assert!(validate_profile_name("").is_err());
+        assert!(validate_profile_name("../x").is_err());
+        assert!(validate_profile_name("a.b").is_err());
+        assert!(validate_profile_name(&"x".repeat(33)).is_err());
+
+        assert_eq!(cookie_file_name(DEFAULT_PROFILE), "cookies.json");
+        assert_eq!(cookie_file_name("妈妈"), "cookies.妈妈.json");
+    }
+
+    #[test]
+    fn test_current_profile_from_state() {
+        let mut state = HashMap::new();
+        assert_eq!(current_profile(&state), DEFAULT_PROFILE);
+        state.insert(CURRENT_PROFILE_KEY.to_string(), Value::String(" mom ".into()));
+        assert_eq!(current_profile(&state), "mom");
+        state.insert(CURRENT_PROFILE_KEY.to_string(), Value::String("../etc".into()));
+        assert_eq!(current_profile(&state), DEFAULT_PROFILE);
+    }
+
+    #[test]
+    fn test_create_list_delete_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(list_profiles_in(dir.path()).unwrap(), vec!["default"]);
+
+        create_profile_in(dir.path(), "mom").unwrap();
+        create_profile_in(dir.path(), "dad").unwrap();
+        fs::write(dir.path().join("cookies.mom.json.bak"), "[]").unwrap();
+        assert!(create_profile_in(dir.path(), "mom").is_err());
+        assert_eq!(list_profiles_in(dir.path()).unwrap(), vec!["default", "dad", "mom"]);
+        assert_eq!(fs::read_to_string(dir.path().join("cookies.mom.json")).unwrap(), "[]");
+
+        assert!(delete_profile_in(dir.path(), "default", "mom").is_err());
+        assert!(delete_profile_in(dir.path(), "mom", "mom").is_err());
+        assert!(delete_profile_in(dir.path(), "ghost", "default").is_err());
+
+        delete_profile_in(dir.path(), "mom", "dad").unwrap();
+        assert_eq!(list_profiles_in(dir.path()).unwrap(), vec!["default", "dad"]);
+        assert!(!dir.path().join("cookies.mom.json.bak").exists());
+    }
+}
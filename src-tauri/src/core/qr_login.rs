@@ -1,20 +1,23 @@
 //! QR Login for QuickDoctor
 //! Corresponds to core/qr_login.go - WeChat QR code login flow
 
+use std::future::Future;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
 use base64::Engine;
 use regex::Regex;
-use reqwest::cookie::Jar;
 use reqwest::header::{HeaderValue, ACCEPT, CONNECTION, ORIGIN, REFERER, USER_AGENT};
 use reqwest::Client;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
-use super::cookies::save_cookie_file;
+use super::client::HealthClient;
+use super::cookies::{apply_cookie_attrs, has_access_hash, RecordingJar};
 use super::errors::{AppError, AppResult};
-use super::types::{CookieRecord, QRLoginResult};
+use super::types::{CookieRecord, QRImage, QRLoginResult};
 
 const WECHAT_APP_ID: &str = "wxdfec0615563d691d";
 const WECHAT_REDIRECT: &str = "http://user.91160.com/supplier-wechat.html";
@@ -92,16 +95,7 @@ impl FastQRLogin {
         let qr_bytes = qr_resp.bytes().await?.to_vec();
 
         // Validate image format (JPEG or PNG)
-        if qr_bytes.len() < 4 {
-            return Err(AppError::ParseError("QR image too small".into()));
-        }
-
-        let is_jpeg = qr_bytes[0] == 0xFF && qr_bytes[1] == 0xD8;
-        let is_png = qr_bytes[0] == 0x89 && qr_bytes[1] == 0x50 && qr_bytes[2] == 0x4E && qr_bytes[3] == 0x47;
-
-        if !is_jpeg && !is_png {
-            return Err(AppError::ParseError("QR image invalid format".into()));
-        }
+        image_info(&qr_bytes)?;
 
         Ok((qr_bytes, uuid))
     }
@@ -125,140 +119,43 @@ impl FastQRLogin {
                 success: false,
                 message: "uuid not initialized".into(),
                 cookie_path: None,
+                cookies: Vec::new(),
             };
         }
 
-        let start = std::time::Instant::now();
-        let mut last_status = String::new();
-        let mut last_param = "404".to_string();
-        let mut retry_404 = 0;
-
-        let re_errcode = Regex::new(r"wx_errcode\s*=\s*(\d+)").unwrap();
-        let re_code = Regex::new(r#"wx_code\s*=\s*['"]([^'"]*)['"]"#).unwrap();
-        let re_redirect = Regex::new(r#"window\.location(?:\.href|\.replace)?\s*\(?['"]([^'"]+)['"]"#).unwrap();
-
-        loop {
-            if start.elapsed() > timeout {
-                return QRLoginResult {
-                    success: false,
-                    message: "qr expired".into(),
-                    cookie_path: None,
-                };
-            }
-
-            let ts = chrono::Utc::now().timestamp_millis();
+        let fetch = |last: String| {
             let poll_url = format!(
                 "https://lp.open.weixin.qq.com/connect/l/qrconnect?uuid={}&last={}&_={}",
-                uuid, last_param, ts
+                uuid,
+                last,
+                chrono::Utc::now().timestamp_millis()
             );
-
-            let resp = match self.client.get(&poll_url).headers(wechat_headers()).send().await {
-                Ok(r) => r,
-                Err(_) => {
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                    continue;
-                }
-            };
-
-            let body = match resp.text().await {
-                Ok(b) => b,
-                Err(_) => {
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                    continue;
-                }
-            };
-
-            let mut status = "0".to_string();
-            if let Some(caps) = re_errcode.captures(&body) {
-                if let Some(m) = caps.get(1) {
-                    status = m.as_str().to_string();
-                }
+            async move {
+                let resp = self.client.get(&poll_url).headers(wechat_headers()).send().await?;
+                Ok::<String, AppError>(resp.text().await?)
             }
+        };
 
-            let mut code = String::new();
-            if let Some(caps) = re_code.captures(&body) {
-                if let Some(m) = caps.get(1) {
-                    code = m.as_str().to_string();
-                }
-            }
-
-            let mut redirect_url = String::new();
-            if let Some(caps) = re_redirect.captures(&body) {
-                if let Some(m) = caps.get(1) {
-                    redirect_url = m.as_str().to_string();
-                }
-            }
-
-            if status == "0" && (!code.is_empty() || !redirect_url.is_empty()) {
-                status = "405".to_string();
-            }
-
-            if ["408", "201", "405", "402", "404"].contains(&status.as_str()) {
-                last_param = status.clone();
-            }
-
-            match status.as_str() {
-                "408" => {
-                    if last_status != "408" {
-                        on_status("waiting for scan");
-                    }
-                    last_status = "408".to_string();
-                    retry_404 = 0;
-                }
-                "404" | "402" => {
-                    retry_404 += 1;
-                    last_status = "404".to_string();
-                    if retry_404 > 60 {
-                        return QRLoginResult {
-                            success: false,
-                            message: "qr expired".into(),
-                            cookie_path: None,
-                        };
-                    }
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                    continue;
-                }
-                "201" => {
-                    if last_status != "201" {
-                        on_status("scanned, confirm on phone");
-                    }
-                    last_status = "201".to_string();
-                    retry_404 = 0;
-                }
-                "405" => {
-                    // Extract code from redirect URL if needed
-                    if code.is_empty() && !redirect_url.is_empty() {
-                        if let Ok(parsed) = Url::parse(&redirect_url) {
-                            if let Some(state_param) = parsed.query_pairs().find(|(k, _)| k == "state") {
-                                let mut state_lock = self.state.write().await;
-                                *state_lock = state_param.1.to_string();
-                            }
-                            if let Some(code_param) = parsed.query_pairs().find(|(k, _)| k == "code") {
-                                code = code_param.1.to_string();
-                            }
-                        }
-                    }
-
-                    if code.is_empty() {
-                        on_status("confirmed but no code, retrying");
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        continue;
-                    }
-
-                    on_status("logging in");
-                    return self.exchange_cookie(&code).await;
+        match poll_until_confirmed(timeout, fetch, &mut on_status).await {
+            Ok((code, redirect_state)) => {
+                if let Some(state) = redirect_state {
+                    *self.state.write().await = state;
                 }
-                _ => {}
+                self.exchange_cookie(&code).await
             }
-
-            tokio::time::sleep(Duration::from_secs(1)).await;
+            Err(message) => QRLoginResult {
+                success: false,
+                message: message.into(),
+                cookie_path: None,
+                cookies: Vec::new(),
+            },
         }
     }
 
     /// Exchange code for cookies
     async fn exchange_cookie(&self, code: &str) -> QRLoginResult {
-        println!(">>> Debug: Starting cookie exchange with code: {}", code);
-        let cookie_jar = Arc::new(Jar::default());
+        tracing::debug!("starting cookie exchange");
+        let cookie_jar = Arc::new(RecordingJar::default());
 
         let client = match Client::builder()
             .user_agent(DEFAULT_USER_AGENT)
@@ -268,11 +165,12 @@ impl FastQRLogin {
         {
             Ok(c) => c,
             Err(e) => {
-                println!(">>> Debug: Client build failed: {}", e);
+                tracing::warn!(error = %e, "cookie exchange client build failed");
                 return QRLoginResult {
                     success: false,
                     message: e.to_string(),
                     cookie_path: None,
+                    cookies: Vec::new(),
                 };
             }
         };
@@ -287,7 +185,6 @@ impl FastQRLogin {
         } else {
             format!("{}?code={}&state={}", WECHAT_REDIRECT, code, urlencoding::encode(&state))
         };
-        println!(">>> Debug: Callback URL: {}", callback_url);
 
         // Follow redirect chain
         match client
@@ -297,8 +194,8 @@ impl FastQRLogin {
             .send()
             .await 
         {
-            Ok(resp) => println!(">>> Debug: Callback response: status={}, url={}", resp.status(), resp.url()),
-            Err(e) => println!(">>> Debug: Callback request failed: {}", e),
+            Ok(resp) => tracing::debug!(status = resp.status().as_u16(), url = %resp.url().path(), "login callback response"),
+            Err(e) => tracing::warn!(error = %e, "login callback request failed"),
         }
 
         let _ = client.get("https://www.91160.com/").send().await;
@@ -311,7 +208,6 @@ impl FastQRLogin {
             if let Ok(url) = Url::parse(start_url) {
                 use reqwest::cookie::CookieStore;
                 if let Some(header_value) = cookie_jar.cookies(&url) {
-                    println!(">>> Debug: Cookies for {}: {:?}", start_url, header_value);
                     if let Ok(cookie_str) = header_value.to_str() {
                         for part in cookie_str.split(';') {
                             let part = part.trim();
@@ -319,81 +215,371 @@ impl FastQRLogin {
                                 let name = part[..eq_pos].trim().to_string();
                                 let value = part[eq_pos + 1..].trim().to_string();
                                 if !name.is_empty() && !value.is_empty() {
-                                    records.push(CookieRecord {
-                                        name,
-                                        value,
-                                        domain: ".91160.com".into(), // Default to root domain
-                                        path: "/".into(),
-                                    });
+                                    records.push(CookieRecord::root(name, value));
                                 }
                             }
                         }
                     }
                 } else {
-                    println!(">>> Debug: No cookies found for {}", start_url);
+                    tracing::debug!(domain = start_url, "no cookies found");
                 }
             }
         }
 
-        if records.is_empty() {
-            println!(">>> Debug: No cookies extracted from any domain");
-            return QRLoginResult {
-                success: false,
-                message: "no cookies received".into(),
-                cookie_path: None,
-            };
+        // Keep expiry and flags from the Set-Cookie headers seen during the exchange
+        apply_cookie_attrs(&mut records, &cookie_jar.attrs());
+
+        login_result(records)
+    }
+
+    /// Get QR image as base64, with its format and size
+    pub async fn get_qr_image_base64(&self) -> AppResult<QRImage> {
+        let (bytes, uuid) = self.get_qr_image().await?;
+        let (mime, width, height) = image_info(&bytes)?;
+        Ok(QRImage {
+            base64: base64::engine::general_purpose::STANDARD.encode(&bytes),
+            mime: mime.to_string(),
+            width,
+            height,
+            uuid,
+        })
+    }
+}
+
+impl Default for FastQRLogin {
+    fn default() -> Self {
+        Self::new().expect("Failed to create FastQRLogin")
+    }
+}
+
+/// Cancelled scans tolerated before the login gives up
+const MAX_QR_CANCELS: u32 = 3;
+/// Consecutive 404/402 answers before the QR counts as expired
+const MAX_QR_NOT_FOUND: u32 = 60;
+/// A poll answered slower than this was held by WeChat's long-poll
+const QR_LONG_POLL_BLOCKED: Duration = Duration::from_secs(2);
+/// Pause after a quick answer with nothing to wait on; doubled once if repeated
+const QR_POLL_BACKOFF: Duration = Duration::from_secs(1);
+/// Pause after a quick answer once scanned, when the confirmation is close
+const QR_POLL_MIN_PAUSE: Duration = Duration::from_millis(200);
+/// Pause after a failed poll request
+const QR_POLL_ERROR_PAUSE: Duration = Duration::from_secs(2);
+/// How long a QR login waits for the scan and confirmation
+pub const QR_LOGIN_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// What one long-poll response means for the login. WaitScan and Scanned are
+/// reported on entering that state only; repeats come back as Retry.
+#[derive(Debug, Clone, PartialEq)]
+enum QrPollOutcome {
+    /// Waiting for the phone to scan (408)
+    WaitScan,
+    /// Scanned, waiting for the confirm tap (201)
+    Scanned,
+    /// Confirmed on the phone; log in with this code
+    GotCode(String),
+    /// Confirmed, but the response carried no code yet
+    NoCode,
+    /// The QR stopped answering (404/402 for too long)
+    Expired,
+    /// Nothing new, poll again
+    Retry,
+    /// Cancelled on the phone (403); `give_up` once that happened too often
+    Cancelled { give_up: bool },
+}
+
+/// Long-poll state: the `last` parameter echoed back to WeChat and what the
+/// user has already been told
+struct QrPollState {
+    last_param: String,
+    last_status: String,
+    retry_404: u32,
+    cancels: u32,
+    /// Polls in a row that WeChat answered at once
+    quick_answers: u32,
+    /// OAuth state from a redirect URL carrying the code
+    redirect_state: Option<String>,
+    re_errcode: Regex,
+    re_code: Regex,
+    re_redirect: Regex,
+}
+
+impl Default for QrPollState {
+    fn default() -> Self {
+        Self {
+            last_param: "404".to_string(),
+            last_status: String::new(),
+            retry_404: 0,
+            cancels: 0,
+            quick_answers: 0,
+            redirect_state: None,
+            re_errcode: Regex::new(r"wx_errcode\s*=\s*(\d+)").unwrap(),
+            re_code: Regex::new(r#"wx_code\s*=\s*['"]([^'"]*)['"]"#).unwrap(),
+            re_redirect: Regex::new(r#"window\.location(?:\.href|\.replace)?\s*\(?['"]([^'"]+)['"]"#).unwrap(),
         }
+    }
+}
+
+impl QrPollState {
+    /// Advance on one poll response body
+    fn step(&mut self, body: &str) -> QrPollOutcome {
+        let capture = |re: &Regex| {
+            re.captures(body)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default()
+        };
+        let mut status = self.re_errcode.captures(body).and_then(|caps| caps.get(1)).map_or("0", |m| m.as_str());
+        let mut code = capture(&self.re_code);
+        let redirect_url = capture(&self.re_redirect);
 
-        // Force allow login even if access_hash is missing for debugging, but log it
-        let has_access = records.iter().any(|r| r.name == "access_hash");
-        if !has_access {
-            println!(">>> Debug: WARNING - access_hash missing in cookies: {:?}", records);
-            // We temporarily allow it to proceed to see if it works anyway or what state we are in
-            // But the original error said "missing access_hash", so let's keep failing but with better logs
-            // Actually, let's NOT fail, let's Try to save anyway so we can inspect the file
+        if status == "0" && (!code.is_empty() || !redirect_url.is_empty()) {
+            status = "405";
         }
 
-        match save_cookie_file(&records) {
-            Ok(()) => {
-                let path = super::paths::cookies_path().ok().map(|p| p.to_string_lossy().to_string());
-                
-                // If we are strictly checking for access_hash, we should return error here if missing
-                if !has_access {
-                     return QRLoginResult {
-                        success: false,
-                        message: "missing access_hash (check console for details)".into(),
-                        cookie_path: path, // Return path so we know it saved something
-                    };
-                }
+        if ["408", "201", "405", "402", "404"].contains(&status) {
+            self.last_param = status.to_string();
+        }
 
-                QRLoginResult {
-                    success: true,
-                    message: "login ok".into(),
-                    cookie_path: path,
+        match status {
+            "408" => {
+                let entered = self.last_status != "408";
+                self.last_status = "408".to_string();
+                self.retry_404 = 0;
+                if entered {
+                    return QrPollOutcome::WaitScan;
                 }
             }
-            Err(e) => QRLoginResult {
-                success: false,
-                message: e.to_string(),
-                cookie_path: None,
-            },
+            "404" | "402" => {
+                self.retry_404 += 1;
+                self.last_status = "404".to_string();
+                if self.retry_404 > MAX_QR_NOT_FOUND {
+                    return QrPollOutcome::Expired;
+                }
+            }
+            "201" => {
+                let entered = self.last_status != "201";
+                self.last_status = "201".to_string();
+                self.retry_404 = 0;
+                if entered {
+                    return QrPollOutcome::Scanned;
+                }
+            }
+            "403" => {
+                // The same QR can be scanned again: poll from scratch, and keep
+                // the cancel notice up while waiting for the next scan
+                self.cancels += 1;
+                self.last_param = "404".to_string();
+                self.last_status = "408".to_string();
+                self.retry_404 = 0;
+                return QrPollOutcome::Cancelled { give_up: self.cancels > MAX_QR_CANCELS };
+            }
+            "405" => {
+                // Extract code from redirect URL if needed
+                if code.is_empty() && !redirect_url.is_empty() {
+                    if let Ok(parsed) = Url::parse(&redirect_url) {
+                        if let Some((_, state)) = parsed.query_pairs().find(|(k, _)| k == "state") {
+                            self.redirect_state = Some(state.to_string());
+                        }
+                        if let Some((_, value)) = parsed.query_pairs().find(|(k, _)| k == "code") {
+                            code = value.to_string();
+                        }
+                    }
+                }
+                return if code.is_empty() { QrPollOutcome::NoCode } else { QrPollOutcome::GotCode(code) };
+            }
+            _ => {}
         }
+        QrPollOutcome::Retry
     }
 
-    /// Get QR image as base64
-    pub async fn get_qr_image_base64(&self) -> AppResult<(String, String)> {
-        let (bytes, uuid) = self.get_qr_image().await?;
-        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
-        Ok((base64, uuid))
+    /// Pause before the next poll, given how long the last one took. A
+    /// long-poll that blocked has already waited, so poll again at once. A
+    /// quick answer after the scan waits 200ms, since the confirmation usually
+    /// lands within seconds; before it, 1s, then 2s.
+    fn next_pause(&mut self, waited: Duration) -> Duration {
+        if waited >= QR_LONG_POLL_BLOCKED {
+            self.quick_answers = 0;
+            return Duration::ZERO;
+        }
+        self.quick_answers += 1;
+        if self.last_status == "201" {
+            return QR_POLL_MIN_PAUSE;
+        }
+        QR_POLL_BACKOFF * self.quick_answers.min(2)
     }
 }
 
-impl Default for FastQRLogin {
-    fn default() -> Self {
-        Self::new().expect("Failed to create FastQRLogin")
+/// Long-poll loop behind `poll_status`: `fetch` sends one request with the
+/// given `last` param. Returns the login code and any OAuth state from the
+/// redirect, or the failure message.
+async fn poll_until_confirmed<F, Fut, S>(
+    timeout: Duration,
+    mut fetch: F,
+    on_status: &mut S,
+) -> Result<(String, Option<String>), &'static str>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = AppResult<String>>,
+    S: FnMut(&str),
+{
+    let start = tokio::time::Instant::now();
+    let mut poll = QrPollState::default();
+
+    loop {
+        if start.elapsed() > timeout {
+            return Err("qr expired");
+        }
+
+        let sent = tokio::time::Instant::now();
+        let body = match fetch(poll.last_param.clone()).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::debug!(error = %e, "qr poll failed");
+                tokio::time::sleep(QR_POLL_ERROR_PAUSE).await;
+                continue;
+            }
+        };
+
+        let outcome = poll.step(&body);
+        let message = match &outcome {
+            QrPollOutcome::WaitScan => "waiting for scan",
+            QrPollOutcome::Scanned => "scanned, confirm on phone",
+            QrPollOutcome::Cancelled { give_up: false } => "scan cancelled, scan again",
+            QrPollOutcome::NoCode => "confirmed but no code, retrying",
+            QrPollOutcome::Retry => "",
+            QrPollOutcome::GotCode(code) => {
+                on_status("logging in");
+                return Ok((code.clone(), poll.redirect_state.take()));
+            }
+            QrPollOutcome::Expired => return Err("qr expired"),
+            QrPollOutcome::Cancelled { give_up: true } => return Err("scan cancelled"),
+        };
+        if !message.is_empty() {
+            on_status(message);
+        }
+
+        let pause = poll.next_pause(sent.elapsed());
+        if !pause.is_zero() {
+            tokio::time::sleep(pause).await;
+        }
     }
 }
 
+/// Run `poll` unless `cancel_token` fires first. A login cancelled by stop,
+/// logout or a profile switch yields None and must not be saved.
+pub async fn poll_unless_cancelled(
+    cancel_token: &CancellationToken,
+    poll: impl Future<Output = QRLoginResult>,
+) -> Option<QRLoginResult> {
+    tokio::select! {
+        biased;
+        _ = cancel_token.cancelled() => None,
+        result = poll => Some(result),
+    }
+}
+
+/// Outcome of the cookie exchange; only a session with access_hash counts
+fn login_result(records: Vec<CookieRecord>) -> QRLoginResult {
+    if records.is_empty() {
+        tracing::warn!("no cookies extracted from any domain");
+        return QRLoginResult {
+            success: false,
+            message: "no cookies received".into(),
+            cookie_path: None,
+            cookies: Vec::new(),
+        };
+    }
+    if !has_access_hash(&records) {
+        let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+        tracing::warn!(cookies = ?names, "access_hash missing in cookies");
+        return QRLoginResult {
+            success: false,
+            message: "missing access_hash".into(),
+            cookie_path: None,
+            cookies: Vec::new(),
+        };
+    }
+    QRLoginResult {
+        success: true,
+        message: "login ok".into(),
+        cookie_path: None,
+        cookies: records,
+    }
+}
+
+/// Hand a successful login to `client`: save the cookies to `path` and switch
+/// the client over in one step. A failed save turns the result into a failure,
+/// so the app never reports a login the client does not have.
+pub async fn complete_login(client: &HealthClient, mut result: QRLoginResult, path: &Path) -> QRLoginResult {
+    if !result.success {
+        return result;
+    }
+    match client.save_cookies_to(path, std::mem::take(&mut result.cookies)).await {
+        Ok(()) => {
+            result.cookie_path = Some(path.to_string_lossy().to_string());
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to save login cookies");
+            result.success = false;
+            result.message = format!("failed to save cookies: {}", e);
+        }
+    }
+    result
+}
+
+/// MIME type and pixel size of a PNG or JPEG, read from its header
+fn image_info(bytes: &[u8]) -> AppResult<(&'static str, u32, u32)> {
+    let invalid = |reason: &str| AppError::ParseError(format!("QR image {}", reason));
+    if bytes.len() < 4 {
+        return Err(invalid("too small"));
+    }
+
+    if bytes.starts_with(b"\x89PNG") {
+        // Signature (8), IHDR length and type (8), then width and height
+        let size = bytes.get(16..24).filter(|_| &bytes[12..16] == b"IHDR").ok_or_else(|| invalid("missing IHDR"))?;
+        let width = u32::from_be_bytes([size[0], size[1], size[2], size[3]]);
+        let height = u32::from_be_bytes([size[4], size[5], size[6], size[7]]);
+        return Ok(("image/png", width, height));
+    }
+
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        // Walk the marker segments up to the start-of-frame
+        let mut pos = 2;
+        while pos + 4 <= bytes.len() {
+            if bytes[pos] != 0xFF {
+                return Err(invalid("has a corrupt JPEG header"));
+            }
+            let marker = bytes[pos + 1];
+            match marker {
+                // Fill byte before a marker
+                0xFF => {
+                    pos += 1;
+                    continue;
+                }
+                // Standalone markers carry no length
+                0x01 | 0xD0..=0xD7 => {
+                    pos += 2;
+                    continue;
+                }
+                0xD9 | 0xDA => break,
+                _ => {}
+            }
+            let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+            if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+                let frame = bytes.get(pos + 5..pos + 9).ok_or_else(|| invalid("has a truncated frame header"))?;
+                let height = u16::from_be_bytes([frame[0], frame[1]]) as u32;
+                let width = u16::from_be_bytes([frame[2], frame[3]]) as u32;
+                return Ok(("image/jpeg", width, height));
+            }
+            pos += 2 + length;
+        }
+        return Err(invalid("has no JPEG frame header"));
+    }
+
+    Err(invalid("invalid format"))
+}
+
 /// Build WeChat API headers
 fn wechat_headers() -> reqwest::header::HeaderMap {
     let mut headers = reqwest::header::HeaderMap::new();
@@ -404,3 +590,248 @@ fn wechat_headers() -> reqwest::header::HeaderMap {
     headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
     headers
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_result_requires_access_hash() {
+        let empty = login_result(Vec::new());
+        assert!(!empty.success && empty.cookies.is_empty());
+
+        let partial = login_result(vec![CookieRecord::root("PHPSESSID", "x")]);
+        assert!(!partial.success);
+        assert_eq!(partial.message, "missing access_hash");
+        assert!(partial.cookies.is_empty());
+
+        let records = vec![
+            CookieRecord::root("PHPSESSID", "x"),
+            CookieRecord::root("access_hash", "abc"),
+        ];
+        let ok = login_result(records.clone());
+        assert!(ok.success);
+        assert_eq!(ok.cookies.len(), 2);
+        // Cookie values stay out of what the UI receives
+        assert!(!serde_json::to_string(&ok).unwrap().contains("abc"));
+    }
+
+    #[test]
+    fn test_image_info_reads_format_and_size() {
+        let png = include_bytes!("../../tests/fixtures/qr_code.png");
+        assert_eq!(image_info(png).unwrap(), ("image/png", 33, 29));
+        let jpeg = include_bytes!("../../tests/fixtures/qr_code.jpg");
+        assert_eq!(image_info(jpeg).unwrap(), ("image/jpeg", 24, 16));
+
+        for bad in [&b"GIF89a"[..], &b"\xff\xd8"[..], &jpeg[..jpeg.len() / 4], &png[..20], &b"<html>"[..]] {
+            assert!(matches!(image_info(bad), Err(AppError::ParseError(_))), "{:?}", &bad[..bad.len().min(8)]);
+        }
+    }
+
+    fn poll_body(errcode: u32, code: &str) -> String {
+        format!("window.wx_errcode={};window.wx_code='{}';", errcode, code)
+    }
+
+    /// Run `bodies` through a fresh state; outcomes and the `last` param after each
+    fn run_poll(bodies: &[&str]) -> (QrPollState, Vec<QrPollOutcome>, Vec<String>) {
+        let mut poll = QrPollState::default();
+        let mut outcomes = Vec::new();
+        let mut last_params = Vec::new();
+        for body in bodies {
+            outcomes.push(poll.step(body));
+            last_params.push(poll.last_param.clone());
+        }
+        (poll, outcomes, last_params)
+    }
+
+    #[test]
+    fn test_poll_errcode_paths() {
+        use QrPollOutcome::*;
+        let redirect = "window.location.replace('http://user.91160.com/supplier-wechat.html?code=abc&state=login_1');";
+        let cases: Vec<(&str, &str, QrPollOutcome, &str)> = vec![
+            ("waiting", "window.wx_errcode=408;window.wx_code='';", WaitScan, "408"),
+            ("scanned", "window.wx_errcode=201;window.wx_code='';", Scanned, "201"),
+            ("confirmed", "window.wx_errcode=405;window.wx_code='071AbCdE';", GotCode("071AbCdE".into()), "405"),
+            ("confirmed, code pending", "window.wx_errcode=405;window.wx_code='';", NoCode, "405"),
+            ("code without errcode", "window.wx_code='071AbCdE';", GotCode("071AbCdE".into()), "405"),
+            ("code in redirect", redirect, GotCode("abc".into()), "405"),
+            ("redirect without code", "window.location('http://user.91160.com/supplier-wechat.html');", NoCode, "405"),
+            ("not found", "window.wx_errcode=404;window.wx_code='';", Retry, "404"),
+            ("server busy", "window.wx_errcode=402;window.wx_code='';", Retry, "402"),
+            ("cancelled", "window.wx_errcode=403;window.wx_code='';", Cancelled { give_up: false }, "404"),
+            ("unknown errcode", "window.wx_errcode=500;window.wx_code='';", Retry, "404"),
+            ("empty body", "", Retry, "404"),
+        ];
+        for (name, body, outcome, last_param) in cases {
+            let (_, outcomes, last_params) = run_poll(&[body]);
+            assert_eq!(outcomes, [outcome], "{}", name);
+            assert_eq!(last_params, [last_param], "{}", name);
+        }
+
+        let (poll, _, _) = run_poll(&[redirect]);
+        assert_eq!(poll.redirect_state.as_deref(), Some("login_1"));
+    }
+
+    #[test]
+    fn test_poll_reports_state_changes_once() {
+        use QrPollOutcome::*;
+        let (_, outcomes, _) = run_poll(&[
+            &poll_body(408, ""),
+            &poll_body(408, ""),
+            &poll_body(201, ""),
+            &poll_body(201, ""),
+            "",
+            &poll_body(201, ""),
+            &poll_body(404, ""),
+            &poll_body(408, ""),
+        ]);
+        assert_eq!(outcomes, [WaitScan, Retry, Scanned, Retry, Retry, Retry, Retry, WaitScan]);
+    }
+
+    #[test]
+    fn test_poll_expires_after_repeated_not_found() {
+        let mut poll = QrPollState::default();
+        for _ in 0..MAX_QR_NOT_FOUND {
+            assert_eq!(poll.step(&poll_body(404, "")), QrPollOutcome::Retry);
+        }
+        assert_eq!(poll.step(&poll_body(402, "")), QrPollOutcome::Expired);
+
+        // A 408 in between resets the count
+        let mut poll = QrPollState::default();
+        for _ in 0..MAX_QR_NOT_FOUND {
+            poll.step(&poll_body(404, ""));
+        }
+        poll.step(&poll_body(408, ""));
+        assert_eq!(poll.step(&poll_body(404, "")), QrPollOutcome::Retry);
+    }
+
+    #[test]
+    fn test_poll_recovers_from_cancelled_scan() {
+        use QrPollOutcome::*;
+        let (_, outcomes, last_params) = run_poll(&[
+            &poll_body(408, ""),
+            &poll_body(201, ""),
+            &poll_body(403, ""),
+            &poll_body(408, ""),
+            &poll_body(405, "c0de"),
+        ]);
+        // The cancel notice stays up: the 408 after it is not re-announced
+        assert_eq!(outcomes, [WaitScan, Scanned, Cancelled { give_up: false }, Retry, GotCode("c0de".into())]);
+        assert_eq!(last_params, ["408", "201", "404", "408", "405"]);
+    }
+
+    #[test]
+    fn test_poll_gives_up_after_repeated_cancels() {
+        let mut poll = QrPollState::default();
+        for _ in 0..MAX_QR_CANCELS {
+            poll.step(&poll_body(201, ""));
+            assert_eq!(poll.step(&poll_body(403, "")), QrPollOutcome::Cancelled { give_up: false });
+        }
+        poll.step(&poll_body(201, ""));
+        assert_eq!(poll.step(&poll_body(403, "")), QrPollOutcome::Cancelled { give_up: true });
+    }
+
+    /// Stubbed long-poll: each call waits `delay` (WeChat holding the request)
+    /// and answers `body`; records when each poll was sent (ms) and its `last` param
+    fn scripted(
+        script: Vec<(u64, String)>,
+        calls: &std::sync::Mutex<Vec<(u64, String)>>,
+    ) -> impl FnMut(String) -> std::pin::Pin<Box<dyn Future<Output = AppResult<String>>>> + '_ {
+        let start = tokio::time::Instant::now();
+        let mut script = script.into_iter();
+        move |last: String| {
+            calls.lock().unwrap().push((start.elapsed().as_millis() as u64, last));
+            let next = script.next();
+            Box::pin(async move {
+                let (delay, body) = next.unwrap_or((1, String::new()));
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                Ok(body)
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_interval_follows_long_poll() {
+        let calls = std::sync::Mutex::new(Vec::new());
+        let script = vec![
+            (0, poll_body(408, "")),  // answered at once: back off 1s
+            (0, poll_body(408, "")),  // again: 2s
+            (25, poll_body(408, "")), // held by the long-poll: no pause
+            (3, poll_body(201, "")),  // scanned, held: no pause
+            (0, poll_body(201, "")),  // scanned, answered at once: 200ms
+            (4, poll_body(405, "c0de")),
+        ];
+        let mut statuses = Vec::new();
+        let result = poll_until_confirmed(
+            Duration::from_secs(180),
+            scripted(script, &calls),
+            &mut |s: &str| statuses.push(s.to_string()),
+        )
+        .await;
+
+        assert_eq!(result, Ok(("c0de".to_string(), None)));
+        let calls = calls.into_inner().unwrap();
+        let sent: Vec<u64> = calls.iter().map(|(at, _)| *at).collect();
+        assert_eq!(sent, [0, 1000, 3000, 28000, 31000, 31200]);
+        let lasts: Vec<&str> = calls.iter().map(|(_, last)| last.as_str()).collect();
+        assert_eq!(lasts, ["404", "408", "408", "408", "201", "201"]);
+        assert_eq!(statuses, ["waiting for scan", "scanned, confirm on phone", "logging in"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_times_out_on_quick_answers() {
+        let calls = std::sync::Mutex::new(Vec::new());
+        let script = vec![(0, poll_body(408, "")); 100];
+        let started = tokio::time::Instant::now();
+        let result = poll_until_confirmed(Duration::from_secs(30), scripted(script, &calls), &mut |_: &str| {}).await;
+
+        assert_eq!(result, Err("qr expired"));
+        assert!(started.elapsed() <= Duration::from_secs(32));
+        // 1s, then 2s between polls that WeChat answers at once
+        let calls = calls.into_inner().unwrap();
+        assert_eq!(calls.len(), 16);
+        assert_eq!(calls[1].0 - calls[0].0, 1000);
+        assert!(calls.windows(2).skip(1).all(|w| w[1].0 - w[0].0 == 2000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_keeps_a_floor_after_scan() {
+        let calls = std::sync::Mutex::new(Vec::new());
+        // Confirmed on the phone, but WeChat answers without the code at once
+        let mut script = vec![(0, poll_body(201, ""))];
+        script.extend(vec![(0, poll_body(405, "")); 3]);
+        script.push((0, poll_body(405, "c0de")));
+        let mut statuses = Vec::new();
+        let result = poll_until_confirmed(
+            Duration::from_secs(180),
+            scripted(script, &calls),
+            &mut |s: &str| statuses.push(s.to_string()),
+        )
+        .await;
+
+        assert_eq!(result, Ok(("c0de".to_string(), None)));
+        let sent: Vec<u64> = calls.into_inner().unwrap().iter().map(|(at, _)| *at).collect();
+        assert_eq!(sent, [0, 200, 400, 600, 800]);
+        assert_eq!(statuses.iter().filter(|s| *s == "confirmed but no code, retrying").count(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_unless_cancelled_drops_cancelled_login() {
+        let confirmed = || async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            login_result(vec![CookieRecord::root("access_hash", "abc")])
+        };
+        let token = CancellationToken::new();
+        assert!(poll_unless_cancelled(&token, confirmed()).await.is_some_and(|r| r.success));
+
+        // A logout during the poll wins over a scan confirmed later
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            canceller.cancel();
+        });
+        let started = tokio::time::Instant::now();
+        assert!(poll_unless_cancelled(&token, confirmed()).await.is_none());
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,250 @@
+//! Outbound request rate limiting for QuickDoctor
+//! One token bucket per host, shared by every feature using the client, so a
+//! grab, the keep-alive and the pickers together stay under the site's limits.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::types::{HostRateStats, RateLimitSettings};
+
+/// Which queue a request joins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Waits for its token behind earlier requests
+    Normal,
+    /// Never waits (order submits); the token is borrowed from the requests queued behind it
+    Priority,
+}
+
+/// Token bucket refilled at `rate` tokens per second, holding at most `burst`
+/// Tokens go negative while callers wait: each one below zero is a
+/// reservation that a queued caller sleeps off.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Full bucket
+    pub fn new(rate: f64, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+
+    /// Take a token; returns how long the caller must wait before sending
+    pub fn reserve(&mut self, lane: Lane, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= 1.0;
+        if lane == Lane::Priority || self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+struct HostBucket {
+    bucket: TokenBucket,
+    stats: HostRateStats,
+}
+
+/// Per-host limiter; `gate_host` gets the gate rate, every other host the www rate
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    gate_host: String,
+    hosts: Mutex<HashMap<String, HostBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings, gate_host: &str) -> Self {
+        Self {
+            settings,
+            gate_host: gate_host.to_string(),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn settings(&self) -> &RateLimitSettings {
+        &self.settings
+    }
+
+    fn rate_for(&self, host: &str) -> f64 {
+        if host == self.gate_host {
+            self.settings.gate_per_sec
+        } else {
+            self.settings.www_per_sec
+        }
+    }
+
+    /// Reserve a token for `host` and return the wait before it may be used
+    fn reserve(&self, host: &str, lane: Lane, now: Instant) -> Duration {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = hosts.entry(host.to_string()).or_insert_with(|| {
+            let rate = self.rate_for(host);
+            HostBucket {
+                bucket: TokenBucket::new(rate, self.settings.burst, now),
+                stats: HostRateStats {
+                    host: host.to_string(),
+                    rate_per_sec: rate,
+                    ..HostRateStats::default()
+                },
+            }
+        });
+
+        let wait = entry.bucket.reserve(lane, now);
+        let stats = &mut entry.stats;
+        stats.requests += 1;
+        if lane == Lane::Priority {
+            stats.priority_requests += 1;
+        }
+        if !wait.is_zero() {
+            let wait_ms = wait.as_millis() as u64;
+            stats.delayed += 1;
+            stats.total_wait_ms += wait_ms;
+            stats.max_wait_ms = stats.max_wait_ms.max(wait_ms);
+        }
+        wait
+    }
+
+    /// Wait until a request to `host` may go out
+    pub async fn acquire(&self, host: &str, lane: Lane) {
+        if !self.settings.enabled {
+            return;
+        }
+        let wait = self.reserve(host, lane, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Counters per host, sorted by host
+    pub fn stats(&self) -> Vec<HostRateStats> {
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats: Vec<HostRateStats> = hosts.values().map(|entry| entry.stats.clone()).collect();
+        stats.sort_by(|a, b| a.host.cmp(&b.host));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(gate_per_sec: f64, www_per_sec: f64, burst: u32) -> RateLimitSettings {
+        RateLimitSettings {
+            enabled: true,
+            gate_per_sec,
+            www_per_sec,
+            burst,
+        }
+    }
+
+    #[test]
+    fn test_bucket_spends_burst_then_spaces_requests() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 2, start);
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::from_millis(1000));
+
+        // Refill pays off the reservations first and never exceeds the burst
+        assert_eq!(bucket.reserve(Lane::Normal, start + Duration::from_secs(1)), Duration::from_millis(500));
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(Lane::Normal, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(Lane::Normal, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(Lane::Normal, later), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_priority_skips_the_backlog() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, 1, start);
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::from_secs(1));
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::from_secs(2));
+
+        assert_eq!(bucket.reserve(Lane::Priority, start), Duration::ZERO);
+        // The borrowed token pushes the next normal request back
+        assert_eq!(bucket.reserve(Lane::Normal, start), Duration::from_secs(4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_waits_instead_of_rejecting() {
+        let limiter = RateLimiter::new(settings(5.0, 2.0, 1), "gate.91160.com");
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("www.91160.com", Lane::Normal).await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(1000));
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("gate.91160.com", Lane::Normal).await;
+        }
+        // The gate bucket is separate and faster
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+
+        let stats = limiter.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].host, "gate.91160.com");
+        assert_eq!(stats[0].rate_per_sec, 5.0);
+        assert_eq!(stats[1].requests, 3);
+        assert_eq!(stats[1].delayed, 2);
+        assert_eq!(stats[1].total_wait_ms, 1000);
+        assert_eq!(stats[1].max_wait_ms, 500);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_submit_not_delayed_by_query_backlog() {
+        let limiter = std::sync::Arc::new(RateLimiter::new(settings(1.0, 1.0, 1), "gate.91160.com"));
+        let start = Instant::now();
+        let mut queries = Vec::new();
+        for _ in 0..5 {
+            let limiter = limiter.clone();
+            queries.push(tokio::spawn(async move {
+                limiter.acquire("www.91160.com", Lane::Normal).await;
+            }));
+        }
+        tokio::task::yield_now().await;
+
+        limiter.acquire("www.91160.com", Lane::Priority).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        for query in queries {
+            query.await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(4));
+        assert_eq!(limiter.stats()[0].priority_requests, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_disabled_limiter_never_waits() {
+        let mut disabled = settings(1.0, 1.0, 1);
+        disabled.enabled = false;
+        let limiter = RateLimiter::new(disabled, "gate.91160.com");
+        let start = Instant::now();
+        for _ in 0..10 {
+            limiter.acquire("www.91160.com", Lane::Normal).await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(limiter.stats().is_empty());
+    }
+}
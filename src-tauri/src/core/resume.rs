@@ -0,0 +1,189 @@
+//! Crash-safe resume of an interrupted grab for QuickDoctor
+//! The running grab's config is written to active_grab.json when it starts
+//! and removed when it ends; a marker still present on launch means the app
+//! went away mid-grab
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Local};
+
+use super::errors::AppResult;
+use super::fsutil::write_atomic;
+use super::grabber::{effective_target_dates, upcoming_target_dates};
+use super::paths::active_grab_path;
+use super::types::{parse_clock_time, ActiveGrabMarker, GrabConfig};
+
+/// Markers older than this are discarded instead of offered
+pub const MAX_MARKER_AGE_HOURS: i64 = 24;
+
+/// Record `config` as the running grab; returns the marker's `saved_at`,
+/// which identifies this run when clearing it again
+pub fn save_active_grab(config: &GrabConfig) -> AppResult<String> {
+    save_active_grab_to(&active_grab_path()?, config, Local::now())
+}
+
+/// Record `config` as the running grab at `path`
+pub fn save_active_grab_to(path: &Path, config: &GrabConfig, now: DateTime<Local>) -> AppResult<String> {
+    let marker = ActiveGrabMarker {
+        saved_at: now.to_rfc3339_opts(chrono::SecondsFormat::Nanos, false),
+        running: true,
+        config: config.clone(),
+    };
+    write_atomic(path, serde_json::to_string_pretty(&marker)?.as_bytes())?;
+    Ok(marker.saved_at)
+}
+
+/// Forget the grab identified by `saved_at`
+pub fn clear_active_grab(saved_at: &str) -> AppResult<()> {
+    clear_active_grab_run(&active_grab_path()?, saved_at)
+}
+
+/// Remove the marker at `path` unless it belongs to a newer run (a grab that
+/// replaced this one must keep its marker)
+pub fn clear_active_grab_run(path: &Path, saved_at: &str) -> AppResult<()> {
+    let newer = fs::read_to_string(path)
+        .ok()
+        .and_then(|data| serde_json::from_str::<ActiveGrabMarker>(&data).ok())
+        .is_some_and(|marker| marker.saved_at != saved_at);
+    if newer {
+        return Ok(());
+    }
+    clear_active_grab_at(path)
+}
+
+/// Remove the marker at `path`
+pub fn clear_active_grab_at(path: &Path) -> AppResult<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The interrupted grab worth resuming, if any
+pub fn pending_grab() -> AppResult<Option<GrabConfig>> {
+    pending_grab_at(&active_grab_path()?, Local::now())
+}
+
+/// The interrupted grab at `path` worth resuming at `now`. Unreadable,
+/// stale (older than MAX_MARKER_AGE_HOURS) and expired markers are removed.
+pub fn pending_grab_at(path: &Path, now: DateTime<Local>) -> AppResult<Option<GrabConfig>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let marker = match serde_json::from_str::<ActiveGrabMarker>(&data) {
+        Ok(marker) => marker,
+        Err(e) => {
+            tracing::warn!(error = %e, "discarding unreadable active grab marker");
+            clear_active_grab_at(path)?;
+            return Ok(None);
+        }
+    };
+
+    let fresh = DateTime::parse_from_rfc3339(&marker.saved_at)
+        .map(|saved| now.signed_duration_since(saved) < Duration::hours(MAX_MARKER_AGE_HOURS))
+        .unwrap_or(false);
+    if !marker.running || !fresh || !window_open(&marker.config, now) {
+        tracing::info!(saved_at = %marker.saved_at, "discarding expired active grab marker");
+        clear_active_grab_at(path)?;
+        return Ok(None);
+    }
+    Ok(Some(marker.config))
+}
+
+/// Whether a grab started with `config` could still do something at `now`
+fn window_open(config: &GrabConfig, now: DateTime<Local>) -> bool {
+    if config.recur_daily {
+        return true;
+    }
+    let today = now.date_naive();
+    if upcoming_target_dates(&effective_target_dates(config, today), today).is_empty() {
+        return false;
+    }
+    match (parse_clock_time(&config.stop_time), parse_clock_time(&config.start_time)) {
+        (Some(stop), _) => now.time() < stop,
+        (None, Some(start)) => now.time() < start,
+        (None, None) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 19, hour, minute, 0).unwrap()
+    }
+
+    fn config() -> GrabConfig {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": "u1",
+            "dep_id": "d1",
+            "member_id": "m1",
+            "target_dates": ["2024-03-20"],
+            "start_time": "08:00:00",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_marker_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("active_grab.json");
+        assert!(pending_grab_at(&path, at(7, 0)).unwrap().is_none());
+
+        save_active_grab_to(&path, &config(), at(7, 0)).unwrap();
+        let pending = pending_grab_at(&path, at(7, 30)).unwrap().unwrap();
+        assert_eq!(pending.unit_id, "u1");
+        assert!(path.exists(), "offering a resume keeps the marker");
+
+        // A replaced run must not remove the marker of the grab that replaced it
+        let first = save_active_grab_to(&path, &config(), at(7, 0)).unwrap();
+        let second = save_active_grab_to(&path, &config(), at(7, 1)).unwrap();
+        clear_active_grab_run(&path, &first).unwrap();
+        assert!(path.exists());
+        clear_active_grab_run(&path, &second).unwrap();
+        assert!(!path.exists());
+        clear_active_grab_run(&path, &second).unwrap();
+        assert!(pending_grab_at(&path, at(7, 30)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_passed_window_discards_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("active_grab.json");
+        save_active_grab_to(&path, &config(), at(7, 0)).unwrap();
+        assert!(pending_grab_at(&path, at(8, 30)).unwrap().is_none());
+        assert!(!path.exists());
+
+        // A stop_time keeps the window open past start_time
+        let mut config = config();
+        config.stop_time = "09:00:00".into();
+        save_active_grab_to(&path, &config, at(7, 0)).unwrap();
+        assert!(pending_grab_at(&path, at(8, 30)).unwrap().is_some());
+
+        // All target dates in the past
+        config.target_dates = vec!["2024-03-18".into()];
+        save_active_grab_to(&path, &config, at(7, 0)).unwrap();
+        assert!(pending_grab_at(&path, at(7, 30)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stale_and_corrupt_markers_are_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("active_grab.json");
+        let mut config = config();
+        config.recur_daily = true;
+        config.stop_time = "09:00:00".into();
+        save_active_grab_to(&path, &config, at(7, 0) - Duration::hours(25)).unwrap();
+        assert!(pending_grab_at(&path, at(7, 0)).unwrap().is_none());
+        assert!(!path.exists());
+
+        fs::write(&path, "{\"saved_at\": ").unwrap();
+        assert!(pending_grab_at(&path, at(7, 0)).unwrap().is_none());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,172 @@
+//! access_hash ranking for QuickDoctor
+//! A login can leave several access_hash cookies behind (one per domain, or
+//! stale ones from earlier logins). Schedule queries try the key that last
+//! worked first and push keys the gateway keeps rejecting to the back.
+
+use std::collections::HashMap;
+
+use super::types::SessionInfo;
+
+/// Consecutive 10022 answers after which a key is demoted (and prunable)
+pub const DEMOTE_AFTER: u32 = 2;
+
+#[derive(Debug, Clone, Default)]
+struct KeyHealth {
+    /// Unix seconds of the last query the gateway accepted with this key
+    last_success: Option<i64>,
+    /// 10022 answers since the last success
+    expired_streak: u32,
+}
+
+/// Per-key outcomes of schedule queries, keyed by the full access_hash value
+#[derive(Debug, Default)]
+pub struct SessionRanker {
+    keys: HashMap<String, KeyHealth>,
+    /// Masked id of the key a grab asked for; see `GrabConfig::session`
+    preferred: Option<String>,
+}
+
+impl SessionRanker {
+    /// Pin the key whose masked id is `id` to the front; `None` or "" clears it
+    pub fn prefer(&mut self, id: Option<&str>) {
+        self.preferred = id.map(str::trim).filter(|id| !id.is_empty()).map(str::to_string);
+    }
+
+    fn is_preferred(&self, key: &str) -> bool {
+        self.preferred.as_deref() == Some(mask_key(key).as_str())
+    }
+
+    /// True once the gateway rejected the key `DEMOTE_AFTER` times in a row
+    pub fn is_dead(&self, key: &str) -> bool {
+        self.keys.get(key).is_some_and(|health| health.expired_streak >= DEMOTE_AFTER)
+    }
+
+    /// `keys` in the order to try them: live keys before demoted ones, the
+    /// preferred key first, then by most recent success; ties keep cookie order
+    pub fn rank(&self, keys: &[String]) -> Vec<String> {
+        let mut ranked = keys.to_vec();
+        ranked.sort_by_key(|key| {
+            let last_success = self.keys.get(key).and_then(|health| health.last_success);
+            (self.is_dead(key), !self.is_preferred(key), std::cmp::Reverse(last_success))
+        });
+        ranked
+    }
+
+    pub fn record_success(&mut self, key: &str, now: i64) {
+        let health = self.keys.entry(key.to_string()).or_default();
+        health.last_success = Some(now);
+        health.expired_streak = 0;
+    }
+
+    /// The gateway answered 10022 (login expired) for `key`
+    pub fn record_expired(&mut self, key: &str) {
+        self.keys.entry(key.to_string()).or_default().expired_streak += 1;
+    }
+
+    /// Drop what is known about `key` once its cookie is gone
+    pub fn forget(&mut self, key: &str) {
+        self.keys.remove(key);
+    }
+
+    /// What is known about each of `keys`, in ranked order
+    pub fn sessions(&self, keys: &[String]) -> Vec<SessionInfo> {
+        self.rank(keys)
+            .iter()
+            .map(|key| {
+                let health = self.keys.get(key).cloned().unwrap_or_default();
+                SessionInfo {
+                    id: mask_key(key),
+                    last_success_at: health.last_success,
+                    expired_streak: health.expired_streak,
+                    demoted: self.is_dead(key),
+                    preferred: self.is_preferred(key),
+                }
+            })
+            .collect()
+    }
+}
+
+/// First and last four characters of an access_hash, safe to show and log
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "***".to_string();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rank_keeps_cookie_order_without_history() {
+        let ranker = SessionRanker::default();
+        let all = keys(&["key-a-0000", "key-b-0000", "key-c-0000"]);
+        assert_eq!(ranker.rank(&all), all);
+    }
+
+    #[test]
+    fn test_rank_tries_last_success_first() {
+        let mut ranker = SessionRanker::default();
+        let all = keys(&["key-a-0000", "key-b-0000", "key-c-0000"]);
+        ranker.record_success("key-b-0000", 100);
+        ranker.record_success("key-c-0000", 200);
+        assert_eq!(ranker.rank(&all), keys(&["key-c-0000", "key-b-0000", "key-a-0000"]));
+    }
+
+    #[test]
+    fn test_rank_demotes_after_two_expired_in_a_row() {
+        let mut ranker = SessionRanker::default();
+        let all = keys(&["key-a-0000", "key-b-0000"]);
+        ranker.record_success("key-a-0000", 100);
+
+        ranker.record_expired("key-a-0000");
+        assert!(!ranker.is_dead("key-a-0000"));
+        assert_eq!(ranker.rank(&all)[0], "key-a-0000");
+
+        ranker.record_expired("key-a-0000");
+        assert!(ranker.is_dead("key-a-0000"));
+        assert_eq!(ranker.rank(&all), keys(&["key-b-0000", "key-a-0000"]));
+
+        // A success resets the streak
+        ranker.record_success("key-a-0000", 300);
+        assert!(!ranker.is_dead("key-a-0000"));
+        assert_eq!(ranker.rank(&all)[0], "key-a-0000");
+    }
+
+    #[test]
+    fn test_preferred_key_goes_first_unless_demoted() {
+        let mut ranker = SessionRanker::default();
+        let all = keys(&["aaaa-111-zzzz", "bbbb-222-yyyy"]);
+        ranker.record_success("aaaa-111-zzzz", 100);
+        ranker.prefer(Some("bbbb…yyyy"));
+        assert_eq!(ranker.rank(&all)[0], "bbbb-222-yyyy");
+
+        ranker.record_expired("bbbb-222-yyyy");
+        ranker.record_expired("bbbb-222-yyyy");
+        assert_eq!(ranker.rank(&all)[0], "aaaa-111-zzzz");
+
+        ranker.prefer(Some(""));
+        ranker.forget("bbbb-222-yyyy");
+        assert_eq!(ranker.rank(&all), all);
+    }
+
+    #[test]
+    fn test_sessions_are_masked() {
+        let mut ranker = SessionRanker::default();
+        ranker.record_success("abcd1234567890wxyz", 42);
+        let sessions = ranker.sessions(&keys(&["abcd1234567890wxyz", "short"]));
+        assert_eq!(sessions[0].id, "abcd…wxyz");
+        assert_eq!(sessions[0].last_success_at, Some(42));
+        assert_eq!(sessions[1].id, "***");
+        assert_eq!(sessions[1].last_success_at, None);
+        assert!(sessions.iter().all(|s| !s.demoted));
+    }
+}
@@ -0,0 +1,202 @@
+//! Settings export / import for QuickDoctor
+//! Bundles user state, grab presets and (optionally) cookies into one JSON
+//! file so a setup can be carried to another machine
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::cookies::load_cookie_file_from;
+use super::errors::{AppError, AppResult};
+use super::fsutil::write_atomic;
+use super::profiles::CURRENT_PROFILE_KEY;
+use super::state::{grab_presets, load_user_state_from, save_user_state_to, GRAB_PRESETS_KEY};
+use super::types::{CookieRecord, GrabPreset};
+
+/// Current bundle format; bump when the layout changes incompatibly
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// Contents of an exported settings file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub version: u32,
+    #[serde(default)]
+    pub exported_at: String,
+    #[serde(default)]
+    pub user_state: HashMap<String, Value>,
+    #[serde(default)]
+    pub presets: Vec<GrabPreset>,
+    /// Left out when the user exports without their login
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookies: Option<Vec<CookieRecord>>,
+}
+
+/// Collect the settings stored at `state_path` / `cookies_path`
+pub fn build_bundle(state_path: &Path, cookies_path: &Path, include_cookies: bool) -> AppResult<SettingsBundle> {
+    let mut user_state = load_user_state_from(state_path)?;
+    let presets = grab_presets(&user_state);
+    user_state.remove(GRAB_PRESETS_KEY);
+    // Cookies travel with whichever profile is active on either side
+    user_state.remove(CURRENT_PROFILE_KEY);
+
+    let cookies = if include_cookies {
+        Some(load_cookie_file_from(cookies_path)?)
+    } else {
+        None
+    };
+
+    Ok(SettingsBundle {
+        version: SETTINGS_VERSION,
+        exported_at: chrono::Local::now().to_rfc3339(),
+        user_state,
+        presets,
+        cookies,
+    })
+}
+
+/// Write a bundle to `path`
+pub fn write_bundle(bundle: &SettingsBundle, path: &Path) -> AppResult<()> {
+    let data = serde_json::to_string_pretty(bundle)?;
+    write_atomic(path, data.as_bytes())
+}
+
+/// Read and version-check a bundle from `path`
+pub fn read_bundle(path: &Path) -> AppResult<SettingsBundle> {
+    let data = fs::read_to_string(path)?;
+    let raw: Value = serde_json::from_str(&data)
+        .map_err(|e| AppError::ConfigError(format!("settings file is not valid JSON: {}", e)))?;
+
+    let version = raw.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version == 0 || version > SETTINGS_VERSION as u64 {
+        return Err(AppError::ConfigError(format!(
+            "unsupported settings version {} (expected {})",
+            version, SETTINGS_VERSION
+        )));
+    }
+
+    serde_json::from_value(raw)
+        .map_err(|e| AppError::ConfigError(format!("settings file is invalid: {}", e)))
+}
+
+/// Merge a bundle into the user state at `state_path`
+/// Fields in the bundle win, fields it lacks are kept, and presets are merged
+/// by name. Returns the bundled cookies for the caller to apply to the client.
+pub fn apply_bundle(bundle: SettingsBundle, state_path: &Path) -> AppResult<Option<Vec<CookieRecord>>> {
+    let mut presets = grab_presets(&load_user_state_from(state_path)?);
+    for preset in bundle.presets {
+        match presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => presets.push(preset),
+        }
+    }
+
+    let mut update = bundle.user_state;
+    update.remove(CURRENT_PROFILE_KEY);
+    update.insert(GRAB_PRESETS_KEY.to_string(), serde_json::to_value(&presets)?);
+    save_user_state_to(state_path, update)?;
+
+    Ok(bundle.cookies.filter(|c| !c.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::cookies::save_cookie_file_to;
+    use crate::core::state::save_grab_preset_to;
+
+    fn preset(name: &str, unit_id: &str) -> GrabPreset {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "config": {
+                "unit_id": unit_id,
+                "dep_id": "200",
+                "member_id": "m1",
+                "target_dates": ["2024-03-20"],
+            },
+        }))
+        .unwrap()
+    }
+
+    fn seed(dir: &Path, unit_id: &str, presets: &[GrabPreset]) {
+        let mut update = HashMap::new();
+        update.insert("unit_id".to_string(), Value::String(unit_id.into()));
+        save_user_state_to(&dir.join("user_state.json"), update).unwrap();
+        for p in presets {
+            save_grab_preset_to(&dir.join("user_state.json"), p.clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let old_pc = tempfile::tempdir().unwrap();
+        let new_pc = tempfile::tempdir().unwrap();
+        seed(old_pc.path(), "21", &[preset("老大", "21"), preset("老二", "22")]);
+        save_cookie_file_to(
+            &old_pc.path().join("cookies.json"),
+            &[CookieRecord {
+                name: "access_hash".into(),
+                value: "abc".into(),
+                domain: ".91160.com".into(),
+                path: "/".into(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+        seed(new_pc.path(), "99", &[preset("老二", "99"), preset("本机", "1")]);
+
+        let bundle = build_bundle(
+            &old_pc.path().join("user_state.json"),
+            &old_pc.path().join("cookies.json"),
+            true,
+        )
+        .unwrap();
+        let file = old_pc.path().join("export").join("settings.json");
+        write_bundle(&bundle, &file).unwrap();
+
+        let state_path = new_pc.path().join("user_state.json");
+        let cookies = apply_bundle(read_bundle(&file).unwrap(), &state_path).unwrap();
+
+        assert_eq!(cookies.unwrap()[0].value, "abc");
+        let state = load_user_state_from(&state_path).unwrap();
+        assert_eq!(state["unit_id"], Value::String("21".into()));
+        let presets = grab_presets(&state);
+        let summary: Vec<(&str, &str)> = presets
+            .iter()
+            .map(|p| (p.name.as_str(), p.config.unit_id.as_str()))
+            .collect();
+        assert_eq!(summary, vec![("老二", "22"), ("本机", "1"), ("老大", "21")]);
+    }
+
+    #[test]
+    fn test_export_without_cookies() {
+        let dir = tempfile::tempdir().unwrap();
+        seed(dir.path(), "21", &[]);
+
+        let bundle = build_bundle(&dir.path().join("user_state.json"), &dir.path().join("cookies.json"), false).unwrap();
+        let file = dir.path().join("settings.json");
+        write_bundle(&bundle, &file).unwrap();
+
+        assert!(!fs::read_to_string(&file).unwrap().contains("cookies"));
+        let cookies = apply_bundle(read_bundle(&file).unwrap(), &dir.path().join("user_state.json")).unwrap();
+        assert!(cookies.is_none());
+    }
+
+    #[test]
+    fn test_read_bundle_checks_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("settings.json");
+
+        for body in [r#"{"user_state": {}}"#, r#"{"version": 99, "user_state": {}}"#, "not json"] {
+            fs::write(&file, body).unwrap();
+            assert!(matches!(read_bundle(&file), Err(AppError::ConfigError(_))), "{}", body);
+        }
+
+        fs::write(&file, r#"{"version": 1, "user_state": {"city_id": "5"}}"#).unwrap();
+        let bundle = read_bundle(&file).unwrap();
+        assert!(bundle.presets.is_empty());
+        assert!(bundle.cookies.is_none());
+    }
+}
@@ -2,46 +2,59 @@
 //! Corresponds to core/state.go
 
 use std::collections::HashMap;
-use std::fs;
+use std::path::Path;
 
 use chrono::{Duration, Local};
 use serde_json::Value;
 
 use super::errors::{AppError, AppResult};
+use super::fsutil::{read_with_backup, write_with_backup};
+use super::keepalive::{DEFAULT_KEEPALIVE_MINUTES, MAX_KEEPALIVE_MINUTES};
 use super::paths::user_state_path;
-use super::types::UserState;
+use super::profiles::{current_profile, CURRENT_PROFILE_KEY, DEFAULT_PROFILE};
+use super::types::{AlertConfig, ClientProfile, GrabPreset, NetworkSettings, RepeatTemplate, UserState};
 
 const DEFAULT_CITY_ID: &str = "5";
+pub const GRAB_PRESETS_KEY: &str = "grab_presets";
+pub const CUSTOM_PROXIES_KEY: &str = "custom_proxies";
+pub const ALERTS_KEY: &str = "alerts";
+pub const CLIENT_PROFILE_KEY: &str = "client_profile";
+pub const NETWORK_KEY: &str = "network";
+pub const REPEAT_TEMPLATES_KEY: &str = "repeat_templates";
+/// Repeat templates kept, most recent first
+pub const REPEAT_TEMPLATE_LIMIT: usize = 20;
 
 /// Load user state from file
 pub fn load_user_state() -> AppResult<HashMap<String, Value>> {
-    let path = user_state_path()?;
-
-    if !path.exists() {
-        return Ok(default_user_state());
-    }
+    load_user_state_from(&user_state_path()?)
+}
 
-    let data = fs::read_to_string(&path)?;
-    let raw: HashMap<String, Value> = serde_json::from_str(&data)?;
+/// Load user state from `path`, recovering from the backup copy if it is corrupt
+pub fn load_user_state_from(path: &Path) -> AppResult<HashMap<String, Value>> {
+    let raw = match read_with_backup(path, parse_user_state)? {
+        Some(raw) => raw,
+        None => return Ok(default_user_state()),
+    };
     let merged = merge_user_state(default_user_state(), raw);
     Ok(normalize_user_state(merged))
 }
 
 /// Save user state to file
 pub fn save_user_state(update: HashMap<String, Value>) -> AppResult<()> {
+    save_user_state_to(&user_state_path()?, update)
+}
+
+/// Merge `update` into the state at `path` and write it atomically
+pub fn save_user_state_to(path: &Path, update: HashMap<String, Value>) -> AppResult<()> {
     if update.is_empty() {
         return Err(AppError::ConfigError("State is empty".into()));
     }
 
-    let path = user_state_path()?;
-
     // Load existing state
-    let existing = if path.exists() {
-        let data = fs::read_to_string(&path)?;
-        serde_json::from_str::<HashMap<String, Value>>(&data).unwrap_or_default()
-    } else {
-        HashMap::new()
-    };
+    let existing = read_with_backup(path, parse_user_state)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
 
     // Merge states
     let merged = merge_user_state(default_user_state(), existing);
@@ -49,12 +62,12 @@ pub fn save_user_state(update: HashMap<String, Value>) -> AppResult<()> {
     let normalized = normalize_user_state(final_state);
 
     // Save
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
-    }
     let data = serde_json::to_string_pretty(&normalized)?;
-    fs::write(&path, data)?;
-    Ok(())
+    write_with_backup(path, data.as_bytes())
+}
+
+fn parse_user_state(data: &str) -> AppResult<HashMap<String, Value>> {
+    Ok(serde_json::from_str(data)?)
 }
 
 /// Get default user state
@@ -72,6 +85,21 @@ pub fn default_user_state() -> HashMap<String, Value> {
         Value::Array(vec![Value::String("am".into()), Value::String("pm".into())]),
     );
     state.insert("proxy_submit_enabled".into(), Value::Bool(true));
+    state.insert("proxy_probe_target".into(), Value::String("submit".into()));
+    state.insert("proxy_probe_method".into(), Value::String("get".into()));
+    state.insert("proxy_rotation".into(), Value::String("round_robin".into()));
+    state.insert("keepalive_minutes".into(), Value::from(DEFAULT_KEEPALIVE_MINUTES));
+    state.insert("auto_resume".into(), Value::Bool(false));
+    state.insert("tray_enabled".into(), Value::Bool(true));
+    state.insert("capture_submit_trace".into(), Value::Bool(false));
+    state.insert(ALERTS_KEY.into(), alert_config_value(&AlertConfig::default()));
+    state.insert(CLIENT_PROFILE_KEY.into(), client_profile_value(&ClientProfile::default()));
+    state.insert(NETWORK_KEY.into(), network_settings_value(&NetworkSettings::default()));
+    state.insert("doctor_blacklist".into(), Value::Object(serde_json::Map::new()));
+    state.insert(GRAB_PRESETS_KEY.into(), Value::Array(vec![]));
+    state.insert(REPEAT_TEMPLATES_KEY.into(), Value::Array(vec![]));
+    state.insert(CUSTOM_PROXIES_KEY.into(), Value::Array(vec![]));
+    state.insert(CURRENT_PROFILE_KEY.into(), Value::String(DEFAULT_PROFILE.into()));
     state
 }
 
@@ -120,7 +148,405 @@ fn normalize_user_state(mut state: HashMap<String, Value>) -> HashMap<String, Va
     let proxy_enabled = normalize_bool(state.get("proxy_submit_enabled"), true);
     state.insert("proxy_submit_enabled".into(), Value::Bool(proxy_enabled));
 
+    // Normalize proxy probe options
+    let (target, method) = proxy_probe_options(&state);
+    state.insert("proxy_probe_target".into(), Value::String(target));
+    state.insert("proxy_probe_method".into(), Value::String(method));
+    let rotation = proxy_rotation(&state);
+    state.insert("proxy_rotation".into(), Value::String(rotation));
+
+    // Normalize keepalive_minutes
+    let keepalive = keepalive_minutes(&state);
+    state.insert("keepalive_minutes".into(), Value::from(keepalive));
+
+    // Normalize auto_resume
+    let resume = auto_resume(&state);
+    state.insert("auto_resume".into(), Value::Bool(resume));
+
+    // Normalize tray_enabled
+    let tray = tray_enabled(&state);
+    state.insert("tray_enabled".into(), Value::Bool(tray));
+
+    // Normalize capture_submit_trace
+    let capture = capture_submit_trace(&state);
+    state.insert("capture_submit_trace".into(), Value::Bool(capture));
+
+    // Normalize alerts
+    let alerts = alert_config(&state);
+    state.insert(ALERTS_KEY.into(), alert_config_value(&alerts));
+
+    // Normalize client_profile
+    let profile = client_profile(&state);
+    state.insert(CLIENT_PROFILE_KEY.into(), client_profile_value(&profile));
+
+    // Normalize network
+    let network = network_settings(&state);
+    state.insert(NETWORK_KEY.into(), network_settings_value(&network));
+
+    // Normalize doctor_blacklist
+    let blacklist = normalize_doctor_blacklist(state.get("doctor_blacklist"));
+    state.insert("doctor_blacklist".into(), Value::Object(blacklist));
+
+    // Normalize grab_presets
+    let presets = normalize_grab_presets(state.get(GRAB_PRESETS_KEY));
+    state.insert(GRAB_PRESETS_KEY.into(), Value::Array(presets));
+
+    // Normalize repeat_templates
+    let templates = normalize_repeat_templates(state.get(REPEAT_TEMPLATES_KEY));
+    state.insert(REPEAT_TEMPLATES_KEY.into(), Value::Array(templates));
+
+    // Normalize custom_proxies
+    let proxies = normalize_string_array(state.get(CUSTOM_PROXIES_KEY));
+    state.insert(CUSTOM_PROXIES_KEY.into(), Value::Array(proxies));
+
+    // Normalize current_profile
+    let profile = current_profile(&state);
+    state.insert(CURRENT_PROFILE_KEY.into(), Value::String(profile));
+
+    state
+}
+
+/// Normalize saved grab presets: trim names, drop entries that do not parse
+/// or fail validation, and keep one entry per name (the last one saved wins)
+fn normalize_grab_presets(value: Option<&Value>) -> Vec<Value> {
+    let mut out: Vec<GrabPreset> = Vec::new();
+    let Some(Value::Array(arr)) = value else {
+        return Vec::new();
+    };
+
+    for raw in arr {
+        let mut preset = match serde_json::from_value::<GrabPreset>(raw.clone()) {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::warn!(error = %e, "dropping unreadable grab preset");
+                continue;
+            }
+        };
+        preset.name = preset.name.trim().to_string();
+        if preset.name.is_empty() {
+            tracing::warn!("dropping grab preset without a name");
+            continue;
+        }
+        if let Err(errors) = preset.config.validate_template() {
+            tracing::warn!(name = %preset.name, error = %errors.join("; "), "dropping invalid grab preset");
+            continue;
+        }
+        match out.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => out.push(preset),
+        }
+    }
+
+    out.iter()
+        .filter_map(|p| serde_json::to_value(p).ok())
+        .collect()
+}
+
+/// Saved grab presets from a (normalized) user state
+pub fn grab_presets(map: &HashMap<String, Value>) -> Vec<GrabPreset> {
+    map.get(GRAB_PRESETS_KEY)
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| serde_json::from_value(v.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Add or replace a preset by name
+pub fn save_grab_preset(preset: GrabPreset) -> AppResult<Vec<GrabPreset>> {
+    save_grab_preset_to(&user_state_path()?, preset)
+}
+
+/// Add or replace a preset by name in the state at `path`
+pub fn save_grab_preset_to(path: &Path, mut preset: GrabPreset) -> AppResult<Vec<GrabPreset>> {
+    preset.name = preset.name.trim().to_string();
+    if preset.name.is_empty() {
+        return Err(AppError::ConfigError("preset name is required".into()));
+    }
+    preset
+        .config
+        .validate_template()
+        .map_err(|errors| AppError::ConfigError(errors.join("; ")))?;
+
+    let mut presets = grab_presets(&load_user_state_from(path)?);
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(existing) => *existing = preset,
+        None => presets.push(preset),
+    }
+    write_grab_presets(path, presets)
+}
+
+/// Remove a preset by name
+pub fn delete_grab_preset(name: &str) -> AppResult<Vec<GrabPreset>> {
+    delete_grab_preset_from(&user_state_path()?, name)
+}
+
+/// Remove a preset by name from the state at `path`
+pub fn delete_grab_preset_from(path: &Path, name: &str) -> AppResult<Vec<GrabPreset>> {
+    let name = name.trim();
+    let mut presets = grab_presets(&load_user_state_from(path)?);
+    let before = presets.len();
+    presets.retain(|p| p.name != name);
+    if presets.len() == before {
+        return Err(AppError::ConfigError(format!("preset {} not found", name)));
+    }
+    write_grab_presets(path, presets)
+}
+
+fn write_grab_presets(path: &Path, presets: Vec<GrabPreset>) -> AppResult<Vec<GrabPreset>> {
+    let mut update = HashMap::new();
+    update.insert(GRAB_PRESETS_KEY.to_string(), serde_json::to_value(&presets)?);
+    save_user_state_to(path, update)?;
+    Ok(presets)
+}
+
+/// Normalize repeat templates: drop entries that do not parse or lack the
+/// hospital, department or member, keep the first (newest) per
+/// unit_id+dep_id and at most `REPEAT_TEMPLATE_LIMIT`
+fn normalize_repeat_templates(value: Option<&Value>) -> Vec<Value> {
+    let mut out: Vec<RepeatTemplate> = Vec::new();
+    let Some(Value::Array(arr)) = value else {
+        return Vec::new();
+    };
+
+    for raw in arr {
+        let mut template = match serde_json::from_value::<RepeatTemplate>(raw.clone()) {
+            Ok(t) => t,
+            Err(e) => {
+                tracing::warn!(error = %e, "dropping unreadable repeat template");
+                continue;
+            }
+        };
+        for id in [&mut template.unit_id, &mut template.dep_id, &mut template.member_id] {
+            *id = id.trim().to_string();
+        }
+        if template.unit_id.is_empty() || template.dep_id.is_empty() || template.member_id.is_empty() {
+            tracing::warn!("dropping repeat template without unit, department or member");
+            continue;
+        }
+        if !out.iter().any(|t| t.is_for(&template.unit_id, &template.dep_id)) {
+            out.push(template);
+        }
+    }
+    out.truncate(REPEAT_TEMPLATE_LIMIT);
+
+    out.iter()
+        .filter_map(|t| serde_json::to_value(t).ok())
+        .collect()
+}
+
+/// Repeat templates from a (normalized) user state, newest first
+pub fn repeat_templates(map: &HashMap<String, Value>) -> Vec<RepeatTemplate> {
+    map.get(REPEAT_TEMPLATES_KEY)
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| serde_json::from_value(v.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Template of the last successful grab in a department
+pub fn repeat_template(map: &HashMap<String, Value>, unit_id: &str, dep_id: &str) -> Option<RepeatTemplate> {
+    repeat_templates(map).into_iter().find(|t| t.is_for(unit_id, dep_id))
+}
+
+/// Remember a successful grab, replacing the department's previous template
+pub fn save_repeat_template(template: RepeatTemplate) -> AppResult<Vec<RepeatTemplate>> {
+    save_repeat_template_to(&user_state_path()?, template)
+}
+
+/// Put `template` first in the state at `path`, dropping the oldest past the limit
+pub fn save_repeat_template_to(path: &Path, template: RepeatTemplate) -> AppResult<Vec<RepeatTemplate>> {
+    let mut templates = repeat_templates(&load_user_state_from(path)?);
+    templates.retain(|t| !t.is_for(&template.unit_id, &template.dep_id));
+    templates.insert(0, template);
+    templates.truncate(REPEAT_TEMPLATE_LIMIT);
+
+    let mut update = HashMap::new();
+    update.insert(REPEAT_TEMPLATES_KEY.to_string(), serde_json::to_value(&templates)?);
+    save_user_state_to(path, update)?;
+    Ok(templates)
+}
+
+/// Normalize per-department doctor blacklist, dropping empty entries
+fn normalize_doctor_blacklist(value: Option<&Value>) -> serde_json::Map<String, Value> {
+    let mut out = serde_json::Map::new();
+    if let Some(Value::Object(map)) = value {
+        for (dep_id, ids) in map {
+            let dep_id = dep_id.trim();
+            if dep_id.is_empty() {
+                continue;
+            }
+            let ids = normalize_string_array(Some(ids));
+            if !ids.is_empty() {
+                out.insert(dep_id.to_string(), Value::Array(ids));
+            }
+        }
+    }
+    out
+}
+
+/// Proxy probe target and method, falling back to "submit" / "get"
+pub fn proxy_probe_options(state: &HashMap<String, Value>) -> (String, String) {
+    let pick = |key: &str, allowed: [&str; 2]| {
+        state
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| allowed.contains(&s.as_str()))
+            .unwrap_or_else(|| allowed[0].to_string())
+    };
+    (pick("proxy_probe_target", ["submit", "neutral"]), pick("proxy_probe_method", ["get", "head"]))
+}
+
+/// Proxy rotation strategy name, falling back to "round_robin"
+pub fn proxy_rotation(state: &HashMap<String, Value>) -> String {
     state
+        .get("proxy_rotation")
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| matches!(s.as_str(), "round_robin" | "consume" | "sticky"))
+        .unwrap_or_else(|| "round_robin".to_string())
+}
+
+/// Keep-alive interval in minutes (0 = off), clamped to a day
+pub fn keepalive_minutes(state: &HashMap<String, Value>) -> u64 {
+    let minutes = match state.get("keepalive_minutes") {
+        Some(Value::Number(n)) => n.as_u64().or_else(|| n.as_f64().filter(|v| *v >= 0.0).map(|v| v as u64)),
+        Some(Value::String(s)) => s.trim().parse::<u64>().ok(),
+        _ => None,
+    };
+    minutes
+        .unwrap_or(DEFAULT_KEEPALIVE_MINUTES)
+        .min(MAX_KEEPALIVE_MINUTES)
+}
+
+/// Whether an interrupted grab restarts on launch without asking
+pub fn auto_resume(state: &HashMap<String, Value>) -> bool {
+    normalize_bool(state.get("auto_resume"), false)
+}
+
+/// Whether the tray icon with the grab status is shown
+pub fn tray_enabled(state: &HashMap<String, Value>) -> bool {
+    normalize_bool(state.get("tray_enabled"), true)
+}
+
+/// Whether recent submits are captured for `get_submit_traces`
+pub fn capture_submit_trace(state: &HashMap<String, Value>) -> bool {
+    normalize_bool(state.get("capture_submit_trace"), false)
+}
+
+/// Alert flags; missing or malformed keys fall back to the defaults
+pub fn alert_config(state: &HashMap<String, Value>) -> AlertConfig {
+    let defaults = AlertConfig::default();
+    let Some(Value::Object(obj)) = state.get(ALERTS_KEY) else {
+        return defaults;
+    };
+    AlertConfig {
+        on_found: normalize_bool(obj.get("on_found"), defaults.on_found),
+        on_success: normalize_bool(obj.get("on_success"), defaults.on_success),
+        on_failure: normalize_bool(obj.get("on_failure"), defaults.on_failure),
+    }
+}
+
+fn alert_config_value(alerts: &AlertConfig) -> Value {
+    serde_json::to_value(alerts).unwrap_or(Value::Null)
+}
+
+/// Persist the alert flags
+pub fn save_alert_config(alerts: AlertConfig) -> AppResult<AlertConfig> {
+    let mut update = HashMap::new();
+    update.insert(ALERTS_KEY.to_string(), alert_config_value(&alerts));
+    save_user_state(update)?;
+    Ok(alerts)
+}
+
+/// Request fingerprint; a preset name or an object, blank fields take the default
+pub fn client_profile(state: &HashMap<String, Value>) -> ClientProfile {
+    let defaults = ClientProfile::default();
+    let obj = match state.get(CLIENT_PROFILE_KEY) {
+        Some(Value::String(name)) => return ClientProfile::preset(name.trim()).unwrap_or(defaults),
+        Some(Value::Object(obj)) => obj,
+        _ => return defaults,
+    };
+    let field = |key: &str, fallback: String| {
+        obj.get(key)
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .unwrap_or(fallback)
+    };
+    ClientProfile {
+        user_agent: field("user_agent", defaults.user_agent),
+        sec_ch_ua: field("sec_ch_ua", defaults.sec_ch_ua),
+        sec_ch_ua_platform: field("sec_ch_ua_platform", defaults.sec_ch_ua_platform),
+        accept_language: field("accept_language", defaults.accept_language),
+    }
+}
+
+fn client_profile_value(profile: &ClientProfile) -> Value {
+    serde_json::to_value(profile).unwrap_or(Value::Null)
+}
+
+/// Persist the request fingerprint
+pub fn save_client_profile(profile: ClientProfile) -> AppResult<ClientProfile> {
+    let mut update = HashMap::new();
+    update.insert(CLIENT_PROFILE_KEY.to_string(), client_profile_value(&profile));
+    save_user_state(update)?;
+    Ok(profile)
+}
+
+/// HTTP timeouts, clamped to their accepted ranges
+pub fn network_settings(state: &HashMap<String, Value>) -> NetworkSettings {
+    let defaults = NetworkSettings::default();
+    let Some(Value::Object(obj)) = state.get(NETWORK_KEY) else {
+        return defaults;
+    };
+    let number = |key: &str, fallback: u64| match obj.get(key) {
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(fallback),
+        Some(Value::String(s)) => s.trim().parse::<u64>().unwrap_or(fallback),
+        _ => fallback,
+    };
+    NetworkSettings {
+        connect_timeout_secs: number("connect_timeout_secs", defaults.connect_timeout_secs),
+        request_timeout_secs: number("request_timeout_secs", defaults.request_timeout_secs),
+        grab_query_timeout_secs: number("grab_query_timeout_secs", defaults.grab_query_timeout_secs),
+        slow_network: normalize_bool(obj.get("slow_network"), defaults.slow_network),
+        schedule_max_pages: u32::try_from(number("schedule_max_pages", u64::from(defaults.schedule_max_pages)))
+            .unwrap_or(u32::MAX),
+        rate_limit: obj
+            .get("rate_limit")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or(defaults.rate_limit),
+        http_cache_ttl_secs: number("http_cache_ttl_secs", defaults.http_cache_ttl_secs),
+    }
+    .clamped()
+}
+
+fn network_settings_value(network: &NetworkSettings) -> Value {
+    serde_json::to_value(network).unwrap_or(Value::Null)
+}
+
+/// Persist the HTTP timeouts after clamping them
+pub fn save_network_settings(network: NetworkSettings) -> AppResult<NetworkSettings> {
+    let network = network.clamped();
+    let mut update = HashMap::new();
+    update.insert(NETWORK_KEY.to_string(), network_settings_value(&network));
+    save_user_state(update)?;
+    Ok(network)
+}
+
+/// Saved custom proxy entries
+pub fn custom_proxies(state: &HashMap<String, Value>) -> Vec<String> {
+    normalize_string_array(state.get(CUSTOM_PROXIES_KEY))
+        .into_iter()
+        .filter_map(|v| v.as_str().map(str::to_string))
+        .collect()
 }
 
 /// Normalize a boolean value
@@ -174,6 +600,7 @@ fn default_target_date() -> String {
 
 /// Convert HashMap to UserState struct
 pub fn to_user_state_struct(map: &HashMap<String, Value>) -> UserState {
+    let (proxy_probe_target, proxy_probe_method) = proxy_probe_options(map);
     UserState {
         city_id: map
             .get("city_id")
@@ -220,6 +647,35 @@ pub fn to_user_state_struct(map: &HashMap<String, Value>) -> UserState {
             })
             .unwrap_or_else(|| vec!["am".into(), "pm".into()]),
         proxy_submit_enabled: normalize_bool(map.get("proxy_submit_enabled"), true),
+        proxy_probe_target,
+        proxy_probe_method,
+        proxy_rotation: proxy_rotation(map),
+        keepalive_minutes: keepalive_minutes(map),
+        auto_resume: auto_resume(map),
+        tray_enabled: tray_enabled(map),
+        capture_submit_trace: capture_submit_trace(map),
+        alerts: alert_config(map),
+        client_profile: client_profile(map),
+        network: network_settings(map),
+        doctor_blacklist: map
+            .get("doctor_blacklist")
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .map(|(dep_id, ids)| {
+                        let ids = ids
+                            .as_array()
+                            .map(|arr| {
+                                arr.iter()
+                                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        (dep_id.clone(), ids)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
     }
 }
 
@@ -243,4 +699,285 @@ mod tests {
         assert!(!normalize_bool(Some(&Value::String("false".into())), true));
         assert!(normalize_bool(None, true));
     }
+
+    #[test]
+    fn test_normalize_doctor_blacklist() {
+        let raw = serde_json::json!({
+            "200": [" 1 ", "", "2"],
+            "300": [],
+            " ": ["9"],
+        });
+        let out = normalize_doctor_blacklist(Some(&raw));
+        assert_eq!(out.len(), 1);
+        assert_eq!(out["200"], serde_json::json!(["1", "2"]));
+    }
+
+    #[test]
+    fn test_user_state_recovers_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user_state.json");
+
+        let mut update = HashMap::new();
+        update.insert("city_id".to_string(), Value::String("21".into()));
+        update.insert("unit_id".to_string(), Value::String("131".into()));
+        save_user_state_to(&path, update).unwrap();
+
+        // Simulate a write cut off by a crash
+        std::fs::write(&path, "{\"city_id\": \"2").unwrap();
+
+        let loaded = load_user_state_from(&path).unwrap();
+        assert_eq!(loaded["city_id"], Value::String("21".into()));
+        assert_eq!(loaded["unit_id"], Value::String("131".into()));
+
+        // Saving over the corrupt file keeps the recovered fields
+        let mut update = HashMap::new();
+        update.insert("dep_id".to_string(), Value::String("7".into()));
+        save_user_state_to(&path, update).unwrap();
+        let loaded = load_user_state_from(&path).unwrap();
+        assert_eq!(loaded["unit_id"], Value::String("131".into()));
+        assert_eq!(loaded["dep_id"], Value::String("7".into()));
+    }
+
+    fn preset(name: &str, unit_id: &str) -> Value {
+        serde_json::json!({
+            "name": name,
+            "config": {
+                "unit_id": unit_id,
+                "dep_id": "200",
+                "member_id": "m1",
+                "target_dates": ["2024-03-20"],
+            },
+        })
+    }
+
+    #[test]
+    fn test_legacy_state_without_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user_state.json");
+        std::fs::write(&path, r#"{"city_id": "5", "unit_id": "21"}"#).unwrap();
+
+        let state = load_user_state_from(&path).unwrap();
+        assert_eq!(state[GRAB_PRESETS_KEY], serde_json::json!([]));
+        assert!(grab_presets(&state).is_empty());
+        assert_eq!(state[REPEAT_TEMPLATES_KEY], serde_json::json!([]));
+        assert!(repeat_templates(&state).is_empty());
+        assert_eq!(to_user_state_struct(&state).unit_id.as_deref(), Some("21"));
+    }
+
+    #[test]
+    fn test_normalize_grab_presets() {
+        let raw = serde_json::json!([
+            preset(" 老大 ", "21"),
+            preset("老二", "22"),
+            preset("老大", "23"),
+            preset("", "24"),
+            preset("bad", ""),
+            {"name": "garbage"},
+        ]);
+        let out = normalize_grab_presets(Some(&raw));
+        let mut state = HashMap::new();
+        state.insert(GRAB_PRESETS_KEY.to_string(), Value::Array(out));
+
+        let presets = grab_presets(&state);
+        let names: Vec<&str> = presets.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["老大", "老二"]);
+        assert_eq!(presets[0].config.unit_id, "23");
+    }
+
+    #[test]
+    fn test_grab_preset_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user_state.json");
+        let mut update = HashMap::new();
+        update.insert("unit_id".to_string(), Value::String("21".into()));
+        save_user_state_to(&path, update).unwrap();
+
+        let first: GrabPreset = serde_json::from_value(preset("老大", "21")).unwrap();
+        let second: GrabPreset = serde_json::from_value(preset("老二", "22")).unwrap();
+        save_grab_preset_to(&path, first).unwrap();
+        let saved = save_grab_preset_to(&path, second).unwrap();
+        assert_eq!(saved.len(), 2);
+
+        let invalid: GrabPreset = serde_json::from_value(preset("坏的", "")).unwrap();
+        assert!(matches!(save_grab_preset_to(&path, invalid), Err(AppError::ConfigError(_))));
+
+        let remaining = delete_grab_preset_from(&path, "老大").unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(delete_grab_preset_from(&path, "老大").is_err());
+
+        let state = load_user_state_from(&path).unwrap();
+        assert_eq!(grab_presets(&state)[0].config.unit_id, "22");
+        assert_eq!(state["unit_id"], Value::String("21".into()));
+    }
+
+    fn template(unit_id: &str, dep_id: &str, saved_at: &str) -> RepeatTemplate {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": unit_id,
+            "dep_id": dep_id,
+            "doctor_id": "100",
+            "member_id": "m1",
+            "preferred_hours": ["09:30-10:00"],
+            "address_id": "3",
+            "address": "深圳",
+            "saved_at": saved_at,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_repeat_template_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user_state.json");
+        save_repeat_template_to(&path, template("21", "200", "2024-02-20 07:30:01")).unwrap();
+        save_repeat_template_to(&path, template("21", "300", "2024-02-27 07:30:01")).unwrap();
+
+        // Booking the same department again replaces its template and moves it first
+        let mut again = template("21", "200", "2024-03-19 07:30:02");
+        again.doctor_id = "101".into();
+        let saved = save_repeat_template_to(&path, again.clone()).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0], again);
+
+        let state = load_user_state_from(&path).unwrap();
+        assert_eq!(repeat_template(&state, " 21 ", "200"), Some(again));
+        assert_eq!(repeat_template(&state, "21", "300").unwrap().preferred_hours, ["09:30-10:00"]);
+        assert_eq!(repeat_template(&state, "21", "400"), None);
+
+        // A UI save of the flat fields keeps the templates
+        let mut update = HashMap::new();
+        update.insert("unit_id".to_string(), Value::String("22".into()));
+        save_user_state_to(&path, update).unwrap();
+        assert_eq!(repeat_templates(&load_user_state_from(&path).unwrap()).len(), 2);
+    }
+
+    #[test]
+    fn test_repeat_templates_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user_state.json");
+        for dep in 0..REPEAT_TEMPLATE_LIMIT + 3 {
+            save_repeat_template_to(&path, template("21", &dep.to_string(), "")).unwrap();
+        }
+        let templates = repeat_templates(&load_user_state_from(&path).unwrap());
+        assert_eq!(templates.len(), REPEAT_TEMPLATE_LIMIT);
+        assert_eq!(templates[0].dep_id, (REPEAT_TEMPLATE_LIMIT + 2).to_string());
+        assert_eq!(templates[REPEAT_TEMPLATE_LIMIT - 1].dep_id, "3");
+
+        let raw = serde_json::json!([
+            template(" 21 ", "200", "new"),
+            template("21", "200", "old"),
+            template("21", "", "x"),
+            {"unit_id": "21", "dep_id": "300"},
+            {"unit_id": "21", "dep_id": "400", "member_id": "m1", "preferred_hours": "am"},
+        ]);
+        let out = normalize_repeat_templates(Some(&raw));
+        let mut state = HashMap::new();
+        state.insert(REPEAT_TEMPLATES_KEY.to_string(), Value::Array(out));
+        let templates = repeat_templates(&state);
+        assert_eq!(templates.len(), 1);
+        assert_eq!((templates[0].unit_id.as_str(), templates[0].saved_at.as_str()), ("21", "new"));
+        assert!(normalize_repeat_templates(Some(&serde_json::json!({"21": {}}))).is_empty());
+    }
+
+    #[test]
+    fn test_client_profile_from_state() {
+        let mut state = HashMap::new();
+        assert_eq!(client_profile(&state), ClientProfile::default());
+
+        state.insert(CLIENT_PROFILE_KEY.to_string(), serde_json::json!("edge_win"));
+        assert_eq!(client_profile(&state), ClientProfile::preset("edge_win").unwrap());
+
+        state.insert(CLIENT_PROFILE_KEY.to_string(), serde_json::json!({"user_agent": "Custom/1.0", "sec_ch_ua": "  "}));
+        let profile = client_profile(&state);
+        assert_eq!(profile.user_agent, "Custom/1.0");
+        assert_eq!(profile.sec_ch_ua, ClientProfile::default().sec_ch_ua);
+
+        let normalized = normalize_user_state(state);
+        assert_eq!(normalized[CLIENT_PROFILE_KEY]["user_agent"], "Custom/1.0");
+        assert_eq!(to_user_state_struct(&normalized).client_profile, profile);
+    }
+
+    #[test]
+    fn test_network_settings_from_state() {
+        let mut state = HashMap::new();
+        assert_eq!(network_settings(&state), NetworkSettings::default());
+
+        state.insert(
+            NETWORK_KEY.to_string(),
+            serde_json::json!({
+                "connect_timeout_secs": "5",
+                "request_timeout_secs": 600,
+                "slow_network": true,
+                "rate_limit": {"gate_per_sec": 0.5},
+            }),
+        );
+        let network = network_settings(&state);
+        assert_eq!(network.connect_timeout_secs, 5);
+        assert_eq!(network.request_timeout_secs, 120);
+        assert_eq!(network.grab_query_timeout_secs, NetworkSettings::default().grab_query_timeout_secs);
+        assert!(network.slow_network);
+        assert_eq!(network.rate_limit.gate_per_sec, 0.5);
+        assert_eq!(network.rate_limit.www_per_sec, 2.0);
+
+        let normalized = normalize_user_state(state);
+        assert_eq!(normalized[NETWORK_KEY]["request_timeout_secs"], 120);
+        assert_eq!(to_user_state_struct(&normalized).network, network);
+    }
+
+    #[test]
+    fn test_keepalive_minutes() {
+        let mut state = HashMap::new();
+        assert_eq!(keepalive_minutes(&state), DEFAULT_KEEPALIVE_MINUTES);
+        for (raw, expected) in [
+            (serde_json::json!(0), 0),
+            (serde_json::json!(45), 45),
+            (serde_json::json!(" 30 "), 30),
+            (serde_json::json!(-5), DEFAULT_KEEPALIVE_MINUTES),
+            (serde_json::json!("soon"), DEFAULT_KEEPALIVE_MINUTES),
+            (serde_json::json!(100000), MAX_KEEPALIVE_MINUTES),
+        ] {
+            state.insert("keepalive_minutes".to_string(), raw.clone());
+            assert_eq!(keepalive_minutes(&state), expected, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_alert_config_normalization() {
+        let mut state = HashMap::new();
+        assert_eq!(alert_config(&state), AlertConfig::default());
+
+        state.insert(ALERTS_KEY.to_string(), serde_json::json!("loud"));
+        assert_eq!(alert_config(&state), AlertConfig::default());
+
+        state.insert(ALERTS_KEY.to_string(), serde_json::json!({"on_found": "off", "on_failure": "1"}));
+        let alerts = alert_config(&state);
+        assert_eq!(
+            alerts,
+            AlertConfig {
+                on_found: false,
+                on_success: true,
+                on_failure: true,
+            }
+        );
+        assert!(!alerts.wants("found") && alerts.wants("failure") && !alerts.wants("bogus"));
+
+        let normalized = normalize_user_state(state);
+        assert_eq!(
+            normalized[ALERTS_KEY],
+            serde_json::json!({"on_found": false, "on_success": true, "on_failure": true})
+        );
+        assert_eq!(to_user_state_struct(&normalized).alerts, alerts);
+    }
+
+    #[test]
+    fn test_proxy_probe_options() {
+        let mut state = HashMap::new();
+        assert_eq!(proxy_probe_options(&state), ("submit".to_string(), "get".to_string()));
+        state.insert("proxy_probe_target".to_string(), Value::String(" Neutral ".into()));
+        state.insert("proxy_probe_method".to_string(), Value::String("POST".into()));
+        assert_eq!(proxy_probe_options(&state), ("neutral".to_string(), "get".to_string()));
+
+        assert_eq!(proxy_rotation(&state), "round_robin");
+        state.insert("proxy_rotation".to_string(), Value::String("STICKY".into()));
+        assert_eq!(proxy_rotation(&state), "sticky");
+    }
 }
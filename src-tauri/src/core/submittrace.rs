@@ -0,0 +1,241 @@
+//! Submit request/response capture for QuickDoctor
+//! Keeps the last few submits in memory, without cookie values or hisMemId
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use reqwest::header::{HeaderMap, COOKIE, SET_COOKIE};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::textutil::truncate_utf8;
+use super::types::SubmitOrderParams;
+
+/// Submits kept, oldest dropped first
+pub const SUBMIT_TRACE_LIMIT: usize = 5;
+/// Longest response body kept per submit
+pub const SUBMIT_TRACE_BODY_BYTES: usize = 16 * 1024;
+
+const REDACTED: &str = "[redacted]";
+/// Form fields whose values are dropped
+const SECRET_FORM_FIELDS: [&str; 1] = ["hisMemId"];
+/// Response headers worth keeping; Set-Cookie keeps only the cookie names
+const RESPONSE_HEADERS: [&str; 6] = ["content-type", "content-length", "location", "retry-after", "server", "set-cookie"];
+
+/// One submit as sent and received
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubmitTrace {
+    pub at: String,
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    /// Form fields as posted
+    pub form: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Where the request ended up after redirects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    pub response_headers: Vec<(String, String)>,
+    pub body: String,
+    /// Transport failure; no response fields are set then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SubmitTrace {
+    /// The request half: headers without Cookie, form without hisMemId
+    pub fn request(url: &str, headers: &HeaderMap, params: &SubmitOrderParams) -> Self {
+        let request_headers = headers
+            .iter()
+            .filter(|(name, _)| **name != COOKIE)
+            .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect();
+        Self {
+            at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            url: url.to_string(),
+            request_headers,
+            form: sanitized_form(params),
+            status: None,
+            final_url: None,
+            response_headers: Vec::new(),
+            body: String::new(),
+            error: None,
+        }
+    }
+
+    /// Fill in the response; `params` is used to scrub hisMemId echoed in the body
+    pub fn respond(mut self, status: u16, final_url: &str, headers: &HeaderMap, body: &str, params: &SubmitOrderParams) -> Self {
+        self.status = Some(status);
+        self.final_url = Some(final_url.to_string());
+        self.response_headers = interesting_headers(headers);
+        let body = if params.his_mem_id.is_empty() {
+            body.to_string()
+        } else {
+            body.replace(&params.his_mem_id, REDACTED)
+        };
+        self.body = truncate_utf8(&body, SUBMIT_TRACE_BODY_BYTES);
+        self
+    }
+
+    pub fn failed(mut self, error: &str) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// The submit form as posted, with secret fields replaced
+pub fn sanitized_form(params: &SubmitOrderParams) -> BTreeMap<String, String> {
+    let Ok(Value::Object(map)) = serde_json::to_value(params) else {
+        return BTreeMap::new();
+    };
+    map.into_iter()
+        .map(|(key, value)| {
+            let text = match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            let text = if SECRET_FORM_FIELDS.contains(&key.as_str()) && !text.is_empty() {
+                REDACTED.to_string()
+            } else {
+                text
+            };
+            (key, text)
+        })
+        .collect()
+}
+
+/// Response headers of interest; a Set-Cookie shows as `name=[redacted]`
+pub fn interesting_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, _)| RESPONSE_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            let value = if *name == SET_COOKIE {
+                let cookie = value.split(';').next().unwrap_or_default();
+                format!("{}={}", cookie.split('=').next().unwrap_or_default().trim(), REDACTED)
+            } else {
+                value
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// The last `SUBMIT_TRACE_LIMIT` submits, captured only while enabled
+#[derive(Debug, Default)]
+pub struct SubmitTraces {
+    enabled: AtomicBool,
+    traces: Mutex<VecDeque<SubmitTrace>>,
+}
+
+impl SubmitTraces {
+    /// Turning capture off also forgets what was captured
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.traces.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, trace: SubmitTrace) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut traces = self.traces.lock().unwrap_or_else(|e| e.into_inner());
+        if traces.len() >= SUBMIT_TRACE_LIMIT {
+            traces.pop_front();
+        }
+        traces.push_back(trace);
+    }
+
+    /// Captured submits, newest first
+    pub fn list(&self) -> Vec<SubmitTrace> {
+        self.traces.lock().unwrap_or_else(|e| e.into_inner()).iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, CONTENT_TYPE, REFERER};
+
+    fn params() -> SubmitOrderParams {
+        serde_json::from_value(serde_json::json!({
+            "sch_data": "abc",
+            "mid": "600123",
+            "hisMemId": "H99887766",
+            "unit_id": "21",
+            "schedule_id": "900",
+            "dep_id": "200",
+            "detlid": "5001",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_trace_redacts_cookies_and_his_mem_id() {
+        let mut sent = HeaderMap::new();
+        sent.insert(COOKIE, HeaderValue::from_static("access_hash=f3a9c0e1"));
+        sent.insert(REFERER, HeaderValue::from_static("https://www.91160.com/guahao/ystep1/uid-21.html"));
+        let mut received = HeaderMap::new();
+        received.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        received.append(SET_COOKIE, HeaderValue::from_static("access_hash=f3a9c0e1; path=/; HttpOnly"));
+        received.append(SET_COOKIE, HeaderValue::from_static("PHPSESSID=sessvalue1"));
+        received.insert("x-powered-by", HeaderValue::from_static("PHP"));
+
+        let body = format!("<div class=\"error\">就诊人 H99887766 信息有误</div>{}", "很".repeat(SUBMIT_TRACE_BODY_BYTES));
+        let trace = SubmitTrace::request("https://www.91160.com/guahao/ysubmit.html", &sent, &params()).respond(
+            200,
+            "https://www.91160.com/guahao/ysubmit.html",
+            &received,
+            &body,
+            &params(),
+        );
+
+        let text = serde_json::to_string(&trace).unwrap();
+        for secret in ["f3a9c0e1", "sessvalue1", "H99887766"] {
+            assert!(!text.contains(secret), "{} leaked: {}", secret, text);
+        }
+        assert_eq!(trace.request_headers, [("referer".to_string(), "https://www.91160.com/guahao/ystep1/uid-21.html".to_string())]);
+        assert_eq!(trace.form["hisMemId"], "[redacted]");
+        assert_eq!(trace.form["mid"], "600123");
+        assert_eq!(trace.form["detlid"], "5001");
+        assert_eq!(
+            trace.response_headers,
+            [
+                ("content-type".to_string(), "text/html".to_string()),
+                ("set-cookie".to_string(), "access_hash=[redacted]".to_string()),
+                ("set-cookie".to_string(), "PHPSESSID=[redacted]".to_string()),
+            ]
+        );
+        assert!(trace.body.starts_with("<div class=\"error\">就诊人 [redacted] 信息有误"));
+        assert!(trace.body.len() <= SUBMIT_TRACE_BODY_BYTES + "…".len());
+    }
+
+    #[test]
+    fn test_capture_only_while_enabled_and_bounded() {
+        let traces = SubmitTraces::default();
+        let trace = |n: u16| SubmitTrace::request("u", &HeaderMap::new(), &params()).respond(n, "u", &HeaderMap::new(), "", &params());
+        traces.record(trace(1));
+        assert!(traces.list().is_empty());
+
+        traces.set_enabled(true);
+        for n in 1..=SUBMIT_TRACE_LIMIT as u16 + 2 {
+            traces.record(trace(n));
+        }
+        let statuses: Vec<Option<u16>> = traces.list().iter().map(|t| t.status).collect();
+        assert_eq!(statuses, [Some(7), Some(6), Some(5), Some(4), Some(3)]);
+
+        traces.record(SubmitTrace::request("u", &HeaderMap::new(), &params()).failed("timed out"));
+        assert_eq!(traces.list()[0].error.as_deref(), Some("timed out"));
+
+        traces.set_enabled(false);
+        assert!(traces.list().is_empty());
+    }
+}
@@ -0,0 +1,289 @@
+//! Long-running task bookkeeping for QuickDoctor
+//! Tracks grab and QR login tasks so exit can wait for them to finish
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Spawned tasks by kind ("grab", "qr_login")
+#[derive(Debug, Default)]
+pub struct RunningTasks {
+    tasks: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
+}
+
+impl RunningTasks {
+    /// Remember a spawned task; finished tasks are dropped on the way
+    pub fn track(&self, kind: &'static str, handle: JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|(_, handle)| !handle.is_finished());
+        tasks.push((kind, handle));
+    }
+
+    /// Kinds of the tasks still running, each listed once
+    pub fn running(&self) -> Vec<&'static str> {
+        let tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut kinds: Vec<&'static str> = Vec::new();
+        for (kind, handle) in tasks.iter() {
+            if !handle.is_finished() && !kinds.contains(kind) {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+
+    /// Hand over every task, e.g. to `shutdown`
+    pub fn take(&self) -> Vec<JoinHandle<()>> {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.drain(..).map(|(_, handle)| handle).collect()
+    }
+}
+
+const PHASE_IDLE: u8 = 0;
+const PHASE_RUNNING: u8 = 1;
+const PHASE_SUCCEEDED: u8 = 2;
+
+/// Grab progress shared with the tray, which polls it
+#[derive(Debug, Default)]
+pub struct GrabStatus {
+    phase: AtomicU8,
+    attempt: AtomicU32,
+    run: AtomicU32,
+}
+
+impl GrabStatus {
+    /// A grab has been launched; it may still be waiting for start_time.
+    /// Returns the run id to pass to `finish`.
+    pub fn start(&self) -> u32 {
+        self.attempt.store(0, Ordering::Relaxed);
+        self.phase.store(PHASE_RUNNING, Ordering::Relaxed);
+        self.run.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Attempt counter of the running grab
+    pub fn set_attempt(&self, attempt: u32) {
+        self.attempt.store(attempt, Ordering::Relaxed);
+    }
+
+    /// The grab ended; a success is shown until the next one starts. A run
+    /// replaced by a newer one (stopped for a restart) leaves the status alone.
+    pub fn finish(&self, run: u32, success: bool) {
+        if self.run.load(Ordering::Relaxed) != run {
+            return;
+        }
+        let phase = if success { PHASE_SUCCEEDED } else { PHASE_IDLE };
+        self.phase.store(phase, Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.phase.load(Ordering::Relaxed) == PHASE_RUNNING
+    }
+
+    /// Status line for the tray tooltip and menu
+    pub fn label(&self) -> String {
+        match self.phase.load(Ordering::Relaxed) {
+            PHASE_RUNNING => match self.attempt.load(Ordering::Relaxed) {
+                0 => "抢号中 (等待开抢)".to_string(),
+                n => format!("抢号中 (第 {} 次尝试)", n),
+            },
+            PHASE_SUCCEEDED => "已成功".to_string(),
+            _ => "空闲".to_string(),
+        }
+    }
+}
+
+/// How a captcha pause ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptchaWait {
+    Resumed,
+    TimedOut,
+    Cancelled,
+}
+
+/// Holds a grab paused on a captcha until the user reports it solved
+#[derive(Debug, Default)]
+pub struct CaptchaGate {
+    resumed: Notify,
+    waiting: AtomicBool,
+}
+
+impl CaptchaGate {
+    /// Pause until `resume`, cancellation or `timeout`, whichever comes first
+    pub async fn wait(&self, timeout: Duration, cancel: &CancellationToken) -> CaptchaWait {
+        let resumed = self.resumed.notified();
+        tokio::pin!(resumed);
+        // Registered before `waiting` is set, so a resume right after cannot be missed
+        resumed.as_mut().enable();
+        self.waiting.store(true, Ordering::SeqCst);
+        let outcome = tokio::select! {
+            _ = &mut resumed => CaptchaWait::Resumed,
+            _ = cancel.cancelled() => CaptchaWait::Cancelled,
+            _ = tokio::time::sleep(timeout) => CaptchaWait::TimedOut,
+        };
+        self.waiting.store(false, Ordering::SeqCst);
+        outcome
+    }
+
+    /// Release the paused grab; false when none is waiting. A resume with
+    /// nothing paused is dropped rather than kept for the next captcha.
+    pub fn resume(&self) -> bool {
+        if !self.waiting.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.resumed.notify_waiters();
+        true
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.waiting.load(Ordering::SeqCst)
+    }
+}
+
+/// Cancel `tokens`, then give `tasks` up to `grace` to finish their cleanup
+/// Tasks still running at the deadline are aborted; returns how many were.
+pub async fn shutdown(tokens: Vec<CancellationToken>, tasks: Vec<JoinHandle<()>>, grace: Duration) -> usize {
+    for token in &tokens {
+        token.cancel();
+    }
+    let deadline = tokio::time::Instant::now() + grace;
+    let mut aborted = 0;
+    for mut task in tasks {
+        if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+            task.abort();
+            aborted += 1;
+        }
+    }
+    aborted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Task that, once cancelled, takes `cleanup` to record "flushed"
+    fn grab_like(token: CancellationToken, cleanup: Duration, events: Arc<Mutex<Vec<&'static str>>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            token.cancelled().await;
+            events.lock().unwrap().push("cancelled");
+            tokio::time::sleep(cleanup).await;
+            events.lock().unwrap().push("flushed");
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_cancels_then_waits_for_cleanup() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let grab = CancellationToken::new();
+        let qr = CancellationToken::new();
+        let tasks = RunningTasks::default();
+        tasks.track("grab", grab_like(grab.clone(), Duration::from_millis(300), events.clone()));
+        tasks.track("qr_login", grab_like(qr.clone(), Duration::from_millis(10), events.clone()));
+        tokio::task::yield_now().await;
+        assert_eq!(tasks.running(), ["grab", "qr_login"]);
+        assert!(events.lock().unwrap().is_empty());
+
+        let started = tokio::time::Instant::now();
+        let aborted = shutdown(vec![grab.clone(), qr.clone()], tasks.take(), Duration::from_secs(3)).await;
+        assert_eq!(aborted, 0);
+        assert!(grab.is_cancelled() && qr.is_cancelled());
+        assert_eq!(*events.lock().unwrap(), ["cancelled", "cancelled", "flushed", "flushed"]);
+        // Returned once the slower cleanup was done, not at the deadline
+        assert!(started.elapsed() >= Duration::from_millis(300) && started.elapsed() < Duration::from_secs(3));
+        assert!(tasks.running().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_aborts_tasks_past_grace() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let token = CancellationToken::new();
+        let stuck = grab_like(token.clone(), Duration::from_secs(60), events.clone());
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(shutdown(vec![token], vec![stuck], Duration::from_secs(2)).await, 1);
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert_eq!(*events.lock().unwrap(), ["cancelled"]);
+    }
+
+    #[test]
+    fn test_grab_status_labels() {
+        let status = GrabStatus::default();
+        assert_eq!(status.label(), "空闲");
+        assert!(!status.is_running());
+
+        let run = status.start();
+        assert!(status.is_running());
+        assert_eq!(status.label(), "抢号中 (等待开抢)");
+        status.set_attempt(12);
+        assert_eq!(status.label(), "抢号中 (第 12 次尝试)");
+        status.finish(run, true);
+        assert_eq!(status.label(), "已成功");
+
+        // A new run clears the previous attempt count and success
+        let old = status.start();
+        assert_eq!(status.label(), "抢号中 (等待开抢)");
+        // Restarted: the stopped run finishing late must not mark the new one idle
+        let new = status.start();
+        status.set_attempt(3);
+        status.finish(old, false);
+        assert_eq!(status.label(), "抢号中 (第 3 次尝试)");
+        status.finish(new, false);
+        assert_eq!(status.label(), "空闲");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_captcha_gate_outcomes() {
+        let gate = Arc::new(CaptchaGate::default());
+        let token = CancellationToken::new();
+        // Nothing paused: the resume is not kept for later
+        assert!(!gate.resume());
+        let started = tokio::time::Instant::now();
+        assert_eq!(gate.wait(Duration::from_secs(30), &token).await, CaptchaWait::TimedOut);
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+        assert!(!gate.is_waiting());
+
+        let waiter = {
+            let (gate, token) = (gate.clone(), token.clone());
+            tokio::spawn(async move { gate.wait(Duration::from_secs(600), &token).await })
+        };
+        while !gate.is_waiting() {
+            tokio::task::yield_now().await;
+        }
+        tokio::time::sleep(Duration::from_secs(45)).await;
+        assert!(gate.resume());
+        assert_eq!(waiter.await.unwrap(), CaptchaWait::Resumed);
+        assert!(!gate.is_waiting());
+
+        let waiter = {
+            let (gate, token) = (gate.clone(), token.clone());
+            tokio::spawn(async move { gate.wait(Duration::from_secs(600), &token).await })
+        };
+        tokio::task::yield_now().await;
+        token.cancel();
+        assert_eq!(waiter.await.unwrap(), CaptchaWait::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_running_tasks_forget_finished() {
+        let tasks = RunningTasks::default();
+        let done = tokio::spawn(async {});
+        while !done.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        tasks.track("qr_login", done);
+        assert!(tasks.running().is_empty());
+
+        let token = CancellationToken::new();
+        let waiting = token.clone();
+        tasks.track("grab", tokio::spawn(async move { waiting.cancelled().await }));
+        assert_eq!(tasks.running(), ["grab"]);
+        token.cancel();
+        for task in tasks.take() {
+            task.await.unwrap();
+        }
+        assert!(tasks.running().is_empty());
+    }
+}
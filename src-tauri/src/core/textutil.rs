@@ -0,0 +1,41 @@
+//! Text helpers for SkylineMed
+//! Pages from 91160 are mostly Chinese, so byte-index slicing can land inside
+//! a multi-byte character; snippets and previews go through here instead
+
+/// Cut `s` to at most `max_bytes`, backing off to a char boundary, and mark
+/// the cut with "…" (not counted against `max_bytes`)
+pub fn truncate_utf8(s: &str, max_bytes: usize) -> String {
+    if s.len() <= max_bytes {
+        return s.to_string();
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &s[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_utf8_keeps_short_text() {
+        assert_eq!(truncate_utf8("", 10), "");
+        assert_eq!(truncate_utf8("挂号成功", 12), "挂号成功");
+        assert_eq!(truncate_utf8("abc", 3), "abc");
+    }
+
+    #[test]
+    fn test_truncate_utf8_floors_to_char_boundary() {
+        // Each character is 3 bytes; every cut inside one backs off to its start
+        let text = "号源已满请稍后再试";
+        assert_eq!(truncate_utf8(text, 3), "号…");
+        assert_eq!(truncate_utf8(text, 4), "号…");
+        assert_eq!(truncate_utf8(text, 5), "号…");
+        assert_eq!(truncate_utf8(text, 6), "号源…");
+        assert_eq!(truncate_utf8(text, 2), "…");
+        assert_eq!(truncate_utf8("ab号源", 4), "ab…");
+        assert_eq!(truncate_utf8("abcdef", 4), "abcd…");
+    }
+}
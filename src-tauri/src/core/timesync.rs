@@ -0,0 +1,251 @@
+//! Server clock offset estimation for QuickDoctor
+//! Median offset of a few Date header samples, with slow round trips dropped
+
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+use super::api::{ScheduleApi, TimeSample};
+use super::errors::{AppError, AppResult};
+use super::types::TimeOffset;
+
+pub const TIME_SYNC_SAMPLES: usize = 5;
+pub const TIME_SYNC_SAMPLE_GAP_MS: u64 = 80;
+/// Longest a whole calibration may take, gaps included
+pub const TIME_SYNC_BUDGET: Duration = Duration::from_secs(3);
+/// A sample is an outlier when its RTT exceeds this multiple of the median RTT
+const RTT_OUTLIER_FACTOR: i64 = 3;
+/// ...and the median by at least this much, so fast links keep their jitter
+const RTT_OUTLIER_SLACK_MS: i64 = 50;
+
+/// Estimated server clock offset (server minus local)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OffsetEstimate {
+    pub offset: chrono::Duration,
+    pub confidence: chrono::Duration,
+    /// Median round trip of the samples used
+    pub rtt: chrono::Duration,
+    /// Samples left after dropping outliers
+    pub samples: usize,
+}
+
+/// Estimate server offset from clock samples.
+/// Each sample compares the Date header (second precision, so shifted by
+/// half a second) against the local midpoint of the request, i.e. after
+/// removing half the round trip. Samples with a negative or outlying RTT are
+/// dropped and the median offset of the rest is used; confidence is half the
+/// median RTT plus the header's half-second quantization.
+pub fn estimate_server_offset(samples: &[TimeSample]) -> Option<OffsetEstimate> {
+    let measured: Vec<(i64, i64)> = samples
+        .iter()
+        .filter_map(|(send, recv, server)| {
+            let rtt = *recv - *send;
+            if rtt < chrono::Duration::zero() {
+                return None;
+            }
+            let midpoint = *send + rtt / 2;
+            let server_mid = *server + chrono::Duration::milliseconds(500);
+            Some(((server_mid - midpoint).num_milliseconds(), rtt.num_milliseconds()))
+        })
+        .collect();
+    if measured.is_empty() {
+        return None;
+    }
+
+    let mut rtts: Vec<i64> = measured.iter().map(|(_, rtt)| *rtt).collect();
+    let limit = outlier_limit(median(&mut rtts));
+    let (mut offsets, mut rtts): (Vec<i64>, Vec<i64>) = measured.into_iter().filter(|(_, rtt)| *rtt <= limit).unzip();
+    let rtt = median(&mut rtts);
+
+    Some(OffsetEstimate {
+        offset: chrono::Duration::milliseconds(median(&mut offsets)),
+        confidence: chrono::Duration::milliseconds(rtt / 2 + 500),
+        rtt: chrono::Duration::milliseconds(rtt),
+        samples: offsets.len(),
+    })
+}
+
+/// Slowest RTT still trusted, given the median RTT
+fn outlier_limit(median_rtt: i64) -> i64 {
+    (median_rtt * RTT_OUTLIER_FACTOR).max(median_rtt + RTT_OUTLIER_SLACK_MS)
+}
+
+/// Median of a non-empty slice (lower middle for even lengths)
+fn median(values: &mut [i64]) -> i64 {
+    values.sort_unstable();
+    values[(values.len() - 1) / 2]
+}
+
+/// Take up to `count` clock samples `gap` apart, giving up at `budget`.
+/// A sample still in flight at the deadline is abandoned. Failed samples are
+/// returned alongside the good ones for the caller to log.
+pub async fn collect_samples<F, Fut>(mut sample: F, count: usize, gap: Duration, budget: Duration) -> (Vec<TimeSample>, Vec<AppError>)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = AppResult<TimeSample>>,
+{
+    let deadline = tokio::time::Instant::now() + budget;
+    let mut samples = Vec::with_capacity(count);
+    let mut errors = Vec::new();
+    for i in 0..count {
+        if i > 0 {
+            if tokio::time::Instant::now() + gap >= deadline {
+                break;
+            }
+            tokio::time::sleep(gap).await;
+        }
+        match tokio::time::timeout_at(deadline, sample()).await {
+            Ok(Ok(taken)) => samples.push(taken),
+            Ok(Err(e)) => errors.push(e),
+            Err(_) => {
+                errors.push(AppError::Timeout(format!("server time sample exceeded {}s budget", budget.as_secs())));
+                break;
+            }
+        }
+    }
+    (samples, errors)
+}
+
+/// Sample with the default count, gap and budget and estimate the offset
+pub async fn measure<A: ScheduleApi>(api: &A) -> (Option<OffsetEstimate>, Vec<AppError>) {
+    let gap = Duration::from_millis(TIME_SYNC_SAMPLE_GAP_MS);
+    let (samples, errors) = collect_samples(|| api.sample_server_time(), TIME_SYNC_SAMPLES, gap, TIME_SYNC_BUDGET).await;
+    (estimate_server_offset(&samples), errors)
+}
+
+/// The estimate as shown to the user, with both clocks read at `local`
+pub fn offset_report(estimate: &OffsetEstimate, local: DateTime<Local>) -> TimeOffset {
+    TimeOffset {
+        offset_ms: estimate.offset.num_milliseconds(),
+        rtt_ms: estimate.rtt.num_milliseconds(),
+        samples: estimate.samples,
+        server_time_iso: (local + estimate.offset).to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        local_time_iso: local.to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn ms(n: i64) -> chrono::Duration {
+        chrono::Duration::milliseconds(n)
+    }
+
+    #[test]
+    fn test_estimate_server_offset() {
+        let base = Local::now();
+        // Server runs ~2s ahead; header truncated to whole seconds
+        let samples: Vec<TimeSample> = vec![
+            (base, base + ms(100), base + ms(1550)),
+            (base + ms(200), base + ms(260), base + ms(1730)),
+            (base + ms(400), base + ms(520), base + ms(1960)),
+        ];
+        let estimate = estimate_server_offset(&samples).unwrap();
+        // Offsets: 1550+500-50=2000, 1730+500-230=2000, 1960+500-460=2000
+        assert_eq!(estimate.offset, ms(2000));
+        assert_eq!(estimate.rtt, ms(100));
+        assert_eq!(estimate.confidence, ms(100 / 2 + 500));
+        assert_eq!(estimate.samples, 3);
+    }
+
+    #[test]
+    fn test_estimate_server_offset_uses_median() {
+        let base = Local::now();
+        let samples: Vec<TimeSample> = vec![
+            (base, base + ms(20), base + ms(-510)),
+            (base, base + ms(20), base + ms(-410)),
+            (base, base + ms(20), base + ms(5000)),
+            (base, base + ms(-5), base),
+        ];
+        // Negative RTT sample is discarded; median of [-20, 80, 5490] is 80
+        let estimate = estimate_server_offset(&samples).unwrap();
+        assert_eq!(estimate.offset, ms(80));
+        assert_eq!(estimate.samples, 3);
+        assert!(estimate_server_offset(&[]).is_none());
+        assert!(estimate_server_offset(&[(base, base - ms(1), base)]).is_none());
+    }
+
+    #[test]
+    fn test_estimate_server_offset_drops_slow_samples() {
+        let base = Local::now();
+        // Three quick samples agree on +1000ms; two stalled ones skew it
+        let samples: Vec<TimeSample> = vec![
+            (base, base + ms(40), base + ms(520)),
+            (base, base + ms(2400), base + ms(3500)),
+            (base, base + ms(60), base + ms(530)),
+            (base, base + ms(1900), base + ms(2700)),
+            (base, base + ms(50), base + ms(525)),
+        ];
+        let estimate = estimate_server_offset(&samples).unwrap();
+        assert_eq!(estimate.offset, ms(1000));
+        assert_eq!(estimate.rtt, ms(50));
+        assert_eq!(estimate.samples, 3);
+
+        // Jitter on a fast link is within the slack and kept
+        assert_eq!(outlier_limit(10), 60);
+        assert_eq!(outlier_limit(200), 600);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_collect_samples_stops_at_budget() {
+        let gap = Duration::from_millis(TIME_SYNC_SAMPLE_GAP_MS);
+        let sample = |delay_ms: u64| async move {
+            let send = Local::now();
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            Ok((send, send, send))
+        };
+
+        let started = tokio::time::Instant::now();
+        let (samples, errors) = collect_samples(|| sample(30), TIME_SYNC_SAMPLES, gap, TIME_SYNC_BUDGET).await;
+        assert_eq!((samples.len(), errors.len()), (TIME_SYNC_SAMPLES, 0));
+        assert_eq!(started.elapsed(), Duration::from_millis(5 * 30 + 4 * 80));
+
+        // 1.2s per sample: two fit, the third is abandoned at the deadline
+        let started = tokio::time::Instant::now();
+        let (samples, errors) = collect_samples(|| sample(1200), TIME_SYNC_SAMPLES, gap, TIME_SYNC_BUDGET).await;
+        assert_eq!(samples.len(), 2);
+        assert!(matches!(errors.as_slice(), [AppError::Timeout(_)]));
+        assert_eq!(started.elapsed(), TIME_SYNC_BUDGET);
+
+        // Failures are reported and sampling carries on
+        let mut calls = 0;
+        let (samples, errors) = collect_samples(
+            || {
+                calls += 1;
+                let fail = calls % 2 == 0;
+                async move {
+                    if fail {
+                        Err(AppError::ParseError("missing Date header".into()))
+                    } else {
+                        sample(10).await
+                    }
+                }
+            },
+            TIME_SYNC_SAMPLES,
+            gap,
+            TIME_SYNC_BUDGET,
+        )
+        .await;
+        assert_eq!((samples.len(), errors.len()), (3, 2));
+    }
+
+    #[test]
+    fn test_offset_report() {
+        let local = Local.with_ymd_and_hms(2024, 3, 20, 7, 29, 58).unwrap() + ms(250);
+        let estimate = OffsetEstimate {
+            offset: ms(1875),
+            confidence: ms(540),
+            rtt: ms(80),
+            samples: 4,
+        };
+        let report = offset_report(&estimate, local);
+        assert_eq!(report.offset_ms, 1875);
+        assert_eq!(report.rtt_ms, 80);
+        assert_eq!(report.samples, 4);
+        assert!(report.local_time_iso.starts_with("2024-03-20T07:29:58.250"));
+        assert!(report.server_time_iso.starts_with("2024-03-20T07:30:00.125"));
+    }
+}
@@ -1,8 +1,12 @@
 //! Type definitions for SkylineMed
 //! Corresponds to core/types.go
 
+use std::collections::{HashMap, HashSet};
+
 use serde::{Deserialize, Serialize};
 
+use super::errors::{AppError, AppResult};
+
 /// Address option for patient location
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AddressOption {
@@ -10,6 +14,20 @@ pub struct AddressOption {
     pub text: String,
 }
 
+/// Saved address from the user-center address book
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddressRecord {
+    pub id: String,
+    /// Full address as submitted with an order
+    pub text: String,
+    #[serde(default)]
+    pub region_id: String,
+    #[serde(default)]
+    pub detail: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
 /// Time slot for appointment
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeSlot {
@@ -17,6 +35,20 @@ pub struct TimeSlot {
     pub value: String,
 }
 
+/// What the booking step page turned out to be
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum TicketPageKind {
+    #[default]
+    Normal,
+    /// Login form instead of the booking form
+    LoginRedirect,
+    /// Risk-control captcha widget
+    Captcha,
+    /// Error banner, with its text
+    Error(String),
+}
+
 /// Ticket detail from appointment page
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TicketDetail {
@@ -36,6 +68,14 @@ pub struct TicketDetail {
     pub address_id: String,
     pub address: String,
     pub addresses: Vec<AddressOption>,
+    /// Required hidden inputs the page lacked
+    #[serde(default)]
+    pub missing_fields: Vec<String>,
+    #[serde(default)]
+    pub page_kind: TicketPageKind,
+    /// Where the page was finally loaded from, after redirects
+    #[serde(default)]
+    pub page_url: String,
 }
 
 impl Default for TicketDetail {
@@ -55,16 +95,163 @@ impl Default for TicketDetail {
             address_id: String::new(),
             address: String::new(),
             addresses: Vec::new(),
+            missing_fields: Vec::new(),
+            page_kind: TicketPageKind::Normal,
+            page_url: String::new(),
         }
     }
 }
 
+impl TicketDetail {
+    /// Names of the hidden inputs without which a submit cannot be built
+    /// that are empty on this page
+    pub fn missing_required_fields(&self) -> Vec<String> {
+        [("sch_data", &self.sch_data), ("detlid_realtime", &self.detlid_realtime), ("level_code", &self.level_code)]
+            .into_iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    pub fn has_required_fields(&self) -> bool {
+        self.missing_required_fields().is_empty()
+    }
+}
+
+/// Result of a login check
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoginStatus {
+    pub logged_in: bool,
+    /// Account name scraped from the user index page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_count: Option<usize>,
+    /// Why the check failed: "no_cookie", "expired" or "network"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Local time of the check, `%Y-%m-%d %H:%M:%S`
+    pub checked_at: String,
+    /// Soonest expiry (unix seconds) among the session cookies, when the server sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_expires_at: Option<i64>,
+}
+
+impl LoginStatus {
+    fn new(logged_in: bool, reason: Option<&str>) -> Self {
+        Self {
+            logged_in,
+            reason: reason.map(str::to_string),
+            checked_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn ok(username: Option<String>) -> Self {
+        Self {
+            username,
+            ..Self::new(true, None)
+        }
+    }
+
+    pub fn failed(reason: &str) -> Self {
+        Self::new(false, Some(reason))
+    }
+
+    /// Failure reason for an error raised while checking
+    pub fn from_error(err: &AppError) -> Self {
+        Self::failed(if err.is_retryable() { "network" } else { "expired" })
+    }
+}
+
+/// Release rule a hospital publishes for a department (放号时间)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BookingRules {
+    /// Daily release time, `HH:MM:SS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_time: Option<String>,
+    /// How many days ahead the released slots are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub advance_days: Option<u8>,
+    /// The page text the rules were read from, for the user to double-check
+    #[serde(default)]
+    pub raw_text: String,
+}
+
+/// Account summary from the user center homepage
+/// Every field is optional because the page layout changes without notice
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub username: Option<String>,
+    /// Bound phone as shown on the site, e.g. 138****5678
+    pub phone_masked: Option<String>,
+    /// Account balance in yuan
+    pub balance: Option<f64>,
+    /// Orders waiting for payment
+    pub pending_payment: Option<u32>,
+    /// Unread site notifications
+    pub unread_notifications: Option<u32>,
+}
+
 /// Member (patient) information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Member {
     pub id: String,
     pub name: String,
     pub certified: bool,
+    /// ID number as shown on the site, e.g. 4403**********1234
+    #[serde(default)]
+    pub id_card_masked: String,
+    #[serde(default)]
+    pub phone_masked: String,
+    /// Relationship to the account holder (本人, 子女, ...)
+    #[serde(default)]
+    pub relation: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+/// New member (patient) to register on the account
+#[derive(Debug, Clone, Default)]
+pub struct NewMemberParams {
+    pub name: String,
+    pub id_card: String,
+    pub phone: String,
+    pub relation: String,
+}
+
+impl NewMemberParams {
+    /// Validate the fields before they are sent to the site
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name is required".into());
+        }
+        if !is_valid_id_card(&self.id_card) {
+            return Err("id_card is not a valid 18-digit ID number".into());
+        }
+        let phone = self.phone.trim();
+        if phone.len() != 11 || !phone.starts_with('1') || !phone.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("phone must be an 11-digit mobile number".into());
+        }
+        Ok(())
+    }
+}
+
+/// Check an 18-digit resident ID number against its GB 11643 check digit
+pub fn is_valid_id_card(id: &str) -> bool {
+    const WEIGHTS: [u32; 17] = [7, 9, 10, 5, 8, 4, 2, 1, 6, 3, 7, 9, 10, 5, 8, 4, 2];
+    const CHECK: &[u8; 11] = b"10X98765432";
+
+    let id = id.trim().as_bytes();
+    if id.len() != 18 || !id[..17].iter().all(u8::is_ascii_digit) {
+        return false;
+    }
+    let sum: u32 = id[..17]
+        .iter()
+        .zip(WEIGHTS)
+        .map(|(d, w)| u32::from(d - b'0') * w)
+        .sum();
+    CHECK[(sum % 11) as usize] == id[17].to_ascii_uppercase()
 }
 
 /// Order submission result
@@ -76,6 +263,154 @@ pub struct SubmitOrderResult {
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<OrderConfirmation>,
+}
+
+/// Confirmation details parsed from the order success page
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderConfirmation {
+    #[serde(default)]
+    pub order_no: String,
+    #[serde(default)]
+    pub queue_no: String,
+    #[serde(default)]
+    pub visit_time: String,
+    #[serde(default)]
+    pub payment_deadline: String,
+}
+
+/// One order in the user center order list
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Appointment {
+    pub order_no: String,
+    pub member_id: String,
+    #[serde(default)]
+    pub member_name: String,
+    pub unit_id: String,
+    #[serde(default)]
+    pub unit_name: String,
+    pub dep_id: String,
+    #[serde(default)]
+    pub dep_name: String,
+    #[serde(default)]
+    pub doctor_name: String,
+    /// Visit date, YYYY-MM-DD
+    pub date: String,
+    /// Visit time as listed, e.g. "2024-03-20 上午 09:00-09:30"
+    #[serde(default)]
+    pub visit_time: String,
+    #[serde(default)]
+    pub status: String,
+    /// Booked and not cancelled, refunded, visited or expired
+    pub active: bool,
+}
+
+impl Appointment {
+    /// Whether this is an active booking of the member in the department on `date`
+    /// Orders listed without a hospital id match any hospital.
+    pub fn matches(&self, member_id: &str, unit_id: &str, dep_id: &str, date: &str) -> bool {
+        self.active
+            && self.member_id == member_id.trim()
+            && self.dep_id == dep_id.trim()
+            && self.date == date.trim()
+            && (self.unit_id.is_empty() || self.unit_id == unit_id.trim())
+    }
+
+    /// Listed without the member or department id, so `matches` never hits it
+    pub fn lacks_ids(&self) -> bool {
+        self.member_id.is_empty() || self.dep_id.is_empty()
+    }
+
+    /// `matches` on member and department names, for orders that lack ids
+    pub fn matches_names(&self, member_name: &str, unit_id: &str, dep_name: &str, date: &str) -> bool {
+        let (member_name, dep_name) = (member_name.trim(), dep_name.trim());
+        self.active
+            && !member_name.is_empty()
+            && !dep_name.is_empty()
+            && self.member_name == member_name
+            && self.dep_name == dep_name
+            && self.date == date.trim()
+            && (self.unit_id.is_empty() || self.unit_id == unit_id.trim())
+    }
+}
+
+/// Order submission parameters
+/// Serializes to the exact form body expected by guahao/ysubmit.html
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubmitOrderParams {
+    pub sch_data: String,
+    #[serde(rename = "mid", alias = "member_id")]
+    pub member_id: String,
+    #[serde(rename = "addressId", alias = "address_id", default)]
+    pub address_id: String,
+    #[serde(default)]
+    pub address: String,
+    #[serde(rename = "hisMemId", alias = "his_mem_id", default)]
+    pub his_mem_id: String,
+    #[serde(default)]
+    pub disease_input: String,
+    #[serde(default)]
+    pub order_no: String,
+    #[serde(default)]
+    pub disease_content: String,
+    #[serde(default = "default_accept")]
+    pub accept: String,
+    pub unit_id: String,
+    pub schedule_id: String,
+    pub dep_id: String,
+    #[serde(default)]
+    pub his_dep_id: String,
+    #[serde(default)]
+    pub sch_date: String,
+    #[serde(default)]
+    pub time_type: String,
+    #[serde(default)]
+    pub doctor_id: String,
+    #[serde(default)]
+    pub his_doc_id: String,
+    pub detlid: String,
+    #[serde(default)]
+    pub detlid_realtime: String,
+    #[serde(default)]
+    pub level_code: String,
+    #[serde(default)]
+    pub is_hot: String,
+}
+
+fn default_accept() -> String {
+    "1".into()
+}
+
+impl SubmitOrderParams {
+    /// Validate that all mandatory form fields are present
+    pub fn validate(&self) -> Result<(), String> {
+        let required = [
+            ("schedule_id", &self.schedule_id),
+            ("unit_id", &self.unit_id),
+            ("dep_id", &self.dep_id),
+            ("detlid", &self.detlid),
+            ("member_id", &self.member_id),
+            ("sch_data", &self.sch_data),
+        ];
+        for (name, value) in required {
+            if value.trim().is_empty() {
+                return Err(format!("{} is required", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// QR code image for the `qr-image` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QRImage {
+    pub base64: String,
+    /// "image/png" or "image/jpeg"
+    pub mime: String,
+    pub width: u32,
+    pub height: u32,
+    pub uuid: String,
 }
 
 /// QR login result
@@ -85,6 +420,9 @@ pub struct QRLoginResult {
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cookie_path: Option<String>,
+    /// Session cookies from a successful login, not yet saved anywhere
+    #[serde(skip)]
+    pub cookies: Vec<CookieRecord>,
 }
 
 /// Grab configuration
@@ -93,15 +431,40 @@ pub struct GrabConfig {
     pub unit_id: String,
     #[serde(default)]
     pub unit_name: String,
+    /// City site subdomain (e.g. "sz"); the booking page is tried there first
+    #[serde(default)]
+    pub city_pinyin: String,
+    #[serde(default)]
     pub dep_id: String,
     #[serde(default)]
     pub dep_name: String,
+    /// More departments cycled on each date after `dep_id`
+    #[serde(default)]
+    pub dep_ids: Vec<String>,
+    /// Category whose child departments are all grabbed; expanded from the
+    /// department list at run start, which needs `city_pinyin`
+    #[serde(default)]
+    pub dep_category_id: String,
     #[serde(default)]
     pub doctor_ids: Vec<String>,
+    /// Doctors for specific dates (YYYY-MM-DD), best first; an entry replaces
+    /// `doctor_ids` on its date, and an empty entry takes any doctor
+    #[serde(default)]
+    pub date_doctor_map: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub exclude_doctor_ids: Vec<String>,
     pub member_id: String,
     #[serde(default)]
     pub member_name: String,
+    #[serde(default)]
     pub target_dates: Vec<String>,
+    /// Query a window of this many days instead of fixed dates; explicit
+    /// `target_dates` win when both are set
+    #[serde(default)]
+    pub rolling_days: Option<u8>,
+    /// Days between today and the first date of the rolling window
+    #[serde(default)]
+    pub release_offset_days: u8,
     #[serde(default)]
     pub time_types: Vec<String>,
     #[serde(default)]
@@ -112,6 +475,9 @@ pub struct GrabConfig {
     pub address: String,
     #[serde(default)]
     pub start_time: String,
+    /// What to do when start_time has already passed today
+    #[serde(default)]
+    pub start_time_policy: StartTimePolicy,
     #[serde(default)]
     pub use_server_time: bool,
     #[serde(default)]
@@ -120,28 +486,374 @@ pub struct GrabConfig {
     pub max_retries: i32,
     #[serde(default = "default_true")]
     pub use_proxy_submit: bool,
+    #[serde(default)]
+    pub auto_open_on_success: bool,
+    /// Keep connections warm during the last seconds before start_time
+    #[serde(default = "default_true")]
+    pub prewarm_connections: bool,
+    /// Length of the fast-retry window right after the start trigger (0 = off)
+    #[serde(default)]
+    pub burst_duration_ms: u64,
+    /// Sleep between attempts inside the burst window
+    #[serde(default)]
+    pub burst_interval_ms: u64,
+    /// Stop the run at this clock time (HH:MM:SS, same clock as start_time)
+    #[serde(default)]
+    pub stop_time: String,
+    /// Stop the run after this many seconds (0 = unlimited)
+    #[serde(default)]
+    pub max_duration_secs: u64,
+    /// Give up on an attempt cycle after this many seconds and start the next one (0 = off)
+    #[serde(default)]
+    pub attempt_timeout_secs: u64,
+    /// Per-request budget for schedule queries (0 = the network setting)
+    #[serde(default)]
+    pub query_timeout_secs: u64,
+    /// access_hash to try first, by the masked id from `get_sessions` ("" = the one that last worked)
+    #[serde(default)]
+    pub session: String,
+    /// Repeat the start_time..stop_time window every day until success
+    #[serde(default)]
+    pub recur_daily: bool,
+    /// Where proxy probes go: "submit" (www.91160.com, default) or "neutral"
+    #[serde(default)]
+    pub proxy_probe_target: String,
+    /// Proxy probe request: "get" (default) or "head"
+    #[serde(default)]
+    pub proxy_probe_method: String,
+    /// Proxy rotation: "round_robin" (default), "consume" or "sticky"
+    #[serde(default)]
+    pub proxy_rotation: String,
+    /// Grab log detail: "quiet", "normal" (default) or "verbose"
+    #[serde(default)]
+    pub log_verbosity: String,
+    /// Submit directly when no proxy is available instead of skipping the slot
+    #[serde(default = "default_true")]
+    pub proxy_fallback_direct: bool,
+    /// Before grabbing, stop if the member already holds an active appointment
+    /// in the department on a target date (the hospital rejects a second one)
+    #[serde(default)]
+    pub precheck_existing: bool,
+    /// Grab even when the precheck finds an existing appointment
+    #[serde(default)]
+    pub force: bool,
+    /// Hold the start until the user confirms the logged-in account
+    #[serde(default)]
+    pub require_identity_confirmation: bool,
 }
 
 fn default_true() -> bool {
     true
 }
 
+/// Handling of a start_time that is already behind the clock when the grab starts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartTimePolicy {
+    /// Start attempting immediately
+    #[default]
+    RunNow,
+    /// Treat start_time as tomorrow's and wait for it
+    NextDay,
+    /// Refuse to start
+    Abort,
+}
+
+/// Parse an HH:MM:SS clock time
+pub fn parse_clock_time(value: &str) -> Option<chrono::NaiveTime> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    chrono::NaiveTime::parse_from_str(value, "%H:%M:%S").ok()
+}
+
+/// Parse a time slot label such as "09:00-09:30" (seconds optional)
+/// Returns None unless both ends parse and the range is not empty.
+pub fn parse_slot_range(value: &str) -> Option<(chrono::NaiveTime, chrono::NaiveTime)> {
+    let (start, end) = value.split_once('-')?;
+    let parse = |part: &str| {
+        let part = part.trim();
+        chrono::NaiveTime::parse_from_str(part, "%H:%M:%S")
+            .or_else(|_| chrono::NaiveTime::parse_from_str(part, "%H:%M"))
+            .ok()
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    (start < end).then_some((start, end))
+}
+
+/// Time types the schedule parser understands
+pub const ALLOWED_TIME_TYPES: [&str; 2] = ["am", "pm"];
+/// Days a target date may lie in the past before validation rejects it
+pub const PAST_DATE_GRACE_DAYS: u32 = 0;
+/// Accepted range for `retry_interval` in seconds (0 means "use the default")
+pub const RETRY_INTERVAL_RANGE: (f64, f64) = (0.2, 60.0);
+/// Shortest sleep between attempts inside the burst window
+pub const MIN_BURST_INTERVAL_MS: u64 = 50;
+
 impl GrabConfig {
-    /// Validate the configuration
-    pub fn validate(&self) -> Result<(), String> {
-        if self.unit_id.is_empty() {
-            return Err("unit_id is required".into());
+    /// `dep_id` followed by `dep_ids`, trimmed, without blanks or repeats
+    pub fn dep_id_list(&self) -> Vec<String> {
+        let mut ids: Vec<String> = Vec::new();
+        for id in std::iter::once(&self.dep_id).chain(&self.dep_ids) {
+            let id = id.trim();
+            if !id.is_empty() && !ids.iter().any(|seen| seen == id) {
+                ids.push(id.to_string());
+            }
+        }
+        ids
+    }
+
+    /// Doctors that qualify on `date`, best first; empty means any doctor
+    pub fn doctors_for(&self, date: &str) -> &[String] {
+        self.date_doctor_map.get(date).unwrap_or(&self.doctor_ids)
+    }
+
+    /// Settings that are allowed but probably not what the user meant
+    pub fn warnings(&self) -> Vec<String> {
+        let mut keys: Vec<&String> = self.date_doctor_map.keys().collect();
+        keys.sort();
+        keys.into_iter()
+            .filter(|date| !self.target_dates.is_empty() && !self.target_dates.contains(date))
+            .map(|date| format!("date_doctor_map date {} is not in target_dates", date))
+            .collect()
+    }
+
+    /// Validate the configuration against the current date and time
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let now = chrono::Local::now().naive_local();
+        let mut errors = self.validate_on(now.date(), PAST_DATE_GRACE_DAYS).err().unwrap_or_default();
+        errors.extend(self.start_time_problem(now.time()));
+        into_result(errors)
+    }
+
+    /// With `StartTimePolicy::Abort`, a start_time at or before `now` is an error
+    pub fn start_time_problem(&self, now: chrono::NaiveTime) -> Option<String> {
+        let start = parse_clock_time(&self.start_time)?;
+        (self.start_time_policy == StartTimePolicy::Abort && start <= now).then(|| {
+            format!(
+                "start_time {} has already passed today ({} now); fix the time or set start_time_policy to run_now or next_day",
+                self.start_time.trim(),
+                now.format("%H:%M:%S")
+            )
+        })
+    }
+
+    /// Validate the configuration, rejecting target dates more than
+    /// `grace_days` before `today`
+    pub fn validate_on(&self, today: chrono::NaiveDate, grace_days: u32) -> Result<(), Vec<String>> {
+        let earliest = today - chrono::Duration::days(i64::from(grace_days));
+        into_result(self.problems(Some(earliest)))
+    }
+
+    /// Validate a saved preset: same rules, except target dates may have passed
+    pub fn validate_template(&self) -> Result<(), Vec<String>> {
+        into_result(self.problems(None))
+    }
+
+    fn problems(&self, earliest_date: Option<chrono::NaiveDate>) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.unit_id.trim().is_empty() {
+            errors.push("unit_id is required".into());
+        }
+        if self.dep_id_list().is_empty() && self.dep_category_id.trim().is_empty() {
+            errors.push("dep_id, dep_ids or dep_category_id is required".into());
+        }
+        if !self.dep_category_id.trim().is_empty() && self.city_pinyin.trim().is_empty() {
+            errors.push("dep_category_id requires city_pinyin".into());
+        }
+        if self.member_id.trim().is_empty() {
+            errors.push("member_id is required".into());
         }
-        if self.dep_id.is_empty() {
-            return Err("dep_id is required".into());
+
+        if self.target_dates.is_empty() && self.rolling_days.is_none() {
+            errors.push("target_dates or rolling_days is required".into());
+        }
+        if self.rolling_days == Some(0) {
+            errors.push("rolling_days must be at least 1".into());
         }
-        if self.member_id.is_empty() {
-            return Err("member_id is required".into());
+        for date in &self.target_dates {
+            match chrono::NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+                Err(_) => errors.push(format!("target date {} must be YYYY-MM-DD", date)),
+                Ok(parsed) if earliest_date.is_some_and(|earliest| parsed < earliest) => {
+                    errors.push(format!("target date {} is in the past", date))
+                }
+                Ok(_) => {}
+            }
         }
-        if self.target_dates.is_empty() {
-            return Err("target_dates is required".into());
+
+        if let Some(id) = self
+            .doctor_ids
+            .iter()
+            .chain(self.date_doctor_map.values().flatten())
+            .find(|id| self.exclude_doctor_ids.contains(id))
+        {
+            errors.push(format!("doctor {} is both targeted and excluded", id));
+        }
+
+        let start = if self.start_time.is_empty() {
+            None
+        } else {
+            let parsed = parse_clock_time(&self.start_time);
+            if parsed.is_none() {
+                errors.push("start_time must be HH:MM:SS".into());
+            }
+            parsed
+        };
+        if !self.stop_time.is_empty() {
+            match parse_clock_time(&self.stop_time) {
+                None => errors.push("stop_time must be HH:MM:SS".into()),
+                Some(stop) if matches!(start, Some(start) if stop <= start) => {
+                    errors.push("stop_time must be after start_time".into())
+                }
+                Some(_) => {}
+            }
         }
+        if self.recur_daily && (self.start_time.is_empty() || self.stop_time.is_empty()) {
+            errors.push("recur_daily requires start_time and stop_time".into());
+        }
+
+        for time_type in &self.time_types {
+            if !ALLOWED_TIME_TYPES.contains(&time_type.as_str()) {
+                errors.push(format!("time type {} must be one of {}", time_type, ALLOWED_TIME_TYPES.join(", ")));
+            }
+        }
+        for hours in &self.preferred_hours {
+            if parse_slot_range(hours).is_none() {
+                errors.push(format!("preferred hours {} must look like 09:00-09:30", hours));
+            }
+        }
+
+        let (min_interval, max_interval) = RETRY_INTERVAL_RANGE;
+        if self.retry_interval != 0.0 && !(min_interval..=max_interval).contains(&self.retry_interval) {
+            errors.push(format!(
+                "retry_interval must be between {} and {} seconds",
+                min_interval, max_interval
+            ));
+        }
+        if self.max_retries < 0 {
+            errors.push("max_retries must not be negative".into());
+        }
+        if self.burst_duration_ms > 0 && self.burst_interval_ms < MIN_BURST_INTERVAL_MS {
+            errors.push(format!("burst_interval_ms must be at least {}", MIN_BURST_INTERVAL_MS));
+        }
+
+        if !matches!(self.proxy_probe_target.as_str(), "" | "submit" | "neutral") {
+            errors.push("proxy_probe_target must be submit or neutral".into());
+        }
+        if !matches!(self.proxy_probe_method.as_str(), "" | "get" | "head") {
+            errors.push("proxy_probe_method must be get or head".into());
+        }
+        if !matches!(self.proxy_rotation.as_str(), "" | "round_robin" | "consume" | "sticky") {
+            errors.push("proxy_rotation must be round_robin, consume or sticky".into());
+        }
+        if !matches!(self.log_verbosity.as_str(), "" | "quiet" | "normal" | "verbose") {
+            errors.push("log_verbosity must be quiet, normal or verbose".into());
+        }
+        errors
+    }
+}
+
+fn into_result(errors: Vec<String>) -> Result<(), Vec<String>> {
+    if errors.is_empty() {
         Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Named grab configuration saved in user state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrabPreset {
+    pub name: String,
+    pub config: GrabConfig,
+}
+
+/// The last successful grab in a department, so a follow-up visit can be
+/// booked again with only new dates. Kept in user state per unit_id+dep_id.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RepeatTemplate {
+    pub unit_id: String,
+    #[serde(default)]
+    pub unit_name: String,
+    #[serde(default)]
+    pub city_pinyin: String,
+    pub dep_id: String,
+    #[serde(default)]
+    pub dep_name: String,
+    /// Doctor who was booked; empty takes any doctor
+    #[serde(default)]
+    pub doctor_id: String,
+    #[serde(default)]
+    pub doctor_name: String,
+    pub member_id: String,
+    #[serde(default)]
+    pub member_name: String,
+    #[serde(default)]
+    pub preferred_hours: Vec<String>,
+    #[serde(default)]
+    pub time_types: Vec<String>,
+    #[serde(default)]
+    pub address_id: String,
+    #[serde(default)]
+    pub address: String,
+    /// When the grab succeeded (YYYY-MM-DD HH:MM:SS)
+    #[serde(default)]
+    pub saved_at: String,
+}
+
+impl RepeatTemplate {
+    /// Template from a successful grab: ids from what was booked, the rest
+    /// from the config it ran with
+    pub fn from_success(config: &GrabConfig, success: &GrabSuccess, saved_at: &str) -> Self {
+        let booked_or = |booked: &str, configured: &str| if booked.is_empty() { configured.to_string() } else { booked.to_string() };
+        // The id and its text go together: mixing the booked id with the
+        // configured text would show one address and submit another
+        let (address_id, address) = if success.address_id.is_empty() {
+            (config.address_id.clone(), config.address.clone())
+        } else {
+            (success.address_id.clone(), success.address.clone())
+        };
+        Self {
+            unit_id: config.unit_id.clone(),
+            unit_name: config.unit_name.clone(),
+            city_pinyin: config.city_pinyin.clone(),
+            dep_id: booked_or(&success.dep_id, &config.dep_id),
+            dep_name: success.dep_name.clone(),
+            doctor_id: success.doctor_id.clone(),
+            doctor_name: success.doctor_name.clone(),
+            member_id: config.member_id.clone(),
+            member_name: config.member_name.clone(),
+            preferred_hours: config.preferred_hours.clone(),
+            time_types: config.time_types.clone(),
+            address_id,
+            address,
+            saved_at: saved_at.to_string(),
+        }
+    }
+
+    pub fn is_for(&self, unit_id: &str, dep_id: &str) -> bool {
+        self.unit_id == unit_id.trim() && self.dep_id == dep_id.trim()
+    }
+
+    /// Grab config for `dates` with everything else at its defaults
+    pub fn to_config(&self, dates: Vec<String>) -> AppResult<GrabConfig> {
+        let doctor_ids: Vec<&String> = std::iter::once(&self.doctor_id).filter(|id| !id.is_empty()).collect();
+        Ok(serde_json::from_value(serde_json::json!({
+            "unit_id": self.unit_id,
+            "unit_name": self.unit_name,
+            "city_pinyin": self.city_pinyin,
+            "dep_id": self.dep_id,
+            "dep_name": self.dep_name,
+            "doctor_ids": doctor_ids,
+            "member_id": self.member_id,
+            "member_name": self.member_name,
+            "target_dates": dates,
+            "preferred_hours": self.preferred_hours,
+            "time_types": self.time_types,
+            "addressId": self.address_id,
+            "address": self.address,
+        }))?)
     }
 }
 
@@ -154,20 +866,136 @@ pub struct GrabSuccess {
     pub date: String,
     pub time_slot: String,
     pub member_name: String,
+    /// What was booked, kept for `repeat_last_grab`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub dep_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub doctor_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub address_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub address: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<OrderConfirmation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Grab result (success or failure)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrabResult {
+    pub success: bool,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<GrabSuccess>,
+}
+
+/// Marker for the grab that is currently running, written so it can be
+/// offered for resume after a crash or reboot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveGrabMarker {
+    /// RFC 3339 time the grab was launched
+    pub saved_at: String,
+    pub running: bool,
+    pub config: GrabConfig,
+}
+
+/// One finished grab run, as kept in the attempt history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrabHistoryEntry {
+    pub started_at: String,
+    pub finished_at: String,
+    pub unit_id: String,
+    #[serde(default)]
+    pub unit_name: String,
+    pub dep_id: String,
+    #[serde(default)]
+    pub dep_name: String,
+    #[serde(default)]
+    pub target_dates: Vec<String>,
+    /// Schedule queries sent during the run
+    #[serde(default)]
+    pub queries: u32,
+    /// "success", "failure" or "stopped"
+    pub outcome: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<GrabSuccess>,
+}
+
+/// One line of a pre-grab validation report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationItem {
+    pub check: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Resolved storage locations shown in the settings view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPaths {
+    pub config_dir: String,
+    pub logs_dir: String,
+    pub cookies: String,
+    pub user_state: String,
+    pub cities: String,
+}
+
+/// Account profiles and which one is active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileList {
+    pub current: String,
+    pub profiles: Vec<String>,
+}
+
+/// Cookie record for persistence
+/// Snapshot of the pool for the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyPoolStatus {
+    pub custom: Vec<CustomProxyStatus>,
+    /// Proxies fetched from the public API and not yet used
+    pub public_pool_size: usize,
+    pub public_protocol: String,
+    /// Proxies validated by background maintenance, fastest first
+    pub ranked: Vec<ProxyEntryStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomProxyStatus {
+    pub url: String,
+    pub failures: u32,
 }
 
-/// Grab result (success or failure)
+/// Outcome of a manual proxy check from the settings screen
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GrabResult {
-    pub success: bool,
-    pub message: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub detail: Option<GrabSuccess>,
+pub struct ProxyTestResult {
+    pub ok: bool,
+    pub proxy_url_masked: String,
+    pub latency_ms: Option<u64>,
+    /// Whether the schedule gateway was reachable; None when it was not probed
+    pub gate_ok: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Latency and failure history of a proxy probed by pool maintenance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyEntryStats {
+    pub url: String,
+    /// From the user's custom list; custom proxies are never evicted
+    pub custom: bool,
+    /// Latency of the last successful probe, None after a failure
+    pub latency_ms: Option<u64>,
+    pub consecutive_failures: u32,
+    pub total_failures: u32,
+    pub last_checked: String,
+    /// RFC 3339 time of the last successful probe
+    #[serde(default)]
+    pub last_success: Option<String>,
 }
 
-/// Cookie record for persistence
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CookieRecord {
     pub name: String,
@@ -176,6 +1004,38 @@ pub struct CookieRecord {
     pub domain: String,
     #[serde(default = "default_path")]
     pub path: String,
+    /// Expiry in unix seconds; None for session cookies or when unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub http_only: bool,
+}
+
+impl CookieRecord {
+    /// Cookie on the 91160 root domain with no known attributes
+    pub fn root(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for CookieRecord {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            value: String::new(),
+            domain: default_domain(),
+            path: default_path(),
+            expires: None,
+            secure: false,
+            http_only: false,
+        }
+    }
 }
 
 fn default_domain() -> String {
@@ -251,6 +1111,50 @@ pub struct Hospital {
     pub unit_id: String,
     #[serde(alias = "name")]
     pub unit_name: String,
+    /// Grade such as 三级甲等
+    #[serde(
+        default,
+        alias = "unit_level",
+        alias = "grade",
+        deserialize_with = "deserialize_flexible_string_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub level: Option<String>,
+    #[serde(
+        default,
+        alias = "unit_addr",
+        alias = "addr",
+        deserialize_with = "deserialize_flexible_string_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub address: Option<String>,
+    #[serde(
+        default,
+        alias = "area_name",
+        alias = "area",
+        deserialize_with = "deserialize_flexible_string_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub district: Option<String>,
+    #[serde(
+        default,
+        alias = "tel",
+        deserialize_with = "deserialize_flexible_string_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub phone: Option<String>,
+    /// Romanized name when the payload carries one, used by keyword search
+    #[serde(default, alias = "py", skip_serializing_if = "Option::is_none")]
+    pub pinyin: Option<String>,
+}
+
+impl Hospital {
+    /// True when the list payload left out the metadata shown to users
+    pub fn is_missing_metadata(&self) -> bool {
+        [&self.level, &self.address, &self.district]
+            .iter()
+            .any(|v| v.as_deref().unwrap_or("").trim().is_empty())
+    }
 }
 
 /// Department information
@@ -269,11 +1173,70 @@ pub struct Department {
     name: Option<String>,
 }
 
+impl Department {
+    /// Site id, falling back to the duplicate `id` field
+    fn effective_id(&self) -> &str {
+        let id = self.dep_id.trim();
+        if id.is_empty() {
+            self.id.as_deref().unwrap_or("").trim()
+        } else {
+            id
+        }
+    }
+
+    /// Flatten category trees of any depth into one list, first occurrence
+    /// of each dep_id wins
+    pub fn flatten(categories: &[DepartmentCategory]) -> Vec<FlatDepartment> {
+        fn walk(
+            departments: &[Department],
+            category_name: &str,
+            path: &mut Vec<String>,
+            seen: &mut HashSet<String>,
+            out: &mut Vec<FlatDepartment>,
+        ) {
+            for dep in departments {
+                let name = dep.dep_name.trim();
+                let id = dep.effective_id();
+                path.push(name.to_string());
+                if !id.is_empty() && !name.is_empty() && seen.insert(id.to_string()) {
+                    out.push(FlatDepartment {
+                        dep_id: id.to_string(),
+                        dep_name: name.to_string(),
+                        category_name: category_name.to_string(),
+                        path: path.clone(),
+                    });
+                }
+                walk(&dep.childs, category_name, path, seen, out);
+                path.pop();
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        for category in categories {
+            let category_name = category.pubcat.trim();
+            let mut path = Vec::new();
+            if !category_name.is_empty() {
+                path.push(category_name.to_string());
+            }
+            walk(&category.childs, category_name, &mut path, &mut seen, &mut out);
+        }
+        out
+    }
+}
+
 /// Department category from API response (top-level structure)
 /// The API returns categories with nested departments: [{pubcat, yuyue_num, childs: [...departments]}]
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DepartmentCategory {
+    #[serde(
+        default,
+        alias = "pubcat_id",
+        deserialize_with = "deserialize_flexible_string_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<String>,
     #[serde(default)]
     pub pubcat: String,
     #[serde(default)]
@@ -282,6 +1245,16 @@ pub struct DepartmentCategory {
     pub childs: Vec<Department>,
 }
 
+/// Department with its category context, from `Department::flatten`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlatDepartment {
+    pub dep_id: String,
+    pub dep_name: String,
+    pub category_name: String,
+    /// Names from the category down to this department
+    pub path: Vec<String>,
+}
+
 /// Log entry for export
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
@@ -299,6 +1272,15 @@ pub struct ScheduleSlot {
     pub time_type_desc: String,
     pub left_num: i32,
     pub sch_date: String,
+    /// Raw status flag from the gate payload (see `is_suspended_status`)
+    #[serde(default)]
+    pub status: String,
+}
+
+impl ScheduleSlot {
+    pub fn is_suspended(&self) -> bool {
+        is_suspended_status(&self.status)
+    }
 }
 
 /// Doctor with schedule information
@@ -321,6 +1303,46 @@ pub struct DoctorSchedule {
     pub schedule_id: String,
     #[serde(default)]
     pub time_type_desc: String,
+    /// Raw status flag from the gate payload (see `is_suspended_status`)
+    #[serde(default)]
+    pub status: String,
+}
+
+impl DoctorSchedule {
+    pub fn is_suspended(&self) -> bool {
+        is_suspended_status(&self.status)
+    }
+}
+
+/// Whether a schedule status flag marks the doctor or slot as 停诊
+pub fn is_suspended_status(status: &str) -> bool {
+    let status = status.trim().to_lowercase();
+    status.contains("停诊") || status.contains("停止") || matches!(status.as_str(), "stop" | "suspend" | "suspended")
+}
+
+/// Ticket availability of one day in a department
+/// `total_left`/`doctors_with_slots` are None when the day could not be fetched
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DayAvailability {
+    pub date: String,
+    pub total_left: Option<i32>,
+    pub doctors_with_slots: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A doctor seen in a department's schedules over the coming days
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepartmentDoctor {
+    pub doctor_id: String,
+    pub doctor_name: String,
+    pub reg_fee: String,
+    /// Nearest date with tickets left; None when booked out or 停诊 throughout
+    pub next_available_date: Option<String>,
+    /// Tickets left on `next_available_date`
+    pub left_num: i32,
+    /// Dates the doctor is scheduled, booked out or not
+    pub schedule_dates: Vec<String>,
 }
 
 /// User state for UI persistence
@@ -340,6 +1362,417 @@ pub struct UserState {
     pub time_slots: Vec<String>,
     #[serde(default = "default_true")]
     pub proxy_submit_enabled: bool,
+    /// Proxy probe target ("submit" or "neutral")
+    #[serde(default = "default_probe_target")]
+    pub proxy_probe_target: String,
+    /// Proxy probe method ("get" or "head")
+    #[serde(default = "default_probe_method")]
+    pub proxy_probe_method: String,
+    /// Proxy rotation strategy ("round_robin", "consume" or "sticky")
+    #[serde(default = "default_proxy_rotation")]
+    pub proxy_rotation: String,
+    /// Login keep-alive interval in minutes, 0 disables it
+    #[serde(default = "default_keepalive_minutes")]
+    pub keepalive_minutes: u64,
+    /// Restart an interrupted grab on launch without asking
+    #[serde(default)]
+    pub auto_resume: bool,
+    /// Show the tray icon with the grab status
+    #[serde(default = "default_true")]
+    pub tray_enabled: bool,
+    /// Keep the last few submit requests/responses in memory for debugging
+    #[serde(default)]
+    pub capture_submit_trace: bool,
+    /// Which grab events play an audible alert
+    #[serde(default)]
+    pub alerts: AlertConfig,
+    /// User-Agent and client hints for site requests
+    #[serde(default)]
+    pub client_profile: ClientProfile,
+    /// HTTP timeouts
+    #[serde(default)]
+    pub network: NetworkSettings,
+    /// Excluded doctor ids keyed by dep_id
+    #[serde(default)]
+    pub doctor_blacklist: HashMap<String, Vec<String>>,
+}
+
+/// Request counters for one endpoint; latencies cover the latest requests
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EndpointStats {
+    pub endpoint: String,
+    pub requests: u64,
+    /// Transport failures and 4xx/5xx responses
+    pub errors: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p50_ms: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p95_ms: Option<u32>,
+}
+
+/// Grab events that trigger a `play-alert` event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertConfig {
+    #[serde(default = "default_true")]
+    pub on_found: bool,
+    #[serde(default = "default_true")]
+    pub on_success: bool,
+    #[serde(default)]
+    pub on_failure: bool,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            on_found: true,
+            on_success: true,
+            on_failure: false,
+        }
+    }
+}
+
+impl AlertConfig {
+    /// Whether an alert of `kind` ("found", "success" or "failure") is wanted
+    pub fn wants(&self, kind: &str) -> bool {
+        match kind {
+            "found" => self.on_found,
+            "success" => self.on_success,
+            "failure" => self.on_failure,
+            _ => false,
+        }
+    }
+}
+
+/// Browser identity sent with every request: User-Agent plus the matching client hints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientProfile {
+    pub user_agent: String,
+    pub sec_ch_ua: String,
+    pub sec_ch_ua_platform: String,
+    pub accept_language: String,
+}
+
+/// Names accepted by `ClientProfile::preset`
+pub const CLIENT_PROFILE_PRESETS: [&str; 3] = ["chrome_win", "edge_win", "chrome_mac"];
+
+impl Default for ClientProfile {
+    fn default() -> Self {
+        Self::preset("chrome_win").expect("built-in preset")
+    }
+}
+
+impl ClientProfile {
+    /// Built-in profile by name (see `CLIENT_PROFILE_PRESETS`)
+    pub fn preset(name: &str) -> Option<Self> {
+        let (user_agent, sec_ch_ua, platform) = match name {
+            "chrome_win" => (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+                "\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"120\", \"Google Chrome\";v=\"120\"",
+                "\"Windows\"",
+            ),
+            "edge_win" => (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.0.0",
+                "\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"120\", \"Microsoft Edge\";v=\"120\"",
+                "\"Windows\"",
+            ),
+            "chrome_mac" => (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+                "\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"120\", \"Google Chrome\";v=\"120\"",
+                "\"macOS\"",
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            user_agent: user_agent.into(),
+            sec_ch_ua: sec_ch_ua.into(),
+            sec_ch_ua_platform: platform.into(),
+            accept_language: "zh-CN,zh;q=0.9,en;q=0.8".into(),
+        })
+    }
+}
+
+/// Accepted ranges (seconds) for the `NetworkSettings` timeouts
+pub const CONNECT_TIMEOUT_RANGE: (u64, u64) = (1, 60);
+pub const REQUEST_TIMEOUT_RANGE: (u64, u64) = (2, 120);
+pub const GRAB_QUERY_TIMEOUT_RANGE: (u64, u64) = (1, 30);
+/// Accepted range for `NetworkSettings::schedule_max_pages`
+pub const SCHEDULE_PAGES_RANGE: (u32, u32) = (1, 20);
+/// Accepted range for `NetworkSettings::http_cache_ttl_secs`; 0 always revalidates
+pub const HTTP_CACHE_TTL_RANGE: (u64, u64) = (0, 7 * 24 * 3600);
+/// Timeout multiplier applied in slow-network mode
+pub const SLOW_NETWORK_FACTOR: u32 = 3;
+
+/// HTTP timeouts; `slow_network` stretches all of them for flaky connections
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NetworkSettings {
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// Tighter budget for schedule queries during a grab
+    #[serde(default = "default_grab_query_timeout_secs")]
+    pub grab_query_timeout_secs: u64,
+    #[serde(default)]
+    pub slow_network: bool,
+    /// Most schedule pages (`p=0..`) fetched per query; 1 reads only the first
+    #[serde(default = "default_schedule_max_pages")]
+    pub schedule_max_pages: u32,
+    /// Outbound request budget per host
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    /// How long cached hospital/department lists are served without asking the server
+    #[serde(default = "default_http_cache_ttl_secs")]
+    pub http_cache_ttl_secs: u64,
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_grab_query_timeout_secs() -> u64 {
+    8
+}
+
+fn default_schedule_max_pages() -> u32 {
+    5
+}
+
+fn default_http_cache_ttl_secs() -> u64 {
+    30 * 60
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: default_connect_timeout_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+            grab_query_timeout_secs: default_grab_query_timeout_secs(),
+            slow_network: false,
+            schedule_max_pages: default_schedule_max_pages(),
+            rate_limit: RateLimitSettings::default(),
+            http_cache_ttl_secs: default_http_cache_ttl_secs(),
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// Pull every timeout into its accepted range
+    pub fn clamped(self) -> Self {
+        let clamp = |value: u64, (min, max): (u64, u64)| value.clamp(min, max);
+        Self {
+            connect_timeout_secs: clamp(self.connect_timeout_secs, CONNECT_TIMEOUT_RANGE),
+            request_timeout_secs: clamp(self.request_timeout_secs, REQUEST_TIMEOUT_RANGE),
+            grab_query_timeout_secs: clamp(self.grab_query_timeout_secs, GRAB_QUERY_TIMEOUT_RANGE),
+            slow_network: self.slow_network,
+            schedule_max_pages: self.schedule_max_pages.clamp(SCHEDULE_PAGES_RANGE.0, SCHEDULE_PAGES_RANGE.1),
+            rate_limit: self.rate_limit.clamped(),
+            http_cache_ttl_secs: clamp(self.http_cache_ttl_secs, HTTP_CACHE_TTL_RANGE),
+        }
+    }
+
+    fn stretch(&self, secs: u64) -> std::time::Duration {
+        let duration = std::time::Duration::from_secs(secs);
+        if self.slow_network {
+            duration * SLOW_NETWORK_FACTOR
+        } else {
+            duration
+        }
+    }
+
+    pub fn connect_timeout(&self) -> std::time::Duration {
+        self.stretch(self.connect_timeout_secs)
+    }
+
+    pub fn request_timeout(&self) -> std::time::Duration {
+        self.stretch(self.request_timeout_secs)
+    }
+
+    pub fn http_cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.http_cache_ttl_secs)
+    }
+
+    /// Timeout for one schedule query during a grab
+    /// An explicit override beats the grab budget; neither may exceed the
+    /// overall request timeout.
+    pub fn grab_query_timeout(&self, override_secs: Option<u64>) -> std::time::Duration {
+        let (min, max) = GRAB_QUERY_TIMEOUT_RANGE;
+        let secs = override_secs
+            .filter(|secs| *secs > 0)
+            .map_or(self.grab_query_timeout_secs, |secs| secs.clamp(min, max));
+        self.stretch(secs).min(self.request_timeout())
+    }
+}
+
+/// Accepted range for the per-host request rates (requests per second)
+pub const REQUEST_RATE_RANGE: (f64, f64) = (0.2, 50.0);
+/// Accepted range for the rate limiter burst size
+pub const REQUEST_BURST_RANGE: (u32, u32) = (1, 20);
+
+/// Token-bucket limits for outbound requests; waits rather than rejects
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Requests per second to the schedule gateway
+    #[serde(default = "default_gate_per_sec")]
+    pub gate_per_sec: f64,
+    /// Requests per second to www and every other host
+    #[serde(default = "default_www_per_sec")]
+    pub www_per_sec: f64,
+    /// Requests a host may take back to back after a quiet spell
+    #[serde(default = "default_request_burst")]
+    pub burst: u32,
+}
+
+fn default_gate_per_sec() -> f64 {
+    5.0
+}
+
+fn default_www_per_sec() -> f64 {
+    2.0
+}
+
+fn default_request_burst() -> u32 {
+    3
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            gate_per_sec: default_gate_per_sec(),
+            www_per_sec: default_www_per_sec(),
+            burst: default_request_burst(),
+        }
+    }
+}
+
+impl RateLimitSettings {
+    /// Pull rates and burst into their accepted ranges (NaN takes the default)
+    pub fn clamped(self) -> Self {
+        let (min, max) = REQUEST_RATE_RANGE;
+        let rate = |value: f64, fallback: f64| if value.is_nan() { fallback } else { value.clamp(min, max) };
+        Self {
+            enabled: self.enabled,
+            gate_per_sec: rate(self.gate_per_sec, default_gate_per_sec()),
+            www_per_sec: rate(self.www_per_sec, default_www_per_sec()),
+            burst: self.burst.clamp(REQUEST_BURST_RANGE.0, REQUEST_BURST_RANGE.1),
+        }
+    }
+}
+
+/// Rate limiter counters for one host
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostRateStats {
+    pub host: String,
+    pub rate_per_sec: f64,
+    pub requests: u64,
+    /// Requests sent through the priority lane (order submits)
+    pub priority_requests: u64,
+    /// Requests that had to wait for a token
+    pub delayed: u64,
+    pub total_wait_ms: u64,
+    pub max_wait_ms: u64,
+}
+
+/// One host of a health check; timings are set for each stage that completed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostHealth {
+    pub host: String,
+    /// Any HTTP response counts as reachable
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// First address the host resolved to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    /// Time to the response headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<u64>,
+    /// Host the request ended up on when it was redirected elsewhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirected_to: Option<String>,
+    /// "dns", "connect" or "http"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_stage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of `HealthClient::health_check`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub hosts: Vec<HostHealth>,
+    /// Local clock minus server clock, from the first Date header seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew_secs: Option<f64>,
+    /// ok, slow, clock_skew, qr_unavailable, degraded, captive_portal,
+    /// dns_poisoned, offline or dns_failed
+    pub status: String,
+    /// Message shown to the user
+    pub verdict: String,
+}
+
+/// Result of `get_time_offset`: the local clock against 91160's
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeOffset {
+    /// Server minus local; positive means the PC clock is behind
+    pub offset_ms: i64,
+    /// Median round trip of the samples used
+    pub rtt_ms: i64,
+    /// Samples the estimate is based on
+    pub samples: usize,
+    pub server_time_iso: String,
+    pub local_time_iso: String,
+}
+
+/// Network settings in effect, the rate limiter counters and request metrics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkStats {
+    pub settings: NetworkSettings,
+    pub hosts: Vec<HostRateStats>,
+    #[serde(default)]
+    pub endpoints: Vec<EndpointStats>,
+}
+
+/// One access_hash the client knows about, as shown by `get_sessions`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Masked key (first and last four characters); also what `GrabConfig::session` takes
+    pub id: String,
+    /// Unix seconds of the last schedule query the gateway accepted with this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success_at: Option<i64>,
+    /// 10022 answers since the last success
+    pub expired_streak: u32,
+    /// Rejected too often; tried last and removed by `prune_sessions`
+    pub demoted: bool,
+    /// Pinned by the running grab's `session`
+    pub preferred: bool,
+}
+
+fn default_keepalive_minutes() -> u64 {
+    super::keepalive::DEFAULT_KEEPALIVE_MINUTES
+}
+
+fn default_probe_target() -> String {
+    "submit".into()
+}
+
+fn default_probe_method() -> String {
+    "get".into()
+}
+
+fn default_proxy_rotation() -> String {
+    "round_robin".into()
 }
 
 fn default_city_id() -> String {
@@ -349,3 +1782,428 @@ fn default_city_id() -> String {
 fn default_time_slots() -> Vec<String> {
     vec!["am".into(), "pm".into()]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn sample_submit_params() -> SubmitOrderParams {
+        SubmitOrderParams {
+            sch_data: "sch".into(),
+            member_id: "m1".into(),
+            address_id: "a1".into(),
+            address: "addr".into(),
+            his_mem_id: "h1".into(),
+            unit_id: "u1".into(),
+            schedule_id: "s1".into(),
+            dep_id: "d1".into(),
+            detlid: "t1".into(),
+            accept: default_accept(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_submit_params_accepts_snake_case_aliases() {
+        let params: SubmitOrderParams = serde_json::from_value(serde_json::json!({
+            "sch_data": "sch",
+            "member_id": "m1",
+            "address_id": "a1",
+            "his_mem_id": "h1",
+            "unit_id": "u1",
+            "schedule_id": "s1",
+            "dep_id": "d1",
+            "detlid": "t1",
+        }))
+        .unwrap();
+        assert_eq!(params.member_id, "m1");
+        assert_eq!(params.his_mem_id, "h1");
+        assert_eq!(params.address_id, "a1");
+        assert_eq!(params.accept, "1");
+        assert!(params.validate().is_ok());
+    }
+
+    fn sample_grab_config() -> GrabConfig {
+        serde_json::from_value(serde_json::json!({
+            "unit_id": "u1",
+            "dep_id": "d1",
+            "member_id": "m1",
+            "target_dates": ["2024-03-20"],
+        }))
+        .unwrap()
+    }
+
+    /// Validate as if today were the day before the sample target date
+    fn check(config: &GrabConfig) -> Result<(), Vec<String>> {
+        config.validate_on(chrono::NaiveDate::from_ymd_opt(2024, 3, 19).unwrap(), 0)
+    }
+
+    #[test]
+    fn test_repeat_template_from_success() {
+        let mut config = sample_grab_config();
+        config.dep_ids = vec!["d2".into()];
+        config.doctor_ids = vec!["100".into(), "200".into()];
+        config.preferred_hours = vec!["09:30-10:00".into()];
+        config.address_id = "3".into();
+        config.address = "配置地址".into();
+        config.start_time = "07:30:00".into();
+        let success: GrabSuccess = serde_json::from_value(serde_json::json!({
+            "unit_name": "u1",
+            "dep_name": "心内科二",
+            "doctor_name": "李医生",
+            "date": "2024-03-20",
+            "time_slot": "09:30-10:00",
+            "member_name": "m1",
+            "dep_id": "d2",
+            "doctor_id": "200",
+            "address_id": "7",
+            "address": "福田区",
+        }))
+        .unwrap();
+
+        let template = RepeatTemplate::from_success(&config, &success, "2024-03-19 07:30:01");
+        assert!(template.is_for("u1", "d2"));
+        assert_eq!(template.doctor_id, "200");
+        assert_eq!((template.address_id.as_str(), template.address.as_str()), ("7", "福田区"));
+        // Nothing booked to go by: the configured pair stays, text included
+        let unbooked = GrabSuccess { address_id: String::new(), address: String::new(), ..success.clone() };
+        let fallback = RepeatTemplate::from_success(&config, &unbooked, "2024-03-19 07:30:01");
+        assert_eq!((fallback.address_id.as_str(), fallback.address.as_str()), ("3", "配置地址"));
+
+        let repeat = template.to_config(vec!["2024-04-17".into()]).unwrap();
+        assert_eq!((repeat.unit_id.as_str(), repeat.dep_id.as_str()), ("u1", "d2"));
+        assert!(repeat.dep_ids.is_empty());
+        assert_eq!(repeat.doctor_ids, ["200"]);
+        assert_eq!(repeat.target_dates, ["2024-04-17"]);
+        assert_eq!(repeat.preferred_hours, ["09:30-10:00"]);
+        assert_eq!((repeat.address_id.as_str(), repeat.address.as_str()), ("7", "福田区"));
+        // Run settings are the defaults, not the old run's
+        assert!(repeat.start_time.is_empty());
+        assert!(repeat.use_proxy_submit);
+
+        let any_doctor = RepeatTemplate { doctor_id: String::new(), ..template };
+        assert!(any_doctor.to_config(vec![]).unwrap().doctor_ids.is_empty());
+    }
+
+    #[test]
+    fn test_grab_config_rejects_blacklisted_target() {
+        let mut config = sample_grab_config();
+        config.doctor_ids = vec!["100".into(), "200".into()];
+        config.exclude_doctor_ids = vec!["300".into()];
+        assert!(check(&config).is_ok());
+
+        config.exclude_doctor_ids.push("200".into());
+        assert!(check(&config).unwrap_err()[0].contains("200"));
+    }
+
+    #[test]
+    fn test_date_doctor_map_overrides_and_warns() {
+        let mut config = sample_grab_config();
+        config.doctor_ids = vec!["100".into()];
+        config.target_dates = vec!["2024-03-20".into(), "2024-03-21".into()];
+        config.date_doctor_map.insert("2024-03-20".into(), vec!["200".into()]);
+        config.date_doctor_map.insert("2024-03-27".into(), vec![]);
+        assert_eq!(config.doctors_for("2024-03-20"), ["200"]);
+        assert_eq!(config.doctors_for("2024-03-21"), ["100"]);
+        assert!(config.doctors_for("2024-03-27").is_empty());
+        assert_eq!(config.warnings(), ["date_doctor_map date 2024-03-27 is not in target_dates"]);
+
+        config.exclude_doctor_ids = vec!["200".into()];
+        assert!(check(&config).unwrap_err()[0].contains("200"));
+    }
+
+    #[test]
+    fn test_grab_query_timeout_precedence() {
+        let network = NetworkSettings::default();
+        assert_eq!(network.grab_query_timeout(None), Duration::from_secs(8));
+        assert_eq!(network.grab_query_timeout(Some(0)), Duration::from_secs(8));
+        assert_eq!(network.grab_query_timeout(Some(3)), Duration::from_secs(3));
+        assert_eq!(network.grab_query_timeout(Some(500)), Duration::from_secs(30));
+
+        // Never longer than the overall request timeout
+        let tight = NetworkSettings { request_timeout_secs: 5, ..network };
+        assert_eq!(tight.grab_query_timeout(Some(20)), Duration::from_secs(5));
+
+        let slow = NetworkSettings { slow_network: true, ..network };
+        assert_eq!(slow.connect_timeout(), Duration::from_secs(30));
+        assert_eq!(slow.request_timeout(), Duration::from_secs(90));
+        assert_eq!(slow.grab_query_timeout(Some(3)), Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_network_settings_clamped() {
+        let wild = NetworkSettings {
+            connect_timeout_secs: 0,
+            request_timeout_secs: 9999,
+            grab_query_timeout_secs: 0,
+            slow_network: true,
+            schedule_max_pages: 0,
+            rate_limit: RateLimitSettings {
+                enabled: true,
+                gate_per_sec: f64::NAN,
+                www_per_sec: 1000.0,
+                burst: 0,
+            },
+            http_cache_ttl_secs: u64::MAX,
+        }
+        .clamped();
+        assert_eq!(wild.connect_timeout_secs, CONNECT_TIMEOUT_RANGE.0);
+        assert_eq!(wild.request_timeout_secs, REQUEST_TIMEOUT_RANGE.1);
+        assert_eq!(wild.grab_query_timeout_secs, GRAB_QUERY_TIMEOUT_RANGE.0);
+        assert!(wild.slow_network);
+        assert_eq!(wild.schedule_max_pages, SCHEDULE_PAGES_RANGE.0);
+        assert_eq!(wild.rate_limit.gate_per_sec, RateLimitSettings::default().gate_per_sec);
+        assert_eq!(wild.rate_limit.www_per_sec, REQUEST_RATE_RANGE.1);
+        assert_eq!(wild.rate_limit.burst, REQUEST_BURST_RANGE.0);
+        assert_eq!(wild.http_cache_ttl_secs, HTTP_CACHE_TTL_RANGE.1);
+        assert_eq!(NetworkSettings::default().clamped(), NetworkSettings::default());
+    }
+
+    #[test]
+    fn test_grab_config_proxy_probe_options() {
+        let mut config = sample_grab_config();
+        config.proxy_probe_target = "neutral".into();
+        config.proxy_probe_method = "head".into();
+        assert!(check(&config).is_ok());
+
+        config.proxy_probe_method = "post".into();
+        assert_eq!(check(&config).unwrap_err(), vec!["proxy_probe_method must be get or head"]);
+
+        config.proxy_probe_method = "get".into();
+        config.proxy_rotation = "sticky".into();
+        assert!(check(&config).is_ok());
+        config.proxy_rotation = "random".into();
+        assert!(check(&config).is_err());
+    }
+
+    #[test]
+    fn test_grab_config_stop_time_after_start_time() {
+        let mut config = sample_grab_config();
+        config.start_time = "07:30:00".into();
+        config.stop_time = "07:45:00".into();
+        assert!(check(&config).is_ok());
+
+        config.stop_time = "07:29:59".into();
+        assert_eq!(check(&config).unwrap_err(), vec!["stop_time must be after start_time"]);
+
+        config.stop_time = "7:45".into();
+        assert!(check(&config).is_err());
+    }
+
+    #[test]
+    fn test_start_time_policy_abort_rejects_passed_time() {
+        let mut config = sample_grab_config();
+        config.start_time = "07:30:00".into();
+        let evening = chrono::NaiveTime::from_hms_opt(19, 0, 0).unwrap();
+        // Default keeps the old behavior: a passed time just starts now
+        assert_eq!(config.start_time_policy, StartTimePolicy::RunNow);
+        assert_eq!(config.start_time_problem(evening), None);
+
+        config.start_time_policy = StartTimePolicy::NextDay;
+        assert_eq!(config.start_time_problem(evening), None);
+
+        config.start_time_policy = StartTimePolicy::Abort;
+        let problem = config.start_time_problem(evening).unwrap();
+        assert!(problem.starts_with("start_time 07:30:00 has already passed today (19:00:00 now)"), "{}", problem);
+        assert!(config.start_time_problem(chrono::NaiveTime::from_hms_opt(7, 30, 0).unwrap()).is_some());
+        assert_eq!(config.start_time_problem(chrono::NaiveTime::from_hms_opt(7, 29, 59).unwrap()), None);
+
+        let parsed: GrabConfig = serde_json::from_value(serde_json::json!({
+            "unit_id": "21",
+            "dep_id": "200",
+            "member_id": "m1",
+            "start_time_policy": "next_day",
+        }))
+        .unwrap();
+        assert_eq!(parsed.start_time_policy, StartTimePolicy::NextDay);
+    }
+
+    #[test]
+    fn test_grab_config_collects_every_problem() {
+        let mut config = sample_grab_config();
+        config.unit_id = " ".into();
+        config.member_id.clear();
+        config.start_time = "7:30".into();
+        let errors = check(&config).unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["unit_id is required", "member_id is required", "start_time must be HH:MM:SS"]
+        );
+    }
+
+    #[test]
+    fn test_grab_config_needs_a_department() {
+        let mut config = sample_grab_config();
+        config.dep_id.clear();
+        assert_eq!(check(&config).unwrap_err(), vec!["dep_id, dep_ids or dep_category_id is required"]);
+
+        config.dep_ids = vec![" d2 ".into(), "d3".into(), "d2".into(), "".into()];
+        assert!(check(&config).is_ok());
+        assert_eq!(config.dep_id_list(), ["d2", "d3"]);
+        config.dep_id = "d3".into();
+        assert_eq!(config.dep_id_list(), ["d3", "d2"]);
+
+        config.dep_id.clear();
+        config.dep_ids.clear();
+        config.dep_category_id = "c1".into();
+        assert_eq!(check(&config).unwrap_err(), vec!["dep_category_id requires city_pinyin"]);
+        config.city_pinyin = "sz".into();
+        assert!(check(&config).is_ok());
+    }
+
+    #[test]
+    fn test_grab_config_target_date_format_and_grace() {
+        let mut config = sample_grab_config();
+        config.target_dates = vec!["2024/03/20".into()];
+        assert_eq!(check(&config).unwrap_err(), vec!["target date 2024/03/20 must be YYYY-MM-DD"]);
+
+        config.target_dates = vec!["2024-03-18".into(), "2024-03-19".into()];
+        assert_eq!(check(&config).unwrap_err(), vec!["target date 2024-03-18 is in the past"]);
+
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 3, 19).unwrap();
+        assert!(config.validate_on(today, 1).is_ok());
+        assert!(config.validate_template().is_ok());
+
+        config.target_dates.clear();
+        assert_eq!(check(&config).unwrap_err(), vec!["target_dates or rolling_days is required"]);
+        config.rolling_days = Some(7);
+        assert!(check(&config).is_ok());
+        config.rolling_days = Some(0);
+        assert_eq!(check(&config).unwrap_err(), vec!["rolling_days must be at least 1"]);
+    }
+
+    #[test]
+    fn test_grab_config_time_types_allowed_set() {
+        let mut config = sample_grab_config();
+        config.time_types = vec!["am".into(), "pm".into()];
+        assert!(check(&config).is_ok());
+
+        config.time_types.push("night".into());
+        assert_eq!(check(&config).unwrap_err(), vec!["time type night must be one of am, pm"]);
+    }
+
+    #[test]
+    fn test_grab_config_retry_limits() {
+        let mut config = sample_grab_config();
+        for ok in [0.0, 0.2, 1.5, 60.0] {
+            config.retry_interval = ok;
+            assert!(check(&config).is_ok(), "{}", ok);
+        }
+        for bad in [0.1, 60.5, -1.0] {
+            config.retry_interval = bad;
+            assert_eq!(check(&config).unwrap_err(), vec!["retry_interval must be between 0.2 and 60 seconds"]);
+        }
+
+        config.retry_interval = 0.5;
+        config.max_retries = -1;
+        assert_eq!(check(&config).unwrap_err(), vec!["max_retries must not be negative"]);
+
+        config.max_retries = 0;
+        config.burst_duration_ms = 3000;
+        config.burst_interval_ms = 0;
+        assert_eq!(check(&config).unwrap_err(), vec!["burst_interval_ms must be at least 50"]);
+        config.burst_interval_ms = MIN_BURST_INTERVAL_MS;
+        assert!(check(&config).is_ok());
+        // The interval is unused while burst mode is off
+        config.burst_duration_ms = 0;
+        config.burst_interval_ms = 0;
+        assert!(check(&config).is_ok());
+    }
+
+    #[test]
+    fn test_grab_config_preferred_hours_parseable() {
+        let mut config = sample_grab_config();
+        config.preferred_hours = vec!["09:00-09:30".into(), "14:00:00 - 14:30:00".into()];
+        assert!(check(&config).is_ok());
+
+        config.preferred_hours = vec!["morning".into(), "10:00-09:30".into()];
+        assert_eq!(check(&config).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_slot_range() {
+        let t = |h, m| chrono::NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(parse_slot_range("09:00-09:30"), Some((t(9, 0), t(9, 30))));
+        assert_eq!(parse_slot_range(" 9:00 - 9:30 "), Some((t(9, 0), t(9, 30))));
+        assert_eq!(parse_slot_range("09:30-09:30"), None);
+        assert_eq!(parse_slot_range("09:00"), None);
+    }
+
+    #[test]
+    fn test_submit_params_validate() {
+        let mut params = sample_submit_params();
+        params.detlid = " ".into();
+        assert_eq!(params.validate().unwrap_err(), "detlid is required");
+    }
+
+    #[test]
+    fn test_hospital_metadata_is_optional() {
+        let minimal: Hospital = serde_json::from_str(r#"{"unit_id": 21, "unit_name": "深圳市人民医院"}"#).unwrap();
+        assert!(minimal.level.is_none() && minimal.address.is_none());
+        assert!(minimal.is_missing_metadata());
+        let json = serde_json::to_value(&minimal).unwrap();
+        assert_eq!(json, serde_json::json!({"unit_id": "21", "unit_name": "深圳市人民医院"}));
+
+        let full: Hospital = serde_json::from_str(
+            r#"{"id": "21", "name": "深圳市人民医院", "unit_level": "三级甲等", "addr": "东门北路1017号", "area_name": "罗湖区", "tel": 75525533018}"#,
+        )
+        .unwrap();
+        assert_eq!(full.level.as_deref(), Some("三级甲等"));
+        assert_eq!(full.address.as_deref(), Some("东门北路1017号"));
+        assert_eq!(full.district.as_deref(), Some("罗湖区"));
+        assert_eq!(full.phone.as_deref(), Some("75525533018"));
+        assert!(!full.is_missing_metadata());
+    }
+
+    #[test]
+    fn test_flatten_departments() {
+        let categories: Vec<DepartmentCategory> =
+            serde_json::from_str(include_str!("../../tests/fixtures/deps_nested.json")).unwrap();
+        assert_eq!(categories[0].id.as_deref(), Some("7"));
+
+        let flat = Department::flatten(&categories);
+        let ids: Vec<&str> = flat.iter().map(|d| d.dep_id.as_str()).collect();
+        assert_eq!(ids, vec!["101", "102", "1021", "10211", "301"]);
+        assert_eq!(flat[0].dep_name, "心血管内科");
+        assert_eq!(flat[3].category_name, "内科");
+        assert_eq!(flat[3].path, vec!["内科", "消化内科", "胃镜中心", "无痛胃镜"]);
+        assert_eq!(flat[4].category_name, "特需");
+        assert_eq!(flat[4].path, vec!["特需", "国际医疗部"]);
+    }
+
+    #[test]
+    fn test_is_suspended_status() {
+        assert!(is_suspended_status("停诊"));
+        assert!(is_suspended_status("医生停诊"));
+        assert!(is_suspended_status(" STOP "));
+        assert!(!is_suspended_status(""));
+        assert!(!is_suspended_status("1"));
+        assert!(!is_suspended_status("正常"));
+    }
+
+    #[test]
+    fn test_is_valid_id_card() {
+        assert!(is_valid_id_card("11010519491231002X"));
+        assert!(is_valid_id_card("11010519491231002x"));
+        assert!(is_valid_id_card("440308199901011234"));
+        assert!(!is_valid_id_card("440308199901011232"));
+        assert!(!is_valid_id_card("4403081999010112"));
+        assert!(!is_valid_id_card("44030819990101123A"));
+    }
+
+    #[test]
+    fn test_new_member_params_validate() {
+        let mut params = NewMemberParams {
+            name: "张三".into(),
+            id_card: "11010519491231002X".into(),
+            phone: "13800138000".into(),
+            relation: String::new(),
+        };
+        assert!(params.validate().is_ok());
+        params.phone = "2380013800".into();
+        assert_eq!(params.validate().unwrap_err(), "phone must be an 11-digit mobile number");
+        params.id_card = "110105194912310021".into();
+        assert!(params.validate().unwrap_err().starts_with("id_card"));
+    }
+}
@@ -7,29 +7,145 @@ mod commands;
 mod core;
 
 use commands::AppState;
+use core::errors::AppError;
+use core::instance::InstanceLock;
+use tauri::Manager;
 
 fn main() {
+    let log_handle = match core::paths::logs_dir().and_then(|dir| core::logging::init(&dir)) {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            eprintln!("logging init failed: {}", e);
+            None
+        }
+    };
+
+    // Checked before the app is built: a refused copy must not load the UI,
+    // whose startup commands could resume a grab the running copy owns
+    let instance = match core::paths::config_dir().and_then(|dir| InstanceLock::acquire(&dir)) {
+        Ok(lock) => Some(lock),
+        Err(e @ AppError::AlreadyRunning { .. }) => {
+            tracing::warn!(error = %e, "another instance is running, exiting");
+            rfd::MessageDialog::new()
+                .set_title("SkylineMed")
+                .set_description(e.to_frontend_string())
+                .set_level(rfd::MessageLevel::Error)
+                .show();
+            drop(log_handle);
+            std::process::exit(0);
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "instance lock unavailable");
+            None
+        }
+    };
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .manage(AppState::default())
+        .manage(log_handle)
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                if !commands::allow_window_close(window.app_handle()) {
+                    api.prevent_close();
+                }
+            }
+        })
+        .setup(|app| {
+            if let Some(lock) = instance {
+                app.manage(lock);
+            }
+            if let Err(e) = core::history::compact_grab_history() {
+                tracing::warn!(error = %e, "grab history compaction failed");
+            }
+            commands::spawn_login_status(app.handle().clone());
+            commands::spawn_keepalive(app.handle().clone());
+            if let Err(e) = commands::spawn_tray(app.handle()) {
+                tracing::warn!(error = %e, "tray icon unavailable");
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             commands::get_cities,
+            commands::get_config_paths,
+            commands::open_config_dir,
+            commands::reveal_path,
+            commands::create_diagnostics_bundle,
+            commands::get_submit_traces,
             commands::get_user_state,
             commands::save_user_state_cmd,
             commands::export_logs,
+            commands::set_log_level,
+            commands::set_keepalive_interval,
+            commands::get_alert_config,
+            commands::set_alert_config,
+            commands::set_client_profile,
+            commands::set_network_settings,
+            commands::set_custom_proxies,
+            commands::get_proxy_pool_status,
+            commands::test_proxy,
+            commands::clear_proxy_cache,
+            commands::clear_http_cache,
+            commands::export_settings,
+            commands::import_settings,
+            commands::export_appointment_ics,
             commands::get_hospitals_by_city,
+            commands::get_hospital_detail,
+            commands::get_booking_rules,
+            commands::apply_booking_rules,
             commands::get_deps_by_unit,
+            commands::get_deps_flat,
+            commands::search_catalog,
+            commands::parse_booking_url,
             commands::get_members,
+            commands::add_member,
+            commands::get_addresses,
+            commands::add_address,
             commands::check_login,
+            commands::get_account_info,
+            commands::clear_session,
+            commands::get_sessions,
+            commands::prune_sessions,
+            commands::list_profiles,
+            commands::create_profile,
+            commands::delete_profile,
+            commands::switch_profile,
             commands::get_schedule,
+            commands::get_availability_calendar,
+            commands::get_department_doctors,
             commands::get_ticket_detail,
+            commands::get_address_options,
             commands::submit_order,
+            commands::open_order_url,
             commands::start_qr_login,
             commands::stop_qr_login,
             commands::start_grab,
+            commands::start_grab_from_preset,
+            commands::repeat_last_grab,
+            commands::list_repeat_templates,
+            commands::list_grab_presets,
+            commands::save_grab_preset,
+            commands::delete_grab_preset,
+            commands::validate_grab_config,
+            commands::get_grab_history,
+            commands::clear_grab_history,
             commands::stop_grab,
+            commands::get_network_stats,
+            commands::run_health_check,
+            commands::get_time_offset,
+            commands::resume_grab_after_captcha,
+            commands::resume_pending_grab,
+            commands::force_exit,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<AppState>().shutdown.cancel();
+                if let Some(lock) = app.try_state::<InstanceLock>() {
+                    lock.release();
+                }
+            }
+        });
 }